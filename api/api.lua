@@ -17,41 +17,422 @@ export type BlockDefinition = {
   name: string,
   description: string,
   textures: Array<string>,
-  drawtype: number
+  drawtype: number,
+  light_source: number?,
+  light_propagates: boolean?,
+  sunlight_propagates: boolean?,
+  pointable: boolean?,
+  liquidtype: string?,
+  liquid_alternative_source: string?,
+  liquid_alternative_flowing: string?,
+  liquid_viscosity: number?,
+  liquid_range: number?,
+  liquid_renewable: boolean?,
+  -- falling_node = 1 makes a block fall when the node under it is air or liquid.
+  -- attached_node = 1 makes a block drop off when the node under it is air or liquid.
+  groups: {[string] : number}?,
+  -- Every block is an item too. How many fit in one stack, 99 if left out.
+  stack_max: number?,
+  on_timer: ((pos: Position, elapsed: number) -> boolean?)?,
+  on_dig: ((pos: Position, node: Node, digger: PlayerRef) -> nil)?,
+  on_place: ((itemstack: ItemStack, placer: PlayerRef, pointed_thing: PointedThing) -> ItemStack?)?,
+  after_place_node: ((pos: Position, placer: PlayerRef, itemstack: ItemStack, pointed_thing: PointedThing) -> nil)?
 }
 
+-- The hand is the item named "".
+-- stack_max is 1 for tools (anything with tool_capabilities), 99 for everything else, if left out.
 export type ItemDefinition = {
   name: string,
   description: string,
   readable_name: string,
   textures: Array<string>,
-  drawtype: number
+  drawtype: number,
+  stack_max: number?,
+  range: number?,
+  tool_capabilities: ToolCapabilities?,
+  -- Crafting recipes can ask for "group:name" instead of an item.
+  groups: {[string] : number}?,
+  -- Items can't be placed unless they have one. Blocks default to minetest.item_place.
+  on_place: ((itemstack: ItemStack, placer: PlayerRef, pointed_thing: PointedThing) -> ItemStack?)?
+}
+
+-- Made with ItemStack(item). item is an item string ("name count wear"), an ItemStack,
+-- a {name, count, wear, meta} table, or nil for an empty stack.
+export type ItemStack = {
+  is_empty: (self: ItemStack) -> boolean,
+  get_name: (self: ItemStack) -> string,
+  set_name: (self: ItemStack, name: string) -> nil,
+  get_count: (self: ItemStack) -> number,
+  set_count: (self: ItemStack, count: number) -> nil,
+  get_wear: (self: ItemStack) -> number,
+  set_wear: (self: ItemStack, wear: number) -> nil,
+  -- Returns true if the tool wore out and broke.
+  add_wear: (self: ItemStack, amount: number) -> boolean,
+  -- Works like NodeMetaRef, without the inventory.
+  get_meta: (self: ItemStack) -> any,
+  clear: (self: ItemStack) -> nil,
+  is_known: (self: ItemStack) -> boolean,
+  get_description: (self: ItemStack) -> string,
+  get_stack_max: (self: ItemStack) -> number,
+  get_free_space: (self: ItemStack) -> number,
+  to_string: (self: ItemStack) -> string,
+  to_table: (self: ItemStack) -> {name: string, count: number, wear: number, meta: {[string] : string}}?,
+  take_item: (self: ItemStack, count: number?) -> ItemStack,
+  peek_item: (self: ItemStack, count: number?) -> ItemStack,
+  -- Returns the leftover that didn't fit.
+  add_item: (self: ItemStack, item: any) -> ItemStack,
+  item_fits: (self: ItemStack, item: any) -> boolean
+}
+
+-- times[rating] is how many seconds it takes to dig a block with that rating in the group.
+-- maxlevel is the highest "level" group of block this can dig. Higher levels dig faster.
+export type GroupCap = {
+  times: Array<number>,
+  maxlevel: number?
+}
+
+export type ToolCapabilities = {
+  groupcaps: {[string] : GroupCap}?
+}
+
+-- What a player is pointing at. under and above are only there for nodes.
+export type PointedThing = {
+  type: string,
+  under: Position?,
+  above: Position?,
+  intersection_point: Position,
+  intersection_normal: Position
+}
+
+-- Handed to callbacks as digger, placer, etc.
+-- Players start out with the lists main (32), craft (9) and hand (1).
+export type PlayerRef = {
+  get_player_name: (self: PlayerRef) -> string,
+  is_player: (self: PlayerRef) -> boolean,
+  get_inventory: (self: PlayerRef) -> InvRef,
+  -- 0 once the player has left. Players have 20 at most.
+  get_hp: (self: PlayerRef) -> number,
+  -- reason ends up in on_dieplayer, {type = "set_hp"} if it's nil.
+  set_hp: (self: PlayerRef, hp: number, reason: {type: string}?) -> nil
+}
+
+-- A handle to a node, player or detached inventory. Slots start at 1.
+-- Anywhere an item goes, an item string, ItemStack or table works too.
+export type InvRef = {
+  is_empty: (self: InvRef, listname: string) -> boolean,
+  get_size: (self: InvRef, listname: string) -> number,
+  -- A size of 0 removes the list.
+  set_size: (self: InvRef, listname: string, size: number) -> boolean,
+  get_width: (self: InvRef, listname: string) -> number,
+  set_width: (self: InvRef, listname: string, width: number) -> boolean,
+  get_stack: (self: InvRef, listname: string, index: number) -> ItemStack,
+  set_stack: (self: InvRef, listname: string, index: number, stack: any) -> boolean,
+  get_list: (self: InvRef, listname: string) -> Array<ItemStack>?,
+  set_list: (self: InvRef, listname: string, list: Array<any>) -> nil,
+  get_lists: (self: InvRef) -> Array<string>,
+  -- Returns what didn't fit.
+  add_item: (self: InvRef, listname: string, item: any) -> ItemStack,
+  room_for_item: (self: InvRef, listname: string, item: any) -> boolean,
+  -- Only the name is matched, not wear or meta.
+  contains_item: (self: InvRef, listname: string, item: any) -> boolean,
+  -- Returns what was taken out, which can be less than asked for.
+  remove_item: (self: InvRef, listname: string, item: any) -> ItemStack,
+  -- {type = "node", pos}, {type = "player", name} or {type = "detached", name}
+  get_location: (self: InvRef) -> {type: string, pos: Position?, name: string?}
+}
+
+-- For "move" inventory_info is {from_list, from_index, to_list, to_index, count},
+-- for "take" and "put" it's {listname, index, stack}.
+-- allow callbacks return how many of the items can move, nil lets all of them.
+export type AllowPlayerInventoryAction = (player: PlayerRef, action: string, inventory: InvRef, inventory_info: any) -> number?
+export type OnPlayerInventoryAction = (player: PlayerRef, action: string, inventory: InvRef, inventory_info: any) -> nil
+
+-- Every callback is optional. allow callbacks return how many of the items can move, nil lets all of them.
+export type DetachedInventoryCallbacks = {
+  allow_move: ((inv: InvRef, from_list: string, from_index: number, to_list: string, to_index: number, count: number, player: PlayerRef) -> number?)?,
+  allow_put: ((inv: InvRef, listname: string, index: number, stack: ItemStack, player: PlayerRef) -> number?)?,
+  allow_take: ((inv: InvRef, listname: string, index: number, stack: ItemStack, player: PlayerRef) -> number?)?,
+  on_move: ((inv: InvRef, from_list: string, from_index: number, to_list: string, to_index: number, count: number, player: PlayerRef) -> nil)?,
+  on_put: ((inv: InvRef, listname: string, index: number, stack: ItemStack, player: PlayerRef) -> nil)?,
+  on_take: ((inv: InvRef, listname: string, index: number, stack: ItemStack, player: PlayerRef) -> nil)?
 }
 
 -- A fancy closure.
 export type OnTick = (delta: number) -> nil
+export type OnProtectionViolation = (pos: Position, name: string) -> nil
+-- Return true to stop the message going any further, commands included.
+export type OnChatMessage = (name: string, message: string) -> boolean?
+
+-- Player and Server lifecycle. Errors get blamed on the mod that registered the callback.
+-- Return a string to turn the player away, it's shown to them as the reason.
+export type OnPrejoinPlayer = (name: string, ip: string) -> string?
+export type OnJoinPlayer = (player: PlayerRef) -> nil
+-- Only the first time a player joins the world, right before on_joinplayer.
+export type OnNewPlayer = (player: PlayerRef) -> nil
+-- Everyone still connected leaves with timed_out false when the Server shuts down.
+export type OnLeavePlayer = (player: PlayerRef, timed_out: boolean) -> nil
+export type OnDiePlayer = (player: PlayerRef, reason: {type: string}) -> nil
+-- todo: returning true will skip moving the player to spawn.
+export type OnRespawnPlayer = (player: PlayerRef) -> boolean?
+export type OnShutdown = () -> nil
+-- Runs once every mod has loaded and the engine has picked up everything they registered.
+export type OnModsLoaded = () -> nil
+
+-- privs is like {interact = true}, players need every one of them to run it.
+-- func gets everything typed after the command. The message is sent back to the player.
+export type ChatCommandDefinition = {
+  params: string?,
+  description: string?,
+  privs: {[string] : boolean}?,
+  func: (name: string, param: string) -> (boolean?, string?)
+}
+
+-- What minetest.get_node gives back.
+export type Node = {
+  name: string,
+  param1: number?,
+  param2: number?
+}
+
+-- An active block modifier. Runs action on matching nodes in the active area.
+-- Every interval seconds, each node in nodenames has a 1 in chance shot.
+-- If neighbors is set, one of them must be touching the node too.
+export type AbmDefinition = {
+  label: string?,
+  nodenames: string | Array<string>,
+  neighbors: (string | Array<string>)?,
+  interval: number?,
+  chance: number?,
+  action: (pos: Position, node: Node) -> nil
+}
+
+----------
+-- Mapgen types.
+
+-- Positions from the engine are vectors. {x, y, z} tables work anywhere a position goes in.
+export type Position = {
+  x: number,
+  y: number,
+  z: number
+}
+
+export type NoiseParams = {
+  offset: number?,
+  scale: number?,
+  spread: Position,
+  seed: number?,
+  octaves: number?,
+  persistence: number?,
+  lacunarity: number?
+}
+
+export type BiomeDefinition = {
+  name: string,
+  node_top: string,
+  depth_top: number?,
+  node_filler: string,
+  depth_filler: number?,
+  node_stone: string,
+  node_water: string?,
+  y_min: number?,
+  y_max: number?,
+  heat_point: number?,
+  humidity_point: number?
+}
+
+-- ore_type is "scatter", "blob" or "vein".
+export type OreDefinition = {
+  ore_type: string,
+  ore: string,
+  wherein: string | Array<string>,
+  clust_scarcity: number?,
+  clust_num_ores: number?,
+  clust_size: number?,
+  y_min: number?,
+  y_max: number?,
+  noise_params: NoiseParams?,
+  noise_threshold: number?
+}
+
+-- name "ignore" leaves whatever is already there.
+-- prob is the chance out of 255 to be placed.
+-- Without force_place, only air gets replaced.
+export type SchematicNode = {
+  name: string,
+  prob: number?,
+  param2: number?,
+  force_place: boolean?
+}
+
+-- data is ordered Z, then Y, then X.
+export type SchematicDefinition = {
+  size: Position,
+  data: Array<SchematicNode>
+}
+
+-- A schematic file path or a schematic table.
+-- File paths are relative to the mod's folder and can't be absolute or use "..".
+export type Schematic = string | SchematicDefinition
+
+-- deco_type is "simple" or "schematic".
+-- Schematic decorations take the same rotation, replacements and flags as minetest.place_schematic.
+export type DecorationDefinition = {
+  deco_type: string,
+  place_on: string | Array<string>,
+  fill_ratio: number?,
+  biomes: Array<string>?,
+  y_min: number?,
+  y_max: number?,
+  decoration: (string | Array<string>)?,
+  height: number?,
+  height_max: number?,
+  schematic: Schematic?,
+  -- Filled in by register_decoration, the mod a schematic file belongs to.
+  schematic_mod: string?,
+  rotation: string?,
+  replacements: {[string] : string}?,
+  force_placement: boolean?,
+  flags: string?
+}
+
+-- type is "shaped" (if left out), "shapeless", "cooking" or "fuel".
+-- Ingredients are item names or "group:name", "group:a,b" needs every group.
+-- * shaped:    recipe = {{"a", "b"}, {"", "c"}}, rows of the grid. It matches wherever it fits.
+-- * shapeless: recipe = {"a", "b"}, in any order.
+-- * cooking:   recipe = "a", cooktime is 3 if left out.
+-- * fuel:      recipe = "a", no output, burntime is 1 if left out.
+export type CraftDefinition = {
+  type: string?,
+  output: string?,
+  recipe: string | Array<string> | Array<Array<string>>,
+  cooktime: number?,
+  burntime: number?
+}
 
 -- Singleton instances of raw data.
-_G.blocks  = _G.blocks  or {}
-_G.items   = _G.items   or {}
-_G.on_tick = _G.on_tick or {}
+-- The engine passes in the internals table when it runs this file.
+-- It only lives in the Lua registry, so mods can never reach it.
+local internals = ...
+
+internals.blocks      = {}
+internals.items       = {}
+internals.on_tick     = {}
+internals.biomes      = {}
+internals.ores        = {}
+internals.decorations = {}
+internals.abms        = {}
+internals.crafts      = {}
+internals.on_chat_message = {}
+internals.chatcommands    = {}
+internals.allow_player_inventory_action = {}
+internals.on_player_inventory_action    = {}
+internals.on_prejoinplayer  = {}
+internals.on_joinplayer     = {}
+internals.on_newplayer      = {}
+internals.on_leaveplayer    = {}
+internals.on_dieplayer      = {}
+internals.on_respawnplayer  = {}
+internals.on_shutdown       = {}
+internals.on_mods_loaded    = {}
+internals.on_protection_violation = {}
+
+local blocks:      {[string] : BlockDefinition} = internals.blocks
+local items:       {[string] : ItemDefinition}  = internals.items
+local on_tick:     Array<OnTick>                = internals.on_tick
+local biomes:      {[string] : BiomeDefinition} = internals.biomes
+local ores:        Array<OreDefinition>         = internals.ores
+local decorations: Array<DecorationDefinition>  = internals.decorations
+local abms:        Array<AbmDefinition>         = internals.abms
+local crafts:      Array<CraftDefinition>       = internals.crafts
+local on_chat_message: Array<OnChatMessage>               = internals.on_chat_message
+local chatcommands:    {[string] : ChatCommandDefinition} = internals.chatcommands
+local allow_player_inventory_action: Array<AllowPlayerInventoryAction> = internals.allow_player_inventory_action
+local on_player_inventory_action:    Array<OnPlayerInventoryAction>    = internals.on_player_inventory_action
+local on_prejoinplayer: Array<OnPrejoinPlayer> = internals.on_prejoinplayer
+local on_joinplayer:    Array<OnJoinPlayer>    = internals.on_joinplayer
+local on_newplayer:     Array<OnNewPlayer>     = internals.on_newplayer
+local on_leaveplayer:   Array<OnLeavePlayer>   = internals.on_leaveplayer
+local on_dieplayer:     Array<OnDiePlayer>     = internals.on_dieplayer
+local on_respawnplayer: Array<OnRespawnPlayer> = internals.on_respawnplayer
+local on_shutdown:      Array<OnShutdown>      = internals.on_shutdown
+local on_mods_loaded:   Array<OnModsLoaded>    = internals.on_mods_loaded
+local on_protection_violation: Array<OnProtectionViolation> = internals.on_protection_violation
+
+
+----------
+-- Definition validation.
+-- The engine does a second pass on these when the game finishes loading,
+-- but catching it here points the modder at the exact line that broke.
+
+local function check_field(kind: string, definition: any, field: string, expected: string, optional: boolean)
+  local value = definition[field]
+  if (value == nil) then
+    if (not optional) then
+      error("minetest: " .. kind .. " is missing required field [" .. field .. "]", 3)
+    end
+    return
+  end
+  if (type(value) ~= expected) then
+    error("minetest: " .. kind .. " field [" .. field .. "] must be a " .. expected .. ", got " .. type(value), 3)
+  end
+end
+
+-- Callbacks get checked when they're registered, not when the engine first runs them.
+local function check_callback(kind: string, callback: any)
+  if (type(callback) ~= "function") then
+    error("minetest: " .. kind .. " callback must be a function, got " .. type(callback), 3)
+  end
+end
 
-local blocks:  {[string] : BlockDefinition} = _G.blocks
-local items:   {[string] : ItemDefinition}  = _G.items
-local on_tick: Array<OnTick>                = _G.on_tick
+-- Fields like wherein can be a single name or an array of names.
+local function check_name_list(kind: string, definition: any, field: string, optional: boolean)
+  local value = definition[field]
+  if (value == nil and optional) then
+    return
+  end
+  if (type(value) == "string") then
+    return
+  end
+  if (type(value) ~= "table" or #value == 0) then
+    error("minetest: " .. kind .. " field [" .. field .. "] must be a string or an array of strings", 3)
+  end
+  for _,name in ipairs(value) do
+    if (type(name) ~= "string") then
+      error("minetest: " .. kind .. " field [" .. field .. "] must only contain strings", 3)
+    end
+  end
+end
+
+local function check_y_range(kind: string, definition: any)
+  check_field(kind, definition, "y_min", "number", true)
+  check_field(kind, definition, "y_max", "number", true)
+  if (definition.y_min ~= nil and definition.y_max ~= nil and definition.y_min > definition.y_max) then
+    error("minetest: " .. kind .. " has y_min greater than y_max", 3)
+  end
+end
+
+local function check_noise_params(kind: string, noise_params: any)
+  if (type(noise_params.spread) ~= "table") then
+    error("minetest: " .. kind .. " noise_params is missing [spread]", 3)
+  end
+  for _,axis in ipairs({"x", "y", "z"}) do
+    if (type(noise_params.spread[axis]) ~= "number" or noise_params.spread[axis] <= 0) then
+      error("minetest: " .. kind .. " noise_params spread." .. axis .. " must be a number above 0", 3)
+    end
+  end
+end
 
 ----------
 -- Now we can ship the rest of the codebase back to the mod as a module.
 
--- We want to avoid piling up memory, so we're just going to overwrite.
--- This spends CPU to save memory.
--- This is also done like this so that linting works properly.
+-- The engine registers its own functions into this.
+-- Mods get it as their minetest global, or through require("api/api").
 local minetest = {}
-
--- Mangle together the internal references.
-minetest = _G.minetest or {}
 _G.minetest = minetest
 
+-- The brightest a block can glow. Sunlight is one brighter.
+minetest.LIGHT_MAX = 14
+
 minetest.draw_type = {
   air       = 0,
   regular   = 1,
@@ -59,24 +440,380 @@ minetest.draw_type = {
   mesh      = 3
 }
 
+-- Provided by the engine:
+-- vector.new(x, y, z) / vector.new(v) / vector.zero() -> vector
+--   Vectors do + - * / with other vectors or numbers, ==, unary - and tostring.
+--   vector.distance, length, normalize, cross, dot, round, floor and copy
+--   work as vector.dot(a, b) or a:dot(b).
+-- minetest.get_node(pos) -> {name, param1, param2}, unloaded nodes are "ignore"
+-- minetest.set_node(pos, {name, param2?}) -> boolean
+-- minetest.remove_node(pos) -> boolean
+-- minetest.get_meta(pos) -> NodeMetaRef
+-- minetest.get_node_timer(pos) -> NodeTimerRef
+-- minetest.emerge_area(pos1, pos2, callback?)
+--   At most 4096 chunks (16 x 16 x 16) at a time, bigger areas are an error.
+-- minetest.raycast(pos1, pos2, objects?, liquids?) -> RaycastRef
+--   Iterate it with a for loop or :next(), each step gives back a pointed_thing:
+--   {type = "node", under, above, intersection_point, intersection_normal}
+-- minetest.create_schematic(pos1, pos2, probability_list?, filename) -> true
+--   probability_list is {{pos, prob}, ...}. Every node in the area has to be loaded.
+--   The file is saved in the world's schematics folder.
+-- minetest.place_schematic(pos, schematic, rotation?, replacements?, force_placement?, flags?) -> boolean
+--   A schematic file is read out of the calling mod's folder.
+--   rotation is "0", "90", "180", "270" or "random".
+--   replacements is {["old"] = "new"}.
+--   flags is a comma separated list of place_center_x, place_center_y, place_center_z, mirror_x, mirror_z.
+--   Returns false if part of it landed in unloaded chunks.
+-- minetest.is_protected(pos, name) -> boolean
+--   True if name is kept out of pos. The engine asks this before digs, places and
+--   NodeMeta/inventory writes made for a player. The default checks the built-in areas.
+--   Mods can replace it, call the old one to stack protection mods.
+-- minetest.record_protection_violation(pos, name)
+--   Runs the on_protection_violation callbacks. The engine calls this whenever
+--   minetest.is_protected turns someone away, protection mods can call it too.
+-- minetest.protect_area(pos1, pos2, owner) -> id
+--   Only the owner can change anything in the area. It's saved with the world.
+-- minetest.unprotect_area(id) -> boolean
+-- minetest.get_protected_areas(pos) -> {{id, owner, min, max}, ...}
+-- minetest.rollback_get_node_actions(pos, range, seconds) -> {{actor, pos, time, oldnode, newnode}, ...}
+--   Newest first. actor is "player:name" for players and the mod name for mod callbacks.
+--   Empty unless enable_rollback is on in game.conf.
+-- minetest.get_modpath(modname) -> string?
+--   The folder a mod lives in, or nil if the game doesn't have it.
+-- minetest.get_current_modname() -> string?
+--   The mod whose main.lua is running. nil once loading is over.
+-- require(name) -> any
+--   Loads name.lua out of the calling mod's folder. Each module only runs once.
+--   require("othermod:name") loads out of a mod listed in depends or optional_depends.
+--   Whatever a mod's main.lua returns is its export, require("othermod:main").
+--   require("api/api") gives back the minetest table.
+-- ItemStack(item) -> ItemStack
+--   See the ItemStack type up top. Stack limits come from the registered items,
+--   so until every mod has loaded, everything stacks up to 99.
+-- minetest.get_inventory(location) -> InvRef?
+--   location is {type = "player", name}, {type = "detached", name} or {type = "node", pos}.
+--   nil for player and detached inventories that don't exist.
+-- minetest.create_detached_inventory(name, callbacks?, player_name?) -> InvRef
+--   An inventory that isn't attached to anything. It's never saved.
+--   Only player_name gets to see it, or everyone if it's nil. See DetachedInventoryCallbacks.
+-- minetest.remove_detached_inventory(name) -> boolean
+-- minetest.get_craft_result({method, width, items}) -> ({item, time}, {method, width, items})
+--   method is "normal", "cooking" or "fuel". items is the grid as a list of ItemStacks, width to a row.
+--   item is empty if nothing matched. The second table is the input with the recipe taken out.
+--   Recipes only match once every mod has loaded.
+-- minetest.get_craft_recipe(output) -> {method, type, width, output, items}
+--   The first recipe making output. items are the ingredient strings, empty slots are nil.
+--   An empty table if nothing makes it.
+-- minetest.chat_send_player(name, message)
+-- minetest.chat_send_all(message)
+--   Sent at the end of the tick. /help and /rollback are built into the engine.
+
+-- The default on_dig. Digs the node out into the digger's main list.
+-- todo: what doesn't fit should drop once there are item entities.
+function minetest.node_dig(pos: Position, node: Node, digger: PlayerRef)
+  minetest.remove_node(pos)
+  digger:get_inventory():add_item("main", node.name)
+end
+
+-- The default on_place. Puts the block in front of the face being pointed at,
+-- then runs its after_place_node. Gives back the itemstack with one item taken.
+function minetest.item_place(itemstack: ItemStack, placer: PlayerRef, pointed_thing: PointedThing): ItemStack
+  if (pointed_thing.type ~= "node" or pointed_thing.above == nil) then
+    return itemstack
+  end
+  local pos = pointed_thing.above
+  if (minetest.get_node(pos).name ~= "air") then
+    return itemstack
+  end
+  local name = itemstack:get_name()
+  minetest.set_node(pos, {name = name})
+  local definition = blocks[name]
+  if (definition ~= nil and definition.after_place_node ~= nil) then
+    definition.after_place_node(pos, placer, itemstack, pointed_thing)
+  end
+  itemstack:take_item()
+  return itemstack
+end
+
+-- What happened to a chunk in a minetest.emerge_area callback.
+-- callback(chunk_position: Position, action: number, calls_remaining: number)
+minetest.emerge_action = {
+  cancelled   = 0,
+  errored     = 1,
+  from_memory = 2,
+  from_disk   = 3,
+  generated   = 4
+}
+
 function minetest.register_block(definition: BlockDefinition)
+  if (definition.name == "air" or definition.name == "ignore") then
+    error(definition.name .. " is built into the engine and cannot be registered.")
+  end
   if (blocks[definition.name] ~= nil) then
     error(definition.name .. " is already a registered block.")
   end
+  if (items[definition.name] ~= nil) then
+    error(definition.name .. " is already a registered item. Blocks are items too.")
+  end
+  local kind = "block [" .. definition.name .. "]"
+  if (string.find(definition.name, "%s") ~= nil) then
+    error("minetest: " .. kind .. " names can't have spaces in them")
+  end
+  check_field(kind, definition, "stack_max", "number", true)
+  check_field(kind, definition, "light_source", "number", true)
+  check_field(kind, definition, "light_propagates", "boolean", true)
+  check_field(kind, definition, "sunlight_propagates", "boolean", true)
+  check_field(kind, definition, "pointable", "boolean", true)
+  check_field(kind, definition, "liquidtype", "string", true)
+  check_field(kind, definition, "liquid_alternative_source", "string", true)
+  check_field(kind, definition, "liquid_alternative_flowing", "string", true)
+  check_field(kind, definition, "liquid_viscosity", "number", true)
+  check_field(kind, definition, "liquid_range", "number", true)
+  check_field(kind, definition, "liquid_renewable", "boolean", true)
+  check_field(kind, definition, "groups", "table", true)
+  check_field(kind, definition, "on_timer", "function", true)
+  check_field(kind, definition, "on_dig", "function", true)
+  check_field(kind, definition, "on_place", "function", true)
+  check_field(kind, definition, "after_place_node", "function", true)
+  if (definition.light_source ~= nil and (definition.light_source < 0 or definition.light_source > minetest.LIGHT_MAX)) then
+    error("minetest: " .. kind .. " light_source must be between 0 and " .. tostring(minetest.LIGHT_MAX))
+  end
+  if (definition.liquidtype ~= nil and definition.liquidtype ~= "none" and definition.liquidtype ~= "source" and definition.liquidtype ~= "flowing") then
+    error("minetest: " .. kind .. " liquidtype must be \"none\", \"source\" or \"flowing\"")
+  end
+  if (definition.liquidtype == "source" or definition.liquidtype == "flowing") then
+    if (definition.liquid_alternative_source == nil or definition.liquid_alternative_flowing == nil) then
+      error("minetest: " .. kind .. " liquids need a liquid_alternative_source and liquid_alternative_flowing")
+    end
+  end
+  definition.on_dig = definition.on_dig or minetest.node_dig
+  definition.on_place = definition.on_place or minetest.item_place
   blocks[definition.name] = definition
   print("minetest: registered block [" .. definition.name .. "]")
 end
 
 function minetest.register_item(definition: ItemDefinition)
+  check_field("item", definition, "name", "string", false)
+  local kind = "item [" .. definition.name .. "]"
   if (items[definition.name] ~= nil) then
-    error("error: " .. definition.name .. " is already a registered ")
+    error(definition.name .. " is already a registered item.")
+  end
+  if (blocks[definition.name] ~= nil) then
+    error(definition.name .. " is already a registered block. Blocks are items too.")
+  end
+  if (string.find(definition.name, "%s") ~= nil) then
+    error("minetest: " .. kind .. " names can't have spaces in them")
   end
+  check_field(kind, definition, "stack_max", "number", true)
+  check_field(kind, definition, "range", "number", true)
+  check_field(kind, definition, "tool_capabilities", "table", true)
+  check_field(kind, definition, "groups", "table", true)
+  check_field(kind, definition, "on_place", "function", true)
+  items[definition.name] = definition
+  print("minetest: registered item [" .. definition.name .. "]")
 end
 
 function minetest.register_on_tick(tick_closure: OnTick)
+  check_callback("on_tick", tick_closure)
   insert(on_tick, tick_closure)
 end
 
+function minetest.register_allow_player_inventory_action(callback: AllowPlayerInventoryAction)
+  check_callback("allow_player_inventory_action", callback)
+  insert(allow_player_inventory_action, callback)
+end
+
+function minetest.register_on_player_inventory_action(callback: OnPlayerInventoryAction)
+  check_callback("on_player_inventory_action", callback)
+  insert(on_player_inventory_action, callback)
+end
+
+function minetest.register_on_chat_message(callback: OnChatMessage)
+  check_callback("on_chat_message", callback)
+  insert(on_chat_message, callback)
+end
+
+function minetest.register_on_prejoinplayer(callback: OnPrejoinPlayer)
+  check_callback("on_prejoinplayer", callback)
+  insert(on_prejoinplayer, callback)
+end
+
+function minetest.register_on_joinplayer(callback: OnJoinPlayer)
+  check_callback("on_joinplayer", callback)
+  insert(on_joinplayer, callback)
+end
+
+function minetest.register_on_newplayer(callback: OnNewPlayer)
+  check_callback("on_newplayer", callback)
+  insert(on_newplayer, callback)
+end
+
+function minetest.register_on_leaveplayer(callback: OnLeavePlayer)
+  check_callback("on_leaveplayer", callback)
+  insert(on_leaveplayer, callback)
+end
+
+function minetest.register_on_dieplayer(callback: OnDiePlayer)
+  check_callback("on_dieplayer", callback)
+  insert(on_dieplayer, callback)
+end
+
+function minetest.register_on_respawnplayer(callback: OnRespawnPlayer)
+  check_callback("on_respawnplayer", callback)
+  insert(on_respawnplayer, callback)
+end
+
+function minetest.register_on_shutdown(callback: OnShutdown)
+  check_callback("on_shutdown", callback)
+  insert(on_shutdown, callback)
+end
+
+function minetest.register_on_mods_loaded(callback: OnModsLoaded)
+  check_callback("on_mods_loaded", callback)
+  insert(on_mods_loaded, callback)
+end
+
+function minetest.register_chatcommand(name: string, definition: ChatCommandDefinition)
+  if (type(name) ~= "string" or name == "" or string.find(name, "[%s/]") ~= nil) then
+    error("minetest: chatcommand names can't be empty or have spaces or slashes in them", 2)
+  end
+  local kind = "chatcommand [/" .. name .. "]"
+  if (chatcommands[name] ~= nil) then
+    error("minetest: " .. kind .. " is already registered", 2)
+  end
+  check_field(kind, definition, "params", "string", true)
+  check_field(kind, definition, "description", "string", true)
+  check_field(kind, definition, "privs", "table", true)
+  check_field(kind, definition, "func", "function", false)
+  chatcommands[name] = definition
+end
+
+function minetest.register_on_protection_violation(callback: OnProtectionViolation)
+  check_callback("on_protection_violation", callback)
+  insert(on_protection_violation, callback)
+end
+
+function minetest.register_biome(definition: BiomeDefinition)
+  check_field("biome", definition, "name", "string", false)
+  local kind = "biome [" .. definition.name .. "]"
+  if (biomes[definition.name] ~= nil) then
+    error(definition.name .. " is already a registered biome.")
+  end
+  check_field(kind, definition, "node_top", "string", false)
+  check_field(kind, definition, "depth_top", "number", true)
+  check_field(kind, definition, "node_filler", "string", false)
+  check_field(kind, definition, "depth_filler", "number", true)
+  check_field(kind, definition, "node_stone", "string", false)
+  check_field(kind, definition, "node_water", "string", true)
+  check_field(kind, definition, "heat_point", "number", true)
+  check_field(kind, definition, "humidity_point", "number", true)
+  check_y_range(kind, definition)
+  biomes[definition.name] = definition
+  print("minetest: registered biome [" .. definition.name .. "]")
+end
+
+function minetest.register_ore(definition: OreDefinition)
+  check_field("ore", definition, "ore", "string", false)
+  local kind = "ore [" .. definition.ore .. "]"
+  check_field(kind, definition, "ore_type", "string", false)
+  local ore_type = definition.ore_type
+  if (ore_type ~= "scatter" and ore_type ~= "blob" and ore_type ~= "vein") then
+    error("minetest: " .. kind .. " has unknown ore_type [" .. ore_type .. "]")
+  end
+  check_name_list(kind, definition, "wherein", false)
+  check_field(kind, definition, "clust_scarcity", "number", true)
+  check_field(kind, definition, "clust_num_ores", "number", true)
+  check_field(kind, definition, "clust_size", "number", true)
+  check_field(kind, definition, "noise_threshold", "number", true)
+  check_field(kind, definition, "noise_params", "table", ore_type ~= "vein")
+  if (definition.noise_params ~= nil) then
+    check_noise_params(kind, definition.noise_params)
+  end
+  check_y_range(kind, definition)
+  insert(ores, definition)
+end
+
+function minetest.register_decoration(definition: DecorationDefinition)
+  local kind = "decoration [" .. tostring(#decorations + 1) .. "]"
+  check_field(kind, definition, "deco_type", "string", false)
+  check_name_list(kind, definition, "place_on", false)
+  check_field(kind, definition, "fill_ratio", "number", true)
+  check_field(kind, definition, "biomes", "table", true)
+  check_y_range(kind, definition)
+  if (definition.deco_type == "simple") then
+    check_name_list(kind, definition, "decoration", false)
+    check_field(kind, definition, "height", "number", true)
+    check_field(kind, definition, "height_max", "number", true)
+  elseif (definition.deco_type == "schematic") then
+    if (type(definition.schematic) == "string") then
+      -- The engine reads the file out of the registering mod's folder.
+      local mod_name = minetest.get_current_modname()
+      if (mod_name == nil) then
+        error("minetest: " .. kind .. " can only name a schematic file while mods load", 2)
+      end
+      definition.schematic_mod = mod_name
+    else
+      check_field(kind, definition, "schematic", "table", false)
+    end
+    check_field(kind, definition, "rotation", "string", true)
+    check_field(kind, definition, "replacements", "table", true)
+    check_field(kind, definition, "force_placement", "boolean", true)
+    check_field(kind, definition, "flags", "string", true)
+  else
+    error("minetest: " .. kind .. " has unknown deco_type [" .. definition.deco_type .. "]")
+  end
+  insert(decorations, definition)
+end
+
+function minetest.register_abm(definition: AbmDefinition)
+  check_field("abm", definition, "label", "string", true)
+  local kind = "abm [" .. (definition.label or tostring(#abms + 1)) .. "]"
+  check_name_list(kind, definition, "nodenames", false)
+  check_name_list(kind, definition, "neighbors", true)
+  check_field(kind, definition, "interval", "number", true)
+  check_field(kind, definition, "chance", "number", true)
+  check_field(kind, definition, "action", "function", false)
+  if (definition.interval ~= nil and definition.interval <= 0) then
+    error("minetest: " .. kind .. " interval must be above 0")
+  end
+  if (definition.chance ~= nil and definition.chance < 1) then
+    error("minetest: " .. kind .. " chance must be at least 1")
+  end
+  insert(abms, definition)
+end
+
+function minetest.register_craft(definition: CraftDefinition)
+  local kind = "craft [" .. tostring(#crafts + 1) .. "]"
+  check_field(kind, definition, "type", "string", true)
+  local craft_type = definition.type or "shaped"
+  if (craft_type == "shaped" or craft_type == "shapeless") then
+    check_field(kind, definition, "output", "string", false)
+    check_field(kind, definition, "recipe", "table", false)
+    for _,slot in ipairs(definition.recipe :: any) do
+      if (craft_type == "shaped") then
+        if (type(slot) ~= "table") then
+          error("minetest: " .. kind .. " shaped recipe must be an array of rows", 2)
+        end
+        for _,ingredient in ipairs(slot) do
+          if (type(ingredient) ~= "string") then
+            error("minetest: " .. kind .. " recipe rows must only contain strings", 2)
+          end
+        end
+      elseif (type(slot) ~= "string") then
+        error("minetest: " .. kind .. " shapeless recipe must only contain strings", 2)
+      end
+    end
+  elseif (craft_type == "cooking" or craft_type == "fuel") then
+    check_field(kind, definition, "output", "string", craft_type == "fuel")
+    check_field(kind, definition, "recipe", "string", false)
+    check_field(kind, definition, "cooktime", "number", true)
+    check_field(kind, definition, "burntime", "number", true)
+  else
+    error("minetest: " .. kind .. " has unknown type [" .. craft_type .. "]")
+  end
+  insert(crafts, definition)
+end
+
 
 ----------
 -- API is returned as a module.
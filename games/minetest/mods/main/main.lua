@@ -8,21 +8,78 @@ minetest.register_block({
   name = "minetest:stone",
   drawtype = minetest.draw_type.regular,
   description = "Stone",
-  textures = {"default_stone.png"}
+  textures = {"default_stone.png"},
+  groups = {cracky = 3}
 })
 
 minetest.register_block({
   name = "minetest:dirt",
   drawtype = minetest.draw_type.regular,
   description = "Stone",
-  textures = {"default_dirt.png"}
+  textures = {"default_dirt.png"},
+  groups = {crumbly = 3}
 })
 
 minetest.register_block({
   name = "minetest:grass",
   drawtype = minetest.draw_type.regular,
   description = "Stone",
-  textures = {"default_stone.png"}
+  textures = {"default_stone.png"},
+  groups = {crumbly = 3}
+})
+
+minetest.register_block({
+  name = "minetest:stone_with_coal",
+  drawtype = minetest.draw_type.regular,
+  description = "Coal Ore",
+  textures = {"default_stone.png^default_mineral_coal.png"},
+  groups = {cracky = 3}
+})
+
+minetest.register_item({
+  name = "",
+  description = "Hand",
+  readable_name = "Hand",
+  textures = {"wieldhand.png"},
+  drawtype = minetest.draw_type.air,
+  range = 4,
+  tool_capabilities = {
+    groupcaps = {
+      crumbly = {times = {3.0, 1.6, 0.9}},
+      cracky  = {times = {7.0, 4.0, 1.4}}
+    }
+  }
+})
+
+minetest.register_biome({
+  name = "minetest:grassland",
+  node_top = "minetest:grass",
+  depth_top = 1,
+  node_filler = "minetest:dirt",
+  depth_filler = 3,
+  node_stone = "minetest:stone",
+  heat_point = 50,
+  humidity_point = 35
+})
+
+minetest.register_ore({
+  ore_type = "scatter",
+  ore = "minetest:stone_with_coal",
+  wherein = "minetest:stone",
+  clust_scarcity = 8 * 8 * 8,
+  clust_num_ores = 9,
+  clust_size = 3,
+  y_max = 64
+})
+
+minetest.register_decoration({
+  deco_type = "simple",
+  place_on = "minetest:grass",
+  fill_ratio = 0.01,
+  biomes = {"minetest:grassland"},
+  decoration = "minetest:dirt",
+  height = 1,
+  height_max = 2
 })
 
 print("lua: minetest/main loaded")
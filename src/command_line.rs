@@ -1,4 +1,5 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use glam::IVec3;
 
 ///
 /// This is the CLI struct.
@@ -29,11 +30,73 @@ pub struct CommandLineInterface {
   #[arg(short, long, default_value_t = String::from("127.0.0.1"))]
   pub address: String,
 
+  /// Start server with a specific world. Worlds live in ./worlds.
+  #[arg(short, long, default_value_t = String::from("world"))]
+  pub world: String,
+
   /// Start server on a specific port.
   #[arg(short, long, default_value_t = 30_001)]
   pub port: i32,
 
-  /// The default name for your player. (this is a placholder)
+  /// The name your player joins servers under. 1 to 20 letters, numbers, - and _.
   #[arg(short, long, default_value_t = String::from("singleplayer"))]
   pub client_name: String,
+
+  /// Run a world tool on --world instead of starting the game.
+  #[command(subcommand)]
+  pub command: Option<WorldCommand>,
+}
+
+///
+/// Tools for working on a world from the command line.
+///
+/// These are safe to run while a server has the world open.
+///
+#[derive(Subcommand, Debug)]
+pub enum WorldCommand {
+  /// Snapshot the world's map database into a new file.
+  Backup {
+    /// Where to put the backup. Defaults to ./worlds/<world>/backups.
+    output: Option<String>,
+  },
+
+  /// Export every node between two corners into a region file.
+  /// Import it into another world with the server console "import" command.
+  ExportRegion {
+    /// The first corner. (x,y,z)
+    #[arg(allow_hyphen_values = true, value_parser = parse_position)]
+    pos1: IVec3,
+
+    /// The opposite corner. (x,y,z)
+    #[arg(allow_hyphen_values = true, value_parser = parse_position)]
+    pos2: IVec3,
+
+    /// The region file to create.
+    output: String,
+  },
+}
+
+///
+/// Parse an "x,y,z" node position.
+///
+pub fn parse_position(position: &str) -> Result<IVec3, String> {
+  let components: Vec<&str> = position
+    .split(',')
+    .map(|component| component.trim())
+    .collect();
+
+  let parse_component = |component: &str| {
+    component
+      .parse::<i32>()
+      .map_err(|e| format!("[{}] is not an x,y,z position. {}", position, e))
+  };
+
+  match components[..] {
+    [x, y, z] => Ok(IVec3::new(
+      parse_component(x)?,
+      parse_component(y)?,
+      parse_component(z)?,
+    )),
+    _ => Err(format!("[{}] is not an x,y,z position.", position)),
+  }
 }
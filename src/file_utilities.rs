@@ -21,6 +21,17 @@ pub fn file_exists(path: &str) -> bool {
   Path::new(path).exists()
 }
 
+///
+/// Create a directory and all of its parents.
+/// Does nothing if it already exists.
+///
+pub fn create_dir(path: &str) -> Result<(), String> {
+  match fs::create_dir_all(path) {
+    Ok(_) => Ok(()),
+    Err(e) => Err(format!("Failed to create directory [{}]. {}", path, e)),
+  }
+}
+
 ///
 /// Get a file name from the path provided.
 ///
@@ -88,3 +99,13 @@ pub fn read_path_to_buf_read(path: &str) -> Result<BufReader<File>, String> {
     Err(e) => Err(format!("Path to BufReader failure. {}", e)),
   }
 }
+
+///
+/// Write a byte slice to a file, replacing it if it exists.
+///
+pub fn write_byte_slice_to_file(path: &str, data: &[u8]) -> Result<(), String> {
+  match fs::write(path, data) {
+    Ok(_) => Ok(()),
+    Err(e) => Err(format!("Byte slice to path write failure. {}", e)),
+  }
+}
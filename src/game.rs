@@ -1,7 +1,15 @@
+mod byte_buffer;
 mod client;
+mod crafting;
 mod delta_reporter;
+mod inventory;
+mod item_registry;
+mod item_stack;
 mod lua_engine;
+mod map;
+mod packet;
 mod server;
+mod tool_capabilities;
 
 use core::panic;
 use std::{
@@ -14,7 +22,11 @@ use spin_sleep_util::{interval, Interval, RateReporter};
 
 use crate::command_line::CommandLineInterface;
 
-use self::{client::Client, delta_reporter::DeltaReporter, server::Server};
+use self::{
+  client::Client,
+  delta_reporter::DeltaReporter,
+  server::{world_tools::run_world_command, Server},
+};
 
 ///
 /// The master container for the game.
@@ -87,8 +99,17 @@ impl Game {
     //todo: make this happen!
     println!("we need a minetest.conf parser for vsync!");
 
+    // World tools do their job and exit, the game itself never starts.
+    let run_world_tool = cli.command.is_some();
+    if let Some(command) = &cli.command {
+      if let Err(e) = run_world_command(command, &cli.world) {
+        println!("Minetest: {}", e);
+        std::process::exit(1);
+      }
+    }
+
     let mut new_game = Game {
-      should_close: Arc::new(RwLock::new(false)),
+      should_close: Arc::new(RwLock::new(run_world_tool)),
 
       goal_frames_per_second,
       goal_ticks_per_second,
@@ -98,10 +119,10 @@ impl Game {
 
       // Simply reverse these then we can plop in a server when
       // the player enters singleplayer.
-      is_client: !cli.server,
+      is_client: !cli.server && !run_world_tool,
 
       // If this is a server we don't do any client things.
-      is_server: cli.server,
+      is_server: cli.server && !run_world_tool,
 
       interval,
       fps_reporter,
@@ -115,14 +136,14 @@ impl Game {
     };
 
     // We could parse the player's name instead from a file, or a first time ask. This is mutable after all.
-    new_game.client = match cli.server {
-      false => Some(Client::new(cli.client_name, cli.address.clone(), cli.port)),
-      true => None,
+    new_game.client = match new_game.is_client {
+      true => Some(Client::new(cli.client_name, cli.address.clone(), cli.port)),
+      false => None,
     };
 
     // Can auto deploy server and treat this struct like a simplified dispatcher.
-    new_game.server = match cli.server {
-      true => Some(Server::new(cli.address, cli.port, cli.game)),
+    new_game.server = match new_game.is_server {
+      true => Some(Server::new(cli.address, cli.port, cli.game, cli.world)),
       false => None,
     };
 
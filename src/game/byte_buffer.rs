@@ -0,0 +1,129 @@
+///
+/// A tiny little endian binary writer and reader.
+///
+/// Chunks, node metadata and inventories get turned into raw bytes for
+/// the database and network. These keep that code readable.
+///
+pub struct ByteWriter {
+  bytes: Vec<u8>,
+}
+
+impl ByteWriter {
+  pub fn new() -> Self {
+    ByteWriter { bytes: vec![] }
+  }
+
+  pub fn write_u8(&mut self, value: u8) {
+    self.bytes.push(value);
+  }
+
+  pub fn write_u16(&mut self, value: u16) {
+    self.bytes.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn write_u32(&mut self, value: u32) {
+    self.bytes.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn write_u64(&mut self, value: u64) {
+    self.bytes.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn write_i32(&mut self, value: i32) {
+    self.bytes.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn write_f32(&mut self, value: f32) {
+    self.bytes.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn write_bytes(&mut self, value: &[u8]) {
+    self.bytes.extend_from_slice(value);
+  }
+
+  ///
+  /// Strings are written as a u32 length followed by UTF-8 bytes.
+  ///
+  pub fn write_string(&mut self, value: &str) {
+    self.write_u32(value.len() as u32);
+    self.bytes.extend_from_slice(value.as_bytes());
+  }
+
+  pub fn into_bytes(self) -> Vec<u8> {
+    self.bytes
+  }
+}
+
+///
+/// Reads back what a ByteWriter wrote.
+///
+/// Every read checks bounds. Corrupted data gives an error, never a panic.
+///
+pub struct ByteReader<'a> {
+  bytes: &'a [u8],
+  position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+  pub fn new(bytes: &'a [u8]) -> Self {
+    ByteReader { bytes, position: 0 }
+  }
+
+  ///
+  /// How many bytes have not been read yet.
+  ///
+  pub fn get_remaining(&self) -> usize {
+    self.bytes.len() - self.position
+  }
+
+  pub fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+    if self.get_remaining() < length {
+      return Err(format!(
+        "ByteReader: tried to read [{}] bytes with only [{}] left.",
+        length,
+        self.get_remaining()
+      ));
+    }
+
+    let slice = &self.bytes[self.position..self.position + length];
+    self.position += length;
+
+    Ok(slice)
+  }
+
+  pub fn read_u8(&mut self) -> Result<u8, String> {
+    Ok(self.read_bytes(1)?[0])
+  }
+
+  pub fn read_u16(&mut self) -> Result<u16, String> {
+    let bytes = self.read_bytes(2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+  }
+
+  pub fn read_u32(&mut self) -> Result<u32, String> {
+    let bytes = self.read_bytes(4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+
+  pub fn read_u64(&mut self) -> Result<u64, String> {
+    let low = self.read_u32()? as u64;
+    let high = self.read_u32()? as u64;
+    Ok(low | (high << 32))
+  }
+
+  pub fn read_i32(&mut self) -> Result<i32, String> {
+    Ok(self.read_u32()? as i32)
+  }
+
+  pub fn read_f32(&mut self) -> Result<f32, String> {
+    Ok(f32::from_bits(self.read_u32()?))
+  }
+
+  pub fn read_string(&mut self) -> Result<String, String> {
+    let length = self.read_u32()? as usize;
+    match String::from_utf8(self.read_bytes(length)?.to_vec()) {
+      Ok(string) => Ok(string),
+      Err(e) => Err(format!("ByteReader: string is not valid UTF-8. {}", e)),
+    }
+  }
+}
@@ -4,19 +4,40 @@ mod mouse;
 mod render_engine;
 mod window_handler;
 
-use glam::{vec3a, vec4, Vec3A};
+use ahash::AHashMap;
+use glam::{IVec3, Vec3, Vec3A};
 
 use self::{
-  client_connection::ClientConnection,
-  keyboard::KeyboardController,
-  mouse::MouseController,
-  render_engine::{instanced_render_matrix::InstanceMatrixRGBA, RenderEngine},
-  window_handler::WindowHandler,
+  client_connection::ClientConnection, keyboard::KeyboardController, mouse::MouseController,
+  render_engine::RenderEngine, window_handler::WindowHandler,
 };
 
 const TESTING_LIMIT: usize = 100;
 
-use super::lua_engine::LuaEngine;
+///
+/// How often the Client tells the Server where it is, in seconds.
+///
+const POSITION_SEND_INTERVAL: f64 = 0.1;
+
+///
+/// How far the player can point, in nodes.
+///
+/// todo: this should be the wielded item's range once the Client has item definitions.
+///
+const POINTING_RANGE: f32 = 4.0;
+
+use super::{
+  inventory::{Inventory, InventoryLocation},
+  item_stack::ItemStack,
+  lua_engine::LuaEngine,
+  map::{
+    block_registry::AIR_ID,
+    chunk::Chunk,
+    raycast::{PointedThing, Raycast},
+    Map,
+  },
+  packet::{Packet, MAX_PACKET_ENTRIES},
+};
 
 ///
 /// The Client component for the engine.
@@ -28,6 +49,7 @@ use super::lua_engine::LuaEngine;
 /// * 4.) [in the future] Be the main handler for ClientAuthentication.
 /// *  - ClientAuthentication does exactly what you think it does.
 /// *  - Maintains a client auth for itself when talking to the server.
+///
 /// ? 5.) Handle GameConfig as a component. This should be received from a server
 /// ? 5 - Marked with ? because it's still being thought out at the moment.
 ///
@@ -38,6 +60,20 @@ pub struct Client {
   connection: ClientConnection,
   lua_engine: LuaEngine,
 
+  // The chunk cache. Filled by the Server.
+  map: Map,
+  position_send_timer: f64,
+
+  // What the player is digging, the Server times the dig.
+  digging: Option<PointedThing>,
+  place_was_down: bool,
+  // The slot in the player's main list. todo: this comes out of the hotbar once there is one.
+  wield_index: usize,
+
+  // Every Inventory the Server lets the player see.
+  inventories: AHashMap<InventoryLocation, Inventory>,
+  player_location: Option<InventoryLocation>,
+
   mouse: MouseController,
   keyboard: KeyboardController,
 
@@ -62,7 +98,7 @@ impl Client {
     let render_engine = RenderEngine::new(&window_handler);
 
     // Set up a blank client connection.
-    let connection = ClientConnection::new(address, port);
+    let connection = ClientConnection::new(address, port, &client_name);
 
     // Finally create the Client-side luau virtual machine.
     let lua_engine = LuaEngine::new(false);
@@ -74,6 +110,16 @@ impl Client {
       connection,
       lua_engine,
 
+      map: Map::new(),
+      position_send_timer: 0.0,
+
+      digging: None,
+      place_was_down: false,
+      wield_index: 0,
+
+      inventories: AHashMap::new(),
+      player_location: None,
+
       mouse,
       keyboard,
 
@@ -135,6 +181,151 @@ impl Client {
     &mut self.window_handler
   }
 
+  ///
+  /// Apply what the Server sent to the chunk cache.
+  ///
+  fn process_packets(&mut self) {
+    let mut received_chunks = vec![];
+
+    for packet in self.connection.take_packets() {
+      match packet {
+        // todo: check block IDs once the Client has the BlockRegistry.
+        Packet::ChunkData { position, data } => {
+          match Chunk::deserialize(position, &data, |_| true) {
+            Ok(chunk) => self.map.insert_chunk(chunk),
+            Err(e) => println!("Client: {}", e),
+          }
+          received_chunks.push(position);
+        }
+        // todo: relight these once the Client has the BlockRegistry.
+        Packet::NodeChanges(node_changes) => {
+          for (position, node) in node_changes {
+            self.map.set_node(position, node);
+          }
+        }
+        Packet::ForgetChunks(chunk_positions) => {
+          for chunk_position in chunk_positions {
+            self.map.remove_chunk(chunk_position);
+          }
+        }
+        // The Server only sends the player their own player inventory.
+        Packet::InventoryData {
+          location,
+          inventory,
+        } => {
+          if let InventoryLocation::Player(_) = location {
+            self.player_location = Some(location.clone());
+          }
+          if inventory.is_empty() {
+            self.inventories.remove(&location);
+          } else {
+            self.inventories.insert(location, inventory);
+          }
+        }
+        // todo: show these on screen once there's a chat window.
+        Packet::ChatMessage(message) => println!("Chat: {}", message),
+        Packet::PlayerPosition(_)
+        | Packet::DigStart { .. }
+        | Packet::DigStop(_)
+        | Packet::Place { .. }
+        | Packet::InventoryMove { .. }
+        | Packet::ChunksReceived(_) => println!("Client: the server sent a client only packet."),
+      }
+    }
+
+    // Otherwise the Server sends them again.
+    for batch in received_chunks.chunks(MAX_PACKET_ENTRIES) {
+      self
+        .connection
+        .send_packet(&Packet::ChunksReceived(batch.to_vec()));
+    }
+  }
+
+  ///
+  /// Let the Server know where the Client is so it can send the right Chunks.
+  ///
+  fn send_position(&mut self, delta: f64) {
+    if !self.connection.is_connected() {
+      return;
+    }
+
+    self.position_send_timer += delta;
+    if self.position_send_timer < POSITION_SEND_INTERVAL {
+      return;
+    }
+    self.position_send_timer = 0.0;
+
+    let position = Vec3::from(*self.render_engine.get_camera().get_position());
+    self
+      .connection
+      .send_packet(&Packet::PlayerPosition(position));
+  }
+
+  ///
+  /// What the player is holding, as far as the Client knows.
+  ///
+  fn get_wielded_item(&self) -> ItemStack {
+    match self
+      .player_location
+      .as_ref()
+      .and_then(|location| self.inventories.get(location))
+    {
+      Some(inventory) => inventory.get_stack("main", self.wield_index),
+      None => ItemStack::default(),
+    }
+  }
+
+  ///
+  /// Dig and place whatever the player is pointing at.
+  ///
+  /// The Client only asks, the Server decides what actually happens.
+  ///
+  /// * Holding left click digs. Pointing at something else starts over on that.
+  /// * Right click places the wielded item.
+  ///
+  fn interact(&mut self) {
+    if !self.connection.is_connected() {
+      return;
+    }
+
+    let (start, forward) = {
+      let camera = self.render_engine.get_camera();
+      (Vec3::from(*camera.get_position()), camera.get_forward())
+    };
+
+    // todo: the Client doesn't have the BlockRegistry yet, so anything that isn't air is pointable.
+    let pointed_thing = Raycast::new(start, start + forward * POINTING_RANGE, false, &[])
+      .next_by(&self.map, |node| node.block_id != AIR_ID);
+
+    let dig_target = match self.mouse.is_button_down("Left") {
+      true => pointed_thing.clone(),
+      false => None,
+    };
+    if get_under(&dig_target) != get_under(&self.digging) {
+      if let Some(digging) = self.digging.take() {
+        self.connection.send_packet(&Packet::DigStop(digging));
+      }
+      if let Some(dig_target) = dig_target {
+        self.connection.send_packet(&Packet::DigStart {
+          pointed_thing: dig_target.clone(),
+          wield_index: self.wield_index as u32,
+        });
+        self.digging = Some(dig_target);
+      }
+    }
+
+    let place_down = self.mouse.is_button_down("Right");
+    if place_down && !self.place_was_down && !self.get_wielded_item().is_empty() {
+      if let Some(pointed_thing) = pointed_thing {
+        self.connection.send_packet(&Packet::Place {
+          pointed_thing,
+          wield_index: self.wield_index as u32,
+        });
+      }
+    }
+    self.place_was_down = place_down;
+  }
+
   ///
   /// Tick tock.
   ///
@@ -153,9 +344,15 @@ impl Client {
       .update(delta, &mut self.mouse, &mut self.keyboard);
 
     // Poll any incoming network traffic. (non blocking)
-    if self.connection.is_connected() {
-      self.connection.receive(delta);
+    // This has to run before the handshake is done, or it will never get done.
+    self.connection.receive(delta);
+    if let Some(reason) = self.connection.get_denied_reason() {
+      println!("Client: couldn't join the server. {}", reason);
+      self.quit();
+      return;
     }
+    self.process_packets();
+    self.send_position(delta);
 
     //todo: probably should do user input here
 
@@ -208,6 +405,8 @@ impl Client {
 
     self.render_engine.get_camera().set_position(&camera_pos);
 
+    self.interact();
+
     // Update the RenderEngine with the WindowHandler.
     self.render_engine.update(&self.window_handler, delta);
 
@@ -366,3 +565,13 @@ impl Client {
     }
   }
 }
+
+///
+/// The node a PointedThing is on, if it's on a node.
+///
+fn get_under(pointed_thing: &Option<PointedThing>) -> Option<IVec3> {
+  match pointed_thing {
+    Some(PointedThing::Node { under, .. }) => Some(*under),
+    _ => None,
+  }
+}
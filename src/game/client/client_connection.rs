@@ -6,6 +6,8 @@ use message_io::{
   node::{self, NodeHandler, NodeTask, StoredNetEvent, StoredNodeEvent},
 };
 
+use crate::game::packet::Packet;
+
 ///
 /// ClientConnection and Client can be considered 1 entity.
 ///
@@ -25,14 +27,20 @@ pub struct ClientConnection {
 
   lost_connection: bool,
 
+  // Why the server turned us away, if it did.
+  denied_reason: Option<String>,
+
   end_point: Endpoint,
   task: NodeTask,
   handler: NodeHandler<()>,
   event_receiver: EventReceiver<StoredNodeEvent<()>>,
+
+  // Binary Packets waiting for the Client to pick them up.
+  received_packets: Vec<Packet>,
 }
 
 impl ClientConnection {
-  pub fn new(address: String, port: i32) -> Self {
+  pub fn new(address: String, port: i32, player_name: &str) -> Self {
     let remote_address = match Self::get_socket(&address, port).to_remote_addr() {
       Ok(address) => address,
       Err(e) => panic!("ClientConnection: Socket get failure. {}", e),
@@ -65,9 +73,11 @@ impl ClientConnection {
     let (task, event_receiver) = listener.enqueue();
     let end_point = server_id;
 
-    // Can possibly be used as a handshake
     // ! Note: this literally is the handshake right now
-    // self.send_data(self.end_point.un no >:(  wrap(), "MINETEST_HAND_SHAKE");
+    handler.network().send(
+      end_point,
+      format!("MINETEST_HAND_SHAKE {}", player_name).as_bytes(),
+    );
 
     ClientConnection {
       address,
@@ -83,10 +93,14 @@ impl ClientConnection {
 
       lost_connection: false,
 
+      denied_reason: None,
+
       end_point,
       task,
       handler,
       event_receiver,
+
+      received_packets: vec![],
     }
   }
 
@@ -97,6 +111,13 @@ impl ClientConnection {
     self.connected
   }
 
+  ///
+  /// Get why the server turned the Client away, if it did.
+  ///
+  pub fn get_denied_reason(&self) -> Option<&str> {
+    self.denied_reason.as_deref()
+  }
+
   ///
   /// Change the address that the server connection will utilize.
   ///
@@ -129,12 +150,37 @@ impl ClientConnection {
     self.handler.network().send(end_point, data.as_bytes());
   }
 
+  ///
+  /// Send a binary Packet to the ServerConnection.
+  ///
+  pub fn send_packet(&self, packet: &Packet) {
+    self
+      .handler
+      .network()
+      .send(self.end_point, &packet.serialize());
+  }
+
+  ///
+  /// Take every binary Packet received since the last call.
+  ///
+  pub fn take_packets(&mut self) -> Vec<Packet> {
+    std::mem::take(&mut self.received_packets)
+  }
+
   ///
   /// A procedure to react to a network event.
   ///
   pub fn event_reaction(&mut self, event: StoredNetEvent) {
     // We don't need to match, we're using UDP which is connectionless.
     if let StoredNetEvent::Message(end_point, raw_message) = event {
+      if Packet::is_packet(&raw_message) {
+        match Packet::deserialize(&raw_message) {
+          Ok(packet) => self.received_packets.push(packet),
+          Err(e) => println!("ClientConnection: bad packet from the server. {}", e),
+        }
+        return;
+      }
+
       // todo: use https://github.com/serde-rs/bytes
       let receieved_string = match String::from_utf8(raw_message) {
         Ok(new_string) => new_string,
@@ -144,15 +190,21 @@ impl ClientConnection {
         }
       };
 
+      if let Some(reason) = receieved_string.strip_prefix("MINETEST_HAND_SHAKE_DENIED ") {
+        if !self.connected && self.denied_reason.is_none() {
+          println!("ClientConnection: the server turned us away. {}", reason);
+          self.denied_reason = Some(reason.to_string());
+        }
+        return;
+      }
+
       match receieved_string.as_str() {
         "hi" => println!("ClientConnection: The server says hi."),
-        "MINETEST_HAND_SHAKE_CONFIRMED" => {
-          // Received handshake with the server.
-          if !self.connected {
-            self.connected = true;
-            self.handshake_timeout = 0.0;
-            println!("ClientConnection: ClientConnection received handshake from ServerConnection.");
-          }
+        // Received handshake with the server.
+        "MINETEST_HAND_SHAKE_CONFIRMED" if !self.connected => {
+          self.connected = true;
+          self.handshake_timeout = 0.0;
+          println!("ClientConnection: ClientConnection received handshake from ServerConnection.");
 
           // ! Do not enable this unless you want the server to
           // ! shutdown as soon as you connect.
@@ -175,7 +227,8 @@ impl ClientConnection {
   ///
   fn check_handshake(&mut self, delta: f64) {
     // Handshake timeout, aka server connection timeout
-    if !self.connected {
+    // A server which turned us away answered, it didn't time out.
+    if !self.connected && self.denied_reason.is_none() {
       self.handshake_timeout += delta;
 
       // 3 second timeout.
@@ -217,7 +270,8 @@ impl ClientConnection {
   /// Non-blocking event receiver for network events.
   ///
   pub fn receive(&mut self, delta: f64) {
-    if let Some(event) = self.event_receiver.receive_timeout(Duration::new(0, 0)) {
+    // Chunks come in a lot faster than one per frame, grind through ALL the events.
+    while let Some(event) = self.event_receiver.receive_timeout(Duration::new(0, 0)) {
       match event {
         StoredNodeEvent::Network(new_event) => self.event_reaction(new_event),
         // todo: figure out what a signal is!
@@ -232,6 +286,11 @@ impl ClientConnection {
 
 impl Drop for ClientConnection {
   fn drop(&mut self) {
+    // Let the server know right away, instead of it waiting for us to time out.
+    if self.connected {
+      self.send_data(self.end_point, "MINETEST_DISCONNECT");
+    }
+
     // ClientConnection must stop the handler entity or the Client
     // will not shut down.
     println!("Clientconnection: Shutting down network handler.");
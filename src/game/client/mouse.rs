@@ -1,3 +1,4 @@
+use ahash::AHashMap;
 use glam::IVec2;
 
 pub struct MouseController {
@@ -5,6 +6,7 @@ pub struct MouseController {
   relative_position: IVec2,
   relative_mode: bool,
   sensitivity: f32,
+  buttons: AHashMap<String, bool>,
 }
 
 impl MouseController {
@@ -14,6 +16,7 @@ impl MouseController {
       relative_position: IVec2::new(0, 0),
       relative_mode: false,
       sensitivity: 0.01,
+      buttons: AHashMap::new(),
     }
   }
 
@@ -106,4 +109,23 @@ impl MouseController {
   pub fn get_sensitivity(&self) -> f32 {
     self.sensitivity
   }
+
+  ///
+  /// Simply dumps a button's state into the memory.
+  ///
+  /// * This should only be used in WindowHandler!
+  ///
+  pub fn set_button(&mut self, button_name: &str, pressed: bool) {
+    self.buttons.insert(button_name.to_owned(), pressed);
+  }
+
+  ///
+  /// Checks if a button is down. ("Left", "Right", "Middle")
+  ///
+  pub fn is_button_down(&self, button_name: &str) -> bool {
+    match self.buttons.get(button_name) {
+      Some(button_down) => *button_down,
+      None => false,
+    }
+  }
 }
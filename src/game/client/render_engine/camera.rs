@@ -118,6 +118,21 @@ impl Camera {
     &self.rotation
   }
 
+  ///
+  /// Get the direction the Camera is looking in.
+  ///
+  pub fn get_forward(&self) -> Vec3 {
+    let rotation = Mat4::from_euler(
+      glam::EulerRot::XYZ,
+      self.rotation.x,
+      self.rotation.y,
+      self.rotation.z,
+    );
+
+    // The view matrix rotates the world, so undo it to get back to world space.
+    rotation.inverse().transform_vector3(Vec3::NEG_Z)
+  }
+
   ///
   /// Rebuild the projection matrix.
   ///
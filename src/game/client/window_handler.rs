@@ -469,7 +469,10 @@ impl WindowHandler {
           clicks,
           x,
           y,
-        } => println!("sdl2: mouse button down event | timestamp: {} | window_id: {} | which: {} | mouse_btn: {:?} | clicks: {} | x: {} | y: {} |", timestamp, window_id, which, mouse_btn, clicks, x, y),
+        } => {
+          // println!("sdl2: mouse button down event | timestamp: {} | window_id: {} | which: {} | mouse_btn: {:?} | clicks: {} | x: {} | y: {} |", timestamp, window_id, which, mouse_btn, clicks, x, y);
+          mouse.set_button(&format!("{:?}", mouse_btn), true);
+        },
         sdl2::event::Event::MouseButtonUp {
           timestamp,
           window_id,
@@ -478,7 +481,10 @@ impl WindowHandler {
           clicks,
           x,
           y,
-        } => println!("sdl2: mouse button up event | timestamp: {} | window_id: {} | which: {} | mouse_btn: {:?} | clicks: {} | x: {} | y: {} |", timestamp, window_id, which, mouse_btn, clicks, x, y),
+        } => {
+          // println!("sdl2: mouse button up event | timestamp: {} | window_id: {} | which: {} | mouse_btn: {:?} | clicks: {} | x: {} | y: {} |", timestamp, window_id, which, mouse_btn, clicks, x, y);
+          mouse.set_button(&format!("{:?}", mouse_btn), false);
+        },
         sdl2::event::Event::MouseWheel {
           timestamp,
           window_id,
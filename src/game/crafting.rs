@@ -0,0 +1,781 @@
+use std::fmt;
+
+use ahash::AHashMap;
+use mlua::Table;
+
+use super::{
+  item_registry::ItemRegistry,
+  item_stack::{check_item_name, ItemStack},
+  lua_engine::lua_table_helpers::{get_field, get_field_or},
+};
+
+///
+/// How long cooking takes if a recipe doesn't say. (in seconds)
+///
+const DEFAULT_COOKTIME: f32 = 3.0;
+
+///
+/// How long fuel burns if a recipe doesn't say. (in seconds)
+///
+const DEFAULT_BURNTIME: f32 = 1.0;
+
+///
+/// The ways items get crafted.
+///
+/// * Normal  - In a crafting grid. Shaped and shapeless recipes.
+/// * Cooking - In a furnace, one item at a time.
+/// * Fuel    - Burning an item for its burntime.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CraftMethod {
+  Normal,
+  Cooking,
+  Fuel,
+}
+
+impl CraftMethod {
+  pub fn from_name(name: &str) -> Result<Self, String> {
+    match name {
+      "normal" => Ok(CraftMethod::Normal),
+      "cooking" => Ok(CraftMethod::Cooking),
+      "fuel" => Ok(CraftMethod::Fuel),
+      _ => Err(format!(
+        "craft method [{}] must be \"normal\", \"cooking\" or \"fuel\".",
+        name
+      )),
+    }
+  }
+
+  pub fn get_name(&self) -> &'static str {
+    match self {
+      CraftMethod::Normal => "normal",
+      CraftMethod::Cooking => "cooking",
+      CraftMethod::Fuel => "fuel",
+    }
+  }
+}
+
+///
+/// One slot of a recipe.
+///
+/// * Empty  - The slot has to be empty. ("")
+/// * Item   - Exactly this item. ("minetest:stone")
+/// * Groups - Any item in every one of the groups. ("group:wood" or "group:wood,flammable")
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ingredient {
+  Empty,
+  Item(String),
+  Groups(Vec<String>),
+}
+
+impl Ingredient {
+  pub fn from_string(ingredient: &str) -> Result<Self, String> {
+    if ingredient.is_empty() {
+      return Ok(Ingredient::Empty);
+    }
+
+    match ingredient.strip_prefix("group:") {
+      Some(groups) => {
+        let groups: Vec<String> = groups.split(',').map(|group| group.to_string()).collect();
+        if groups.iter().any(|group| group.is_empty()) {
+          return Err(format!("ingredient [{}] has an empty group.", ingredient));
+        }
+        Ok(Ingredient::Groups(groups))
+      }
+      None => {
+        check_item_name(ingredient)?;
+        Ok(Ingredient::Item(ingredient.to_string()))
+      }
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    *self == Ingredient::Empty
+  }
+
+  pub fn matches(&self, stack: &ItemStack, item_registry: &ItemRegistry) -> bool {
+    match self {
+      Ingredient::Empty => stack.is_empty(),
+      Ingredient::Item(name) => !stack.is_empty() && stack.get_name() == name,
+      Ingredient::Groups(groups) => {
+        !stack.is_empty()
+          && groups
+            .iter()
+            .all(|group| item_registry.get_group(stack.get_name(), group) != 0)
+      }
+    }
+  }
+}
+
+///
+/// The recipe string, like it was registered.
+///
+impl fmt::Display for Ingredient {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Ingredient::Empty => Ok(()),
+      Ingredient::Item(name) => write!(f, "{}", name),
+      Ingredient::Groups(groups) => write!(f, "group:{}", groups.join(",")),
+    }
+  }
+}
+
+///
+/// What a recipe takes.
+///
+/// Shaped recipes are stored trimmed down to the smallest box around their items,
+/// row by row. Inputs get trimmed the same way, so a shaped recipe works
+/// anywhere in the grid it fits.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum CraftRecipe {
+  Shaped {
+    width: usize,
+    height: usize,
+    ingredients: Vec<Ingredient>,
+  },
+  Shapeless(Vec<Ingredient>),
+  Cooking {
+    ingredient: Ingredient,
+    cooktime: f32,
+  },
+  Fuel {
+    ingredient: Ingredient,
+    burntime: f32,
+  },
+}
+
+///
+/// A registered recipe. Fuel has an empty output.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct CraftDefinition {
+  pub output: ItemStack,
+  pub recipe: CraftRecipe,
+}
+
+impl CraftDefinition {
+  ///
+  /// Parse a recipe out of a Lua table from minetest.register_craft.
+  ///
+  /// * shaped    - {output, recipe = {{"a", "b"}, {"", "c"}}} (type is optional)
+  /// * shapeless - {type = "shapeless", output, recipe = {"a", "b"}}
+  /// * cooking   - {type = "cooking", output, recipe = "a", cooktime?}
+  /// * fuel      - {type = "fuel", recipe = "a", burntime?}
+  ///
+  pub fn from_lua_table(table: &Table) -> Result<Self, String> {
+    let kind: String = get_field_or(table, "type", "shaped".to_string())?;
+
+    let output = match kind.as_str() {
+      "fuel" => ItemStack::default(),
+      _ => {
+        let output = ItemStack::from_string(&get_field::<String>(table, "output")?)?;
+        if output.is_empty() {
+          return Err(format!("{} recipe has an empty output.", kind));
+        }
+        output
+      }
+    };
+
+    let recipe = match kind.as_str() {
+      "shaped" => {
+        let rows: Vec<Vec<String>> = get_field(table, "recipe")?;
+        let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+
+        let mut ingredients = vec![];
+        for row in &rows {
+          for column in 0..width {
+            let ingredient = row.get(column).map(|slot| slot.as_str()).unwrap_or("");
+            ingredients.push(Ingredient::from_string(ingredient)?);
+          }
+        }
+
+        match trim_grid(width, &ingredients, |ingredient| ingredient.is_empty()) {
+          Some((width, height, ingredients)) => CraftRecipe::Shaped {
+            width,
+            height,
+            ingredients: ingredients.into_iter().cloned().collect(),
+          },
+          None => return Err(format!("shaped recipe for [{}] is empty.", output)),
+        }
+      }
+      "shapeless" => {
+        let mut ingredients = vec![];
+        for ingredient in get_field::<Vec<String>>(table, "recipe")? {
+          match Ingredient::from_string(&ingredient)? {
+            Ingredient::Empty => (),
+            ingredient => ingredients.push(ingredient),
+          }
+        }
+        if ingredients.is_empty() {
+          return Err(format!("shapeless recipe for [{}] is empty.", output));
+        }
+        // Exact items get placed first, they narrow the search down the most.
+        ingredients.sort_by_key(|ingredient| matches!(ingredient, Ingredient::Groups(_)));
+        CraftRecipe::Shapeless(ingredients)
+      }
+      "cooking" | "fuel" => {
+        let ingredient = Ingredient::from_string(&get_field::<String>(table, "recipe")?)?;
+        if ingredient.is_empty() {
+          return Err(format!("{} recipe has an empty recipe.", kind));
+        }
+        match kind.as_str() {
+          "cooking" => CraftRecipe::Cooking {
+            ingredient,
+            cooktime: get_field_or(table, "cooktime", DEFAULT_COOKTIME)?,
+          },
+          _ => CraftRecipe::Fuel {
+            ingredient,
+            burntime: get_field_or(table, "burntime", DEFAULT_BURNTIME)?,
+          },
+        }
+      }
+      _ => {
+        return Err(format!(
+          "craft type [{}] must be \"shaped\", \"shapeless\", \"cooking\" or \"fuel\".",
+          kind
+        ))
+      }
+    };
+
+    Ok(CraftDefinition { output, recipe })
+  }
+
+  pub fn get_method(&self) -> CraftMethod {
+    match self.recipe {
+      CraftRecipe::Shaped { .. } | CraftRecipe::Shapeless(_) => CraftMethod::Normal,
+      CraftRecipe::Cooking { .. } => CraftMethod::Cooking,
+      CraftRecipe::Fuel { .. } => CraftMethod::Fuel,
+    }
+  }
+
+  ///
+  /// Check if the recipe needs anything by group. Recipes naming exact items get tried first.
+  ///
+  fn uses_groups(&self) -> bool {
+    let is_group = |ingredient: &Ingredient| matches!(ingredient, Ingredient::Groups(_));
+    match &self.recipe {
+      CraftRecipe::Shaped { ingredients, .. } | CraftRecipe::Shapeless(ingredients) => {
+        ingredients.iter().any(is_group)
+      }
+      CraftRecipe::Cooking { ingredient, .. } | CraftRecipe::Fuel { ingredient, .. } => {
+        is_group(ingredient)
+      }
+    }
+  }
+}
+
+///
+/// What's in a crafting grid, or a furnace.
+///
+/// items is the grid row by row, width slots to a row.
+///
+pub struct CraftInput {
+  pub method: CraftMethod,
+  pub width: usize,
+  pub items: Vec<ItemStack>,
+}
+
+///
+/// A recipe that matched.
+///
+/// * output            - What comes out.
+/// * time              - cooktime for cooking, burntime for fuel, 0 for normal crafting.
+/// * decremented_input - The input with one item taken out of every slot the recipe used.
+///
+pub struct CraftResult {
+  pub output: ItemStack,
+  pub time: f32,
+  pub decremented_input: Vec<ItemStack>,
+}
+
+///
+/// Recipes of one kind, sorted for lookup.
+///
+/// Exact recipes come before group recipes in every list,
+/// otherwise it's the order they were registered in.
+///
+#[derive(Default)]
+struct RecipeIndex<K> {
+  exact: AHashMap<K, Vec<usize>>,
+  groups: AHashMap<K, Vec<usize>>,
+}
+
+impl<K: std::hash::Hash + Eq> RecipeIndex<K> {
+  fn insert(&mut self, key: K, craft_index: usize, uses_groups: bool) {
+    let index = match uses_groups {
+      true => &mut self.groups,
+      false => &mut self.exact,
+    };
+    index.entry(key).or_default().push(craft_index);
+  }
+
+  fn get(&self, key: &K) -> impl Iterator<Item = usize> + '_ {
+    let exact = self.exact.get(key).into_iter().flatten();
+    let groups = self.groups.get(key).into_iter().flatten();
+    exact.chain(groups).copied()
+  }
+}
+
+///
+/// Holds every registered recipe.
+///
+/// Looking a recipe up never goes through every recipe:
+/// * Shaped recipes are indexed by their trimmed width and height.
+/// * Shapeless recipes are indexed by how many items they take.
+/// * Cooking and fuel recipes are indexed by item name. Group ones are few, they get checked one by one.
+///
+pub struct CraftRegistry {
+  crafts: Vec<CraftDefinition>,
+
+  shaped: RecipeIndex<(usize, usize)>,
+  shapeless: RecipeIndex<usize>,
+  cooking: RecipeIndex<String>,
+  fuel: RecipeIndex<String>,
+  // Cooking and fuel recipes by group. They can't be indexed by name.
+  cooking_groups: Vec<usize>,
+  fuel_groups: Vec<usize>,
+}
+
+impl CraftRegistry {
+  pub fn new() -> Self {
+    CraftRegistry {
+      crafts: vec![],
+
+      shaped: RecipeIndex::default(),
+      shapeless: RecipeIndex::default(),
+      cooking: RecipeIndex::default(),
+      fuel: RecipeIndex::default(),
+      cooking_groups: vec![],
+      fuel_groups: vec![],
+    }
+  }
+
+  ///
+  /// Build a CraftRegistry out of the crafts table in a LuaEngine's internals.
+  ///
+  pub fn from_lua_table(crafts: &Table) -> Result<Self, String> {
+    let mut new_registry = CraftRegistry::new();
+
+    for (index, craft) in crafts.clone().sequence_values::<Table>().enumerate() {
+      let craft = match craft {
+        Ok(craft) => craft,
+        Err(e) => return Err(format!("CraftRegistry: malformed crafts table. {}", e)),
+      };
+      match CraftDefinition::from_lua_table(&craft) {
+        Ok(definition) => new_registry.register_craft(definition),
+        Err(e) => return Err(format!("CraftRegistry: recipe [{}] {}", index + 1, e)),
+      }
+    }
+
+    Ok(new_registry)
+  }
+
+  pub fn register_craft(&mut self, definition: CraftDefinition) {
+    let craft_index = self.crafts.len();
+    let uses_groups = definition.uses_groups();
+
+    match &definition.recipe {
+      CraftRecipe::Shaped { width, height, .. } => {
+        self
+          .shaped
+          .insert((*width, *height), craft_index, uses_groups)
+      }
+      CraftRecipe::Shapeless(ingredients) => {
+        self
+          .shapeless
+          .insert(ingredients.len(), craft_index, uses_groups)
+      }
+      CraftRecipe::Cooking { ingredient, .. } => match ingredient {
+        Ingredient::Item(name) => self.cooking.insert(name.clone(), craft_index, false),
+        _ => self.cooking_groups.push(craft_index),
+      },
+      CraftRecipe::Fuel { ingredient, .. } => match ingredient {
+        Ingredient::Item(name) => self.fuel.insert(name.clone(), craft_index, false),
+        _ => self.fuel_groups.push(craft_index),
+      },
+    }
+
+    self.crafts.push(definition);
+  }
+
+  ///
+  /// Find the recipe an input makes, if there is one.
+  ///
+  pub fn get_craft_result(
+    &self,
+    input: &CraftInput,
+    item_registry: &ItemRegistry,
+  ) -> Option<CraftResult> {
+    if input.width == 0 {
+      return None;
+    }
+
+    let (width, height, items) = trim_grid(input.width, &input.items, |stack| stack.is_empty())?;
+    let stacks: Vec<&ItemStack> = items
+      .iter()
+      .copied()
+      .filter(|stack| !stack.is_empty())
+      .collect();
+
+    let craft = match input.method {
+      CraftMethod::Normal => {
+        let shaped = self.shaped.get(&(width, height)).find(|craft_index| {
+          match &self.crafts[*craft_index].recipe {
+            CraftRecipe::Shaped { ingredients, .. } => ingredients
+              .iter()
+              .zip(&items)
+              .all(|(ingredient, stack)| ingredient.matches(stack, item_registry)),
+            _ => false,
+          }
+        });
+
+        shaped.or_else(|| {
+          self.shapeless.get(&stacks.len()).find(|craft_index| {
+            match &self.crafts[*craft_index].recipe {
+              CraftRecipe::Shapeless(ingredients) => {
+                match_shapeless(ingredients, &stacks, item_registry)
+              }
+              _ => false,
+            }
+          })
+        })
+      }
+      CraftMethod::Cooking | CraftMethod::Fuel => {
+        // Furnaces cook one item at a time.
+        if stacks.len() != 1 {
+          return None;
+        }
+        let name = stacks[0].get_name().to_string();
+        let (index, groups) = match input.method {
+          CraftMethod::Cooking => (&self.cooking, &self.cooking_groups),
+          _ => (&self.fuel, &self.fuel_groups),
+        };
+        index
+          .get(&name)
+          .chain(groups.iter().copied())
+          .find(|craft_index| match &self.crafts[*craft_index].recipe {
+            CraftRecipe::Cooking { ingredient, .. } | CraftRecipe::Fuel { ingredient, .. } => {
+              ingredient.matches(stacks[0], item_registry)
+            }
+            _ => false,
+          })
+      }
+    }?;
+
+    let definition = &self.crafts[craft];
+    let time = match definition.recipe {
+      CraftRecipe::Cooking { cooktime, .. } => cooktime,
+      CraftRecipe::Fuel { burntime, .. } => burntime,
+      _ => 0.0,
+    };
+
+    // Every recipe takes exactly one item out of each slot it uses.
+    let decremented_input = input
+      .items
+      .iter()
+      .map(|stack| {
+        let mut stack = stack.clone();
+        stack.take_item(1);
+        stack
+      })
+      .collect();
+
+    Some(CraftResult {
+      output: definition.output.clone(),
+      time,
+      decremented_input,
+    })
+  }
+
+  ///
+  /// Get the first recipe which makes an item. Only the output's name has to match.
+  ///
+  pub fn get_craft_recipe(&self, output: &str) -> Option<&CraftDefinition> {
+    self
+      .crafts
+      .iter()
+      .find(|definition| !output.is_empty() && definition.output.get_name() == output)
+  }
+}
+
+///
+/// Cut a grid down to the smallest box around the slots that aren't empty.
+///
+/// Returns (width, height, slots row by row), or None if every slot is empty.
+///
+fn trim_grid<T>(
+  width: usize,
+  slots: &[T],
+  is_empty: impl Fn(&T) -> bool,
+) -> Option<(usize, usize, Vec<&T>)> {
+  let filled = || {
+    slots
+      .iter()
+      .enumerate()
+      .filter(|(_, slot)| !is_empty(slot))
+      .map(|(index, _)| (index % width, index / width))
+  };
+
+  let min_x = filled().map(|(x, _)| x).min()?;
+  let max_x = filled().map(|(x, _)| x).max()?;
+  let min_y = filled().map(|(_, y)| y).min()?;
+  let max_y = filled().map(|(_, y)| y).max()?;
+
+  let mut trimmed = vec![];
+  for y in min_y..=max_y {
+    for x in min_x..=max_x {
+      trimmed.push(&slots[y * width + x]);
+    }
+  }
+
+  Some((max_x - min_x + 1, max_y - min_y + 1, trimmed))
+}
+
+///
+/// Check if every ingredient of a shapeless recipe can be given its own stack.
+///
+/// Groups can overlap, so a stack one ingredient takes might be the only one
+/// another could have used. Backtracking sorts that out, a grid is small enough for it.
+///
+fn match_shapeless(
+  ingredients: &[Ingredient],
+  stacks: &[&ItemStack],
+  item_registry: &ItemRegistry,
+) -> bool {
+  fn assign(
+    ingredients: &[Ingredient],
+    stacks: &[&ItemStack],
+    used: &mut [bool],
+    item_registry: &ItemRegistry,
+  ) -> bool {
+    let (ingredient, rest) = match ingredients.split_first() {
+      Some(split) => split,
+      None => return true,
+    };
+
+    for (index, stack) in stacks.iter().enumerate() {
+      if used[index] || !ingredient.matches(stack, item_registry) {
+        continue;
+      }
+      used[index] = true;
+      if assign(rest, stacks, used, item_registry) {
+        return true;
+      }
+      used[index] = false;
+    }
+
+    false
+  }
+
+  ingredients.len() == stacks.len()
+    && assign(
+      ingredients,
+      stacks,
+      &mut vec![false; stacks.len()],
+      item_registry,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use super::{CraftDefinition, CraftInput, CraftMethod, CraftRecipe, CraftRegistry, Ingredient};
+  use crate::game::{
+    item_registry::{ItemDefinition, ItemRegistry, DEFAULT_ITEM_RANGE, DEFAULT_STACK_MAX},
+    item_stack::ItemStack,
+  };
+
+  fn test_registry() -> ItemRegistry {
+    let mut item_registry = ItemRegistry::new();
+    for (name, groups) in [
+      ("test:oak_wood", vec!["wood"]),
+      ("test:pine_wood", vec!["wood"]),
+      ("test:stick", vec!["stick", "flammable"]),
+      ("test:coal", vec!["flammable"]),
+      ("test:iron_lump", vec![]),
+    ] {
+      let definition = ItemDefinition {
+        name: name.to_string(),
+        description: name.to_string(),
+        stack_max: DEFAULT_STACK_MAX,
+        range: DEFAULT_ITEM_RANGE,
+        tool_capabilities: None,
+        groups: groups
+          .into_iter()
+          .map(|group| (group.to_string(), 1))
+          .collect::<BTreeMap<String, i32>>(),
+      };
+      if let Err(e) = item_registry.register_item(definition) {
+        panic!("{}", e);
+      }
+    }
+    item_registry
+  }
+
+  fn ingredients(ingredients: &[&str]) -> Vec<Ingredient> {
+    ingredients
+      .iter()
+      .map(|ingredient| match Ingredient::from_string(ingredient) {
+        Ok(ingredient) => ingredient,
+        Err(e) => panic!("{}", e),
+      })
+      .collect()
+  }
+
+  fn grid(width: usize, items: &[&str]) -> CraftInput {
+    CraftInput {
+      method: CraftMethod::Normal,
+      width,
+      items: items.iter().map(|name| ItemStack::new(name, 1)).collect(),
+    }
+  }
+
+  fn craft(registry: &CraftRegistry, input: &CraftInput, item_registry: &ItemRegistry) -> String {
+    match registry.get_craft_result(input, item_registry) {
+      Some(result) => result.output.to_string(),
+      None => String::new(),
+    }
+  }
+
+  #[test]
+  fn shaped_recipes_match_anywhere_in_the_grid() {
+    let item_registry = test_registry();
+    let mut registry = CraftRegistry::new();
+
+    // A 1x2 stick recipe out of any wood, and an exact one which wins over it.
+    registry.register_craft(CraftDefinition {
+      output: ItemStack::new("test:stick", 4),
+      recipe: CraftRecipe::Shaped {
+        width: 1,
+        height: 2,
+        ingredients: ingredients(&["group:wood", "group:wood"]),
+      },
+    });
+    registry.register_craft(CraftDefinition {
+      output: ItemStack::new("test:stick", 5),
+      recipe: CraftRecipe::Shaped {
+        width: 1,
+        height: 2,
+        ingredients: ingredients(&["test:oak_wood", "test:oak_wood"]),
+      },
+    });
+
+    let mixed = [
+      "",
+      "",
+      "",
+      "",
+      "",
+      "test:pine_wood",
+      "",
+      "",
+      "test:oak_wood",
+    ];
+    assert_eq!(
+      craft(&registry, &grid(3, &mixed), &item_registry),
+      "test:stick 4"
+    );
+
+    let oak = ["test:oak_wood", "", "", "test:oak_wood", "", "", "", "", ""];
+    assert_eq!(
+      craft(&registry, &grid(3, &oak), &item_registry),
+      "test:stick 5"
+    );
+    assert_eq!(
+      craft(
+        &registry,
+        &grid(2, &["", "test:oak_wood", "", "test:oak_wood"]),
+        &item_registry
+      ),
+      "test:stick 5"
+    );
+
+    // Side by side is a different shape, and extra items don't match.
+    let sideways = ["test:oak_wood", "test:oak_wood", "", "", "", "", "", "", ""];
+    assert_eq!(craft(&registry, &grid(3, &sideways), &item_registry), "");
+    let extra = [
+      "test:oak_wood",
+      "",
+      "test:coal",
+      "test:oak_wood",
+      "",
+      "",
+      "",
+      "",
+      "",
+    ];
+    assert_eq!(craft(&registry, &grid(3, &extra), &item_registry), "");
+
+    let mut input = grid(3, &oak);
+    input.items[0] = ItemStack::new("test:oak_wood", 3);
+    let result = match registry.get_craft_result(&input, &item_registry) {
+      Some(result) => result,
+      None => panic!("the recipe should match"),
+    };
+    assert_eq!(
+      result.decremented_input[0],
+      ItemStack::new("test:oak_wood", 2)
+    );
+    assert!(result.decremented_input[3].is_empty());
+  }
+
+  #[test]
+  fn shapeless_cooking_and_fuel_recipes_match() {
+    let item_registry = test_registry();
+    let mut registry = CraftRegistry::new();
+
+    // group:flammable could take the stick that group:stick needs.
+    registry.register_craft(CraftDefinition {
+      output: ItemStack::new("test:torch", 4),
+      recipe: CraftRecipe::Shapeless(ingredients(&["group:flammable", "group:stick"])),
+    });
+    registry.register_craft(CraftDefinition {
+      output: ItemStack::new("test:iron_ingot", 1),
+      recipe: CraftRecipe::Cooking {
+        ingredient: ingredients(&["test:iron_lump"]).remove(0),
+        cooktime: 10.0,
+      },
+    });
+    registry.register_craft(CraftDefinition {
+      output: ItemStack::default(),
+      recipe: CraftRecipe::Fuel {
+        ingredient: ingredients(&["group:wood"]).remove(0),
+        burntime: 15.0,
+      },
+    });
+
+    let torch = ["test:stick", "", "", "", "", "", "", "test:coal", ""];
+    assert_eq!(
+      craft(&registry, &grid(3, &torch), &item_registry),
+      "test:torch 4"
+    );
+    let no_stick = ["test:coal", "test:coal", "", "", "", "", "", "", ""];
+    assert_eq!(craft(&registry, &grid(3, &no_stick), &item_registry), "");
+
+    let mut furnace = grid(1, &["test:iron_lump"]);
+    furnace.method = CraftMethod::Cooking;
+    match registry.get_craft_result(&furnace, &item_registry) {
+      Some(result) => {
+        assert_eq!(result.output, ItemStack::new("test:iron_ingot", 1));
+        assert_eq!(result.time, 10.0);
+      }
+      None => panic!("iron should cook"),
+    }
+
+    furnace.method = CraftMethod::Fuel;
+    assert!(registry
+      .get_craft_result(&furnace, &item_registry)
+      .is_none());
+    furnace.items[0] = ItemStack::new("test:pine_wood", 1);
+    match registry.get_craft_result(&furnace, &item_registry) {
+      Some(result) => assert_eq!(result.time, 15.0),
+      None => panic!("wood should burn"),
+    }
+
+    assert!(registry.get_craft_recipe("test:torch").is_some());
+    assert!(registry.get_craft_recipe("test:coal").is_none());
+  }
+}
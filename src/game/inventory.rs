@@ -0,0 +1,479 @@
+use std::{collections::BTreeMap, fmt};
+
+use glam::IVec3;
+
+use super::{
+  byte_buffer::{ByteReader, ByteWriter},
+  item_registry::ItemRegistry,
+  item_stack::ItemStack,
+};
+
+///
+/// Where an Inventory lives.
+///
+/// * Node     - In the NodeMeta of a node. (chests, furnaces)
+/// * Player   - A player's own inventory, saved in the world database.
+/// * Detached - Made by mods with minetest.create_detached_inventory. Never saved.
+///
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum InventoryLocation {
+  Node(IVec3),
+  Player(String),
+  Detached(String),
+}
+
+impl InventoryLocation {
+  pub fn serialize(&self, writer: &mut ByteWriter) {
+    match self {
+      InventoryLocation::Node(position) => {
+        writer.write_u8(0);
+        writer.write_i32(position.x);
+        writer.write_i32(position.y);
+        writer.write_i32(position.z);
+      }
+      InventoryLocation::Player(name) => {
+        writer.write_u8(1);
+        writer.write_string(name);
+      }
+      InventoryLocation::Detached(name) => {
+        writer.write_u8(2);
+        writer.write_string(name);
+      }
+    }
+  }
+
+  pub fn deserialize(reader: &mut ByteReader) -> Result<Self, String> {
+    match reader.read_u8()? {
+      0 => Ok(InventoryLocation::Node(IVec3::new(
+        reader.read_i32()?,
+        reader.read_i32()?,
+        reader.read_i32()?,
+      ))),
+      1 => Ok(InventoryLocation::Player(reader.read_string()?)),
+      2 => Ok(InventoryLocation::Detached(reader.read_string()?)),
+      kind => Err(format!(
+        "InventoryLocation: unknown location type [{}].",
+        kind
+      )),
+    }
+  }
+}
+
+impl fmt::Display for InventoryLocation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      InventoryLocation::Node(position) => write!(f, "node [{}]", position),
+      InventoryLocation::Player(name) => write!(f, "player [{}]", name),
+      InventoryLocation::Detached(name) => write!(f, "detached [{}]", name),
+    }
+  }
+}
+
+///
+/// A single named list of item slots in an Inventory.
+///
+/// * width  - How many slots wide the list is drawn. (0 means no preference)
+/// * stacks - The slots. Empty slots are empty ItemStacks.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InventoryList {
+  pub width: u32,
+  pub stacks: Vec<ItemStack>,
+}
+
+impl InventoryList {
+  pub fn new(size: usize) -> Self {
+    InventoryList {
+      width: 0,
+      stacks: vec![ItemStack::default(); size],
+    }
+  }
+
+  ///
+  /// Check if every slot is empty.
+  ///
+  pub fn is_empty(&self) -> bool {
+    self.stacks.iter().all(|stack| stack.is_empty())
+  }
+}
+
+///
+/// A collection of named InventoryLists.
+///
+/// Nodes (chests, furnaces), players and detached inventories all use this.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Inventory {
+  lists: BTreeMap<String, InventoryList>,
+}
+
+impl Inventory {
+  pub fn new() -> Self {
+    Inventory {
+      lists: BTreeMap::new(),
+    }
+  }
+
+  ///
+  /// Check if the Inventory has no lists at all.
+  ///
+  pub fn is_empty(&self) -> bool {
+    self.lists.is_empty()
+  }
+
+  pub fn get_list(&self, list_name: &str) -> Option<&InventoryList> {
+    self.lists.get(list_name)
+  }
+
+  pub fn get_list_mut(&mut self, list_name: &str) -> Option<&mut InventoryList> {
+    self.lists.get_mut(list_name)
+  }
+
+  pub fn get_list_names(&self) -> Vec<String> {
+    self.lists.keys().cloned().collect()
+  }
+
+  ///
+  /// Replace an entire list.
+  ///
+  pub fn set_list(&mut self, list_name: &str, list: InventoryList) {
+    self.lists.insert(list_name.to_string(), list);
+  }
+
+  ///
+  /// Get how many slots a list has. 0 if it doesn't exist.
+  ///
+  pub fn get_size(&self, list_name: &str) -> usize {
+    match self.lists.get(list_name) {
+      Some(list) => list.stacks.len(),
+      None => 0,
+    }
+  }
+
+  ///
+  /// Resize a list, creating it if it doesn't exist.
+  ///
+  /// A size of 0 removes the list.
+  ///
+  pub fn set_size(&mut self, list_name: &str, size: usize) {
+    if size == 0 {
+      self.lists.remove(list_name);
+      return;
+    }
+
+    self
+      .lists
+      .entry(list_name.to_string())
+      .or_default()
+      .stacks
+      .resize(size, ItemStack::default());
+  }
+
+  ///
+  /// Get a list's width. 0 if it doesn't exist.
+  ///
+  pub fn get_width(&self, list_name: &str) -> u32 {
+    match self.lists.get(list_name) {
+      Some(list) => list.width,
+      None => 0,
+    }
+  }
+
+  pub fn set_width(&mut self, list_name: &str, width: u32) -> Result<(), String> {
+    match self.lists.get_mut(list_name) {
+      Some(list) => {
+        list.width = width;
+        Ok(())
+      }
+      None => Err(format!("Inventory: list [{}] does not exist.", list_name)),
+    }
+  }
+
+  ///
+  /// Get the ItemStack in a slot. Slots start at 0.
+  ///
+  /// Returns an empty ItemStack for empty or nonexistent slots.
+  ///
+  pub fn get_stack(&self, list_name: &str, index: usize) -> ItemStack {
+    match self.lists.get(list_name) {
+      Some(list) => match list.stacks.get(index) {
+        Some(stack) => stack.clone(),
+        None => ItemStack::default(),
+      },
+      None => ItemStack::default(),
+    }
+  }
+
+  ///
+  /// Put an ItemStack into a slot. Slots start at 0.
+  ///
+  pub fn set_stack(
+    &mut self,
+    list_name: &str,
+    index: usize,
+    stack: ItemStack,
+  ) -> Result<(), String> {
+    let list = match self.lists.get_mut(list_name) {
+      Some(list) => list,
+      None => return Err(format!("Inventory: list [{}] does not exist.", list_name)),
+    };
+
+    match list.stacks.get_mut(index) {
+      Some(slot) => {
+        *slot = stack;
+        Ok(())
+      }
+      None => Err(format!(
+        "Inventory: slot [{}] is out of bounds for list [{}] of size [{}].",
+        index,
+        list_name,
+        list.stacks.len()
+      )),
+    }
+  }
+
+  ///
+  /// Check if a list is empty, or doesn't exist.
+  ///
+  pub fn is_list_empty(&self, list_name: &str) -> bool {
+    match self.lists.get(list_name) {
+      Some(list) => list.is_empty(),
+      None => true,
+    }
+  }
+
+  ///
+  /// Put an item into a list. Stacks of the same item get filled up first,
+  /// then empty slots from the start of the list.
+  ///
+  /// Returns what didn't fit. It's empty if everything fit.
+  ///
+  pub fn add_item(
+    &mut self,
+    list_name: &str,
+    mut item: ItemStack,
+    item_registry: &ItemRegistry,
+  ) -> ItemStack {
+    let list = match self.lists.get_mut(list_name) {
+      Some(list) => list,
+      None => return item,
+    };
+    let stack_max = item_registry.get_stack_max(item.get_name());
+
+    for filling_empty in [false, true] {
+      for stack in &mut list.stacks {
+        if item.is_empty() {
+          return item;
+        }
+        if stack.is_empty() == filling_empty {
+          item = stack.add_item(item, stack_max);
+        }
+      }
+    }
+
+    item
+  }
+
+  ///
+  /// Check if all of an item would fit into a list.
+  ///
+  pub fn room_for_item(
+    &self,
+    list_name: &str,
+    item: &ItemStack,
+    item_registry: &ItemRegistry,
+  ) -> bool {
+    self
+      .clone()
+      .add_item(list_name, item.clone(), item_registry)
+      .is_empty()
+  }
+
+  ///
+  /// Check if a list holds at least as many of an item as the ItemStack has.
+  ///
+  /// Only the name is matched. Wear and meta don't matter.
+  ///
+  pub fn contains_item(&self, list_name: &str, item: &ItemStack) -> bool {
+    let list = match self.lists.get(list_name) {
+      Some(list) => list,
+      None => return item.is_empty(),
+    };
+
+    let count: u32 = list
+      .stacks
+      .iter()
+      .filter(|stack| stack.get_name() == item.get_name())
+      .map(|stack| stack.get_count() as u32)
+      .sum();
+
+    count >= item.get_count() as u32
+  }
+
+  ///
+  /// Take as many of an item out of a list as the ItemStack has,
+  /// starting from the end of the list. Only the name is matched.
+  ///
+  /// Returns what was taken. It can be less than was asked for.
+  ///
+  pub fn remove_item(&mut self, list_name: &str, item: &ItemStack) -> ItemStack {
+    let list = match self.lists.get_mut(list_name) {
+      Some(list) => list,
+      None => return ItemStack::default(),
+    };
+
+    let mut removed = ItemStack::default();
+    for stack in list.stacks.iter_mut().rev() {
+      let wanted = item.get_count() - removed.get_count();
+      if wanted == 0 {
+        break;
+      }
+      if stack.is_empty() || stack.get_name() != item.get_name() {
+        continue;
+      }
+
+      let taken = stack.take_item(wanted);
+      if removed.is_empty() {
+        removed = taken;
+      } else {
+        removed.set_count(removed.get_count() + taken.get_count());
+      }
+    }
+
+    removed
+  }
+
+  pub fn serialize(&self, writer: &mut ByteWriter) {
+    writer.write_u32(self.lists.len() as u32);
+    for (list_name, list) in &self.lists {
+      writer.write_string(list_name);
+      writer.write_u32(list.width);
+      writer.write_u32(list.stacks.len() as u32);
+      for stack in &list.stacks {
+        writer.write_string(&stack.to_string());
+      }
+    }
+  }
+
+  pub fn deserialize(reader: &mut ByteReader) -> Result<Self, String> {
+    let mut inventory = Inventory::new();
+
+    let list_count = reader.read_u32()?;
+    for _ in 0..list_count {
+      let list_name = reader.read_string()?;
+      let width = reader.read_u32()?;
+      let size = reader.read_u32()?;
+
+      let mut stacks = vec![];
+      for _ in 0..size {
+        stacks.push(ItemStack::from_string(&reader.read_string()?)?);
+      }
+
+      inventory
+        .lists
+        .insert(list_name, InventoryList { width, stacks });
+    }
+
+    Ok(inventory)
+  }
+}
+
+///
+/// Work out moving up to count items from one slot onto another.
+///
+/// Items pile onto a stack of the same item. Moving a whole stack onto a
+/// different item swaps the two.
+///
+/// Returns what the two slots hold afterwards, or None if nothing can move.
+///
+pub fn move_stack(
+  from: &ItemStack,
+  to: &ItemStack,
+  count: u16,
+  stack_max: u16,
+) -> Option<(ItemStack, ItemStack)> {
+  if from.is_empty() || count == 0 {
+    return None;
+  }
+
+  let mut from_after = from.clone();
+  let moving = from_after.take_item(count);
+
+  let mut to_after = to.clone();
+  let leftover = to_after.add_item(moving.clone(), stack_max);
+
+  if leftover.get_count() == moving.get_count() {
+    if moving.get_count() == from.get_count() && !to.is_empty() && !to.stacks_with(from) {
+      return Some((to.clone(), from.clone()));
+    }
+    return None;
+  }
+
+  // Whatever didn't fit goes back where it came from.
+  from_after.add_item(leftover, from.get_count());
+
+  Some((from_after, to_after))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{move_stack, Inventory};
+  use crate::game::{
+    byte_buffer::{ByteReader, ByteWriter},
+    item_registry::ItemRegistry,
+    item_stack::ItemStack,
+  };
+
+  fn stack(item_string: &str) -> ItemStack {
+    match ItemStack::from_string(item_string) {
+      Ok(stack) => stack,
+      Err(e) => panic!("{}", e),
+    }
+  }
+
+  #[test]
+  fn items_move_between_slots_and_lists() {
+    // Nothing is registered, so everything stacks up to 99.
+    let item_registry = ItemRegistry::new();
+    let mut inventory = Inventory::new();
+    inventory.set_size("main", 3);
+
+    assert!(inventory
+      .set_stack("main", 2, stack("minetest:dirt 90"))
+      .is_ok());
+    let leftover = inventory.add_item("main", stack("minetest:dirt 111"), &item_registry);
+    assert_eq!(inventory.get_stack("main", 2), stack("minetest:dirt 99"));
+    assert_eq!(inventory.get_stack("main", 0), stack("minetest:dirt 99"));
+    assert_eq!(inventory.get_stack("main", 1), stack("minetest:dirt 3"));
+    assert!(leftover.is_empty());
+    assert!(!inventory.room_for_item("main", &stack("minetest:stone"), &item_registry));
+
+    assert!(inventory.contains_item("main", &stack("minetest:dirt 201")));
+    assert!(!inventory.contains_item("main", &stack("minetest:dirt 202")));
+    let removed = inventory.remove_item("main", &stack("minetest:dirt 150"));
+    assert_eq!(removed, stack("minetest:dirt 150"));
+    assert_eq!(inventory.get_stack("main", 2), ItemStack::default());
+    assert_eq!(inventory.get_stack("main", 0), stack("minetest:dirt 51"));
+
+    // Piling up, partial moves, and swapping different items.
+    let dirt = stack("minetest:dirt 10");
+    assert_eq!(
+      move_stack(&dirt, &stack("minetest:dirt 95"), 10, 99),
+      Some((stack("minetest:dirt 6"), stack("minetest:dirt 99")))
+    );
+    assert_eq!(
+      move_stack(&dirt, &stack("minetest:stone"), 10, 99),
+      Some((stack("minetest:stone"), dirt.clone()))
+    );
+    assert_eq!(move_stack(&dirt, &stack("minetest:stone"), 5, 99), None);
+    assert_eq!(move_stack(&dirt, &stack("minetest:dirt 99"), 5, 99), None);
+
+    let mut writer = ByteWriter::new();
+    inventory.serialize(&mut writer);
+    let bytes = writer.into_bytes();
+    assert_eq!(
+      Inventory::deserialize(&mut ByteReader::new(&bytes)),
+      Ok(inventory)
+    );
+  }
+}
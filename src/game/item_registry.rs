@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use ahash::AHashMap;
+use mlua::Table;
+
+use super::{
+  lua_engine::lua_table_helpers::{get_field, get_field_or},
+  tool_capabilities::ToolCapabilities,
+};
+
+///
+/// How many of an item fit in one stack if its definition doesn't say.
+///
+pub const DEFAULT_STACK_MAX: u16 = 99;
+
+///
+/// How far an item reaches if its definition doesn't say. (in nodes)
+///
+pub const DEFAULT_ITEM_RANGE: f32 = 4.0;
+
+///
+/// The engine side of a Lua ItemDefinition.
+///
+/// Every block is an item too, so blocks get one of these as well.
+///
+/// * stack_max         - How many fit in one stack. Tools default to 1, everything else to DEFAULT_STACK_MAX.
+/// * range             - How far a player holding it can reach.
+/// * tool_capabilities - What it digs and how fast. None for anything that isn't a tool.
+/// * groups            - Group ratings, like wood = 1. Crafting recipes match on these.
+///
+#[derive(Clone, Debug)]
+pub struct ItemDefinition {
+  pub name: String,
+  pub description: String,
+  pub stack_max: u16,
+  pub range: f32,
+  pub tool_capabilities: Option<ToolCapabilities>,
+  pub groups: BTreeMap<String, i32>,
+}
+
+impl ItemDefinition {
+  ///
+  /// Parse an ItemDefinition out of a Lua table from minetest.register_item or minetest.register_block.
+  ///
+  pub fn from_lua_table(table: &Table) -> Result<Self, String> {
+    let name: String = get_field(table, "name")?;
+    if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+      return Err(format!(
+        "item [{}] names can't have whitespace in them.",
+        name
+      ));
+    }
+
+    let tool_capabilities = match get_field_or::<Option<Table>>(table, "tool_capabilities", None)? {
+      Some(tool_capabilities) => Some(
+        ToolCapabilities::from_lua_table(&tool_capabilities)
+          .map_err(|e| format!("item [{}] {}", name, e))?,
+      ),
+      None => None,
+    };
+
+    let default_stack_max = match tool_capabilities {
+      Some(_) => 1,
+      None => DEFAULT_STACK_MAX,
+    };
+    let stack_max: u16 = get_field_or(table, "stack_max", default_stack_max)?;
+    if stack_max == 0 {
+      return Err(format!("item [{}] stack_max must be at least 1.", name));
+    }
+
+    let groups = get_field_or(table, "groups", BTreeMap::new())
+      .map_err(|e| format!("item [{}] {}", name, e))?;
+
+    Ok(ItemDefinition {
+      description: get_field_or(table, "description", name.clone())?,
+      name,
+      stack_max,
+      range: get_field_or(table, "range", DEFAULT_ITEM_RANGE)?,
+      tool_capabilities,
+      groups,
+    })
+  }
+}
+
+///
+/// Holds every registered item, blocks included.
+///
+/// Unlike blocks, items are only ever stored by name. (in ItemStacks)
+///
+pub struct ItemRegistry {
+  definitions: AHashMap<String, ItemDefinition>,
+}
+
+impl ItemRegistry {
+  pub fn new() -> Self {
+    ItemRegistry {
+      definitions: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Build an ItemRegistry out of the blocks and items tables in a LuaEngine's internals.
+  ///
+  pub fn from_lua_tables(blocks: &Table, items: &Table) -> Result<Self, String> {
+    let mut new_registry = ItemRegistry::new();
+
+    for (kind, table) in [("block", blocks), ("item", items)] {
+      for pair in table.clone().pairs::<String, Table>() {
+        let (name, definition_table) = match pair {
+          Ok(pair) => pair,
+          Err(e) => return Err(format!("ItemRegistry: malformed {} table. {}", kind, e)),
+        };
+        match ItemDefinition::from_lua_table(&definition_table) {
+          Ok(definition) => new_registry.register_item(definition)?,
+          Err(e) => return Err(format!("ItemRegistry: {} [{}] {}", kind, name, e)),
+        }
+      }
+    }
+
+    Ok(new_registry)
+  }
+
+  ///
+  /// Add an item into the registry.
+  ///
+  pub fn register_item(&mut self, definition: ItemDefinition) -> Result<(), String> {
+    if self.definitions.contains_key(&definition.name) {
+      return Err(format!(
+        "ItemRegistry: item [{}] is already registered.",
+        definition.name
+      ));
+    }
+
+    self.definitions.insert(definition.name.clone(), definition);
+
+    Ok(())
+  }
+
+  pub fn get_definition(&self, name: &str) -> Option<&ItemDefinition> {
+    self.definitions.get(name)
+  }
+
+  pub fn is_known(&self, name: &str) -> bool {
+    self.definitions.contains_key(name)
+  }
+
+  ///
+  /// Get an item's rating in a group. 0 means it's not in the group, or isn't registered.
+  ///
+  pub fn get_group(&self, name: &str, group: &str) -> i32 {
+    match self.definitions.get(name) {
+      Some(definition) => definition.groups.get(group).copied().unwrap_or(0),
+      None => 0,
+    }
+  }
+
+  ///
+  /// How many of an item fit in one stack. Unknown items get DEFAULT_STACK_MAX.
+  ///
+  pub fn get_stack_max(&self, name: &str) -> u16 {
+    match self.definitions.get(name) {
+      Some(definition) => definition.stack_max,
+      None => DEFAULT_STACK_MAX,
+    }
+  }
+}
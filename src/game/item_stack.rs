@@ -0,0 +1,407 @@
+use std::{collections::BTreeMap, fmt};
+
+///
+/// The most wear a tool can have. One more and it breaks.
+///
+pub const WEAR_MAX: u16 = 65535;
+
+///
+/// Item metadata gets stored after this marker in an item string,
+/// as key KEY_DELIMITER value PAIR_DELIMITER pairs. (Same as C++ minetest)
+///
+const META_START: char = '\u{1}';
+const KEY_DELIMITER: char = '\u{2}';
+const PAIR_DELIMITER: char = '\u{3}';
+
+///
+/// Some number of one item.
+///
+/// * name  - The item's name. Empty stacks always have an empty name.
+/// * count - How many of the item there are. 0 is an empty stack.
+/// * wear  - How worn out a tool is. (0 to WEAR_MAX)
+/// * meta  - String key/value pairs, like NodeMeta fields.
+///
+/// Stacks only merge when the name, wear and meta all match.
+/// How many fit in one stack is up to the item's definition, so anything
+/// that fills a stack up takes the stack_max from the ItemRegistry.
+///
+/// Item strings are "name count wear meta", leaving off what's left at the default:
+/// * "minetest:dirt"            - 1 dirt.
+/// * "minetest:dirt 5"          - 5 dirt.
+/// * "minetest:pickaxe 1 30000" - A worn out pickaxe.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ItemStack {
+  name: String,
+  count: u16,
+  wear: u16,
+  meta: BTreeMap<String, String>,
+}
+
+impl ItemStack {
+  ///
+  /// The name must pass check_item_name.
+  ///
+  pub fn new(name: &str, count: u16) -> Self {
+    let mut new_stack = ItemStack {
+      name: name.to_string(),
+      count,
+      wear: 0,
+      meta: BTreeMap::new(),
+    };
+    if new_stack.is_empty() {
+      new_stack.clear();
+    }
+    new_stack
+  }
+
+  ///
+  /// Parse an item string. An empty string is an empty stack.
+  ///
+  pub fn from_string(item_string: &str) -> Result<Self, String> {
+    let mut parts = item_string.trim_start().splitn(4, ' ');
+
+    let name = parts.next().unwrap_or("");
+    check_item_name(name)?;
+
+    let count = match parts.next() {
+      Some(count) => match count.parse::<u16>() {
+        Ok(count) => count,
+        Err(_) => {
+          return Err(format!(
+            "ItemStack: count [{}] in [{}] is not a number from 0 to {}.",
+            count,
+            item_string,
+            u16::MAX
+          ))
+        }
+      },
+      None => 1,
+    };
+
+    let wear = match parts.next() {
+      Some(wear) => match wear.parse::<u16>() {
+        Ok(wear) => wear,
+        Err(_) => {
+          return Err(format!(
+            "ItemStack: wear [{}] in [{}] is not a number from 0 to {}.",
+            wear, item_string, WEAR_MAX
+          ))
+        }
+      },
+      None => 0,
+    };
+
+    let mut item_stack = ItemStack::new(name, count);
+    if item_stack.is_empty() {
+      return Ok(item_stack);
+    }
+    item_stack.wear = wear;
+
+    if let Some(meta) = parts.next() {
+      let pairs = match meta.strip_prefix(META_START) {
+        Some(pairs) => pairs,
+        None => {
+          return Err(format!(
+            "ItemStack: [{}] has something after the wear that isn't meta.",
+            item_string
+          ))
+        }
+      };
+      for pair in pairs.split_terminator(PAIR_DELIMITER) {
+        match pair.split_once(KEY_DELIMITER) {
+          Some((key, value)) => item_stack.set_meta_string(key, value)?,
+          None => return Err(format!("ItemStack: [{}] has malformed meta.", item_string)),
+        }
+      }
+    }
+
+    Ok(item_stack)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.count == 0 || self.name.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    *self = ItemStack::default();
+  }
+
+  pub fn get_name(&self) -> &str {
+    &self.name
+  }
+
+  ///
+  /// An empty name clears the stack.
+  ///
+  pub fn set_name(&mut self, name: &str) -> Result<(), String> {
+    check_item_name(name)?;
+    self.name = name.to_string();
+    if self.is_empty() {
+      self.clear();
+    }
+    Ok(())
+  }
+
+  pub fn get_count(&self) -> u16 {
+    self.count
+  }
+
+  ///
+  /// A count of 0 clears the stack.
+  ///
+  pub fn set_count(&mut self, count: u16) {
+    self.count = count;
+    if self.is_empty() {
+      self.clear();
+    }
+  }
+
+  pub fn get_wear(&self) -> u16 {
+    self.wear
+  }
+
+  pub fn set_wear(&mut self, wear: u16) {
+    if !self.is_empty() {
+      self.wear = wear;
+    }
+  }
+
+  ///
+  /// Wear a tool down. Wearing past WEAR_MAX breaks it, which clears the stack.
+  ///
+  /// Returns true if it broke.
+  ///
+  pub fn add_wear(&mut self, amount: u16) -> bool {
+    if self.is_empty() {
+      return false;
+    }
+
+    match self.wear.checked_add(amount) {
+      Some(wear) => {
+        self.wear = wear;
+        false
+      }
+      None => {
+        self.clear();
+        true
+      }
+    }
+  }
+
+  ///
+  /// Get a meta field. Missing fields are an empty string.
+  ///
+  pub fn get_meta_string(&self, key: &str) -> String {
+    match self.meta.get(key) {
+      Some(value) => value.clone(),
+      None => String::new(),
+    }
+  }
+
+  ///
+  /// Set a meta field. Setting an empty string removes the field.
+  ///
+  /// The control characters item strings use to store meta aren't allowed.
+  ///
+  pub fn set_meta_string(&mut self, key: &str, value: &str) -> Result<(), String> {
+    let is_delimiter = |c: char| [META_START, KEY_DELIMITER, PAIR_DELIMITER].contains(&c);
+    if key.contains(is_delimiter) || value.contains(is_delimiter) {
+      return Err(format!(
+        "ItemStack: meta field [{}] can't contain the characters \\1, \\2 or \\3.",
+        key.escape_debug()
+      ));
+    }
+
+    if value.is_empty() {
+      self.meta.remove(key);
+    } else {
+      self.meta.insert(key.to_string(), value.to_string());
+    }
+    Ok(())
+  }
+
+  pub fn get_meta_fields(&self) -> &BTreeMap<String, String> {
+    &self.meta
+  }
+
+  ///
+  /// Check if another stack is the same item, so the two can merge.
+  ///
+  pub fn stacks_with(&self, other: &ItemStack) -> bool {
+    self.name == other.name && self.wear == other.wear && self.meta == other.meta
+  }
+
+  ///
+  /// How many more items fit in the stack.
+  ///
+  pub fn get_free_space(&self, stack_max: u16) -> u16 {
+    stack_max.saturating_sub(self.count)
+  }
+
+  ///
+  /// Put as much of an item into the stack as will fit.
+  ///
+  /// Returns what's left over. It's empty if everything fit.
+  ///
+  pub fn add_item(&mut self, mut item: ItemStack, stack_max: u16) -> ItemStack {
+    if item.is_empty() {
+      return item;
+    }
+
+    if self.is_empty() {
+      self.name = item.name.clone();
+      self.wear = item.wear;
+      self.meta = item.meta.clone();
+    } else if !self.stacks_with(&item) {
+      return item;
+    }
+
+    let moved = item.count.min(self.get_free_space(stack_max));
+    self.count += moved;
+    item.set_count(item.count - moved);
+
+    if self.is_empty() {
+      self.clear();
+    }
+
+    item
+  }
+
+  ///
+  /// Check if all of an item would fit in the stack.
+  ///
+  pub fn item_fits(&self, item: &ItemStack, stack_max: u16) -> bool {
+    self.clone().add_item(item.clone(), stack_max).is_empty()
+  }
+
+  ///
+  /// Take up to count items off the stack.
+  ///
+  pub fn take_item(&mut self, count: u16) -> ItemStack {
+    let taken = self.peek_item(count);
+    self.set_count(self.count - taken.count);
+    taken
+  }
+
+  ///
+  /// What take_item would give back, without taking it.
+  ///
+  pub fn peek_item(&self, count: u16) -> ItemStack {
+    if self.is_empty() {
+      return ItemStack::default();
+    }
+
+    let mut taken = self.clone();
+    taken.set_count(count.min(self.count));
+    taken
+  }
+}
+
+///
+/// Names go into item strings, so they can't have whitespace in them.
+///
+pub fn check_item_name(name: &str) -> Result<(), String> {
+  if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+    return Err(format!(
+      "ItemStack: [{}] is not a valid item name.",
+      name.escape_debug()
+    ));
+  }
+  Ok(())
+}
+
+///
+/// The item string. Empty stacks are an empty string.
+///
+impl fmt::Display for ItemStack {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.is_empty() {
+      return Ok(());
+    }
+
+    write!(f, "{}", self.name)?;
+
+    let has_meta = !self.meta.is_empty();
+    let has_wear = self.wear != 0 || has_meta;
+    if self.count != 1 || has_wear {
+      write!(f, " {}", self.count)?;
+    }
+    if has_wear {
+      write!(f, " {}", self.wear)?;
+    }
+    if has_meta {
+      write!(f, " {}", META_START)?;
+      for (key, value) in &self.meta {
+        write!(f, "{}{}{}{}", key, KEY_DELIMITER, value, PAIR_DELIMITER)?;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ItemStack, WEAR_MAX};
+
+  fn parse(item_string: &str) -> ItemStack {
+    match ItemStack::from_string(item_string) {
+      Ok(item_stack) => item_stack,
+      Err(e) => panic!("{}", e),
+    }
+  }
+
+  #[test]
+  fn item_strings_round_trip() {
+    for item_string in [
+      "",
+      "minetest:dirt",
+      "minetest:dirt 5",
+      "minetest:pickaxe 1 30000",
+      "minetest:book 1 0 \u{1}text\u{2}hello world\u{3}title\u{2}Notes\u{3}",
+    ] {
+      assert_eq!(parse(item_string).to_string(), item_string);
+    }
+
+    let book = parse("minetest:book 2 0 \u{1}text\u{2}hello world\u{3}");
+    assert_eq!(book.get_count(), 2);
+    assert_eq!(book.get_meta_string("text"), "hello world");
+
+    // Defaults get left off, empty stacks forget everything.
+    assert_eq!(parse("minetest:dirt 1 0").to_string(), "minetest:dirt");
+    assert_eq!(parse("minetest:dirt 0 5"), ItemStack::default());
+
+    assert!(ItemStack::from_string("minetest:dirt lots").is_err());
+    assert!(ItemStack::from_string("minetest:dirt 1 0 junk").is_err());
+    assert!(ItemStack::new("minetest:book", 1)
+      .set_meta_string("text", "\u{3}")
+      .is_err());
+  }
+
+  #[test]
+  fn stacks_respect_stack_max() {
+    let mut stack = ItemStack::new("minetest:dirt", 90);
+
+    let leftover = stack.add_item(ItemStack::new("minetest:dirt", 20), 99);
+    assert_eq!(stack.get_count(), 99);
+    assert_eq!(leftover, ItemStack::new("minetest:dirt", 11));
+
+    // Different items don't merge.
+    let stone = ItemStack::new("minetest:stone", 1);
+    assert!(!ItemStack::new("minetest:dirt", 1).item_fits(&stone, 99));
+    assert!(ItemStack::default().item_fits(&stone, 99));
+
+    let taken = stack.take_item(100);
+    assert_eq!(taken.get_count(), 99);
+    assert!(stack.is_empty());
+    assert_eq!(stack.get_name(), "");
+
+    // Tools have a stack_max of 1 and break once they're worn out.
+    let mut pickaxe = ItemStack::new("minetest:pickaxe", 1);
+    assert!(!pickaxe.item_fits(&pickaxe.clone(), 1));
+    assert!(!pickaxe.add_wear(WEAR_MAX));
+    assert!(pickaxe.add_wear(1));
+    assert!(pickaxe.is_empty());
+  }
+}
@@ -1,13 +1,88 @@
+pub mod lua_error;
 mod lua_file_helpers;
+pub mod lua_item_stack;
+pub mod lua_table_helpers;
+pub mod lua_vector;
+mod mod_conf;
+pub mod module_loader;
 
 use core::panic;
+use std::{cell::RefCell, rc::Rc};
 
+use ahash::AHashMap;
 use configparser::ini::Ini;
-use mlua::Lua;
+use mlua::{FromLuaMulti, Function, IntoLuaMulti, Lua, Table};
 
 use crate::file_utilities::read_file_to_string;
 
-use self::lua_file_helpers::{check_game, get_game_mod_folders, get_game_path};
+use self::{
+  lua_error::{
+    add_mod, call_mod_function, install_error_handler, report_error, set_error_policy,
+    LuaErrorPolicy,
+  },
+  lua_file_helpers::{check_game, get_game_mod_folders, get_game_path},
+  lua_item_stack::register_item_stack_api,
+  lua_vector::register_vector_api,
+  mod_conf::{sort_mods, ModConf},
+  module_loader::{install_module_loader, register_mod, require_module},
+};
+
+///
+/// Registry keys of the engine internals.
+///
+/// These only live in the Lua registry, which mod code has no way to reach.
+///
+const INTERNALS_KEY: &str = "minetest_internals";
+const SANDBOX_KEY: &str = "minetest_sandbox";
+
+///
+/// The base functions mods are allowed to use.
+///
+/// Anything that can load code or swap environments (loadstring, getfenv, setfenv) is left out.
+///
+const SANDBOX_FUNCTIONS: [&str; 22] = [
+  "assert",
+  "error",
+  "getmetatable",
+  "ipairs",
+  "next",
+  "pairs",
+  "pcall",
+  "print",
+  "rawequal",
+  "rawget",
+  "rawlen",
+  "rawset",
+  "select",
+  "setmetatable",
+  "tonumber",
+  "tostring",
+  "type",
+  "typeof",
+  "unpack",
+  "xpcall",
+  "ItemStack",
+  "_VERSION",
+];
+
+///
+/// The libraries mods are allowed to use. These get frozen so mods can't tamper with them.
+///
+const SANDBOX_LIBRARIES: [&str; 8] = [
+  "string",
+  "table",
+  "math",
+  "bit32",
+  "utf8",
+  "coroutine",
+  "buffer",
+  "vector",
+];
+
+///
+/// The harmless part of os. No execute, exit, getenv, remove or rename.
+///
+const SANDBOX_OS_FUNCTIONS: [&str; 4] = ["clock", "time", "date", "difftime"];
 
 ///
 /// LuaEngine encapsulates the LuauJIT virtual machine.
@@ -16,16 +91,22 @@ use self::lua_file_helpers::{check_game, get_game_mod_folders, get_game_path};
 ///
 pub struct LuaEngine {
   lua: Lua,
-  output_code_string: bool,
   server_vm: bool,
+  game_conf: Ini,
 }
 
 impl LuaEngine {
   pub fn new(server_vm: bool) -> Self {
+    let lua = Lua::new();
+    install_error_handler(&lua);
+    if let Err(e) = install_module_loader(&lua) {
+      panic!("LuaEngine: failed to install the module loader. {}", e)
+    }
+
     let new_engine = LuaEngine {
-      lua: Lua::new(),
-      output_code_string: false,
+      lua,
       server_vm,
+      game_conf: Ini::new(),
     };
 
     new_engine.generate_internal();
@@ -34,71 +115,229 @@ impl LuaEngine {
   }
 
   ///
-  /// Run the global on_tick function in the LuauJIT VM environment.
+  /// Run every registered on_tick function in the LuauJIT VM environment.
+  ///
+  /// A broken callback gets reported and dealt with by the error policy.
+  /// The rest still run.
   ///
   pub fn on_tick(&self, delta: f64) {
-    self.run_code(format!("_G.engine_on_tick_function({})", delta))
+    let on_tick = match self.get_internal_table("on_tick") {
+      Ok(on_tick) => on_tick,
+      Err(e) => panic!("{}", e),
+    };
+
+    for function in on_tick.sequence_values::<Function>() {
+      match function {
+        Ok(function) => {
+          call_mod_function::<_, ()>(&self.lua, &function, "on_tick", delta);
+        }
+        Err(e) => panic!("LuaEngine: the internal on_tick is broken! {}", e),
+      }
+    }
   }
 
   ///
-  /// Generates the on_tick(delta: number) function so it becomes a secret and hidden engine component.
+  /// Generates the internals and the mod sandbox.
+  ///
+  /// The internal tables get stored in the Lua registry
+  /// so they become a secret and hidden engine component.
   ///
   pub fn generate_internal(&self) {
     // We want the game to simply crash if the internals have problems.
     // You can't build upon what is fundamentally broken.
-    if self.server_vm {
+    if let Err(e) = register_vector_api(&self.lua) {
+      panic!("LuaEngine: failed to create the vector API. {}", e)
+    }
+    if let Err(e) = register_item_stack_api(&self.lua) {
+      panic!("LuaEngine: failed to create the ItemStack API. {}", e)
+    }
+
+    let internals = match self.lua.create_table() {
+      Ok(internals) => internals,
+      Err(e) => panic!("LuaEngine: failed to create the internals. {}", e),
+    };
+
+    if let Err(e) = self
+      .lua
+      .set_named_registry_value(INTERNALS_KEY, internals.clone())
+    {
+      panic!("LuaEngine: failed to store the internals. {}", e)
+    }
+
+    if let Err(e) = self.run_internal_file::<()>("./api/api.lua", internals.clone()) {
+      panic!("LuaEngine: Failed to load the API. {}", e)
+    }
+
+    let internal_file = if self.server_vm {
       // it's a server vm
-      match self.run_file("./api/server/__internal_server.lua") {
-        Ok(_) => (),
-        Err(e) => panic!("LuaEngine: Failed to load Server API. {}", e),
-      }
+      "./api/server/__internal_server.lua"
     } else {
       // it's a client vm
-      match self.run_file("./api/client/__internal_client.lua") {
-        Ok(_) => (),
-        Err(e) => panic!("LuaEngine: Failed to load Client API. {}", e),
-      }
+      "./api/client/__internal_client.lua"
+    };
+
+    if let Err(e) = self.run_internal_file::<()>(internal_file, internals) {
+      panic!("LuaEngine: Failed to load internals. {}", e)
     }
-  }
 
-  ///
-  /// Completely unfiltered and unsandboxed code compiler/runner.
-  ///
-  pub fn run_code(&self, raw_code: String) {
-    match self.lua.load(raw_code).exec() {
-      Ok(_) => (),
-      Err(err) => panic!("LuaEngine: A fatal error has occurred! {}", err),
+    let sandbox = match self.create_sandbox() {
+      Ok(sandbox) => sandbox,
+      Err(e) => panic!("LuaEngine: failed to create the mod sandbox. {}", e),
+    };
+
+    if let Err(e) = self.lua.set_named_registry_value(SANDBOX_KEY, sandbox) {
+      panic!("LuaEngine: failed to store the mod sandbox. {}", e)
     }
   }
 
   ///
-  /// Completely unfiltered and unsandboxed file compiler/runner.
+  /// Run one of the engine's own files with the real globals.
   ///
-  /// ! We are letting LuauJIT be the sandbox. Look into this if
-  /// ! we think we should implement better protection!
+  /// The internals table gets passed in as the chunk's arguments (...).
   ///
-  pub fn run_file(&self, file_location: &str) -> Result<(), String> {
+  fn run_internal_file<'lua, R: FromLuaMulti<'lua>>(
+    &'lua self,
+    file_location: &str,
+    internals: Table<'lua>,
+  ) -> Result<R, String> {
     let raw_code_string = match read_file_to_string(file_location) {
       Ok(raw_code) => raw_code,
       Err(e) => panic!("LuaEngine: {}", e),
     };
 
-    if self.output_code_string {
-      println!("{}", raw_code_string);
+    match self
+      .lua
+      .load(raw_code_string)
+      .set_name(format!("@{}", file_location))
+      .call(internals)
+    {
+      Ok(result) => Ok(result),
+      Err(e) => Err(format!(
+        "LuaEngine: encountered fatal error in {}: {}",
+        file_location, e
+      )),
     }
+  }
 
-    match self.lua.load(raw_code_string).exec() {
-      Ok(_) => Ok(()),
-      Err(err) => {
-        // This needs some modification so we can post just these two elements:
-        // 1.) Lua file
-        // 2.) Line/Offset
-        Err(format!(
-          "LuaEngine: encountered fatal mod error in {}: {}",
-          file_location, err
-        ))
+  ///
+  /// Build the read-only base environment every mod environment falls back to.
+  ///
+  /// Only the whitelisted parts of the real globals make it in.
+  ///
+  fn create_sandbox(&self) -> mlua::Result<Table<'_>> {
+    let globals = self.lua.globals();
+    let sandbox = self.lua.create_table()?;
+
+    for function_name in SANDBOX_FUNCTIONS {
+      sandbox.set(function_name, globals.get::<_, mlua::Value>(function_name)?)?;
+    }
+
+    // The internals use these too, so they get frozen in place instead of copied.
+    // ("").upper goes through the string metatable, so that gets frozen as well.
+    for library_name in SANDBOX_LIBRARIES {
+      if let Some(library) = globals.get::<_, Option<Table>>(library_name)? {
+        library.set_readonly(true);
+        sandbox.set(library_name, library)?;
       }
     }
+    let string_metatable: Table = self.lua.load("return getmetatable(\"\")").eval()?;
+    string_metatable.set_readonly(true);
+
+    let os = globals.get::<_, Table>("os")?;
+    let safe_os = self.lua.create_table()?;
+    for function_name in SANDBOX_OS_FUNCTIONS {
+      safe_os.set(function_name, os.get::<_, Function>(function_name)?)?;
+    }
+    safe_os.set_readonly(true);
+    sandbox.set("os", safe_os)?;
+
+    let minetest = globals.get::<_, Table>("minetest")?;
+    sandbox.set("minetest", minetest)?;
+
+    // Each mod environment gets its own require. (module_loader)
+    sandbox.set_readonly(true);
+
+    Ok(sandbox)
+  }
+
+  ///
+  /// Create a fresh global environment for a mod.
+  ///
+  /// Globals a mod sets stay in its own environment, shared by all of its modules.
+  /// Everything else gets read out of the sandbox.
+  ///
+  fn create_mod_environment(&self) -> mlua::Result<Table<'_>> {
+    let sandbox = self.lua.named_registry_value::<Table>(SANDBOX_KEY)?;
+
+    let environment = self.lua.create_table()?;
+    environment.set("_G", environment.clone())?;
+
+    // __metatable locks it so mods can't dig the sandbox back out.
+    let metatable = self.lua.create_table()?;
+    metatable.set("__index", sandbox)?;
+    metatable.set("__metatable", false)?;
+    environment.set_metatable(Some(metatable));
+
+    Ok(environment)
+  }
+
+  ///
+  /// Get one of the internal tables out of the registry.
+  ///
+  /// This is how the engine picks up definitions that mods registered.
+  ///
+  pub fn get_internal_table(&self, table_name: &str) -> Result<Table<'_>, String> {
+    let internals = match self.lua.named_registry_value::<Table>(INTERNALS_KEY) {
+      Ok(internals) => internals,
+      Err(e) => return Err(format!("LuaEngine: the internals are missing. {}", e)),
+    };
+
+    match internals.get::<_, Table>(table_name) {
+      Ok(table) => Ok(table),
+      Err(e) => Err(format!(
+        "LuaEngine: failed to get internal table [{}]. {}",
+        table_name, e
+      )),
+    }
+  }
+
+  ///
+  /// Borrow the raw LuauJIT VM.
+  ///
+  /// This is how the Server calls back into Lua with engine results.
+  ///
+  pub fn get_lua(&self) -> &Lua {
+    &self.lua
+  }
+
+  ///
+  /// Expose a Rust function to mods as minetest.<name>.
+  ///
+  /// The function gets put into the global minetest table which api.lua creates.
+  /// Just like the internals, failing to register is fatal.
+  ///
+  pub fn register_api_function<'lua, A, R, F>(&'lua self, name: &str, function: F)
+  where
+    A: FromLuaMulti<'lua>,
+    R: IntoLuaMulti<'lua>,
+    F: Fn(&'lua Lua, A) -> mlua::Result<R> + 'static,
+  {
+    let minetest = match self.lua.globals().get::<_, Table>("minetest") {
+      Ok(minetest) => minetest,
+      Err(e) => panic!("LuaEngine: the minetest table is missing. {}", e),
+    };
+
+    let lua_function = match self.lua.create_function(function) {
+      Ok(lua_function) => lua_function,
+      Err(e) => panic!("LuaEngine: failed to create API function [{}]. {}", name, e),
+    };
+
+    if let Err(e) = minetest.set(name, lua_function) {
+      panic!(
+        "LuaEngine: failed to register API function [{}]. {}",
+        name, e
+      )
+    }
   }
 
   ///
@@ -130,14 +369,34 @@ impl LuaEngine {
     };
 
     println!("we got: {}", real_game_name);
+
+    // Mods start running before the Server reads the rest of [config], so this one gets picked up here.
+    if let Some(policy_name) = config.get("config", "lua_error_policy") {
+      match LuaErrorPolicy::from_name(&policy_name) {
+        Ok(policy) => set_error_policy(&self.lua, policy),
+        Err(e) => panic!("LuaEngine: {}", e),
+      }
+    }
+
+    self.game_conf = config;
+  }
+
+  ///
+  /// Get the parsed game.conf of the loaded game.
+  ///
+  /// This is empty until load_game() has been run.
+  ///
+  pub fn get_game_conf(&self) -> &Ini {
+    &self.game_conf
   }
 
   ///
   /// Load up each mod in a game.
-  /// todo: use dependency hierarchy [topological sorting (reverse postorder)topological sorting (reverse postorder)] <- luatic
   ///
-  /// ? note: Due to the nature of LuauJIT, I'm not sure we
-  /// ? actually need to sort anything.
+  /// Mods get loaded in dependency order, worked out from each mod.conf.
+  /// A missing dependency or a dependency cycle stops the game from loading.
+  ///
+  /// While a mod's main.lua runs, minetest.get_current_modname() gives back its name.
   ///
   /// This function blindly accepts that check_game was already ran
   /// on this game.
@@ -146,12 +405,37 @@ impl LuaEngine {
   /// _You're asking for trouble._
   ///
   fn load_game_files(&self, games_dir: &str, game_name: &str) {
-    let game_mod_path = get_game_path(games_dir, game_name);
-
+    let mut mod_confs = vec![];
     for mod_directory in get_game_mod_folders(games_dir, game_name) {
+      match ModConf::load(&mod_directory) {
+        Ok(mod_conf) => mod_confs.push(mod_conf),
+        Err(e) => panic!("LuaEngine: {}", e),
+      }
+    }
+
+    let mod_confs = match sort_mods(mod_confs) {
+      Ok(mod_confs) => mod_confs,
+      Err(e) => panic!("LuaEngine: game [{}] can't be loaded. {}", game_name, e),
+    };
+
+    let mod_paths: AHashMap<String, String> = mod_confs
+      .iter()
+      .map(|mod_conf| (mod_conf.name.clone(), mod_conf.mod_path.clone()))
+      .collect();
+    self.register_api_function("get_modpath", move |_, mod_name: String| {
+      Ok(mod_paths.get(&mod_name).cloned())
+    });
+
+    let current_mod_name = Rc::new(RefCell::new(None::<String>));
+    let current_mod_name_getter = current_mod_name.clone();
+    self.register_api_function("get_current_modname", move |_, ()| {
+      Ok(current_mod_name_getter.borrow().clone())
+    });
+
+    for mod_conf in &mod_confs {
       // ! this is a naive approach.
       // ! this might not work on windows!
-      let mut mod_path = mod_directory.mod_path.clone();
+      let mut mod_path = mod_conf.mod_path.clone();
       mod_path.push_str("/main.lua");
 
       println!(
@@ -159,15 +443,32 @@ impl LuaEngine {
         &mod_path
       );
 
-      // This simply panics for now, but in the future we can push errors to the GUI.
-      match self.run_file(&mod_path) {
+      add_mod(&self.lua, &mod_conf.name, &mod_conf.mod_path);
+      let registered = self
+        .create_mod_environment()
+        .and_then(|environment| register_mod(&self.lua, mod_conf, environment));
+      if let Err(e) = registered {
+        panic!(
+          "LuaEngine: failed to create environment for mod [{}]. {}",
+          mod_conf.name, e
+        )
+      }
+      *current_mod_name.borrow_mut() = Some(mod_conf.name.clone());
+
+      // main.lua goes through require, so whatever it returns is the mod's export.
+      // The error policy decides if a broken mod stops the game from loading.
+      match require_module(&self.lua, &mod_conf.name, "main") {
         Ok(_) => println!(
           "LuaEngine: Server loaded mod file [{}]\n--------------------",
           &mod_path
         ),
-        Err(e) => panic!("{}", e),
+        Err(e) => {
+          report_error(&self.lua, &format!("loading mod [{}]", mod_conf.name), &e);
+        }
       }
     }
+
+    *current_mod_name.borrow_mut() = None;
   }
 
   ///
@@ -199,3 +500,170 @@ impl LuaEngine {
     self.load_game_files(&games_dir, &game_name);
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::fs::{create_dir_all, remove_dir_all, write};
+
+  use mlua::Table;
+
+  use super::{
+    mod_conf::ModConf,
+    module_loader::{register_mod, require_module},
+    LuaEngine,
+  };
+
+  fn run(lua_engine: &LuaEngine, environment: Table, raw_code: &str) -> mlua::Result<()> {
+    lua_engine
+      .get_lua()
+      .load(raw_code)
+      .set_environment(environment)
+      .exec()
+  }
+
+  fn create_mod_environment(lua_engine: &LuaEngine) -> Table<'_> {
+    match lua_engine.create_mod_environment() {
+      Ok(environment) => environment,
+      Err(e) => panic!("{}", e),
+    }
+  }
+
+  #[test]
+  fn mods_cant_escape_the_sandbox() {
+    let lua_engine = LuaEngine::new(true);
+
+    let result = run(
+      &lua_engine,
+      create_mod_environment(&lua_engine),
+      r#"
+      assert(io == nil and debug == nil)
+      assert(loadstring == nil and getfenv == nil and setfenv == nil)
+      assert(os.execute == nil and os.getenv == nil and os.clock ~= nil)
+      assert(engine_on_tick_function == nil and blocks == nil and on_tick == nil)
+      assert(getmetatable(_G) == false)
+      assert(not pcall(function() string.rep = nil end))
+      assert(not pcall(function() getmetatable("").__index = {} end))
+      assert(not pcall(function() os.execute = print end))
+      leaked = true
+      "#,
+    );
+    if let Err(e) = result {
+      panic!("{}", e);
+    }
+
+    // Globals stay inside of the mod that set them.
+    assert!(run(
+      &lua_engine,
+      create_mod_environment(&lua_engine),
+      "assert(leaked == nil)"
+    )
+    .is_ok());
+    assert!(matches!(
+      lua_engine
+        .get_lua()
+        .globals()
+        .get::<_, Option<bool>>("leaked"),
+      Ok(None)
+    ));
+  }
+
+  #[test]
+  fn mods_require_their_own_modules_and_their_dependencies() {
+    let mods_dir =
+      std::env::temp_dir().join(format!("minetest_require_test_{}", std::process::id()));
+    let files = [
+      (
+        "farming/helper.lua",
+        "loads = (loads or 0) + 1\nreturn {value = 42}",
+      ),
+      ("farming/broken.lua", "\nlocal t = nil\nreturn t.x"),
+      ("default/main.lua", "return \"exported\""),
+      ("stranger/main.lua", "return \"hidden\""),
+    ];
+    for (file, raw_code) in files {
+      let path = mods_dir.join(file);
+      if let Some(parent) = path.parent() {
+        if let Err(e) = create_dir_all(parent) {
+          panic!("{}", e);
+        }
+      }
+      if let Err(e) = write(path, raw_code) {
+        panic!("{}", e);
+      }
+    }
+
+    let lua_engine = LuaEngine::new(true);
+    let mut environments = vec![];
+    for (name, depends) in [
+      ("farming", vec!["default"]),
+      ("default", vec![]),
+      ("stranger", vec![]),
+    ] {
+      let mod_conf = ModConf {
+        name: name.to_string(),
+        depends: depends.into_iter().map(String::from).collect(),
+        optional_depends: vec![],
+        mod_path: mods_dir.join(name).to_string_lossy().to_string(),
+      };
+      let environment = create_mod_environment(&lua_engine);
+      if let Err(e) = register_mod(lua_engine.get_lua(), &mod_conf, environment.clone()) {
+        panic!("{}", e);
+      }
+      environments.push(environment);
+    }
+    let farming_environment = environments.swap_remove(0);
+
+    let result = run(
+      &lua_engine,
+      farming_environment,
+      r#"
+      assert(require("api/api") == minetest)
+      local helper = require("helper")
+      assert(helper.value == 42 and require("farming:helper") == helper and loads == 1)
+      assert(require("default:main") == "exported")
+      assert(not pcall(require, "stranger:main"))
+      assert(not pcall(require, "../stranger/main"))
+      assert(not pcall(require, "missing"))
+      "#,
+    );
+    if let Err(e) = result {
+      panic!("{}", e);
+    }
+
+    // Tracebacks keep the real path of the module.
+    match require_module(lua_engine.get_lua(), "farming", "broken") {
+      Ok(_) => panic!("broken.lua worked"),
+      Err(e) => assert!(e.to_string().contains("farming/broken.lua:3:")),
+    }
+
+    if let Err(e) = remove_dir_all(mods_dir) {
+      panic!("{}", e);
+    }
+  }
+
+  #[test]
+  fn callbacks_get_checked_when_registered() {
+    let lua_engine = LuaEngine::new(true);
+
+    // Every register_on_* and register_allow_* turns away anything that isn't a function.
+    let result = run(
+      &lua_engine,
+      create_mod_environment(&lua_engine),
+      r#"
+      for _, name in ipairs({
+        "register_on_tick", "register_on_chat_message", "register_on_protection_violation",
+        "register_allow_player_inventory_action", "register_on_player_inventory_action",
+        "register_on_prejoinplayer", "register_on_joinplayer", "register_on_newplayer",
+        "register_on_leaveplayer", "register_on_dieplayer", "register_on_respawnplayer",
+        "register_on_shutdown", "register_on_mods_loaded",
+      }) do
+        local ok, e = pcall(minetest[name], "not a function")
+        assert(not ok and e:find("callback must be a function, got string"), name)
+      end
+      "#,
+    );
+    if let Err(e) = result {
+      panic!("{}", e);
+    }
+  }
+}
@@ -0,0 +1,469 @@
+///
+/// This module turns Lua errors into something the engine can reason about.
+///
+/// mlua hands back errors as one big string. A LuaError picks out which mod,
+/// file and line it came from, so a broken mod can be named and dealt with
+/// according to the LuaErrorPolicy instead of taking the whole process down.
+///
+use std::fmt;
+
+use ahash::AHashSet;
+use mlua::{FromLuaMulti, Function, IntoLuaMulti, Lua};
+
+///
+/// What happens when mod code errors.
+///
+/// * Crash      - Panic. Nothing broken gets to keep running.
+/// * DisableMod - Log it, then stop running every callback of the mod which errored.
+/// * LogOnly    - Log it and carry on.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LuaErrorPolicy {
+  Crash,
+  DisableMod,
+  LogOnly,
+}
+
+impl LuaErrorPolicy {
+  ///
+  /// Parse the lua_error_policy setting in game.conf.
+  ///
+  pub fn from_name(name: &str) -> Result<Self, String> {
+    match name.trim() {
+      "crash" => Ok(LuaErrorPolicy::Crash),
+      "disable_mod" => Ok(LuaErrorPolicy::DisableMod),
+      "log_only" => Ok(LuaErrorPolicy::LogOnly),
+      _ => Err(format!(
+        "LuaErrorPolicy: [{}] is not a policy. Use crash, disable_mod or log_only.",
+        name
+      )),
+    }
+  }
+}
+
+///
+/// A Lua error broken down into its parts.
+///
+/// * mod_name  - The mod the error came from, if it came from a mod at all.
+/// * file      - The Lua file the error was raised in.
+/// * line      - The line in that file.
+/// * message   - What went wrong, without the location.
+/// * traceback - The Lua stack at the time of the error.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LuaError {
+  pub mod_name: Option<String>,
+  pub file: Option<String>,
+  pub line: Option<u32>,
+  pub message: String,
+  pub traceback: Option<String>,
+}
+
+impl LuaError {
+  pub fn new(error: &mlua::Error) -> Self {
+    let (message, traceback) = match error {
+      mlua::Error::CallbackError { traceback, cause } => {
+        (get_cause_message(cause), Some(traceback.clone()))
+      }
+      mlua::Error::RuntimeError(text) => split_traceback(text),
+      mlua::Error::SyntaxError { message, .. } => split_traceback(message),
+      error => (error.to_string(), None),
+    };
+
+    // Errors raised from Rust have no location of their own.
+    // The first Lua frame in the traceback is where the mod called in.
+    let (file, line, message) = match parse_location(&message) {
+      Some((file, line, rest)) => (Some(file), Some(line), rest.to_string()),
+      None => match traceback_locations(&traceback).next() {
+        Some((file, line)) => (Some(file), Some(line), message),
+        None => (None, None, message),
+      },
+    };
+
+    LuaError {
+      mod_name: None,
+      file,
+      line,
+      message,
+      traceback,
+    }
+  }
+}
+
+impl fmt::Display for LuaError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if let Some(mod_name) = &self.mod_name {
+      write!(f, "mod [{}] ", mod_name)?;
+    }
+    match (&self.file, self.line) {
+      (Some(file), Some(line)) => write!(f, "at {}:{}: ", file, line)?,
+      (Some(file), None) => write!(f, "at {}: ", file)?,
+      _ => (),
+    }
+    write!(f, "{}", self.message)?;
+    if let Some(traceback) = &self.traceback {
+      write!(f, "\n{}", traceback)?;
+    }
+    Ok(())
+  }
+}
+
+///
+/// Lives in the app data of a Lua VM, so anything holding the Lua can report errors.
+///
+/// mods holds (mod_name, mod_path) so errors and functions can be traced back to their mod.
+///
+pub struct LuaErrorHandler {
+  policy: LuaErrorPolicy,
+  mods: Vec<(String, String)>,
+  disabled_mods: AHashSet<String>,
+}
+
+impl LuaErrorHandler {
+  pub fn new() -> Self {
+    LuaErrorHandler {
+      policy: LuaErrorPolicy::DisableMod,
+      mods: vec![],
+      disabled_mods: AHashSet::new(),
+    }
+  }
+
+  ///
+  /// Find which mod a Lua file belongs to.
+  ///
+  fn get_mod_name(&self, file: &str) -> Option<&String> {
+    self.get_mod(file).map(|(mod_name, _)| mod_name)
+  }
+
+  ///
+  /// Find the (mod_name, mod_path) a Lua file belongs to.
+  ///
+  fn get_mod(&self, file: &str) -> Option<&(String, String)> {
+    self.mods.iter().find(|(_, mod_path)| {
+      file
+        .strip_prefix(mod_path.as_str())
+        .is_some_and(|rest| rest.starts_with('/'))
+    })
+  }
+}
+
+///
+/// Install a fresh LuaErrorHandler into a Lua VM.
+///
+pub fn install_error_handler(lua: &Lua) {
+  lua.set_app_data(LuaErrorHandler::new());
+}
+
+pub fn set_error_policy(lua: &Lua, policy: LuaErrorPolicy) {
+  if let Some(mut handler) = lua.app_data_mut::<LuaErrorHandler>() {
+    handler.policy = policy;
+  }
+}
+
+///
+/// Let the error handler know where a mod lives, so its errors can be blamed on it.
+///
+pub fn add_mod(lua: &Lua, mod_name: &str, mod_path: &str) {
+  if let Some(mut handler) = lua.app_data_mut::<LuaErrorHandler>() {
+    handler
+      .mods
+      .push((mod_name.to_string(), mod_path.to_string()));
+  }
+}
+
+///
+/// Check if a function was defined by a mod which got disabled.
+///
+pub fn is_function_disabled(lua: &Lua, function: &Function) -> bool {
+  let mod_name = match get_function_mod_name(lua, function) {
+    Some(mod_name) => mod_name,
+    None => return false,
+  };
+
+  match lua.app_data_ref::<LuaErrorHandler>() {
+    Some(handler) => handler.disabled_mods.contains(&mod_name),
+    None => false,
+  }
+}
+
+///
+/// Find which mod a function was defined by.
+///
+pub fn get_function_mod_name(lua: &Lua, function: &Function) -> Option<String> {
+  let source = function.info().source?;
+  let file = source.trim_start_matches(['@', '=']);
+
+  let handler = lua.app_data_ref::<LuaErrorHandler>()?;
+  handler.get_mod_name(file).cloned()
+}
+
+///
+/// Find the (mod_name, mod_path) of the mod which called into the engine.
+///
+/// Walks up the Lua stack, so it works from inside an API function wherever the mod called it from.
+///
+pub fn get_calling_mod(lua: &Lua) -> Option<(String, String)> {
+  let handler = lua.app_data_ref::<LuaErrorHandler>()?;
+
+  let mut level = 0;
+  while let Some(debug) = lua.inspect_stack(level) {
+    if let Some(source) = debug.source().source {
+      if let Some(found) = handler.get_mod(source.trim_start_matches(['@', '='])) {
+        return Some(found.clone());
+      }
+    }
+    level += 1;
+  }
+
+  None
+}
+
+///
+/// Blame a Lua error on a mod, log it, and carry out the error policy.
+///
+/// context says what the engine was doing, like "on_tick" or "abm [default:grass]".
+///
+pub fn report_error(lua: &Lua, context: &str, error: &mlua::Error) -> LuaError {
+  let mut lua_error = LuaError::new(error);
+
+  let policy = match lua.app_data_mut::<LuaErrorHandler>() {
+    Some(mut handler) => {
+      // The error might have been raised in the API on behalf of a mod, so check the whole stack.
+      let mod_name = lua_error
+        .file
+        .clone()
+        .into_iter()
+        .chain(traceback_locations(&lua_error.traceback).map(|(file, _)| file))
+        .find_map(|file| handler.get_mod_name(&file).cloned());
+
+      if let (LuaErrorPolicy::DisableMod, Some(mod_name)) = (handler.policy, &mod_name) {
+        handler.disabled_mods.insert(mod_name.clone());
+      }
+      lua_error.mod_name = mod_name;
+      handler.policy
+    }
+    None => LuaErrorPolicy::Crash,
+  };
+
+  match policy {
+    LuaErrorPolicy::Crash => panic!("LuaEngine: {} failed. {}", context, lua_error),
+    LuaErrorPolicy::DisableMod => {
+      println!("LuaEngine: {} failed. {}", context, lua_error);
+      if let Some(mod_name) = &lua_error.mod_name {
+        println!("LuaEngine: disabled mod [{}].", mod_name);
+      }
+    }
+    LuaErrorPolicy::LogOnly => println!("LuaEngine: {} failed. {}", context, lua_error),
+  }
+
+  lua_error
+}
+
+///
+/// Call a function which a mod handed to the engine.
+///
+/// Functions of disabled mods are skipped and errors get reported under the error policy.
+/// Returns None if the function didn't run to completion.
+///
+pub fn call_mod_function<'lua, A, R>(
+  lua: &'lua Lua,
+  function: &Function<'lua>,
+  context: &str,
+  args: A,
+) -> Option<R>
+where
+  A: IntoLuaMulti<'lua>,
+  R: FromLuaMulti<'lua>,
+{
+  if is_function_disabled(lua, function) {
+    return None;
+  }
+
+  match function.call(args) {
+    Ok(result) => Some(result),
+    Err(e) => {
+      report_error(lua, context, &e);
+      None
+    }
+  }
+}
+
+///
+/// Dig the message out of whatever a Rust function errored with.
+///
+fn get_cause_message(cause: &mlua::Error) -> String {
+  match cause {
+    mlua::Error::CallbackError { cause, .. } => get_cause_message(cause),
+    mlua::Error::RuntimeError(message) => message.clone(),
+    cause => cause.to_string(),
+  }
+}
+
+///
+/// Split the traceback mlua appends onto runtime errors off of the message.
+///
+fn split_traceback(text: &str) -> (String, Option<String>) {
+  match text.find("\nstack traceback:") {
+    Some(index) => (
+      text[..index].to_string(),
+      Some(text[index + 1..].to_string()),
+    ),
+    None => (text.to_string(), None),
+  }
+}
+
+///
+/// Every file:line in a traceback, innermost first.
+///
+fn traceback_locations(traceback: &Option<String>) -> impl Iterator<Item = (String, u32)> + '_ {
+  traceback
+    .iter()
+    .flat_map(|traceback| traceback.lines())
+    .filter_map(|line| parse_location(line).map(|(file, line, _)| (file, line)))
+}
+
+///
+/// Pick the location off the front of an error or traceback line.
+///
+/// Luau writes it as either file:line: or [string "file"]:line:
+/// Returns the file, the line, and whatever comes after it.
+///
+fn parse_location(text: &str) -> Option<(String, u32, &str)> {
+  let text = text.trim_start();
+
+  if let Some(quoted) = text.strip_prefix("[string \"") {
+    let end = quoted.find("\"]:")?;
+    let (line, rest) = parse_line_number(&quoted[end + 3..])?;
+    return Some((quoted[..end].to_string(), line, rest));
+  }
+
+  let mut search_from = 0;
+  while let Some(offset) = text[search_from..].find(':') {
+    let colon = search_from + offset;
+    if let Some((line, rest)) = parse_line_number(&text[colon + 1..]) {
+      if colon > 0 && !text[..colon].contains(char::is_whitespace) {
+        return Some((text[..colon].to_string(), line, rest));
+      }
+    }
+    search_from = colon + 1;
+  }
+
+  None
+}
+
+///
+/// Parse "12: rest" into (12, "rest").
+///
+fn parse_line_number(text: &str) -> Option<(u32, &str)> {
+  let digits = text.find(|c: char| !c.is_ascii_digit())?;
+  if digits == 0 || !text[digits..].starts_with(':') {
+    return None;
+  }
+  let line = text[..digits].parse().ok()?;
+  Some((line, text[digits + 1..].trim_start()))
+}
+
+#[cfg(test)]
+mod tests {
+  use mlua::{Function, Lua};
+
+  use super::{add_mod, call_mod_function, get_calling_mod, install_error_handler, LuaError};
+
+  #[test]
+  fn broken_mods_get_blamed_and_disabled() {
+    let lua = Lua::new();
+    install_error_handler(&lua);
+    add_mod(&lua, "broken", "./mods/broken");
+
+    let raise = match lua
+      .create_function(|_, ()| -> mlua::Result<()> { Err(mlua::Error::runtime("bad position")) })
+    {
+      Ok(raise) => raise,
+      Err(e) => panic!("{}", e),
+    };
+    if let Err(e) = lua.globals().set("raise", raise) {
+      panic!("{}", e);
+    }
+
+    let functions = lua
+      .load(
+        "calls = 0\n\
+         local function broken() calls = calls + 1; local t = nil; return t.x end\n\
+         local function calls_rust() raise() end\n\
+         return broken, calls_rust",
+      )
+      .set_name("@./mods/broken/init.lua")
+      .eval::<(Function, Function)>();
+    let (broken, calls_rust) = match functions {
+      Ok(functions) => functions,
+      Err(e) => panic!("{}", e),
+    };
+
+    match broken.call::<_, ()>(()) {
+      Ok(_) => panic!("broken() worked"),
+      Err(e) => {
+        let lua_error = LuaError::new(&e);
+        assert_eq!(lua_error.file.as_deref(), Some("./mods/broken/init.lua"));
+        assert_eq!(lua_error.line, Some(2));
+        assert_eq!(lua_error.message, "attempt to index nil with 'x'");
+        assert!(lua_error.traceback.is_some());
+      }
+    }
+
+    // Errors out of Rust get the location of the Lua which called in.
+    match calls_rust.call::<_, ()>(()) {
+      Ok(_) => panic!("calls_rust() worked"),
+      Err(e) => {
+        let lua_error = LuaError::new(&e);
+        assert_eq!(lua_error.line, Some(3));
+        assert_eq!(lua_error.message, "bad position");
+      }
+    }
+
+    // The default policy disables the mod, so it only gets one shot.
+    assert_eq!(call_mod_function::<_, ()>(&lua, &broken, "test", ()), None);
+    assert_eq!(call_mod_function::<_, ()>(&lua, &broken, "test", ()), None);
+    assert!(matches!(lua.globals().get::<_, i32>("calls"), Ok(2)));
+  }
+
+  #[test]
+  fn calling_mods_are_found_up_the_stack() {
+    let lua = Lua::new();
+    install_error_handler(&lua);
+    add_mod(&lua, "trees", "./mods/trees");
+
+    let get_caller = match lua.create_function(|lua, ()| {
+      Ok(get_calling_mod(lua).map(|(mod_name, mod_path)| format!("{} {}", mod_name, mod_path)))
+    }) {
+      Ok(get_caller) => get_caller,
+      Err(e) => panic!("{}", e),
+    };
+    if let Err(e) = lua.globals().set("get_caller", get_caller) {
+      panic!("{}", e);
+    }
+
+    // The engine API sits between the mod and Rust, like api.lua does.
+    let api = match lua
+      .load("return function() return get_caller() end")
+      .set_name("@./api/api.lua")
+      .eval::<Function>()
+    {
+      Ok(api) => api,
+      Err(e) => panic!("{}", e),
+    };
+    if let Err(e) = lua.globals().set("api", api.clone()) {
+      panic!("{}", e);
+    }
+
+    match lua
+      .load("return api()")
+      .set_name("@./mods/trees/main.lua")
+      .eval::<Option<String>>()
+    {
+      Ok(from_mod) => assert_eq!(from_mod.as_deref(), Some("trees ./mods/trees")),
+      Err(e) => panic!("{}", e),
+    }
+
+    assert!(matches!(api.call::<_, Option<String>>(()), Ok(None)));
+  }
+}
@@ -0,0 +1,409 @@
+///
+/// This module is the ItemStack type Lua works with.
+///
+/// In Lua:
+/// * ItemStack(item) where item is an item string, an ItemStack,
+///   a {name, count, wear, meta} table or nil for an empty stack.
+/// * get/set name, count and wear, add_wear, is_empty, clear, is_known,
+///   get_description, get_stack_max, get_free_space, to_string and to_table.
+/// * take_item(n), peek_item(n), add_item(item) and item_fits(item).
+/// * get_meta() gives back an ItemStackMetaRef, which works like NodeMetaRef.
+///
+/// Stack limits come from the ItemRegistry, which only exists once every mod
+/// has been loaded. Until then every item stacks up to DEFAULT_STACK_MAX.
+///
+use std::{cell::RefCell, rc::Rc};
+
+use mlua::{FromLua, Lua, MetaMethod, Table, UserData, UserDataMethods, Value};
+
+use crate::game::{
+  item_registry::{ItemRegistry, DEFAULT_STACK_MAX},
+  item_stack::{check_item_name, ItemStack},
+};
+
+use super::lua_table_helpers::get_field_or;
+
+///
+/// An ItemStack in Lua. The ItemStackMetaRefs made by get_meta() share it.
+///
+#[derive(Clone)]
+pub struct LuaItemStack(Rc<RefCell<ItemStack>>);
+
+impl LuaItemStack {
+  pub fn new(item_stack: ItemStack) -> Self {
+    LuaItemStack(Rc::new(RefCell::new(item_stack)))
+  }
+}
+
+///
+/// Rust functions can take a LuaItemStack to accept anything ItemStack() does.
+///
+/// The stack is always a copy, changing it won't change the one Lua passed in.
+///
+impl<'lua> FromLua<'lua> for LuaItemStack {
+  fn from_lua(value: Value<'lua>, _: &'lua Lua) -> mlua::Result<Self> {
+    Ok(LuaItemStack::new(get_item_stack(&value)?))
+  }
+}
+
+///
+/// Hand the ItemRegistry to a Lua VM so ItemStacks know their stack limits.
+///
+pub fn set_item_registry(lua: &Lua, item_registry: Rc<ItemRegistry>) {
+  lua.set_app_data(item_registry);
+}
+
+fn get_stack_max(lua: &Lua, name: &str) -> u16 {
+  match lua.app_data_ref::<Rc<ItemRegistry>>() {
+    Some(item_registry) => item_registry.get_stack_max(name),
+    None => DEFAULT_STACK_MAX,
+  }
+}
+
+///
+/// Get an ItemStack out of a Lua value.
+///
+pub fn get_item_stack(value: &Value) -> mlua::Result<ItemStack> {
+  match value {
+    Value::Nil => Ok(ItemStack::default()),
+    Value::String(item_string) => {
+      ItemStack::from_string(item_string.to_str()?).map_err(mlua::Error::runtime)
+    }
+    Value::UserData(userdata) => match userdata.borrow::<LuaItemStack>() {
+      Ok(item_stack) => Ok(item_stack.0.borrow().clone()),
+      Err(_) => Err(mlua::Error::runtime(
+        "ItemStack: userdata is not an ItemStack.",
+      )),
+    },
+    Value::Table(table) => item_stack_from_table(table).map_err(mlua::Error::runtime),
+    other => Err(mlua::Error::runtime(format!(
+      "ItemStack: expected an item string, ItemStack, table or nil, got [{}].",
+      other.type_name()
+    ))),
+  }
+}
+
+///
+/// {name = string, count = number?, wear = number?, meta = {[string] = string}?}
+///
+fn item_stack_from_table(table: &Table) -> Result<ItemStack, String> {
+  let name: String = get_field_or(table, "name", String::new())?;
+  check_item_name(&name)?;
+
+  let mut item_stack = ItemStack::new(&name, get_field_or(table, "count", 1)?);
+  item_stack.set_wear(get_field_or(table, "wear", 0)?);
+
+  if let Some(meta) = get_field_or::<Option<Table>>(table, "meta", None)? {
+    for pair in meta.pairs::<String, String>() {
+      match pair {
+        Ok((key, value)) => item_stack.set_meta_string(&key, &value)?,
+        Err(e) => {
+          return Err(format!(
+            "ItemStack: meta must be string = string pairs. {}",
+            e
+          ))
+        }
+      }
+    }
+  }
+
+  Ok(item_stack)
+}
+
+impl UserData for LuaItemStack {
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    methods.add_method("is_empty", |_, this, ()| Ok(this.0.borrow().is_empty()));
+
+    methods.add_method("get_name", |_, this, ()| {
+      Ok(this.0.borrow().get_name().to_string())
+    });
+
+    methods.add_method("set_name", |_, this, name: String| {
+      this
+        .0
+        .borrow_mut()
+        .set_name(&name)
+        .map_err(mlua::Error::runtime)
+    });
+
+    methods.add_method("get_count", |_, this, ()| Ok(this.0.borrow().get_count()));
+
+    methods.add_method("set_count", |_, this, count: u16| {
+      this.0.borrow_mut().set_count(count);
+      Ok(())
+    });
+
+    methods.add_method("get_wear", |_, this, ()| Ok(this.0.borrow().get_wear()));
+
+    methods.add_method("set_wear", |_, this, wear: u16| {
+      this.0.borrow_mut().set_wear(wear);
+      Ok(())
+    });
+
+    // Returns true if the tool broke.
+    methods.add_method("add_wear", |_, this, amount: u16| {
+      Ok(this.0.borrow_mut().add_wear(amount))
+    });
+
+    methods.add_method("get_meta", |_, this, ()| {
+      Ok(ItemStackMetaRef(this.0.clone()))
+    });
+
+    methods.add_method("clear", |_, this, ()| {
+      this.0.borrow_mut().clear();
+      Ok(())
+    });
+
+    methods.add_method("is_known", |lua, this, ()| {
+      let name = this.0.borrow().get_name().to_string();
+      Ok(match lua.app_data_ref::<Rc<ItemRegistry>>() {
+        Some(item_registry) => item_registry.is_known(&name),
+        None => false,
+      })
+    });
+
+    methods.add_method("get_description", |lua, this, ()| {
+      let name = this.0.borrow().get_name().to_string();
+      Ok(match lua.app_data_ref::<Rc<ItemRegistry>>() {
+        Some(item_registry) => match item_registry.get_definition(&name) {
+          Some(definition) => definition.description.clone(),
+          None => name,
+        },
+        None => name,
+      })
+    });
+
+    methods.add_method("get_stack_max", |lua, this, ()| {
+      Ok(get_stack_max(lua, this.0.borrow().get_name()))
+    });
+
+    methods.add_method("get_free_space", |lua, this, ()| {
+      let item_stack = this.0.borrow();
+      Ok(item_stack.get_free_space(get_stack_max(lua, item_stack.get_name())))
+    });
+
+    methods.add_method("to_string", |_, this, ()| Ok(this.0.borrow().to_string()));
+
+    methods.add_method("to_table", |lua, this, ()| {
+      let item_stack = this.0.borrow();
+      if item_stack.is_empty() {
+        return Ok(None);
+      }
+
+      let table = lua.create_table()?;
+      table.set("name", item_stack.get_name())?;
+      table.set("count", item_stack.get_count())?;
+      table.set("wear", item_stack.get_wear())?;
+      let meta = lua.create_table()?;
+      for (key, value) in item_stack.get_meta_fields() {
+        meta.set(key.as_str(), value.as_str())?;
+      }
+      table.set("meta", meta)?;
+      Ok(Some(table))
+    });
+
+    methods.add_method("take_item", |_, this, count: Option<u16>| {
+      Ok(LuaItemStack::new(
+        this.0.borrow_mut().take_item(count.unwrap_or(1)),
+      ))
+    });
+
+    methods.add_method("peek_item", |_, this, count: Option<u16>| {
+      Ok(LuaItemStack::new(
+        this.0.borrow().peek_item(count.unwrap_or(1)),
+      ))
+    });
+
+    // Returns the leftover.
+    methods.add_method("add_item", |lua, this, item: LuaItemStack| {
+      let item = item.0.take();
+      let stack_max = get_stack_max(lua, item.get_name());
+      Ok(LuaItemStack::new(
+        this.0.borrow_mut().add_item(item, stack_max),
+      ))
+    });
+
+    methods.add_method("item_fits", |lua, this, item: LuaItemStack| {
+      let item = item.0.take();
+      let stack_max = get_stack_max(lua, item.get_name());
+      Ok(this.0.borrow().item_fits(&item, stack_max))
+    });
+
+    methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+      Ok(this.0.borrow().to_string())
+    });
+  }
+}
+
+///
+/// The Lua handle to an ItemStack's meta. Made by ItemStack:get_meta().
+///
+/// Writes go straight into the ItemStack it came from.
+///
+pub struct ItemStackMetaRef(Rc<RefCell<ItemStack>>);
+
+impl UserData for ItemStackMetaRef {
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    methods.add_method("contains", |_, this, key: String| {
+      Ok(this.0.borrow().get_meta_fields().contains_key(&key))
+    });
+
+    methods.add_method("get_string", |_, this, key: String| {
+      Ok(this.0.borrow().get_meta_string(&key))
+    });
+
+    methods.add_method(
+      "set_string",
+      |_, this, (key, value): (String, Option<String>)| {
+        this
+          .0
+          .borrow_mut()
+          .set_meta_string(&key, &value.unwrap_or_default())
+          .map_err(mlua::Error::runtime)
+      },
+    );
+
+    // Missing or non-numeric fields are 0, just like NodeMetaRef.
+    methods.add_method("get_int", |_, this, key: String| {
+      Ok(
+        this
+          .0
+          .borrow()
+          .get_meta_string(&key)
+          .trim()
+          .parse::<i64>()
+          .unwrap_or(0),
+      )
+    });
+
+    methods.add_method("set_int", |_, this, (key, value): (String, i64)| {
+      this
+        .0
+        .borrow_mut()
+        .set_meta_string(&key, &value.to_string())
+        .map_err(mlua::Error::runtime)
+    });
+
+    methods.add_method("get_float", |_, this, key: String| {
+      Ok(
+        this
+          .0
+          .borrow()
+          .get_meta_string(&key)
+          .trim()
+          .parse::<f64>()
+          .unwrap_or(0.0),
+      )
+    });
+
+    methods.add_method("set_float", |_, this, (key, value): (String, f64)| {
+      this
+        .0
+        .borrow_mut()
+        .set_meta_string(&key, &value.to_string())
+        .map_err(mlua::Error::runtime)
+    });
+
+    methods.add_method("to_table", |lua, this, ()| {
+      let fields = lua.create_table()?;
+      for (key, value) in this.0.borrow().get_meta_fields() {
+        fields.set(key.as_str(), value.as_str())?;
+      }
+      let table = lua.create_table()?;
+      table.set("fields", fields)?;
+      Ok(table)
+    });
+  }
+}
+
+///
+/// Put the ItemStack constructor into the globals of a Lua VM.
+///
+pub fn register_item_stack_api(lua: &Lua) -> mlua::Result<()> {
+  lua.globals().set(
+    "ItemStack",
+    lua.create_function(|_, item: LuaItemStack| Ok(item))?,
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::BTreeMap, rc::Rc};
+
+  use mlua::Lua;
+
+  use super::{register_item_stack_api, set_item_registry};
+  use crate::game::{
+    item_registry::{ItemDefinition, ItemRegistry, DEFAULT_ITEM_RANGE},
+    item_stack::ItemStack,
+  };
+
+  #[test]
+  fn item_stacks_work_from_lua() {
+    let lua = Lua::new();
+    if let Err(e) = register_item_stack_api(&lua) {
+      panic!("{}", e);
+    }
+
+    let mut item_registry = ItemRegistry::new();
+    for (name, stack_max) in [("test:dirt", 10), ("test:pickaxe", 1)] {
+      let definition = ItemDefinition {
+        name: name.to_string(),
+        description: name.to_string(),
+        stack_max,
+        range: DEFAULT_ITEM_RANGE,
+        tool_capabilities: None,
+        groups: BTreeMap::new(),
+      };
+      if let Err(e) = item_registry.register_item(definition) {
+        panic!("{}", e);
+      }
+    }
+    set_item_registry(&lua, Rc::new(item_registry));
+
+    let result = lua
+      .load(
+        r#"
+        local dirt = ItemStack("test:dirt 8")
+        assert(dirt:get_name() == "test:dirt" and dirt:get_count() == 8)
+        assert(dirt:get_stack_max() == 10 and dirt:get_free_space() == 2)
+
+        local leftover = dirt:add_item("test:dirt 5")
+        assert(dirt:get_count() == 10 and leftover:get_count() == 3)
+        assert(not dirt:item_fits("test:dirt"))
+
+        local taken = dirt:take_item(4)
+        assert(taken:to_string() == "test:dirt 4" and dirt:get_count() == 6)
+        assert(dirt:peek_item(100):get_count() == 6 and dirt:get_count() == 6)
+
+        -- ItemStack() copies, it never shares.
+        local copy = ItemStack(dirt)
+        copy:set_count(1)
+        assert(dirt:get_count() == 6)
+
+        local pickaxe = ItemStack({name = "test:pickaxe", wear = 100})
+        local meta = pickaxe:get_meta()
+        meta:set_string("owner", "singleplayer")
+        meta:set_int("uses", 3)
+        assert(pickaxe:get_meta():get_int("uses") == 3)
+        assert(ItemStack(pickaxe:to_string()):get_meta():get_string("owner") == "singleplayer")
+        assert(pickaxe:to_table().meta.owner == "singleplayer")
+        assert(not pickaxe:add_wear(1000) and pickaxe:get_wear() == 1100)
+
+        assert(ItemStack("test:unknown"):is_known() == false)
+        assert(ItemStack():is_empty() and ItemStack():to_table() == nil)
+        assert(not pcall(ItemStack, "test:dirt lots"))
+        return tostring(pickaxe)
+        "#,
+      )
+      .eval::<String>();
+
+    match result {
+      Ok(pickaxe) => assert!(matches!(
+        ItemStack::from_string(&pickaxe),
+        Ok(pickaxe) if pickaxe.get_wear() == 1100 && pickaxe.get_meta_string("uses") == "3"
+      )),
+      Err(e) => panic!("{}", e),
+    }
+  }
+}
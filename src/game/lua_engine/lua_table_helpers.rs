@@ -0,0 +1,118 @@
+///
+/// This module is a thin wrapper around reading fields out of mlua Tables.
+///
+/// Definitions (blocks, biomes, ores, etc) come in from Lua as Tables.
+/// These helpers turn mlua's generic conversion errors into something
+/// a modder can actually read. Every error names the field it failed on.
+///
+use glam::{IVec3, Vec3};
+use mlua::{AnyUserData, FromLua, Lua, Table, Value};
+
+use super::lua_vector::LuaVector;
+
+///
+/// Get a required field out of a Lua Table.
+///
+pub fn get_field<'lua, T: FromLua<'lua>>(table: &Table<'lua>, field: &str) -> Result<T, String> {
+  match table.get::<_, Option<T>>(field) {
+    Ok(Some(value)) => Ok(value),
+    Ok(None) => Err(format!("missing required field [{}]", field)),
+    Err(e) => Err(format!("field [{}] is the wrong type. {}", field, e)),
+  }
+}
+
+///
+/// Get an optional field out of a Lua Table.
+///
+/// Returns the default value if the field is nil.
+///
+pub fn get_field_or<'lua, T: FromLua<'lua>>(
+  table: &Table<'lua>,
+  field: &str,
+  default: T,
+) -> Result<T, String> {
+  match table.get::<_, Option<T>>(field) {
+    Ok(Some(value)) => Ok(value),
+    Ok(None) => Ok(default),
+    Err(e) => Err(format!("field [{}] is the wrong type. {}", field, e)),
+  }
+}
+
+///
+/// Get a field which can either be a single string or an array of strings.
+///
+/// Example: wherein = "minetest:stone" or wherein = {"minetest:stone", "minetest:dirt"}
+///
+/// Returns an empty Vec if the field is nil.
+///
+pub fn get_string_list(table: &Table, field: &str) -> Result<Vec<String>, String> {
+  match table.get::<_, Value>(field) {
+    Ok(Value::Nil) => Ok(vec![]),
+    Ok(Value::String(string)) => match string.to_str() {
+      Ok(string) => Ok(vec![string.to_string()]),
+      Err(e) => Err(format!("field [{}] is not valid UTF-8. {}", field, e)),
+    },
+    Ok(Value::Table(list)) => {
+      let mut container = vec![];
+      for value in list.sequence_values::<String>() {
+        match value {
+          Ok(string) => container.push(string),
+          Err(e) => return Err(format!("field [{}] contains a non-string. {}", field, e)),
+        }
+      }
+      Ok(container)
+    }
+    Ok(other) => Err(format!(
+      "field [{}] must be a string or array of strings, got [{}]",
+      field,
+      other.type_name()
+    )),
+    Err(e) => Err(format!("failed to read field [{}]. {}", field, e)),
+  }
+}
+
+///
+/// Get a position out of a Lua value.
+///
+/// Positions come in as vectors or {x = number, y = number, z = number} tables.
+///
+pub fn get_position(value: &Value) -> Result<Vec3, String> {
+  match value {
+    Value::UserData(userdata) => match userdata.borrow::<LuaVector>() {
+      Ok(vector) => Ok(vector.0),
+      Err(_) => Err("position must be a vector or {x, y, z} table, got [userdata]".to_string()),
+    },
+    Value::Table(table) => Ok(Vec3::new(
+      get_field(table, "x")?,
+      get_field(table, "y")?,
+      get_field(table, "z")?,
+    )),
+    other => Err(format!(
+      "position must be a vector or {{x, y, z}} table, got [{}]",
+      other.type_name()
+    )),
+  }
+}
+
+///
+/// Get a node position out of a Lua value.
+///
+/// Same as get_position but rounded to the nearest node.
+///
+pub fn get_node_position(value: &Value) -> Result<IVec3, String> {
+  Ok(get_position(value)?.round().as_ivec3())
+}
+
+///
+/// Turn a node position into a vector for Lua.
+///
+pub fn node_position_to_vector(lua: &Lua, position: IVec3) -> mlua::Result<AnyUserData<'_>> {
+  lua.create_userdata(LuaVector(position.as_vec3()))
+}
+
+///
+/// Turn a position into a vector for Lua.
+///
+pub fn position_to_vector(lua: &Lua, position: Vec3) -> mlua::Result<AnyUserData<'_>> {
+  lua.create_userdata(LuaVector(position))
+}
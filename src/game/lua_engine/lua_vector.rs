@@ -0,0 +1,284 @@
+///
+/// This module is the vector type Lua works with, backed by a glam Vec3.
+///
+/// Every position the engine hands to Lua is one of these. Positions coming
+/// back in can be one of these or a plain {x, y, z} table.
+///
+/// In Lua:
+/// * vector.new(x, y, z), vector.new(v), vector.zero()
+/// * a + b, a - b, a * b, a / b, -a. Either side of + - * / can be a number.
+/// * a == b, tostring(a), a.x, a.y = 2
+/// * distance, length, normalize, cross, dot, round, floor and copy.
+///   These work as vector.dot(a, b) or a:dot(b).
+///
+use glam::Vec3;
+use mlua::{FromLua, Lua, MetaMethod, UserData, UserDataFields, UserDataMethods, Value};
+
+use super::lua_table_helpers::get_position;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LuaVector(pub Vec3);
+
+///
+/// Rust functions can take a LuaVector to accept both vectors and {x, y, z} tables.
+///
+impl<'lua> FromLua<'lua> for LuaVector {
+  fn from_lua(value: Value<'lua>, _: &'lua Lua) -> mlua::Result<Self> {
+    Ok(LuaVector(get_vector(&value)?))
+  }
+}
+
+impl UserData for LuaVector {
+  fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+    fields.add_field_method_get("x", |_, this| Ok(this.0.x));
+    fields.add_field_method_get("y", |_, this| Ok(this.0.y));
+    fields.add_field_method_get("z", |_, this| Ok(this.0.z));
+
+    fields.add_field_method_set("x", |_, this, x: f32| {
+      this.0.x = x;
+      Ok(())
+    });
+    fields.add_field_method_set("y", |_, this, y: f32| {
+      this.0.y = y;
+      Ok(())
+    });
+    fields.add_field_method_set("z", |_, this, z: f32| {
+      this.0.z = z;
+      Ok(())
+    });
+  }
+
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    methods.add_meta_function(MetaMethod::Add, |_, (a, b): (Value, Value)| {
+      Ok(LuaVector(get_operand(&a)? + get_operand(&b)?))
+    });
+    methods.add_meta_function(MetaMethod::Sub, |_, (a, b): (Value, Value)| {
+      Ok(LuaVector(get_operand(&a)? - get_operand(&b)?))
+    });
+    methods.add_meta_function(MetaMethod::Mul, |_, (a, b): (Value, Value)| {
+      Ok(LuaVector(get_operand(&a)? * get_operand(&b)?))
+    });
+    methods.add_meta_function(MetaMethod::Div, |_, (a, b): (Value, Value)| {
+      Ok(LuaVector(get_operand(&a)? / get_operand(&b)?))
+    });
+    methods.add_meta_method(MetaMethod::Unm, |_, this, ()| Ok(LuaVector(-this.0)));
+    methods.add_meta_function(MetaMethod::Eq, |_, (a, b): (Value, Value)| {
+      Ok(get_vector(&a)? == get_vector(&b)?)
+    });
+    methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+      Ok(format!("({}, {}, {})", this.0.x, this.0.y, this.0.z))
+    });
+
+    methods.add_function("distance", distance);
+    methods.add_function("length", length);
+    methods.add_function("normalize", normalize);
+    methods.add_function("cross", cross);
+    methods.add_function("dot", dot);
+    methods.add_function("round", round);
+    methods.add_function("floor", floor);
+    methods.add_function("copy", copy);
+  }
+}
+
+///
+/// Get a vector out of a Lua value. Vectors and {x, y, z} tables both work.
+///
+pub fn get_vector(value: &Value) -> mlua::Result<Vec3> {
+  get_position(value).map_err(mlua::Error::runtime)
+}
+
+///
+/// One side of vector arithmetic. Numbers apply to every axis.
+///
+fn get_operand(value: &Value) -> mlua::Result<Vec3> {
+  match value {
+    Value::Integer(number) => Ok(Vec3::splat(*number as f32)),
+    Value::Number(number) => Ok(Vec3::splat(*number as f32)),
+    value => get_vector(value),
+  }
+}
+
+fn distance(_: &Lua, (a, b): (Value, Value)) -> mlua::Result<f32> {
+  Ok(get_vector(&a)?.distance(get_vector(&b)?))
+}
+
+fn length(_: &Lua, v: Value) -> mlua::Result<f32> {
+  Ok(get_vector(&v)?.length())
+}
+
+///
+/// The zero vector stays zero instead of turning into NaN.
+///
+fn normalize(_: &Lua, v: Value) -> mlua::Result<LuaVector> {
+  Ok(LuaVector(get_vector(&v)?.normalize_or_zero()))
+}
+
+fn cross(_: &Lua, (a, b): (Value, Value)) -> mlua::Result<LuaVector> {
+  Ok(LuaVector(get_vector(&a)?.cross(get_vector(&b)?)))
+}
+
+fn dot(_: &Lua, (a, b): (Value, Value)) -> mlua::Result<f32> {
+  Ok(get_vector(&a)?.dot(get_vector(&b)?))
+}
+
+///
+/// Halves round away from zero, the same way positions get rounded onto nodes.
+///
+fn round(_: &Lua, v: Value) -> mlua::Result<LuaVector> {
+  Ok(LuaVector(get_vector(&v)?.round()))
+}
+
+fn floor(_: &Lua, v: Value) -> mlua::Result<LuaVector> {
+  Ok(LuaVector(get_vector(&v)?.floor()))
+}
+
+fn copy(_: &Lua, v: Value) -> mlua::Result<LuaVector> {
+  Ok(LuaVector(get_vector(&v)?))
+}
+
+///
+/// vector.new(x, y, z), vector.new(v) or vector.new() for zero.
+///
+fn new(_: &Lua, (x, y, z): (Value, Option<f32>, Option<f32>)) -> mlua::Result<LuaVector> {
+  match (x, y, z) {
+    (Value::Nil, None, None) => Ok(LuaVector(Vec3::ZERO)),
+    (x, Some(y), Some(z)) => match x {
+      Value::Integer(x) => Ok(LuaVector(Vec3::new(x as f32, y, z))),
+      Value::Number(x) => Ok(LuaVector(Vec3::new(x as f32, y, z))),
+      x => Err(mlua::Error::runtime(format!(
+        "vector.new: x must be a number, got [{}]",
+        x.type_name()
+      ))),
+    },
+    (v, None, None) => Ok(LuaVector(get_vector(&v)?)),
+    _ => Err(mlua::Error::runtime(
+      "vector.new: takes (x, y, z), a vector, or nothing",
+    )),
+  }
+}
+
+///
+/// Put the vector table into the globals of a Lua VM.
+///
+pub fn register_vector_api(lua: &Lua) -> mlua::Result<()> {
+  let vector = lua.create_table()?;
+
+  vector.set("new", lua.create_function(new)?)?;
+  vector.set(
+    "zero",
+    lua.create_function(|_, ()| Ok(LuaVector(Vec3::ZERO)))?,
+  )?;
+  vector.set("distance", lua.create_function(distance)?)?;
+  vector.set("length", lua.create_function(length)?)?;
+  vector.set("normalize", lua.create_function(normalize)?)?;
+  vector.set("cross", lua.create_function(cross)?)?;
+  vector.set("dot", lua.create_function(dot)?)?;
+  vector.set("round", lua.create_function(round)?)?;
+  vector.set("floor", lua.create_function(floor)?)?;
+  vector.set("copy", lua.create_function(copy)?)?;
+
+  lua.globals().set("vector", vector)
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{vec3, Vec3};
+  use mlua::{Function, Lua, Value};
+
+  use super::{get_vector, register_vector_api, LuaVector};
+
+  fn create_lua() -> Lua {
+    let lua = Lua::new();
+    if let Err(e) = register_vector_api(&lua) {
+      panic!("{}", e);
+    }
+    lua
+  }
+
+  #[test]
+  fn vectors_work_from_rust() {
+    let lua = create_lua();
+
+    let get_function = |name: &str| match lua
+      .load(format!("return vector.{}", name))
+      .eval::<Function>()
+    {
+      Ok(function) => function,
+      Err(e) => panic!("{}", e),
+    };
+    let a = LuaVector(vec3(1.0, 2.0, 3.0));
+    let b = LuaVector(vec3(4.0, 6.0, 3.0));
+
+    let call_number =
+      |name: &str, args: (LuaVector, LuaVector)| match get_function(name).call::<_, f32>(args) {
+        Ok(result) => result,
+        Err(e) => panic!("{}", e),
+      };
+    let call_vector = |name: &str, v: LuaVector| match get_function(name).call::<_, LuaVector>(v) {
+      Ok(LuaVector(result)) => result,
+      Err(e) => panic!("{}", e),
+    };
+
+    assert_eq!(call_number("distance", (a, b)), 5.0);
+    assert_eq!(call_number("dot", (a, b)), 25.0);
+    assert!(matches!(
+      get_function("cross").call::<_, LuaVector>((a, b)),
+      Ok(LuaVector(cross)) if cross == vec3(-12.0, 9.0, -2.0)
+    ));
+
+    let halves = LuaVector(vec3(0.5, -0.5, 1.4));
+    assert_eq!(call_vector("normalize", LuaVector(Vec3::ZERO)), Vec3::ZERO);
+    assert_eq!(call_vector("round", halves), vec3(1.0, -1.0, 1.0));
+    assert_eq!(call_vector("floor", halves), vec3(0.0, -1.0, 1.0));
+
+    // Old style {x, y, z} tables still count as vectors.
+    let table = match lua.load("return {x = 1, y = 2, z = 3}").eval::<Value>() {
+      Ok(table) => table,
+      Err(e) => panic!("{}", e),
+    };
+    assert!(matches!(get_vector(&table), Ok(v) if v == a.0));
+    let userdata = match lua.create_userdata(b) {
+      Ok(userdata) => Value::UserData(userdata),
+      Err(e) => panic!("{}", e),
+    };
+    assert!(matches!(get_vector(&userdata), Ok(v) if v == b.0));
+  }
+
+  #[test]
+  fn vectors_work_from_lua() {
+    let lua = create_lua();
+
+    let result = lua
+      .load(
+        r#"
+        local a = vector.new(1, 2, 3)
+        local b = vector.new({x = 4, y = 6, z = 3})
+        assert(a + b == vector.new(5, 8, 6))
+        assert(b - a == vector.new(3, 4, 0))
+        assert(a * 2 == vector.new(2, 4, 6) and 2 * a == a * 2)
+        assert(a * b == vector.new(4, 12, 9))
+        assert(b / 2 == vector.new(2, 3, 1.5))
+        assert(-a == vector.new(-1, -2, -3))
+        assert(a + {x = 1, y = 1, z = 1} == vector.new(2, 3, 4))
+        assert(a:distance(b) == 5 and vector.distance(a, b) == 5)
+        assert(a:dot(b) == 25)
+        assert(a:cross(b) == vector.new(-12, 9, -2))
+        assert(vector.new(3, 0, 4):length() == 5)
+        assert(vector.new(0, 0, 5):normalize() == vector.new(0, 0, 1))
+        assert(vector.new(0.5, -0.5, 1.4):round() == vector.new(1, -1, 1))
+        assert(vector.floor(vector.new(0.5, -0.5, 1.4)) == vector.new(0, -1, 1))
+        assert(tostring(a) == "(1, 2, 3)")
+        assert(vector.new() == vector.zero())
+
+        local c = a:copy()
+        c.x = 10
+        assert(c.x == 10 and a.x == 1)
+        assert(not pcall(vector.new, 1, 2))
+        "#,
+      )
+      .exec();
+    if let Err(e) = result {
+      panic!("{}", e);
+    }
+  }
+}
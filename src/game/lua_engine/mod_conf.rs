@@ -0,0 +1,240 @@
+///
+/// This module reads each mod's mod.conf and works out what order mods load in.
+///
+/// A mod always loads after everything it depends on. Mods are walked in name order,
+/// so the load order is the same every time no matter what read_dir returns.
+///
+use ahash::{AHashMap, AHashSet};
+use configparser::ini::Ini;
+
+use crate::file_utilities::read_file_to_string;
+
+use super::lua_file_helpers::ModDirectory;
+
+///
+/// The parsed mod.conf of a mod.
+///
+/// * name             - Falls back to the folder name if mod.conf leaves it out.
+/// * depends          - Mods which have to be loaded first. The game won't load without them.
+/// * optional_depends - Mods which get loaded first if the game has them.
+/// * mod_path         - The folder the mod lives in.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModConf {
+  pub name: String,
+  pub depends: Vec<String>,
+  pub optional_depends: Vec<String>,
+  pub mod_path: String,
+}
+
+impl ModConf {
+  ///
+  /// Parse the raw contents of a mod.conf.
+  ///
+  pub fn from_string(raw_conf: String, mod_directory: &ModDirectory) -> Result<Self, String> {
+    let mut config = Ini::new();
+    if let Err(e) = config.read(raw_conf) {
+      return Err(format!(
+        "ModConf: mod.conf of [{}] is malformed. {}",
+        mod_directory.mod_name, e
+      ));
+    }
+
+    // Keys without a [section] land in the default section.
+    let name = config
+      .get("default", "name")
+      .unwrap_or(mod_directory.mod_name.clone());
+    if name.is_empty()
+      || !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+      return Err(format!(
+        "ModConf: [{}] is not a valid mod name. Only a-z, 0-9 and _ are allowed.",
+        name
+      ));
+    }
+
+    Ok(ModConf {
+      name,
+      depends: get_mod_list(&config, "depends"),
+      optional_depends: get_mod_list(&config, "optional_depends"),
+      mod_path: mod_directory.mod_path.clone(),
+    })
+  }
+
+  ///
+  /// Read the mod.conf out of a mod's folder.
+  ///
+  pub fn load(mod_directory: &ModDirectory) -> Result<Self, String> {
+    let mut conf_path = mod_directory.mod_path.clone();
+    conf_path.push_str("/mod.conf");
+
+    match read_file_to_string(&conf_path) {
+      Ok(raw_conf) => Self::from_string(raw_conf, mod_directory),
+      Err(e) => Err(format!("ModConf: {}", e)),
+    }
+  }
+}
+
+///
+/// Split a comma separated list of mod names.
+///
+fn get_mod_list(config: &Ini, key: &str) -> Vec<String> {
+  match config.get("default", key) {
+    Some(mod_list) => mod_list
+      .split(',')
+      .map(|mod_name| mod_name.trim().to_string())
+      .filter(|mod_name| !mod_name.is_empty())
+      .collect(),
+    None => vec![],
+  }
+}
+
+///
+/// Put mods into load order. (topological sorting, depth first)
+///
+/// Missing hard dependencies and dependency cycles are errors naming every mod involved.
+/// Optional dependencies which aren't in the game are skipped.
+///
+pub fn sort_mods(mut mods: Vec<ModConf>) -> Result<Vec<ModConf>, String> {
+  mods.sort_by(|a, b| a.name.cmp(&b.name));
+
+  let mut indices = AHashMap::new();
+  for (index, mod_conf) in mods.iter().enumerate() {
+    if indices.insert(mod_conf.name.clone(), index).is_some() {
+      return Err(format!(
+        "ModConf: there's more than one mod named [{}].",
+        mod_conf.name
+      ));
+    }
+  }
+
+  let mut missing = vec![];
+  for mod_conf in &mods {
+    for dependency in &mod_conf.depends {
+      if !indices.contains_key(dependency) {
+        missing.push(format!("[{}] needs [{}]", mod_conf.name, dependency));
+      }
+    }
+  }
+  if !missing.is_empty() {
+    return Err(format!(
+      "ModConf: missing dependencies. {}",
+      missing.join(", ")
+    ));
+  }
+
+  let mut sorted = Vec::with_capacity(mods.len());
+  let mut done = AHashSet::new();
+  let mut path = vec![];
+  for index in 0..mods.len() {
+    visit(&mods, &indices, index, &mut done, &mut path, &mut sorted)?;
+  }
+
+  Ok(
+    sorted
+      .into_iter()
+      .map(|index| mods[index].clone())
+      .collect(),
+  )
+}
+
+///
+/// Depth first walk of a mod's dependencies. path is the chain of mods being visited,
+/// so running into one of them again means there's a cycle.
+///
+fn visit(
+  mods: &[ModConf],
+  indices: &AHashMap<String, usize>,
+  index: usize,
+  done: &mut AHashSet<usize>,
+  path: &mut Vec<usize>,
+  sorted: &mut Vec<usize>,
+) -> Result<(), String> {
+  if done.contains(&index) {
+    return Ok(());
+  }
+
+  if let Some(start) = path.iter().position(|visiting| *visiting == index) {
+    let cycle: Vec<&str> = path[start..]
+      .iter()
+      .chain([&index])
+      .map(|index| mods[*index].name.as_str())
+      .collect();
+    return Err(format!("ModConf: dependency cycle. {}", cycle.join(" -> ")));
+  }
+
+  path.push(index);
+  let mod_conf = &mods[index];
+  for dependency in mod_conf.depends.iter().chain(&mod_conf.optional_depends) {
+    if let Some(dependency_index) = indices.get(dependency) {
+      visit(mods, indices, *dependency_index, done, path, sorted)?;
+    }
+  }
+  path.pop();
+
+  done.insert(index);
+  sorted.push(index);
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{sort_mods, ModConf};
+  use crate::game::lua_engine::lua_file_helpers::ModDirectory;
+
+  fn mod_conf(folder_name: &str, raw_conf: &str) -> ModConf {
+    let mod_directory = ModDirectory {
+      mod_name: folder_name.to_string(),
+      mod_path: format!("./mods/{}", folder_name),
+    };
+    match ModConf::from_string(raw_conf.to_string(), &mod_directory) {
+      Ok(mod_conf) => mod_conf,
+      Err(e) => panic!("{}", e),
+    }
+  }
+
+  fn load_order(mods: Vec<ModConf>) -> Result<Vec<String>, String> {
+    sort_mods(mods).map(|mods| mods.into_iter().map(|mod_conf| mod_conf.name).collect())
+  }
+
+  #[test]
+  fn mods_load_after_their_dependencies() {
+    let farming = mod_conf("farming", "name = farming\ndepends = default, bucket");
+    assert_eq!(farming.depends, vec!["default", "bucket"]);
+    assert_eq!(mod_conf("default", "").name, "default");
+
+    let order = load_order(vec![
+      farming,
+      mod_conf("bucket", "name = bucket\ndepends = default"),
+      mod_conf("default", "name = default\noptional_depends = not_in_game"),
+      mod_conf("aaa", "name = aaa\noptional_depends = farming"),
+    ]);
+    assert_eq!(
+      order,
+      Ok(
+        ["default", "bucket", "farming", "aaa"]
+          .map(String::from)
+          .to_vec()
+      )
+    );
+
+    let missing = load_order(vec![mod_conf("farming", "depends = default")]);
+    assert_eq!(
+      missing,
+      Err("ModConf: missing dependencies. [farming] needs [default]".to_string())
+    );
+
+    let cycle = load_order(vec![
+      mod_conf("a", "depends = b"),
+      mod_conf("b", "depends = c"),
+      mod_conf("c", "optional_depends = a"),
+    ]);
+    assert_eq!(
+      cycle,
+      Err("ModConf: dependency cycle. a -> b -> c -> a".to_string())
+    );
+  }
+}
@@ -0,0 +1,373 @@
+///
+/// This module is the require() that mods get.
+///
+/// Every module is known as modname:path, which is the file path.lua in that mod's folder.
+/// * require("path")          - A file in the calling mod's folder.
+/// * require("othermod:path") - A file in another mod's folder. Only for mods in depends or optional_depends.
+/// * require("api/api")       - The engine API, the minetest table.
+///
+/// A mod's main.lua gets loaded through here too, so whatever it returns is
+/// what the mod exports to others as modname:main.
+///
+/// Modules run once per VM in the environment of the mod they belong to,
+/// and the result is cached for every later require.
+///
+use ahash::{AHashMap, AHashSet};
+use mlua::{Lua, Table, Value};
+
+use crate::file_utilities::{file_exists, read_file_to_string};
+
+use super::mod_conf::ModConf;
+
+///
+/// Registry keys of the mod environments and the module cache.
+///
+const ENVIRONMENTS_KEY: &str = "minetest_mod_environments";
+const MODULES_KEY: &str = "minetest_modules";
+
+///
+/// The module name of the engine API.
+///
+pub const ENGINE_API_MODULE: &str = "api/api";
+
+struct LoaderMod {
+  mod_path: String,
+  dependencies: AHashSet<String>,
+}
+
+///
+/// Lives in the app data of a Lua VM, so require() can get to it.
+///
+/// loading holds the modules which are partway through running, to catch require loops.
+///
+pub struct ModuleLoader {
+  mods: AHashMap<String, LoaderMod>,
+  loading: AHashSet<String>,
+}
+
+///
+/// Install a fresh ModuleLoader into a Lua VM.
+///
+pub fn install_module_loader(lua: &Lua) -> mlua::Result<()> {
+  lua.set_app_data(ModuleLoader {
+    mods: AHashMap::new(),
+    loading: AHashSet::new(),
+  });
+  lua.set_named_registry_value(ENVIRONMENTS_KEY, lua.create_table()?)?;
+  lua.set_named_registry_value(MODULES_KEY, lua.create_table()?)
+}
+
+///
+/// Hand a mod its environment and give it a require() of its own.
+///
+pub fn register_mod(lua: &Lua, mod_conf: &ModConf, environment: Table) -> mlua::Result<()> {
+  let mod_name = mod_conf.name.clone();
+  environment.raw_set(
+    "require",
+    lua.create_function(move |lua, module_name: String| {
+      require_module(lua, &mod_name, &module_name)
+    })?,
+  )?;
+
+  let environments: Table = lua.named_registry_value(ENVIRONMENTS_KEY)?;
+  environments.set(mod_conf.name.as_str(), environment)?;
+
+  match lua.app_data_mut::<ModuleLoader>() {
+    Some(mut loader) => {
+      loader.mods.insert(
+        mod_conf.name.clone(),
+        LoaderMod {
+          mod_path: mod_conf.mod_path.clone(),
+          dependencies: mod_conf
+            .depends
+            .iter()
+            .chain(&mod_conf.optional_depends)
+            .cloned()
+            .collect(),
+        },
+      );
+      Ok(())
+    }
+    None => Err(mlua::Error::runtime("ModuleLoader is not installed.")),
+  }
+}
+
+///
+/// Check that a path a mod handed in stays inside the folder it gets joined onto.
+///
+/// Empty paths, absolute paths and paths with .. in them are turned away.
+///
+pub fn is_valid_mod_file_path(path: &str) -> bool {
+  !(path.is_empty() || path.starts_with('/') || path.split('/').any(|part| part == ".."))
+}
+
+///
+/// Where a file a mod names lives, inside of that mod's folder.
+///
+pub fn get_mod_file_path(lua: &Lua, mod_name: &str, path: &str) -> Result<String, String> {
+  if !is_valid_mod_file_path(path) {
+    return Err(format!(
+      "[{}] is not a valid file. It has to be a path inside of mod [{}]'s folder.",
+      path, mod_name
+    ));
+  }
+
+  let loader = match lua.app_data_ref::<ModuleLoader>() {
+    Some(loader) => loader,
+    None => return Err("ModuleLoader is not installed.".to_string()),
+  };
+
+  match loader.mods.get(mod_name) {
+    Some(loader_mod) => Ok(format!("{}/{}", loader_mod.mod_path, path)),
+    None => Err(format!("there's no mod named [{}].", mod_name)),
+  }
+}
+
+///
+/// Load a module on behalf of a mod. Modules only ever run once.
+///
+pub fn require_module<'lua>(
+  lua: &'lua Lua,
+  from_mod: &str,
+  module_name: &str,
+) -> mlua::Result<Value<'lua>> {
+  if module_name == ENGINE_API_MODULE {
+    return lua.globals().get("minetest");
+  }
+
+  let (mod_name, path) = match module_name.split_once(':') {
+    Some((mod_name, path)) => (mod_name, path),
+    None => (from_mod, module_name),
+  };
+
+  if !is_valid_mod_file_path(path) {
+    return Err(mlua::Error::runtime(format!(
+      "require: [{}] is not a valid module name.",
+      module_name
+    )));
+  }
+
+  let file_location = {
+    let loader = match lua.app_data_ref::<ModuleLoader>() {
+      Some(loader) => loader,
+      None => return Err(mlua::Error::runtime("ModuleLoader is not installed.")),
+    };
+
+    if mod_name != from_mod {
+      let from = loader.mods.get(from_mod);
+      if !from.is_some_and(|from| from.dependencies.contains(mod_name)) {
+        return Err(mlua::Error::runtime(format!(
+          "require: mod [{}] can't require [{}], [{}] isn't in its mod.conf depends.",
+          from_mod, module_name, mod_name
+        )));
+      }
+    }
+
+    match loader.mods.get(mod_name) {
+      Some(loader_mod) => format!("{}/{}.lua", loader_mod.mod_path, path),
+      None => {
+        return Err(mlua::Error::runtime(format!(
+          "require: there's no mod named [{}].",
+          mod_name
+        )))
+      }
+    }
+  };
+
+  let key = format!("{}:{}", mod_name, path);
+  let modules: Table = lua.named_registry_value(MODULES_KEY)?;
+  let cached: Value = modules.get(key.as_str())?;
+  if !cached.is_nil() {
+    return Ok(cached);
+  }
+
+  if !file_exists(&file_location) {
+    return Err(mlua::Error::runtime(format!(
+      "require: can't find module [{}] at [{}].",
+      key, file_location
+    )));
+  }
+
+  let raw_code = match read_file_to_string(&file_location) {
+    Ok(raw_code) => raw_code,
+    Err(e) => return Err(mlua::Error::runtime(format!("require: {}", e))),
+  };
+
+  let environments: Table = lua.named_registry_value(ENVIRONMENTS_KEY)?;
+  let environment: Table = environments.get(mod_name)?;
+
+  set_loading(lua, &key, true)?;
+  let result = lua
+    .load(raw_code)
+    .set_name(format!("@{}", file_location))
+    .set_environment(environment)
+    .call::<_, Value>(());
+  set_loading(lua, &key, false)?;
+
+  // Just like Lua's own require, a module which returns nothing is cached as true.
+  let module = match result? {
+    Value::Nil => Value::Boolean(true),
+    module => module,
+  };
+  modules.set(key.as_str(), module.clone())?;
+
+  Ok(module)
+}
+
+///
+/// Mark a module as partway through running.
+///
+/// Starting a module which is already running means the modules require each other.
+///
+fn set_loading(lua: &Lua, key: &str, loading: bool) -> mlua::Result<()> {
+  let mut loader = match lua.app_data_mut::<ModuleLoader>() {
+    Some(loader) => loader,
+    None => return Err(mlua::Error::runtime("ModuleLoader is not installed.")),
+  };
+
+  if !loading {
+    loader.loading.remove(key);
+  } else if !loader.loading.insert(key.to_string()) {
+    return Err(mlua::Error::runtime(format!(
+      "require: [{}] requires itself through a loop.",
+      key
+    )));
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::{create_dir_all, remove_dir_all, write};
+
+  use mlua::{Lua, Value};
+
+  use super::{
+    get_mod_file_path, install_module_loader, is_valid_mod_file_path, register_mod, require_module,
+  };
+  use crate::game::lua_engine::mod_conf::ModConf;
+
+  fn create_loader() -> Lua {
+    let lua = Lua::new();
+    if let Err(e) = install_module_loader(&lua) {
+      panic!("{}", e);
+    }
+    lua
+  }
+
+  fn add_test_mod(lua: &Lua, mods_dir: &str, name: &str, depends: &[&str]) {
+    let mod_conf = ModConf {
+      name: name.to_string(),
+      depends: depends.iter().map(|depend| depend.to_string()).collect(),
+      optional_depends: vec![],
+      mod_path: format!("{}/{}", mods_dir, name),
+    };
+    let environment = match lua.create_table() {
+      Ok(environment) => environment,
+      Err(e) => panic!("{}", e),
+    };
+    if let Err(e) = register_mod(lua, &mod_conf, environment) {
+      panic!("{}", e);
+    }
+  }
+
+  fn require_error(lua: &Lua, from_mod: &str, module_name: &str) -> String {
+    match require_module(lua, from_mod, module_name) {
+      Ok(_) => panic!("[{}] required [{}]", from_mod, module_name),
+      Err(e) => e.to_string(),
+    }
+  }
+
+  #[test]
+  fn mod_file_paths_stay_inside_the_mod() {
+    assert!(is_valid_mod_file_path("helper"));
+    assert!(is_valid_mod_file_path("schematics/oak.mts"));
+    assert!(is_valid_mod_file_path("schematics/..oak.mts"));
+    assert!(!is_valid_mod_file_path(""));
+    assert!(!is_valid_mod_file_path(".."));
+    assert!(!is_valid_mod_file_path("../other/main"));
+    assert!(!is_valid_mod_file_path("schematics/../../other/main"));
+    assert!(!is_valid_mod_file_path("/etc/passwd"));
+  }
+
+  #[test]
+  fn mod_files_resolve_inside_their_mod() {
+    let lua = create_loader();
+    add_test_mod(&lua, "./mods", "trees", &[]);
+
+    assert_eq!(
+      get_mod_file_path(&lua, "trees", "schematics/oak.mts"),
+      Ok("./mods/trees/schematics/oak.mts".to_string())
+    );
+    assert!(get_mod_file_path(&lua, "trees", "../other/secret.mts").is_err());
+    assert!(get_mod_file_path(&lua, "trees", "/etc/passwd").is_err());
+    assert!(get_mod_file_path(&lua, "flowers", "schematics/rose.mts").is_err());
+  }
+
+  #[test]
+  fn modules_run_once_and_only_for_their_dependencies() {
+    let mods_dir = std::env::temp_dir().join(format!(
+      "minetest_module_loader_test_{}",
+      std::process::id()
+    ));
+    let files = [
+      (
+        "farming/counter.lua",
+        "loads = (loads or 0) + 1\nreturn loads",
+      ),
+      ("farming/loop_a.lua", "return require(\"loop_b\")"),
+      ("farming/loop_b.lua", "return require(\"loop_a\")"),
+      ("default/main.lua", "return \"exported\""),
+      ("stranger/main.lua", "return \"hidden\""),
+    ];
+    for (file, raw_code) in files {
+      let path = mods_dir.join(file);
+      if let Some(parent) = path.parent() {
+        if let Err(e) = create_dir_all(parent) {
+          panic!("{}", e);
+        }
+      }
+      if let Err(e) = write(path, raw_code) {
+        panic!("{}", e);
+      }
+    }
+
+    let lua = create_loader();
+    let mods_path = mods_dir.to_string_lossy().to_string();
+    add_test_mod(&lua, &mods_path, "farming", &["default"]);
+    add_test_mod(&lua, &mods_path, "default", &[]);
+    add_test_mod(&lua, &mods_path, "stranger", &[]);
+
+    // The second require gets the cached result instead of running the file again.
+    for _ in 0..2 {
+      match require_module(&lua, "farming", "counter") {
+        Ok(Value::Integer(loads)) => assert_eq!(loads, 1),
+        Ok(Value::Number(loads)) => assert_eq!(loads, 1.0),
+        Ok(value) => panic!("counter returned {:?}", value),
+        Err(e) => panic!("{}", e),
+      }
+    }
+
+    // Only mods in depends can be required.
+    match require_module(&lua, "farming", "default:main") {
+      Ok(Value::String(exported)) => assert_eq!(exported.to_string_lossy(), "exported"),
+      Ok(value) => panic!("default:main returned {:?}", value),
+      Err(e) => panic!("{}", e),
+    }
+    assert!(
+      require_error(&lua, "farming", "stranger:main").contains("isn't in its mod.conf depends")
+    );
+    assert!(require_error(&lua, "farming", "../stranger/main").contains("not a valid module name"));
+
+    // Modules which require each other get caught, and can be tried again afterwards.
+    for _ in 0..2 {
+      assert!(require_error(&lua, "farming", "loop_a")
+        .contains("[farming:loop_a] requires itself through a loop"));
+    }
+
+    if let Err(e) = remove_dir_all(mods_dir) {
+      panic!("{}", e);
+    }
+  }
+}
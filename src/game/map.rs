@@ -0,0 +1,234 @@
+// The map is the voxel world itself.
+//
+// Everything in here is shared between the Server and the Client.
+// The Server generates and owns the authoritative map, the Client
+// simply holds a cache of what the Server sent it.
+//
+// The map is broken up into Chunks. A Chunk is a 16x16x16 cube of nodes.
+
+pub mod block_registry;
+pub mod chunk;
+pub mod lighting;
+pub mod node_meta;
+pub mod node_timer;
+pub mod raycast;
+pub mod region;
+pub mod schematic;
+
+use ahash::AHashMap;
+use glam::IVec3;
+
+use self::{
+  chunk::{world_to_chunk_position, world_to_local_position, Chunk, Node},
+  node_meta::NodeMeta,
+  node_timer::NodeTimer,
+};
+
+///
+/// The container for every Chunk which is currently in memory.
+///
+/// Map does not know how Chunks get here. The Server fills it from the
+/// emerge workers, and the Client fills it from the network.
+///
+pub struct Map {
+  chunks: AHashMap<IVec3, Chunk>,
+}
+
+impl Map {
+  pub fn new() -> Self {
+    Map {
+      chunks: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Check if a Chunk is in memory.
+  ///
+  pub fn has_chunk(&self, chunk_position: IVec3) -> bool {
+    self.chunks.contains_key(&chunk_position)
+  }
+
+  ///
+  /// Borrow a Chunk.
+  ///
+  pub fn get_chunk(&self, chunk_position: IVec3) -> Option<&Chunk> {
+    self.chunks.get(&chunk_position)
+  }
+
+  ///
+  /// Borrow a Chunk mutably.
+  ///
+  pub fn get_chunk_mut(&mut self, chunk_position: IVec3) -> Option<&mut Chunk> {
+    self.chunks.get_mut(&chunk_position)
+  }
+
+  ///
+  /// Put a Chunk into the map. Replaces the old one if it exists.
+  ///
+  pub fn insert_chunk(&mut self, chunk: Chunk) {
+    self.chunks.insert(chunk.get_position(), chunk);
+  }
+
+  ///
+  /// Take a Chunk out of the map.
+  ///
+  pub fn remove_chunk(&mut self, chunk_position: IVec3) -> Option<Chunk> {
+    self.chunks.remove(&chunk_position)
+  }
+
+  ///
+  /// Get the position of every Chunk in memory.
+  ///
+  pub fn get_chunk_positions(&self) -> Vec<IVec3> {
+    self.chunks.keys().copied().collect()
+  }
+
+  ///
+  /// Iterate every Chunk in memory.
+  ///
+  pub fn get_chunks(&self) -> impl Iterator<Item = &Chunk> {
+    self.chunks.values()
+  }
+
+  ///
+  /// How many Chunks are in memory.
+  ///
+  pub fn get_chunk_count(&self) -> usize {
+    self.chunks.len()
+  }
+
+  ///
+  /// Get a Node at a world position.
+  ///
+  /// Returns None if the Chunk is not in memory.
+  ///
+  pub fn get_node(&self, world_position: IVec3) -> Option<Node> {
+    self
+      .chunks
+      .get(&world_to_chunk_position(world_position))
+      .map(|chunk| chunk.get_node(world_to_local_position(world_position)))
+  }
+
+  ///
+  /// Set a Node at a world position.
+  ///
+  /// Returns false if the Chunk is not in memory.
+  ///
+  pub fn set_node(&mut self, world_position: IVec3, node: Node) -> bool {
+    match self
+      .chunks
+      .get_mut(&world_to_chunk_position(world_position))
+    {
+      Some(chunk) => {
+        chunk.set_node(world_to_local_position(world_position), node);
+        true
+      }
+      None => false,
+    }
+  }
+
+  ///
+  /// Get the light param at a world position.
+  ///
+  /// Returns None if the Chunk is not in memory.
+  ///
+  pub fn get_light(&self, world_position: IVec3) -> Option<u8> {
+    self
+      .chunks
+      .get(&world_to_chunk_position(world_position))
+      .map(|chunk| chunk.get_light(world_to_local_position(world_position)))
+  }
+
+  ///
+  /// Set the light param at a world position.
+  ///
+  /// Returns false if the Chunk is not in memory.
+  ///
+  pub fn set_light(&mut self, world_position: IVec3, light: u8) -> bool {
+    match self
+      .chunks
+      .get_mut(&world_to_chunk_position(world_position))
+    {
+      Some(chunk) => {
+        chunk.set_light(world_to_local_position(world_position), light);
+        true
+      }
+      None => false,
+    }
+  }
+
+  ///
+  /// Get the NodeMeta at a world position, if it has any.
+  ///
+  pub fn get_node_meta(&self, world_position: IVec3) -> Option<&NodeMeta> {
+    self
+      .chunks
+      .get(&world_to_chunk_position(world_position))
+      .and_then(|chunk| chunk.get_node_meta(world_to_local_position(world_position)))
+  }
+
+  ///
+  /// Get the NodeMeta at a world position to modify it.
+  /// It gets created if it doesn't exist.
+  ///
+  /// Returns None if the Chunk is not in memory.
+  ///
+  pub fn get_node_meta_mut(&mut self, world_position: IVec3) -> Option<&mut NodeMeta> {
+    self
+      .chunks
+      .get_mut(&world_to_chunk_position(world_position))
+      .map(|chunk| chunk.get_node_meta_mut(world_to_local_position(world_position)))
+  }
+
+  ///
+  /// Remove the NodeMeta at a world position.
+  ///
+  pub fn remove_node_meta(&mut self, world_position: IVec3) {
+    if let Some(chunk) = self
+      .chunks
+      .get_mut(&world_to_chunk_position(world_position))
+    {
+      chunk.remove_node_meta(world_to_local_position(world_position));
+    }
+  }
+
+  ///
+  /// Get the NodeTimer at a world position, if one is running.
+  ///
+  pub fn get_node_timer(&self, world_position: IVec3) -> Option<NodeTimer> {
+    self
+      .chunks
+      .get(&world_to_chunk_position(world_position))
+      .and_then(|chunk| chunk.get_node_timer(world_to_local_position(world_position)))
+  }
+
+  ///
+  /// Start or replace the NodeTimer at a world position.
+  ///
+  /// Returns false if the Chunk is not in memory.
+  ///
+  pub fn set_node_timer(&mut self, world_position: IVec3, node_timer: NodeTimer) -> bool {
+    match self
+      .chunks
+      .get_mut(&world_to_chunk_position(world_position))
+    {
+      Some(chunk) => {
+        chunk.set_node_timer(world_to_local_position(world_position), node_timer);
+        true
+      }
+      None => false,
+    }
+  }
+
+  ///
+  /// Stop the NodeTimer at a world position.
+  ///
+  pub fn remove_node_timer(&mut self, world_position: IVec3) {
+    if let Some(chunk) = self
+      .chunks
+      .get_mut(&world_to_chunk_position(world_position))
+    {
+      chunk.remove_node_timer(world_to_local_position(world_position));
+    }
+  }
+}
@@ -0,0 +1,504 @@
+use std::collections::BTreeMap;
+
+use ahash::AHashMap;
+use mlua::Table;
+
+use crate::game::lua_engine::lua_table_helpers::{get_field, get_field_or};
+
+///
+/// Air is always the first block. It is built into the engine.
+///
+pub const AIR_ID: u16 = 0;
+pub const AIR_NAME: &str = "air";
+
+///
+/// The name given to nodes in Chunks which are not in memory.
+/// It is never registered, it only exists so Lua can tell.
+///
+pub const IGNORE_NAME: &str = "ignore";
+
+///
+/// The ID saved nodes get when their block isn't registered anymore.
+/// Like ignore itself, it never has a definition.
+///
+pub const IGNORE_ID: u16 = u16::MAX;
+
+///
+/// The brightest a block can glow. Sunlight is one brighter than this.
+///
+pub const LIGHT_SOURCE_MAX: u8 = 14;
+
+///
+/// Mirrors minetest.draw_type in api.lua.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawType {
+  Air,
+  Regular,
+  BlockBox,
+  Mesh,
+}
+
+impl DrawType {
+  pub fn from_number(number: u8) -> Result<Self, String> {
+    match number {
+      0 => Ok(DrawType::Air),
+      1 => Ok(DrawType::Regular),
+      2 => Ok(DrawType::BlockBox),
+      3 => Ok(DrawType::Mesh),
+      _ => Err(format!("invalid draw type [{}]", number)),
+    }
+  }
+}
+
+///
+/// The fullest a flowing liquid gets. Flowing liquid levels go from 0 to this.
+///
+pub const LIQUID_LEVEL_MAX: u8 = 7;
+
+///
+/// Mirrors liquidtype in a Lua BlockDefinition.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LiquidType {
+  None,
+  Source,
+  Flowing,
+}
+
+impl LiquidType {
+  pub fn from_name(name: &str) -> Result<Self, String> {
+    match name {
+      "none" => Ok(LiquidType::None),
+      "source" => Ok(LiquidType::Source),
+      "flowing" => Ok(LiquidType::Flowing),
+      _ => Err(format!("invalid liquidtype [{}]", name)),
+    }
+  }
+}
+
+///
+/// The engine side of a Lua BlockDefinition.
+///
+/// Only the data the engine needs to work with is stored here.
+/// Callbacks and anything else mods want stay in Lua.
+///
+/// * light_source               - How bright the block glows. (0 to LIGHT_SOURCE_MAX)
+/// * light_propagates           - If light can pass through the block.
+/// * sunlight_propagates        - If sunlight passes straight down through the block without dimming.
+/// * pointable                  - If raycasts (and so players) can point at the block.
+/// * liquid_type                - If the block is a liquid, and which kind.
+/// * liquid_alternative_source  - The source block of this liquid.
+/// * liquid_alternative_flowing - The flowing block of this liquid.
+/// * liquid_viscosity           - How slowly the liquid flows. 0 and 1 are full speed.
+/// * liquid_range               - How many nodes out the liquid flows. (1 to LIQUID_LEVEL_MAX + 1)
+/// * liquid_renewable           - If two sources next to each other create new sources.
+/// * groups                     - Group ratings, like cracky = 3. Tools dig by these.
+///
+#[derive(Clone, Debug)]
+pub struct BlockDefinition {
+  pub name: String,
+  pub description: String,
+  pub draw_type: DrawType,
+  pub light_source: u8,
+  pub light_propagates: bool,
+  pub sunlight_propagates: bool,
+  pub pointable: bool,
+  pub liquid_type: LiquidType,
+  pub liquid_alternative_source: String,
+  pub liquid_alternative_flowing: String,
+  pub liquid_viscosity: u8,
+  pub liquid_range: u8,
+  pub liquid_renewable: bool,
+  pub groups: BTreeMap<String, i32>,
+}
+
+impl BlockDefinition {
+  ///
+  /// A plain solid block. Tests change what they need with struct update syntax.
+  ///
+  #[cfg(test)]
+  pub fn test(name: &str) -> Self {
+    BlockDefinition {
+      name: name.to_string(),
+      description: name.to_string(),
+      draw_type: DrawType::Regular,
+      light_source: 0,
+      light_propagates: false,
+      sunlight_propagates: false,
+      pointable: true,
+      liquid_type: LiquidType::None,
+      liquid_alternative_source: String::new(),
+      liquid_alternative_flowing: String::new(),
+      liquid_viscosity: 0,
+      liquid_range: 8,
+      liquid_renewable: true,
+      groups: BTreeMap::new(),
+    }
+  }
+
+  ///
+  /// Parse a BlockDefinition out of a Lua table from minetest.register_block.
+  ///
+  pub fn from_lua_table(table: &Table) -> Result<Self, String> {
+    let name: String = get_field(table, "name")?;
+
+    let draw_type = DrawType::from_number(get_field(table, "drawtype")?)
+      .map_err(|e| format!("block [{}] {}", name, e))?;
+
+    let description = get_field_or(table, "description", name.clone())?;
+
+    let light_source: u8 = get_field_or(table, "light_source", 0)?;
+    if light_source > LIGHT_SOURCE_MAX {
+      return Err(format!(
+        "block [{}] light_source [{}] is brighter than the max of [{}].",
+        name, light_source, LIGHT_SOURCE_MAX
+      ));
+    }
+
+    // Regular blocks are full cubes, nothing gets through them by default.
+    let light_propagates = get_field_or(table, "light_propagates", draw_type != DrawType::Regular)?;
+    let sunlight_propagates =
+      get_field_or(table, "sunlight_propagates", draw_type == DrawType::Air)?;
+
+    let pointable = get_field_or(table, "pointable", draw_type != DrawType::Air)?;
+
+    let liquid_type =
+      LiquidType::from_name(&get_field_or(table, "liquidtype", "none".to_string())?)
+        .map_err(|e| format!("block [{}] {}", name, e))?;
+
+    let liquid_range: u8 = get_field_or(table, "liquid_range", LIQUID_LEVEL_MAX + 1)?;
+    if liquid_range == 0 || liquid_range > LIQUID_LEVEL_MAX + 1 {
+      return Err(format!(
+        "block [{}] liquid_range must be between 1 and [{}].",
+        name,
+        LIQUID_LEVEL_MAX + 1
+      ));
+    }
+
+    let groups = get_field_or(table, "groups", BTreeMap::new())
+      .map_err(|e| format!("block [{}] {}", name, e))?;
+
+    Ok(BlockDefinition {
+      name,
+      description,
+      draw_type,
+      light_source,
+      light_propagates,
+      sunlight_propagates,
+      pointable,
+      liquid_type,
+      liquid_alternative_source: get_field_or(table, "liquid_alternative_source", String::new())?,
+      liquid_alternative_flowing: get_field_or(table, "liquid_alternative_flowing", String::new())?,
+      liquid_viscosity: get_field_or(table, "liquid_viscosity", 0)?,
+      liquid_range,
+      liquid_renewable: get_field_or(table, "liquid_renewable", true)?,
+      groups,
+    })
+  }
+
+  ///
+  /// Get the block's rating in a group. 0 means it's not in the group.
+  ///
+  pub fn get_group(&self, group: &str) -> i32 {
+    self.groups.get(group).copied().unwrap_or(0)
+  }
+}
+
+///
+/// Holds every registered block and gives each one a numeric ID.
+///
+/// The map stores IDs, not names. This is how we go between the two.
+///
+/// IDs are only ever appended. A world keeps the name of every ID it has
+/// handed out, even for blocks whose mod is gone, so saved Chunks never
+/// change meaning. Those missing blocks have no definition.
+///
+#[derive(Clone)]
+pub struct BlockRegistry {
+  definitions: Vec<Option<BlockDefinition>>,
+  block_names: Vec<String>,
+  name_to_id: AHashMap<String, u16>,
+}
+
+impl BlockRegistry {
+  pub fn new() -> Self {
+    let mut new_registry = BlockRegistry {
+      definitions: vec![],
+      block_names: vec![],
+      name_to_id: AHashMap::new(),
+    };
+
+    // Air always exists.
+    new_registry.block_names.push(AIR_NAME.to_string());
+    new_registry.definitions.push(Some(BlockDefinition {
+      name: AIR_NAME.to_string(),
+      description: "Air".to_string(),
+      draw_type: DrawType::Air,
+      light_source: 0,
+      light_propagates: true,
+      sunlight_propagates: true,
+      pointable: false,
+      liquid_type: LiquidType::None,
+      liquid_alternative_source: String::new(),
+      liquid_alternative_flowing: String::new(),
+      liquid_viscosity: 0,
+      liquid_range: LIQUID_LEVEL_MAX + 1,
+      liquid_renewable: true,
+      groups: BTreeMap::new(),
+    }));
+    new_registry.name_to_id.insert(AIR_NAME.to_string(), AIR_ID);
+
+    new_registry
+  }
+
+  ///
+  /// Build a BlockRegistry out of the _G.blocks table in a LuaEngine.
+  ///
+  /// saved_block_names is the world's block name table, see get_block_names().
+  /// Every block keeps the ID it had in there. New blocks are sorted by name
+  /// and go on the end, so a new world gets the same IDs with the same mods.
+  ///
+  pub fn from_lua_table(blocks: &Table, saved_block_names: &[String]) -> Result<Self, String> {
+    let mut definitions = BTreeMap::new();
+
+    for pair in blocks.clone().pairs::<String, Table>() {
+      let (name, definition_table) = match pair {
+        Ok(pair) => pair,
+        Err(e) => return Err(format!("BlockRegistry: malformed block table. {}", e)),
+      };
+      match BlockDefinition::from_lua_table(&definition_table) {
+        Ok(definition) => definitions.insert(definition.name.clone(), definition),
+        Err(e) => return Err(format!("BlockRegistry: block [{}] {}", name, e)),
+      };
+    }
+
+    let mut new_registry = BlockRegistry::new();
+
+    if let Some(first) = saved_block_names.first() {
+      if first != AIR_NAME {
+        return Err(format!(
+          "BlockRegistry: the world's block table is corrupted. ID 0 is [{}], not air.",
+          first
+        ));
+      }
+    }
+    for name in saved_block_names.iter().skip(1) {
+      match definitions.remove(name) {
+        Some(definition) => {
+          new_registry.register_block(definition)?;
+        }
+        None => {
+          if new_registry.name_to_id.contains_key(name) || name == IGNORE_NAME {
+            return Err(format!(
+              "BlockRegistry: the world's block table is corrupted. [{}] is in it twice.",
+              name
+            ));
+          }
+          println!(
+            "BlockRegistry: block [{}] is not registered anymore, it will load as ignore.",
+            name
+          );
+          new_registry.add_missing_block(name)?;
+        }
+      }
+    }
+
+    for (_, definition) in definitions {
+      new_registry.register_block(definition)?;
+    }
+
+    Ok(new_registry)
+  }
+
+  ///
+  /// Hand out the next ID.
+  ///
+  fn get_next_id(&self) -> Result<u16, String> {
+    match u16::try_from(self.definitions.len()) {
+      Ok(id) if id != IGNORE_ID => Ok(id),
+      _ => Err("BlockRegistry: ran out of block IDs.".to_string()),
+    }
+  }
+
+  ///
+  /// Hold on to the ID of a block the world has but the game doesn't.
+  ///
+  fn add_missing_block(&mut self, name: &str) -> Result<(), String> {
+    let id = self.get_next_id()?;
+    self.name_to_id.insert(name.to_string(), id);
+    self.definitions.push(None);
+    self.block_names.push(name.to_string());
+    Ok(())
+  }
+
+  ///
+  /// Add a block into the registry.
+  ///
+  /// Returns the new block's ID.
+  ///
+  pub fn register_block(&mut self, definition: BlockDefinition) -> Result<u16, String> {
+    if self.name_to_id.contains_key(&definition.name) {
+      return Err(format!(
+        "BlockRegistry: block [{}] is already registered.",
+        definition.name
+      ));
+    }
+
+    if definition.name == IGNORE_NAME {
+      return Err(format!(
+        "BlockRegistry: [{}] is built into the engine.",
+        IGNORE_NAME
+      ));
+    }
+
+    let id = self.get_next_id()?;
+
+    self.name_to_id.insert(definition.name.clone(), id);
+    self.block_names.push(definition.name.clone());
+    self.definitions.push(Some(definition));
+
+    Ok(id)
+  }
+
+  ///
+  /// Get a block's ID from its name. Missing blocks have none.
+  ///
+  pub fn get_id(&self, name: &str) -> Option<u16> {
+    self
+      .name_to_id
+      .get(name)
+      .copied()
+      .filter(|id| self.is_registered(*id))
+  }
+
+  ///
+  /// Get a block's ID from its name, with an error naming what wanted it.
+  ///
+  pub fn require_id(&self, name: &str, requester: &str) -> Result<u16, String> {
+    match self.get_id(name) {
+      Some(id) => Ok(id),
+      None => Err(format!(
+        "{} references unknown block [{}].",
+        requester, name
+      )),
+    }
+  }
+
+  ///
+  /// Get a block's definition from its ID.
+  ///
+  pub fn get_definition(&self, id: u16) -> Option<&BlockDefinition> {
+    self.definitions.get(id as usize).and_then(Option::as_ref)
+  }
+
+  ///
+  /// Check if an ID belongs to a registered block.
+  ///
+  /// Saved nodes which fail this become IGNORE_ID when they're loaded.
+  ///
+  pub fn is_registered(&self, id: u16) -> bool {
+    self.get_definition(id).is_some()
+  }
+
+  ///
+  /// Get a block's name from its ID. Missing blocks have none.
+  ///
+  pub fn get_name(&self, id: u16) -> Option<&str> {
+    self
+      .get_definition(id)
+      .map(|definition| definition.name.as_str())
+  }
+
+  ///
+  /// Get the name of every ID, indexed by ID. Missing blocks are included.
+  ///
+  /// This is the table a world saves, to hand back into from_lua_table().
+  ///
+  pub fn get_block_names(&self) -> &[String] {
+    &self.block_names
+  }
+
+  ///
+  /// How many IDs are handed out, including air and missing blocks.
+  ///
+  pub fn get_block_count(&self) -> usize {
+    self.definitions.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use mlua::{Lua, Table};
+
+  use super::{BlockDefinition, BlockRegistry, AIR_ID, AIR_NAME};
+
+  fn from_lua(lua: &Lua, block_names: &[&str], saved_block_names: &[String]) -> BlockRegistry {
+    let blocks = match lua.create_table() {
+      Ok(blocks) => blocks,
+      Err(e) => panic!("{}", e),
+    };
+    for name in block_names {
+      let definition: Table = match lua
+        .load(format!("return {{name = \"{}\", drawtype = 1}}", name))
+        .eval()
+      {
+        Ok(definition) => definition,
+        Err(e) => panic!("{}", e),
+      };
+      if let Err(e) = blocks.set(*name, definition) {
+        panic!("{}", e);
+      }
+    }
+
+    match BlockRegistry::from_lua_table(&blocks, saved_block_names) {
+      Ok(block_registry) => block_registry,
+      Err(e) => panic!("{}", e),
+    }
+  }
+
+  #[test]
+  fn block_ids_never_move() {
+    let lua = Lua::new();
+
+    // A new world sorts by name.
+    let first = from_lua(&lua, &["test:stone", "test:dirt"], &[]);
+    assert_eq!(first.get_id("test:dirt"), Some(1));
+    assert_eq!(first.get_id("test:stone"), Some(2));
+    let saved = first.get_block_names().to_vec();
+
+    // Dirt's mod is gone and sand showed up. Stone stays put, sand goes on the end.
+    let second = from_lua(&lua, &["test:stone", "test:sand"], &saved);
+    assert_eq!(second.get_id(AIR_NAME), Some(AIR_ID));
+    assert_eq!(second.get_id("test:stone"), Some(2));
+    assert_eq!(second.get_id("test:sand"), Some(3));
+    assert_eq!(second.get_id("test:dirt"), None);
+    assert!(!second.is_registered(1));
+    assert_eq!(second.get_name(1), None);
+    assert_eq!(second.get_block_names()[1], "test:dirt");
+
+    // Dirt coming back gets its old ID again.
+    let third = from_lua(
+      &lua,
+      &["test:dirt", "test:stone", "test:sand"],
+      second.get_block_names(),
+    );
+    assert_eq!(third.get_id("test:dirt"), Some(1));
+    assert_eq!(third.get_id("test:sand"), Some(3));
+    assert_eq!(third.get_block_count(), 4);
+
+    let blocks = match lua.create_table() {
+      Ok(blocks) => blocks,
+      Err(e) => panic!("{}", e),
+    };
+    assert!(BlockRegistry::from_lua_table(&blocks, &["test:stone".to_string()]).is_err());
+
+    // A missing block can't show up twice, or be registered over.
+    let twice = [AIR_NAME, "test:gone", "test:gone"].map(String::from);
+    assert!(BlockRegistry::from_lua_table(&blocks, &twice).is_err());
+    let mut fourth = from_lua(&lua, &[], &saved);
+    assert!(fourth
+      .register_block(BlockDefinition::test("test:dirt"))
+      .is_err());
+  }
+}
@@ -0,0 +1,617 @@
+use std::collections::BTreeMap;
+
+use glam::IVec3;
+
+use crate::game::byte_buffer::{ByteReader, ByteWriter};
+
+use super::{
+  block_registry::{AIR_ID, IGNORE_ID},
+  node_meta::NodeMeta,
+  node_timer::NodeTimer,
+};
+
+///
+/// The width, height, and depth of a Chunk in nodes.
+///
+pub const CHUNK_SIZE: i32 = 16;
+
+///
+/// How many nodes are in a Chunk.
+///
+pub const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+///
+/// Bump this when the serialized Chunk layout changes.
+///
+/// * 1 - Nodes and light.
+/// * 2 - Node metadata.
+/// * 3 - Node timers.
+///
+const SERIALIZATION_VERSION: u8 = 3;
+
+///
+/// A single voxel in the map.
+///
+/// This is a simple data container, that's why everything is public.
+///
+/// * block_id - The BlockRegistry ID of the block.
+/// * param2   - Block specific data. (rotation, liquid level, etc)
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Node {
+  pub block_id: u16,
+  pub param2: u8,
+}
+
+impl Node {
+  pub fn new(block_id: u16, param2: u8) -> Self {
+    Node { block_id, param2 }
+  }
+
+  ///
+  /// A node of nothing.
+  ///
+  pub fn air() -> Self {
+    Node {
+      block_id: AIR_ID,
+      param2: 0,
+    }
+  }
+}
+
+///
+/// A Chunk is a 16x16x16 section of the map.
+///
+/// The data is stored as flat arrays for cache friendliness.
+/// The index order is X, then Z, then Y.
+///
+/// * block_ids - What block is in each position.
+/// * light     - The light level in each position. (param1, see lighting::LightBank)
+/// * param2    - Block specific data.
+/// * node_meta - NodeMeta keyed by array index. Most nodes have none.
+/// * node_timers - Running NodeTimers keyed by array index.
+/// * modified  - If the Chunk has changed since it was last saved. (not serialized)
+///
+#[derive(Clone, Debug)]
+pub struct Chunk {
+  position: IVec3,
+  block_ids: Vec<u16>,
+  light: Vec<u8>,
+  param2: Vec<u8>,
+  node_meta: BTreeMap<u16, NodeMeta>,
+  node_timers: BTreeMap<u16, NodeTimer>,
+  modified: bool,
+}
+
+impl Chunk {
+  ///
+  /// Create a new Chunk completely filled with air.
+  ///
+  pub fn new(position: IVec3) -> Self {
+    Chunk {
+      position,
+      block_ids: vec![AIR_ID; CHUNK_VOLUME],
+      light: vec![0; CHUNK_VOLUME],
+      param2: vec![0; CHUNK_VOLUME],
+      node_meta: BTreeMap::new(),
+      node_timers: BTreeMap::new(),
+      modified: false,
+    }
+  }
+
+  ///
+  /// Get the Chunk's position in Chunk coordinates.
+  ///
+  pub fn get_position(&self) -> IVec3 {
+    self.position
+  }
+
+  ///
+  /// Get the world position of the Chunk's (0,0,0) node.
+  ///
+  pub fn get_world_origin(&self) -> IVec3 {
+    chunk_to_world_position(self.position)
+  }
+
+  ///
+  /// Check if a local position is inside of the Chunk.
+  ///
+  pub fn is_in_bounds(local_position: IVec3) -> bool {
+    local_position.cmpge(IVec3::ZERO).all() && local_position.cmplt(IVec3::splat(CHUNK_SIZE)).all()
+  }
+
+  ///
+  /// Turn a local position into an array index.
+  ///
+  /// ! This does not check bounds! Use is_in_bounds() first if you're unsure.
+  ///
+  fn index(local_position: IVec3) -> usize {
+    (local_position.x
+      + (local_position.z * CHUNK_SIZE)
+      + (local_position.y * CHUNK_SIZE * CHUNK_SIZE)) as usize
+  }
+
+  ///
+  /// Get a Node at a local position.
+  ///
+  pub fn get_node(&self, local_position: IVec3) -> Node {
+    let index = Self::index(local_position);
+    Node::new(self.block_ids[index], self.param2[index])
+  }
+
+  ///
+  /// Turn an array index back into a local position.
+  ///
+  fn position_from_index(index: usize) -> IVec3 {
+    let index = index as i32;
+    IVec3::new(
+      index % CHUNK_SIZE,
+      index / (CHUNK_SIZE * CHUNK_SIZE),
+      (index / CHUNK_SIZE) % CHUNK_SIZE,
+    )
+  }
+
+  ///
+  /// Set a Node at a local position.
+  ///
+  /// Replacing a node with a different block removes its NodeMeta and NodeTimer.
+  ///
+  pub fn set_node(&mut self, local_position: IVec3, node: Node) {
+    let index = Self::index(local_position);
+    if self.block_ids[index] != node.block_id {
+      self.node_meta.remove(&(index as u16));
+      self.node_timers.remove(&(index as u16));
+    }
+    self.block_ids[index] = node.block_id;
+    self.param2[index] = node.param2;
+    self.modified = true;
+  }
+
+  ///
+  /// Get the light level (param1) at a local position.
+  ///
+  pub fn get_light(&self, local_position: IVec3) -> u8 {
+    self.light[Self::index(local_position)]
+  }
+
+  ///
+  /// Set the light level (param1) at a local position.
+  ///
+  pub fn set_light(&mut self, local_position: IVec3, light: u8) {
+    self.light[Self::index(local_position)] = light;
+    self.modified = true;
+  }
+
+  ///
+  /// Fill the entire Chunk with a single Node.
+  ///
+  pub fn fill(&mut self, node: Node) {
+    self.node_meta.clear();
+    self.node_timers.clear();
+    self.block_ids.fill(node.block_id);
+    self.param2.fill(node.param2);
+    self.modified = true;
+  }
+
+  ///
+  /// Get the NodeMeta at a local position, if it has any.
+  ///
+  pub fn get_node_meta(&self, local_position: IVec3) -> Option<&NodeMeta> {
+    self.node_meta.get(&(Self::index(local_position) as u16))
+  }
+
+  ///
+  /// Get the NodeMeta at a local position to modify it.
+  /// It gets created if it doesn't exist.
+  ///
+  pub fn get_node_meta_mut(&mut self, local_position: IVec3) -> &mut NodeMeta {
+    self.modified = true;
+    self
+      .node_meta
+      .entry(Self::index(local_position) as u16)
+      .or_default()
+  }
+
+  ///
+  /// Remove the NodeMeta at a local position.
+  ///
+  pub fn remove_node_meta(&mut self, local_position: IVec3) {
+    if self
+      .node_meta
+      .remove(&(Self::index(local_position) as u16))
+      .is_some()
+    {
+      self.modified = true;
+    }
+  }
+
+  ///
+  /// Get the local position of every node which has NodeMeta.
+  ///
+  pub fn get_node_meta_positions(&self) -> Vec<IVec3> {
+    self
+      .node_meta
+      .keys()
+      .map(|index| Self::position_from_index(*index as usize))
+      .collect()
+  }
+
+  ///
+  /// Get the NodeTimer at a local position, if one is running.
+  ///
+  pub fn get_node_timer(&self, local_position: IVec3) -> Option<NodeTimer> {
+    self
+      .node_timers
+      .get(&(Self::index(local_position) as u16))
+      .copied()
+  }
+
+  ///
+  /// Start or replace the NodeTimer at a local position.
+  ///
+  pub fn set_node_timer(&mut self, local_position: IVec3, node_timer: NodeTimer) {
+    self
+      .node_timers
+      .insert(Self::index(local_position) as u16, node_timer);
+    self.modified = true;
+  }
+
+  ///
+  /// Stop the NodeTimer at a local position.
+  ///
+  pub fn remove_node_timer(&mut self, local_position: IVec3) {
+    if self
+      .node_timers
+      .remove(&(Self::index(local_position) as u16))
+      .is_some()
+    {
+      self.modified = true;
+    }
+  }
+
+  ///
+  /// Move every NodeTimer forward by delta seconds.
+  ///
+  /// Returns the local position of every expired NodeTimer.
+  /// They are left in place, it's up to the caller to run and remove them.
+  ///
+  pub fn step_node_timers(&mut self, delta: f32) -> Vec<(IVec3, NodeTimer)> {
+    if self.node_timers.is_empty() {
+      return vec![];
+    }
+
+    // Elapsed time gets saved, so the Chunk really did change.
+    self.modified = true;
+
+    let mut expired = vec![];
+    for (index, node_timer) in self.node_timers.iter_mut() {
+      node_timer.elapsed += delta;
+      if node_timer.is_expired() {
+        expired.push((Self::position_from_index(*index as usize), *node_timer));
+      }
+    }
+
+    expired
+  }
+
+  ///
+  /// Check if the Chunk has changed since it was last saved.
+  ///
+  pub fn is_modified(&self) -> bool {
+    self.modified
+  }
+
+  ///
+  /// Mark the Chunk as matching what is in the database.
+  ///
+  pub fn set_saved(&mut self) {
+    self.modified = false;
+  }
+
+  ///
+  /// Check if the Chunk is nothing but air.
+  ///
+  pub fn is_empty(&self) -> bool {
+    self.block_ids.iter().all(|block_id| *block_id == AIR_ID)
+  }
+
+  ///
+  /// Turn the Chunk into raw bytes for the database and network.
+  ///
+  /// Layout:
+  /// * 1 byte               - version
+  /// * CHUNK_VOLUME * 2     - block IDs (little endian u16)
+  /// * CHUNK_VOLUME         - light
+  /// * CHUNK_VOLUME         - param2
+  /// * u32                  - NodeMeta count
+  /// * (u16 index, NodeMeta) for each NodeMeta
+  /// * u32                  - NodeTimer count
+  /// * (u16 index, NodeTimer) for each NodeTimer
+  ///
+  /// The position is not included, the database and network already key by it.
+  ///
+  pub fn serialize(&self) -> Vec<u8> {
+    self.write_chunk(true)
+  }
+
+  ///
+  /// Same as serialize(), but NodeMeta and NodeTimers are left out.
+  ///
+  /// Clients don't need them, and it keeps a Chunk small enough
+  /// to fit into a single packet. deserialize() reads it just the same.
+  ///
+  pub fn serialize_for_client(&self) -> Vec<u8> {
+    self.write_chunk(false)
+  }
+
+  fn write_chunk(&self, include_server_data: bool) -> Vec<u8> {
+    let mut writer = ByteWriter::new();
+
+    writer.write_u8(SERIALIZATION_VERSION);
+    for block_id in &self.block_ids {
+      writer.write_u16(*block_id);
+    }
+    writer.write_bytes(&self.light);
+    writer.write_bytes(&self.param2);
+
+    if !include_server_data {
+      writer.write_u32(0);
+      writer.write_u32(0);
+      return writer.into_bytes();
+    }
+
+    // Empty NodeMeta is not worth saving.
+    let node_meta: Vec<(&u16, &NodeMeta)> = self
+      .node_meta
+      .iter()
+      .filter(|(_, node_meta)| !node_meta.is_empty())
+      .collect();
+
+    writer.write_u32(node_meta.len() as u32);
+    for (index, node_meta) in node_meta {
+      writer.write_u16(*index);
+      node_meta.serialize(&mut writer);
+    }
+
+    writer.write_u32(self.node_timers.len() as u32);
+    for (index, node_timer) in &self.node_timers {
+      writer.write_u16(*index);
+      node_timer.serialize(&mut writer);
+    }
+
+    writer.into_bytes()
+  }
+
+  ///
+  /// Rebuild a Chunk out of raw bytes made by serialize().
+  ///
+  /// Older versions are still readable.
+  ///
+  /// Block IDs is_registered turns down become IGNORE_ID. Either their mod
+  /// is gone, or the data is bad.
+  ///
+  pub fn deserialize<F: Fn(u16) -> bool>(
+    position: IVec3,
+    bytes: &[u8],
+    is_registered: F,
+  ) -> Result<Self, String> {
+    Self::read_chunk(position, &mut ByteReader::new(bytes), is_registered)
+      .map_err(|e| format!("Chunk: [{}] is corrupted. {}", position, e))
+  }
+
+  fn read_chunk<F: Fn(u16) -> bool>(
+    position: IVec3,
+    reader: &mut ByteReader,
+    is_registered: F,
+  ) -> Result<Self, String> {
+    let version = reader.read_u8()?;
+    if version == 0 || version > SERIALIZATION_VERSION {
+      return Err(format!("unknown serialization version [{}].", version));
+    }
+
+    let mut chunk = Chunk::new(position);
+
+    for block_id in chunk.block_ids.iter_mut() {
+      *block_id = reader.read_u16()?;
+      if !is_registered(*block_id) {
+        *block_id = IGNORE_ID;
+      }
+    }
+    chunk.light = reader.read_bytes(CHUNK_VOLUME)?.to_vec();
+    chunk.param2 = reader.read_bytes(CHUNK_VOLUME)?.to_vec();
+
+    if version >= 2 {
+      let node_meta_count = reader.read_u32()?;
+      for _ in 0..node_meta_count {
+        let index = reader.read_u16()?;
+        if index as usize >= CHUNK_VOLUME {
+          return Err(format!("NodeMeta index [{}] is out of bounds.", index));
+        }
+        chunk
+          .node_meta
+          .insert(index, NodeMeta::deserialize(reader)?);
+      }
+    }
+
+    if version >= 3 {
+      let node_timer_count = reader.read_u32()?;
+      for _ in 0..node_timer_count {
+        let index = reader.read_u16()?;
+        if index as usize >= CHUNK_VOLUME {
+          return Err(format!("NodeTimer index [{}] is out of bounds.", index));
+        }
+        chunk
+          .node_timers
+          .insert(index, NodeTimer::deserialize(reader)?);
+      }
+    }
+
+    if reader.get_remaining() != 0 {
+      return Err(format!(
+        "[{}] bytes of trailing data.",
+        reader.get_remaining()
+      ));
+    }
+
+    Ok(chunk)
+  }
+}
+
+///
+/// Get which Chunk a world position is in.
+///
+pub fn world_to_chunk_position(world_position: IVec3) -> IVec3 {
+  world_position.div_euclid(IVec3::splat(CHUNK_SIZE))
+}
+
+///
+/// Get the position inside of a Chunk from a world position.
+///
+pub fn world_to_local_position(world_position: IVec3) -> IVec3 {
+  world_position.rem_euclid(IVec3::splat(CHUNK_SIZE))
+}
+
+///
+/// Get the world position of a Chunk's (0,0,0) node.
+///
+pub fn chunk_to_world_position(chunk_position: IVec3) -> IVec3 {
+  chunk_position * CHUNK_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, IVec3};
+
+  use super::{Chunk, Node, CHUNK_VOLUME};
+  use crate::game::{
+    byte_buffer::ByteWriter,
+    item_stack::ItemStack,
+    map::{block_registry::IGNORE_ID, node_meta::NodeMeta, node_timer::NodeTimer},
+  };
+
+  fn load(bytes: &[u8]) -> Result<Chunk, String> {
+    Chunk::deserialize(IVec3::ZERO, bytes, |_| true)
+  }
+
+  ///
+  /// Raw version 3 bytes of an all air Chunk, with the NodeMeta and NodeTimers given.
+  ///
+  fn write_raw(node_meta: &[(u16, NodeMeta)], node_timers: &[(u16, NodeTimer)]) -> Vec<u8> {
+    let mut writer = ByteWriter::new();
+    writer.write_u8(3);
+    for _ in 0..CHUNK_VOLUME {
+      writer.write_u16(0);
+    }
+    writer.write_bytes(&[0; CHUNK_VOLUME * 2]);
+
+    writer.write_u32(node_meta.len() as u32);
+    for (index, node_meta) in node_meta {
+      writer.write_u16(*index);
+      node_meta.serialize(&mut writer);
+    }
+    writer.write_u32(node_timers.len() as u32);
+    for (index, node_timer) in node_timers {
+      writer.write_u16(*index);
+      node_timer.serialize(&mut writer);
+    }
+
+    writer.into_bytes()
+  }
+
+  #[test]
+  fn chunks_round_trip_with_meta_and_timers() {
+    let mut chunk = Chunk::new(IVec3::ZERO);
+    chunk.set_node(ivec3(1, 2, 3), Node::new(3, 4));
+    chunk.set_light(ivec3(1, 2, 3), 0x5A);
+
+    let node_meta = chunk.get_node_meta_mut(ivec3(1, 2, 3));
+    node_meta.set_string("infotext", "Chest");
+    node_meta.get_inventory_mut().set_size("main", 4);
+    if let Err(e) =
+      node_meta
+        .get_inventory_mut()
+        .set_stack("main", 2, ItemStack::new("test:dirt", 33))
+    {
+      panic!("{}", e);
+    }
+    chunk.set_node_timer(ivec3(15, 15, 15), NodeTimer::new(5.0, 1.5));
+
+    // Empty NodeMeta is not saved.
+    chunk.get_node_meta_mut(ivec3(9, 9, 9));
+
+    let bytes = chunk.serialize();
+    let loaded = match load(&bytes) {
+      Ok(loaded) => loaded,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(loaded.serialize(), bytes);
+    assert_eq!(loaded.get_node(ivec3(1, 2, 3)), Node::new(3, 4));
+    assert_eq!(loaded.get_light(ivec3(1, 2, 3)), 0x5A);
+    assert_eq!(
+      loaded.get_node_meta(ivec3(1, 2, 3)),
+      chunk.get_node_meta(ivec3(1, 2, 3))
+    );
+    assert_eq!(loaded.get_node_meta(ivec3(9, 9, 9)), None);
+    assert_eq!(
+      loaded.get_node_timer(ivec3(15, 15, 15)),
+      Some(NodeTimer::new(5.0, 1.5))
+    );
+
+    // The client never sees NodeMeta or NodeTimers.
+    let client = match load(&chunk.serialize_for_client()) {
+      Ok(client) => client,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(client.get_node(ivec3(1, 2, 3)), Node::new(3, 4));
+    assert!(client.get_node_meta_positions().is_empty());
+    assert_eq!(client.get_node_timer(ivec3(15, 15, 15)), None);
+  }
+
+  #[test]
+  fn old_and_broken_chunks() {
+    let mut chunk = Chunk::new(IVec3::ZERO);
+    chunk.set_node(ivec3(4, 5, 6), Node::new(2, 1));
+    let bytes = chunk.serialize();
+
+    // Version 1 stopped after param2, version 2 before the NodeTimers.
+    let mut version_1 = bytes[..bytes.len() - 8].to_vec();
+    version_1[0] = 1;
+    let mut version_2 = bytes[..bytes.len() - 4].to_vec();
+    version_2[0] = 2;
+    for old_bytes in [version_1, version_2] {
+      match load(&old_bytes) {
+        Ok(loaded) => assert_eq!(loaded.get_node(ivec3(4, 5, 6)), Node::new(2, 1)),
+        Err(e) => panic!("{}", e),
+      }
+    }
+
+    let mut future = bytes.clone();
+    future[0] = 4;
+    assert!(load(&future).is_err());
+    assert!(load(&bytes[..bytes.len() - 1]).is_err());
+    assert!(load(&[bytes.as_slice(), &[0]].concat()).is_err());
+
+    assert!(load(&write_raw(&[(4095, NodeMeta::new())], &[])).is_ok());
+    assert!(load(&write_raw(&[(4096, NodeMeta::new())], &[])).is_err());
+    assert!(load(&write_raw(&[], &[(4095, NodeTimer::new(1.0, 0.0))])).is_ok());
+    assert!(load(&write_raw(&[], &[(u16::MAX, NodeTimer::new(1.0, 0.0))])).is_err());
+  }
+
+  #[test]
+  fn unknown_blocks_load_as_ignore() {
+    let mut chunk = Chunk::new(IVec3::ZERO);
+    chunk.set_node(ivec3(1, 2, 3), Node::new(1, 0));
+    chunk.set_node(ivec3(4, 5, 6), Node::new(2, 7));
+    chunk.set_node(ivec3(7, 8, 9), Node::new(900, 0));
+
+    // Only air and block 1 are registered.
+    let loaded = match Chunk::deserialize(IVec3::ZERO, &chunk.serialize(), |id| id <= 1) {
+      Ok(loaded) => loaded,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(loaded.get_node(ivec3(1, 2, 3)), Node::new(1, 0));
+    assert_eq!(loaded.get_node(ivec3(4, 5, 6)), Node::new(IGNORE_ID, 7));
+    assert_eq!(loaded.get_node(ivec3(7, 8, 9)).block_id, IGNORE_ID);
+    assert_eq!(loaded.get_node(IVec3::ZERO), Node::air());
+  }
+}
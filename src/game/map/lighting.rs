@@ -0,0 +1,543 @@
+use std::collections::VecDeque;
+
+use glam::{ivec3, IVec3};
+
+use super::{
+  block_registry::BlockRegistry,
+  chunk::{chunk_to_world_position, CHUNK_SIZE},
+  Map,
+};
+
+///
+/// The light level of open sky.
+///
+/// Sunlight travels straight down through sunlight_propagates blocks
+/// at this level without dimming. Everywhere else light dims by 1 per node.
+///
+pub const SUNLIGHT: u8 = 15;
+
+///
+/// A Chunk's light param holds 2 banks of light in 1 byte.
+///
+/// * Sun        - High 4 bits. Light which came from the sky.
+/// * Artificial - Low 4 bits. Light which came from light_source blocks.
+///
+/// They are kept separate so the client can dim the sun at night
+/// without touching torches. The brightness of a node is
+/// max(sun * daylight, artificial).
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightBank {
+  Sun,
+  Artificial,
+}
+
+///
+/// Pull a single bank out of a light param.
+///
+pub fn get_bank_light(light: u8, bank: LightBank) -> u8 {
+  match bank {
+    LightBank::Sun => light >> 4,
+    LightBank::Artificial => light & 0x0F,
+  }
+}
+
+///
+/// Replace a single bank in a light param.
+///
+pub fn set_bank_light(light: u8, bank: LightBank, level: u8) -> u8 {
+  match bank {
+    LightBank::Sun => (light & 0x0F) | (level << 4),
+    LightBank::Artificial => (light & 0xF0) | (level & 0x0F),
+  }
+}
+
+const BANKS: [LightBank; 2] = [LightBank::Sun, LightBank::Artificial];
+
+const DIRECTIONS: [IVec3; 6] = [
+  IVec3::X,
+  IVec3::NEG_X,
+  IVec3::Y,
+  IVec3::NEG_Y,
+  IVec3::Z,
+  IVec3::NEG_Z,
+];
+
+///
+/// What the lighting engine needs to know about the node at a position.
+///
+/// Unknown block IDs are treated like an opaque block.
+///
+struct LightProperties {
+  light_source: u8,
+  light_propagates: bool,
+  sunlight_propagates: bool,
+}
+
+fn get_light_properties(
+  map: &Map,
+  block_registry: &BlockRegistry,
+  world_position: IVec3,
+) -> Option<LightProperties> {
+  let node = map.get_node(world_position)?;
+
+  Some(match block_registry.get_definition(node.block_id) {
+    Some(definition) => LightProperties {
+      light_source: definition.light_source,
+      light_propagates: definition.light_propagates,
+      sunlight_propagates: definition.sunlight_propagates,
+    },
+    None => LightProperties {
+      light_source: 0,
+      light_propagates: false,
+      sunlight_propagates: false,
+    },
+  })
+}
+
+///
+/// Get a single bank's light level at a world position.
+///
+/// Returns None if the Chunk is not in memory.
+///
+pub fn get_light_level(map: &Map, world_position: IVec3, bank: LightBank) -> Option<u8> {
+  map
+    .get_light(world_position)
+    .map(|light| get_bank_light(light, bank))
+}
+
+fn set_light_level(map: &mut Map, world_position: IVec3, bank: LightBank, level: u8) {
+  if let Some(light) = map.get_light(world_position) {
+    map.set_light(world_position, set_bank_light(light, bank, level));
+  }
+}
+
+///
+/// Flood fill light outwards from every position in the queue.
+///
+/// The positions in the queue must already have their light set.
+///
+fn propagate_light(
+  map: &mut Map,
+  block_registry: &BlockRegistry,
+  bank: LightBank,
+  mut queue: VecDeque<IVec3>,
+) {
+  while let Some(world_position) = queue.pop_front() {
+    let level = match get_light_level(map, world_position, bank) {
+      Some(level) if level > 0 => level,
+      _ => continue,
+    };
+
+    for direction in DIRECTIONS {
+      let neighbor = world_position + direction;
+
+      // Light does not travel into Chunks which are not in memory.
+      let properties = match get_light_properties(map, block_registry, neighbor) {
+        Some(properties) => properties,
+        None => continue,
+      };
+
+      if !properties.light_propagates {
+        continue;
+      }
+
+      let new_level = match bank == LightBank::Sun
+        && direction == IVec3::NEG_Y
+        && level == SUNLIGHT
+        && properties.sunlight_propagates
+      {
+        true => SUNLIGHT,
+        false => level - 1,
+      };
+
+      if new_level == 0 {
+        continue;
+      }
+
+      match get_light_level(map, neighbor, bank) {
+        Some(neighbor_level) if neighbor_level < new_level => {
+          set_light_level(map, neighbor, bank, new_level);
+          queue.push_back(neighbor);
+        }
+        _ => (),
+      }
+    }
+  }
+}
+
+///
+/// Flood fill darkness outwards from every (position, old level) in the seeds.
+///
+/// The seed positions must already have their light set to 0.
+///
+/// Any light that was coming from somewhere else gets flooded back in afterwards.
+///
+fn remove_light(
+  map: &mut Map,
+  block_registry: &BlockRegistry,
+  bank: LightBank,
+  seeds: Vec<(IVec3, u8)>,
+) {
+  let mut removal_queue: VecDeque<(IVec3, u8)> = seeds.into();
+  let mut refill_queue = VecDeque::new();
+
+  while let Some((world_position, old_level)) = removal_queue.pop_front() {
+    for direction in DIRECTIONS {
+      let neighbor = world_position + direction;
+
+      let neighbor_level = match get_light_level(map, neighbor, bank) {
+        Some(neighbor_level) if neighbor_level > 0 => neighbor_level,
+        _ => continue,
+      };
+
+      let sun_column = bank == LightBank::Sun
+        && direction == IVec3::NEG_Y
+        && old_level == SUNLIGHT
+        && neighbor_level == SUNLIGHT;
+
+      if neighbor_level < old_level || sun_column {
+        // This neighbor was lit by what got removed.
+        set_light_level(map, neighbor, bank, 0);
+        removal_queue.push_back((neighbor, neighbor_level));
+
+        // Light sources always relight themselves.
+        if bank == LightBank::Artificial {
+          if let Some(properties) = get_light_properties(map, block_registry, neighbor) {
+            if properties.light_source > 0 {
+              set_light_level(map, neighbor, bank, properties.light_source);
+              refill_queue.push_back(neighbor);
+            }
+          }
+        }
+      } else {
+        // This neighbor is lit by something else, let it flow back in.
+        refill_queue.push_back(neighbor);
+      }
+    }
+  }
+
+  propagate_light(map, block_registry, bank, refill_queue);
+}
+
+///
+/// Fix up the light around a node which just changed.
+///
+/// Run this after every Map::set_node on the Server.
+///
+pub fn update_node_light(map: &mut Map, block_registry: &BlockRegistry, world_position: IVec3) {
+  let properties = match get_light_properties(map, block_registry, world_position) {
+    Some(properties) => properties,
+    None => return,
+  };
+
+  for bank in BANKS {
+    let old_level = match get_light_level(map, world_position, bank) {
+      Some(old_level) => old_level,
+      None => return,
+    };
+
+    // Darken everything this node was lighting up.
+    set_light_level(map, world_position, bank, 0);
+    if old_level > 0 {
+      remove_light(map, block_registry, bank, vec![(world_position, old_level)]);
+    }
+
+    // Then let the light back in.
+    let mut queue = VecDeque::new();
+
+    if bank == LightBank::Artificial && properties.light_source > 0 {
+      set_light_level(map, world_position, bank, properties.light_source);
+      queue.push_back(world_position);
+    }
+
+    for direction in DIRECTIONS {
+      let neighbor = world_position + direction;
+      if let Some(level) = get_light_level(map, neighbor, bank) {
+        if level > 0 {
+          queue.push_back(neighbor);
+        }
+      }
+    }
+
+    propagate_light(map, block_registry, bank, queue);
+  }
+}
+
+///
+/// Light up a Chunk which was just generated.
+///
+/// ! The Chunk's light must be all 0. (Fresh out of mapgen)
+///
+/// If the Chunk above is not in memory, open sky is assumed.
+/// When that Chunk does show up, connect_chunk_light() fixes the assumption.
+///
+pub fn light_new_chunk(map: &mut Map, block_registry: &BlockRegistry, chunk_position: IVec3) {
+  if !map.has_chunk(chunk_position) {
+    return;
+  }
+
+  let origin = chunk_to_world_position(chunk_position);
+  let above_in_memory = map.has_chunk(chunk_position + IVec3::Y);
+
+  let mut sun_queue = VecDeque::new();
+  let mut artificial_queue = VecDeque::new();
+
+  for x in 0..CHUNK_SIZE {
+    for z in 0..CHUNK_SIZE {
+      // Sunlight columns straight down.
+      let mut sunlit = match above_in_memory {
+        true => {
+          get_light_level(map, origin + ivec3(x, CHUNK_SIZE, z), LightBank::Sun) == Some(SUNLIGHT)
+        }
+        false => true,
+      };
+
+      for y in (0..CHUNK_SIZE).rev() {
+        let world_position = origin + ivec3(x, y, z);
+
+        let properties = match get_light_properties(map, block_registry, world_position) {
+          Some(properties) => properties,
+          None => continue,
+        };
+
+        sunlit = sunlit && properties.sunlight_propagates;
+        if sunlit {
+          set_light_level(map, world_position, LightBank::Sun, SUNLIGHT);
+          sun_queue.push_back(world_position);
+        }
+
+        if properties.light_source > 0 {
+          set_light_level(
+            map,
+            world_position,
+            LightBank::Artificial,
+            properties.light_source,
+          );
+          artificial_queue.push_back(world_position);
+        }
+      }
+    }
+  }
+
+  propagate_light(map, block_registry, LightBank::Sun, sun_queue);
+  propagate_light(map, block_registry, LightBank::Artificial, artificial_queue);
+
+  connect_chunk_light(map, block_registry, chunk_position);
+}
+
+///
+/// Join a Chunk's light up with its neighbors.
+///
+/// Run this when a Chunk comes into memory. New Chunks get this
+/// automatically from light_new_chunk().
+///
+/// 1.) Light in neighboring Chunks flows in across the borders.
+/// 2.) Light in this Chunk flows out across the borders.
+/// 3.) Sunlight that assumed open sky gets removed. Either the Chunk below was
+///     lit before this one blocked it, or this one was lit before the Chunk above.
+///
+pub fn connect_chunk_light(map: &mut Map, block_registry: &BlockRegistry, chunk_position: IVec3) {
+  if !map.has_chunk(chunk_position) {
+    return;
+  }
+
+  let origin = chunk_to_world_position(chunk_position);
+
+  for bank in BANKS {
+    let mut queue = VecDeque::new();
+
+    for a in 0..CHUNK_SIZE {
+      for b in 0..CHUNK_SIZE {
+        // Both sides of all 6 faces.
+        let border_positions = [
+          ivec3(-1, a, b),
+          ivec3(0, a, b),
+          ivec3(CHUNK_SIZE - 1, a, b),
+          ivec3(CHUNK_SIZE, a, b),
+          ivec3(a, -1, b),
+          ivec3(a, 0, b),
+          ivec3(a, CHUNK_SIZE - 1, b),
+          ivec3(a, CHUNK_SIZE, b),
+          ivec3(a, b, -1),
+          ivec3(a, b, 0),
+          ivec3(a, b, CHUNK_SIZE - 1),
+          ivec3(a, b, CHUNK_SIZE),
+        ];
+
+        for local_position in border_positions {
+          let world_position = origin + local_position;
+          if let Some(level) = get_light_level(map, world_position, bank) {
+            if level > 0 {
+              queue.push_back(world_position);
+            }
+          }
+        }
+      }
+    }
+
+    propagate_light(map, block_registry, bank, queue);
+  }
+
+  // Sunlight which assumed open sky, on either side of the top and bottom faces.
+  // (upper, lower) pairs. The lower side is always sunlit wrongly if the upper isn't.
+  let mut seeds = vec![];
+
+  for x in 0..CHUNK_SIZE {
+    for z in 0..CHUNK_SIZE {
+      let faces = [
+        (origin + ivec3(x, 0, z), origin + ivec3(x, -1, z)),
+        (
+          origin + ivec3(x, CHUNK_SIZE, z),
+          origin + ivec3(x, CHUNK_SIZE - 1, z),
+        ),
+      ];
+
+      for (upper, lower) in faces {
+        let upper_dark = matches!(
+          get_light_level(map, upper, LightBank::Sun),
+          Some(level) if level < SUNLIGHT
+        );
+        let lower_sunlit = get_light_level(map, lower, LightBank::Sun) == Some(SUNLIGHT);
+
+        if upper_dark && lower_sunlit {
+          set_light_level(map, lower, LightBank::Sun, 0);
+          seeds.push((lower, SUNLIGHT));
+        }
+      }
+    }
+  }
+
+  if !seeds.is_empty() {
+    remove_light(map, block_registry, LightBank::Sun, seeds);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, IVec3};
+
+  use super::{
+    connect_chunk_light, get_light_level, light_new_chunk, update_node_light, LightBank, SUNLIGHT,
+  };
+  use crate::game::map::{
+    block_registry::{BlockDefinition, BlockRegistry},
+    chunk::{Chunk, Node, CHUNK_SIZE},
+    Map,
+  };
+
+  fn test_block(name: &str, light_source: u8, light_propagates: bool) -> BlockDefinition {
+    BlockDefinition {
+      light_source,
+      light_propagates,
+      ..BlockDefinition::test(name)
+    }
+  }
+
+  ///
+  /// Air, stone and a torch which gives off 10.
+  ///
+  fn test_registry() -> (BlockRegistry, Node, Node) {
+    let mut block_registry = BlockRegistry::new();
+    let stone = block_registry
+      .register_block(test_block("test:stone", 0, false))
+      .unwrap_or(0);
+    let torch = block_registry
+      .register_block(test_block("test:torch", 10, true))
+      .unwrap_or(0);
+
+    (block_registry, Node::new(stone, 0), Node::new(torch, 0))
+  }
+
+  fn place(map: &mut Map, block_registry: &BlockRegistry, position: IVec3, node: Node) {
+    map.set_node(position, node);
+    update_node_light(map, block_registry, position);
+  }
+
+  fn get_sun(map: &Map, position: IVec3) -> Option<u8> {
+    get_light_level(map, position, LightBank::Sun)
+  }
+
+  fn get_artificial(map: &Map, position: IVec3) -> Option<u8> {
+    get_light_level(map, position, LightBank::Artificial)
+  }
+
+  #[test]
+  fn placing_and_removing_nodes_updates_light() {
+    let (block_registry, stone, torch) = test_registry();
+    let mut map = Map::new();
+    map.insert_chunk(Chunk::new(IVec3::ZERO));
+    light_new_chunk(&mut map, &block_registry, IVec3::ZERO);
+    assert_eq!(get_sun(&map, ivec3(8, 0, 8)), Some(SUNLIGHT));
+
+    // A roof under the top layer blocks all of the sun.
+    for x in 0..CHUNK_SIZE {
+      for z in 0..CHUNK_SIZE {
+        place(&mut map, &block_registry, ivec3(x, 14, z), stone);
+      }
+    }
+    assert_eq!(get_sun(&map, ivec3(8, 8, 8)), Some(0));
+
+    // A hole lets a column back in, which spreads sideways.
+    place(&mut map, &block_registry, ivec3(8, 14, 8), Node::air());
+    assert_eq!(get_sun(&map, ivec3(8, 0, 8)), Some(SUNLIGHT));
+    assert_eq!(get_sun(&map, ivec3(10, 0, 8)), Some(SUNLIGHT - 2));
+
+    place(&mut map, &block_registry, ivec3(3, 3, 3), torch);
+    assert_eq!(get_artificial(&map, ivec3(3, 3, 3)), Some(10));
+    assert_eq!(get_artificial(&map, ivec3(5, 4, 3)), Some(7));
+
+    // Closing everything back up leaves no trace.
+    place(&mut map, &block_registry, ivec3(3, 3, 3), Node::air());
+    place(&mut map, &block_registry, ivec3(8, 14, 8), stone);
+    assert_eq!(get_artificial(&map, ivec3(5, 4, 3)), Some(0));
+    assert_eq!(get_sun(&map, ivec3(8, 0, 8)), Some(0));
+    assert_eq!(get_sun(&map, ivec3(10, 0, 8)), Some(0));
+  }
+
+  #[test]
+  fn light_crosses_chunk_borders() {
+    let (block_registry, stone, torch) = test_registry();
+
+    // Torch light flows into a Chunk which loads later.
+    let mut map = Map::new();
+    let mut chunk = Chunk::new(IVec3::ZERO);
+    chunk.set_node(ivec3(15, 8, 8), torch);
+    map.insert_chunk(chunk);
+    light_new_chunk(&mut map, &block_registry, IVec3::ZERO);
+    map.insert_chunk(Chunk::new(IVec3::X));
+    light_new_chunk(&mut map, &block_registry, IVec3::X);
+    assert_eq!(get_artificial(&map, ivec3(16, 8, 8)), Some(9));
+    assert_eq!(get_artificial(&map, ivec3(18, 8, 8)), Some(7));
+
+    // An all air Chunk lit on its own, under the open sky.
+    let mut sky = Map::new();
+    sky.insert_chunk(Chunk::new(IVec3::NEG_Y));
+    light_new_chunk(&mut sky, &block_registry, IVec3::NEG_Y);
+    let air_chunk = match sky.remove_chunk(IVec3::NEG_Y) {
+      Some(air_chunk) => air_chunk,
+      None => panic!("the air chunk disappeared."),
+    };
+
+    // Reloaded under a solid stone Chunk, the sun has to go.
+    let mut map = Map::new();
+    let mut stone_chunk = Chunk::new(IVec3::ZERO);
+    stone_chunk.fill(stone);
+    map.insert_chunk(stone_chunk);
+    light_new_chunk(&mut map, &block_registry, IVec3::ZERO);
+    map.insert_chunk(air_chunk);
+    connect_chunk_light(&mut map, &block_registry, IVec3::NEG_Y);
+    assert_eq!(get_sun(&map, ivec3(8, -8, 8)), Some(0));
+    assert_eq!(get_sun(&map, ivec3(0, -1, 0)), Some(0));
+
+    // Loaded the other way around.
+    let mut map = Map::new();
+    map.insert_chunk(Chunk::new(IVec3::NEG_Y));
+    light_new_chunk(&mut map, &block_registry, IVec3::NEG_Y);
+    let mut stone_chunk = Chunk::new(IVec3::ZERO);
+    stone_chunk.fill(stone);
+    map.insert_chunk(stone_chunk);
+    light_new_chunk(&mut map, &block_registry, IVec3::ZERO);
+    assert_eq!(get_sun(&map, ivec3(8, -8, 8)), Some(0));
+  }
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use crate::game::{
+  byte_buffer::{ByteReader, ByteWriter},
+  inventory::Inventory,
+};
+
+///
+/// Per node data. This is what chests, signs and furnaces store their stuff in.
+///
+/// * fields    - String key/value pairs. Numbers are stored as strings too.
+/// * inventory - Named item lists.
+///
+/// NodeMeta lives in the Chunk that its node is in and gets removed
+/// when the node is replaced with a different block.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeMeta {
+  fields: BTreeMap<String, String>,
+  inventory: Inventory,
+}
+
+impl NodeMeta {
+  pub fn new() -> Self {
+    NodeMeta {
+      fields: BTreeMap::new(),
+      inventory: Inventory::new(),
+    }
+  }
+
+  ///
+  /// Check if there's nothing stored. Empty NodeMeta doesn't need to be kept.
+  ///
+  pub fn is_empty(&self) -> bool {
+    self.fields.is_empty() && self.inventory.is_empty()
+  }
+
+  pub fn contains(&self, key: &str) -> bool {
+    self.fields.contains_key(key)
+  }
+
+  ///
+  /// Get a string field. Missing fields are an empty string.
+  ///
+  pub fn get_string(&self, key: &str) -> String {
+    match self.fields.get(key) {
+      Some(value) => value.clone(),
+      None => String::new(),
+    }
+  }
+
+  ///
+  /// Set a string field. Setting an empty string removes the field.
+  ///
+  pub fn set_string(&mut self, key: &str, value: &str) {
+    if value.is_empty() {
+      self.fields.remove(key);
+    } else {
+      self.fields.insert(key.to_string(), value.to_string());
+    }
+  }
+
+  ///
+  /// Get an integer field. Missing or non-numeric fields are 0.
+  ///
+  pub fn get_int(&self, key: &str) -> i64 {
+    self.get_string(key).trim().parse::<i64>().unwrap_or(0)
+  }
+
+  pub fn set_int(&mut self, key: &str, value: i64) {
+    self.set_string(key, &value.to_string());
+  }
+
+  ///
+  /// Get a float field. Missing or non-numeric fields are 0.
+  ///
+  pub fn get_float(&self, key: &str) -> f64 {
+    self.get_string(key).trim().parse::<f64>().unwrap_or(0.0)
+  }
+
+  pub fn set_float(&mut self, key: &str, value: f64) {
+    self.set_string(key, &value.to_string());
+  }
+
+  pub fn get_fields(&self) -> &BTreeMap<String, String> {
+    &self.fields
+  }
+
+  pub fn get_inventory(&self) -> &Inventory {
+    &self.inventory
+  }
+
+  pub fn get_inventory_mut(&mut self) -> &mut Inventory {
+    &mut self.inventory
+  }
+
+  pub fn serialize(&self, writer: &mut ByteWriter) {
+    writer.write_u32(self.fields.len() as u32);
+    for (key, value) in &self.fields {
+      writer.write_string(key);
+      writer.write_string(value);
+    }
+    self.inventory.serialize(writer);
+  }
+
+  pub fn deserialize(reader: &mut ByteReader) -> Result<Self, String> {
+    let mut node_meta = NodeMeta::new();
+
+    let field_count = reader.read_u32()?;
+    for _ in 0..field_count {
+      let key = reader.read_string()?;
+      let value = reader.read_string()?;
+      node_meta.fields.insert(key, value);
+    }
+
+    node_meta.inventory = Inventory::deserialize(reader)?;
+
+    Ok(node_meta)
+  }
+}
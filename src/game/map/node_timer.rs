@@ -0,0 +1,49 @@
+use crate::game::byte_buffer::{ByteReader, ByteWriter};
+
+///
+/// A countdown attached to a single node. (NodeTimer in C++ minetest)
+///
+/// Started with minetest.get_node_timer(pos):start(timeout). When elapsed
+/// reaches timeout, the Server runs the block's on_timer callback.
+///
+/// * timeout - Seconds until the timer goes off.
+/// * elapsed - Seconds that have passed since the timer was started.
+///
+/// NodeTimers live in the Chunk that their node is in and only tick while
+/// that Chunk is active. Just like NodeMeta, they get removed when the node
+/// is replaced with a different block.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NodeTimer {
+  pub timeout: f32,
+  pub elapsed: f32,
+}
+
+impl NodeTimer {
+  pub fn new(timeout: f32, elapsed: f32) -> Self {
+    NodeTimer { timeout, elapsed }
+  }
+
+  ///
+  /// Check if the timer has gone off.
+  ///
+  pub fn is_expired(&self) -> bool {
+    self.elapsed >= self.timeout
+  }
+
+  ///
+  /// How long the timer has been waiting past its timeout.
+  ///
+  pub fn get_overdue(&self) -> f32 {
+    self.elapsed - self.timeout
+  }
+
+  pub fn serialize(&self, writer: &mut ByteWriter) {
+    writer.write_f32(self.timeout);
+    writer.write_f32(self.elapsed);
+  }
+
+  pub fn deserialize(reader: &mut ByteReader) -> Result<Self, String> {
+    Ok(NodeTimer::new(reader.read_f32()?, reader.read_f32()?))
+  }
+}
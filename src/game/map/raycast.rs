@@ -0,0 +1,578 @@
+use std::collections::VecDeque;
+
+use glam::{IVec3, Vec3};
+
+use super::{
+  block_registry::{BlockRegistry, LiquidType},
+  chunk::Node,
+  Map,
+};
+
+///
+/// Something a raycast went through. (pointed_thing in C++ minetest)
+///
+/// * under               - The node that was hit.
+/// * above               - The node in front of the face that was hit. This is where placing goes.
+/// * intersection_point  - Where the ray entered the node or entity.
+/// * intersection_normal - The face the ray came in through. Zero if the ray started inside.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum PointedThing {
+  Node {
+    under: IVec3,
+    above: IVec3,
+    intersection_point: Vec3,
+    intersection_normal: IVec3,
+  },
+  Entity {
+    id: u64,
+    intersection_point: Vec3,
+    intersection_normal: IVec3,
+  },
+}
+
+///
+/// An entity's hitbox, in world space.
+///
+/// The Map doesn't know about entities, so whoever owns them hands these in.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaycastEntity {
+  pub id: u64,
+  pub min: Vec3,
+  pub max: Vec3,
+}
+
+///
+/// A voxel raycast over the Map. (Amanatides & Woo's DDA)
+///
+/// Walks every node the ray passes through, in order, and hands back the
+/// pointable ones. Entity hits get mixed in by distance.
+///
+/// Nodes are centered on whole numbers, so node (0,0,0) covers -0.5 to 0.5.
+/// Every node is treated as a full cube for now.
+///
+/// The Raycast doesn't hold onto the Map, so it can be stepped a little at a
+/// time. (This is what the Lua iterator does.)
+///
+pub struct Raycast {
+  start: Vec3,
+  direction: Vec3,
+  length: f32,
+  include_liquids: bool,
+
+  // DDA state.
+  voxel: IVec3,
+  step: IVec3,
+  t_max: Vec3,
+  t_delta: Vec3,
+  t: f32,
+  normal: IVec3,
+  nodes_finished: bool,
+
+  // Entity hits are all found up front, nearest first.
+  entity_hits: VecDeque<(f32, PointedThing)>,
+  next_node_hit: Option<(f32, PointedThing)>,
+}
+
+impl Raycast {
+  ///
+  /// Set up a ray from start to end.
+  ///
+  /// * include_liquids - Point at liquids even if they are not pointable.
+  /// * entities        - Hitboxes to check. Pass an empty slice for nodes only.
+  ///
+  pub fn new(start: Vec3, end: Vec3, include_liquids: bool, entities: &[RaycastEntity]) -> Self {
+    let length = start.distance(end);
+    let direction = (end - start).normalize_or_zero();
+
+    // Shift by half a node so each node covers whole number to whole number.
+    let shifted_start = start + 0.5;
+    let voxel = shifted_start.floor().as_ivec3();
+
+    let mut step = IVec3::ZERO;
+    let mut t_max = Vec3::INFINITY;
+    let mut t_delta = Vec3::INFINITY;
+    for axis in 0..3 {
+      if direction[axis] > 0.0 {
+        step[axis] = 1;
+        t_delta[axis] = 1.0 / direction[axis];
+        t_max[axis] = (voxel[axis] as f32 + 1.0 - shifted_start[axis]) / direction[axis];
+      } else if direction[axis] < 0.0 {
+        step[axis] = -1;
+        t_delta[axis] = -1.0 / direction[axis];
+        t_max[axis] = (shifted_start[axis] - voxel[axis] as f32) / -direction[axis];
+      }
+    }
+
+    let mut entity_hits: Vec<(f32, PointedThing)> = entities
+      .iter()
+      .filter_map(|entity| intersect_entity(start, direction, length, entity))
+      .collect();
+    entity_hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    Raycast {
+      start,
+      direction,
+      length,
+      include_liquids,
+
+      voxel,
+      step,
+      t_max,
+      t_delta,
+      t: 0.0,
+      normal: IVec3::ZERO,
+      nodes_finished: false,
+
+      entity_hits: entity_hits.into(),
+      next_node_hit: None,
+    }
+  }
+
+  ///
+  /// Get the next thing the ray goes through, nearest first.
+  ///
+  /// Returns None when the ray reaches the end.
+  ///
+  pub fn next(&mut self, map: &Map, block_registry: &BlockRegistry) -> Option<PointedThing> {
+    let include_liquids = self.include_liquids;
+    self.next_by(map, |node| {
+      match block_registry.get_definition(node.block_id) {
+        Some(definition) => {
+          definition.pointable || (include_liquids && definition.liquid_type != LiquidType::None)
+        }
+        None => false,
+      }
+    })
+  }
+
+  ///
+  /// Same as next, but the caller decides which nodes can be pointed at.
+  ///
+  pub fn next_by<F: Fn(Node) -> bool>(
+    &mut self,
+    map: &Map,
+    is_pointable: F,
+  ) -> Option<PointedThing> {
+    if self.next_node_hit.is_none() {
+      self.next_node_hit = self.next_node(map, &is_pointable);
+    }
+
+    let entity_is_nearer = match (&self.next_node_hit, self.entity_hits.front()) {
+      (Some((node_t, _)), Some((entity_t, _))) => entity_t < node_t,
+      (None, Some(_)) => true,
+      _ => false,
+    };
+
+    match entity_is_nearer {
+      true => self.entity_hits.pop_front().map(|(_, hit)| hit),
+      false => self.next_node_hit.take().map(|(_, hit)| hit),
+    }
+  }
+
+  ///
+  /// Walk the DDA forward until it finds a pointable node.
+  ///
+  fn next_node<F: Fn(Node) -> bool>(
+    &mut self,
+    map: &Map,
+    is_pointable: &F,
+  ) -> Option<(f32, PointedThing)> {
+    while !self.nodes_finished {
+      let voxel = self.voxel;
+      let t = self.t;
+      let normal = self.normal;
+
+      // Move on to the next node before checking this one, that way
+      // the next call picks up right where this one left off.
+      let axis = min_axis(self.t_max);
+      if self.t_max[axis] > self.length {
+        self.nodes_finished = true;
+      } else {
+        self.voxel[axis] += self.step[axis];
+        self.t = self.t_max[axis];
+        self.t_max[axis] += self.t_delta[axis];
+        self.normal = IVec3::ZERO;
+        self.normal[axis] = -self.step[axis];
+      }
+
+      // Unloaded nodes can't be pointed at.
+      if map.get_node(voxel).is_some_and(is_pointable) {
+        return Some((
+          t,
+          PointedThing::Node {
+            under: voxel,
+            above: voxel + normal,
+            intersection_point: self.start + self.direction * t,
+            intersection_normal: normal,
+          },
+        ));
+      }
+    }
+
+    None
+  }
+}
+
+///
+/// Cast a ray and collect everything it goes through, nearest first.
+///
+pub fn raycast(
+  map: &Map,
+  block_registry: &BlockRegistry,
+  start: Vec3,
+  end: Vec3,
+  include_liquids: bool,
+  entities: &[RaycastEntity],
+) -> Vec<PointedThing> {
+  let mut ray = Raycast::new(start, end, include_liquids, entities);
+  let mut hits = vec![];
+  while let Some(hit) = ray.next(map, block_registry) {
+    hits.push(hit);
+  }
+  hits
+}
+
+///
+/// Which axis has the smallest value. Ties go to the lowest axis.
+///
+fn min_axis(value: Vec3) -> usize {
+  if value.x <= value.y && value.x <= value.z {
+    0
+  } else if value.y <= value.z {
+    1
+  } else {
+    2
+  }
+}
+
+///
+/// Slab test of a ray against an entity's hitbox.
+///
+fn intersect_entity(
+  start: Vec3,
+  direction: Vec3,
+  length: f32,
+  entity: &RaycastEntity,
+) -> Option<(f32, PointedThing)> {
+  let mut t_near = f32::NEG_INFINITY;
+  let mut t_far = f32::INFINITY;
+  let mut near_axis = None;
+
+  for axis in 0..3 {
+    if direction[axis] == 0.0 {
+      if start[axis] < entity.min[axis] || start[axis] > entity.max[axis] {
+        return None;
+      }
+      continue;
+    }
+
+    let t1 = (entity.min[axis] - start[axis]) / direction[axis];
+    let t2 = (entity.max[axis] - start[axis]) / direction[axis];
+    let (t1, t2) = (t1.min(t2), t1.max(t2));
+
+    if t1 > t_near {
+      t_near = t1;
+      near_axis = Some(axis);
+    }
+    t_far = t_far.min(t2);
+  }
+
+  if t_near > t_far || t_far < 0.0 || t_near > length {
+    return None;
+  }
+
+  // Started inside of the hitbox.
+  let mut intersection_normal = IVec3::ZERO;
+  let t = match near_axis {
+    Some(axis) if t_near >= 0.0 => {
+      intersection_normal[axis] = if direction[axis] > 0.0 { -1 } else { 1 };
+      t_near
+    }
+    _ => 0.0,
+  };
+
+  Some((
+    t,
+    PointedThing::Entity {
+      id: entity.id,
+      intersection_point: start + direction * t,
+      intersection_normal,
+    },
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, vec3, IVec3, Vec3};
+
+  use super::{raycast, PointedThing, RaycastEntity};
+  use crate::game::map::{
+    block_registry::{BlockDefinition, BlockRegistry, LiquidType},
+    chunk::{Chunk, Node},
+    Map,
+  };
+
+  fn test_block(name: &str, pointable: bool, liquid_type: LiquidType) -> BlockDefinition {
+    BlockDefinition {
+      pointable,
+      liquid_type,
+      ..BlockDefinition::test(name)
+    }
+  }
+
+  ///
+  /// Two loaded Chunks side by side, (-1,0,0) and (0,0,0), full of air.
+  ///
+  fn test_world() -> (Map, BlockRegistry, Node, Node) {
+    let mut block_registry = BlockRegistry::new();
+    let stone = block_registry
+      .register_block(test_block("test:stone", true, LiquidType::None))
+      .unwrap_or(0);
+    let water = block_registry
+      .register_block(test_block("test:water", false, LiquidType::Source))
+      .unwrap_or(0);
+
+    let mut map = Map::new();
+    map.insert_chunk(Chunk::new(ivec3(-1, 0, 0)));
+    map.insert_chunk(Chunk::new(ivec3(0, 0, 0)));
+
+    (
+      map,
+      block_registry,
+      Node::new(stone, 0),
+      Node::new(water, 0),
+    )
+  }
+
+  fn get_under(hit: &PointedThing) -> IVec3 {
+    match hit {
+      PointedThing::Node { under, .. } => *under,
+      PointedThing::Entity { .. } => panic!("expected a node, got {:?}", hit),
+    }
+  }
+
+  #[test]
+  fn hits_the_face_it_enters() {
+    let (mut map, block_registry, stone, _) = test_world();
+    map.set_node(ivec3(5, 2, 2), stone);
+
+    let hits = raycast(
+      &map,
+      &block_registry,
+      vec3(0.0, 2.0, 2.0),
+      vec3(10.0, 2.0, 2.0),
+      false,
+      &[],
+    );
+
+    assert_eq!(
+      hits,
+      vec![PointedThing::Node {
+        under: ivec3(5, 2, 2),
+        above: ivec3(4, 2, 2),
+        intersection_point: vec3(4.5, 2.0, 2.0),
+        intersection_normal: ivec3(-1, 0, 0),
+      }]
+    );
+  }
+
+  #[test]
+  fn nearest_hit_comes_first_across_chunks() {
+    let (mut map, block_registry, stone, _) = test_world();
+    map.set_node(ivec3(-3, 3, 3), stone);
+    map.set_node(ivec3(-10, 3, 3), stone);
+    map.set_node(ivec3(2, 3, 3), stone);
+
+    let hits = raycast(
+      &map,
+      &block_registry,
+      vec3(4.0, 3.0, 3.0),
+      vec3(-12.0, 3.0, 3.0),
+      false,
+      &[],
+    );
+
+    let unders: Vec<IVec3> = hits.iter().map(get_under).collect();
+    assert_eq!(
+      unders,
+      vec![ivec3(2, 3, 3), ivec3(-3, 3, 3), ivec3(-10, 3, 3)]
+    );
+  }
+
+  #[test]
+  fn diagonal_rays_walk_every_node() {
+    let (mut map, block_registry, stone, _) = test_world();
+    map.set_node(ivec3(4, 5, 6), stone);
+
+    let hits = raycast(
+      &map,
+      &block_registry,
+      vec3(1.2, 1.7, 2.9),
+      vec3(4.0, 5.0, 6.0),
+      false,
+      &[],
+    );
+
+    assert_eq!(hits.len(), 1);
+    match hits[0] {
+      PointedThing::Node {
+        under,
+        above,
+        intersection_point,
+        intersection_normal,
+      } => {
+        assert_eq!(under, ivec3(4, 5, 6));
+        assert_eq!(above, under + intersection_normal);
+        assert_eq!(intersection_normal.abs().dot(IVec3::ONE), 1);
+        // The hit point is on the face of the node.
+        let local = intersection_point - under.as_vec3();
+        assert!(local.abs().max_element() <= 0.5 + 1e-4);
+        assert!((local.abs().max_element() - 0.5).abs() < 1e-4);
+      }
+      _ => panic!("expected a node"),
+    }
+  }
+
+  #[test]
+  fn starting_inside_a_node_has_no_normal() {
+    let (mut map, block_registry, stone, _) = test_world();
+    map.set_node(ivec3(1, 1, 1), stone);
+
+    let hits = raycast(
+      &map,
+      &block_registry,
+      vec3(1.2, 1.0, 1.0),
+      vec3(3.0, 1.0, 1.0),
+      false,
+      &[],
+    );
+
+    assert_eq!(
+      hits,
+      vec![PointedThing::Node {
+        under: ivec3(1, 1, 1),
+        above: ivec3(1, 1, 1),
+        intersection_point: vec3(1.2, 1.0, 1.0),
+        intersection_normal: IVec3::ZERO,
+      }]
+    );
+  }
+
+  #[test]
+  fn liquids_are_optional_and_unloaded_nodes_are_skipped() {
+    let (mut map, block_registry, stone, water) = test_world();
+    map.set_node(ivec3(0, 8, 8), water);
+    // Past the end of the loaded Chunks.
+    let start = vec3(0.0, 8.0, 3.0);
+    let end = vec3(0.0, 8.0, 40.0);
+
+    assert!(raycast(&map, &block_registry, start, end, false, &[]).is_empty());
+
+    let hits = raycast(&map, &block_registry, start, end, true, &[]);
+    assert_eq!(
+      hits.iter().map(get_under).collect::<Vec<_>>(),
+      vec![ivec3(0, 8, 8)]
+    );
+
+    map.set_node(ivec3(0, 8, 8), stone);
+    let hits = raycast(&map, &block_registry, start, end, false, &[]);
+    assert_eq!(hits.len(), 1);
+  }
+
+  #[test]
+  fn entities_are_mixed_in_by_distance() {
+    let (mut map, block_registry, stone, _) = test_world();
+    map.set_node(ivec3(6, 1, 1), stone);
+
+    let entities = [
+      RaycastEntity {
+        id: 7,
+        min: vec3(2.7, 0.5, 0.5),
+        max: vec3(3.3, 1.5, 1.5),
+      },
+      RaycastEntity {
+        id: 8,
+        min: vec3(8.0, 0.5, 0.5),
+        max: vec3(9.0, 1.5, 1.5),
+      },
+      // Off to the side, never hit.
+      RaycastEntity {
+        id: 9,
+        min: vec3(4.0, 5.0, 5.0),
+        max: vec3(5.0, 6.0, 6.0),
+      },
+    ];
+
+    let hits = raycast(
+      &map,
+      &block_registry,
+      vec3(0.0, 1.0, 1.0),
+      vec3(12.0, 1.0, 1.0),
+      false,
+      &entities,
+    );
+
+    assert_eq!(hits.len(), 3);
+    assert_eq!(
+      hits[0],
+      PointedThing::Entity {
+        id: 7,
+        intersection_point: vec3(2.7, 1.0, 1.0),
+        intersection_normal: ivec3(-1, 0, 0),
+      }
+    );
+    assert_eq!(get_under(&hits[1]), ivec3(6, 1, 1));
+    assert!(matches!(hits[2], PointedThing::Entity { id: 8, .. }));
+
+    // Nodes only.
+    let hits = raycast(
+      &map,
+      &block_registry,
+      vec3(0.0, 1.0, 1.0),
+      vec3(12.0, 1.0, 1.0),
+      false,
+      &[],
+    );
+    assert_eq!(hits.len(), 1);
+  }
+
+  #[test]
+  fn short_rays_stop_at_the_end() {
+    let (mut map, block_registry, stone, _) = test_world();
+    map.set_node(ivec3(5, 1, 1), stone);
+
+    let start = vec3(0.0, 1.0, 1.0);
+    assert!(raycast(
+      &map,
+      &block_registry,
+      start,
+      vec3(4.4, 1.0, 1.0),
+      false,
+      &[]
+    )
+    .is_empty());
+    assert_eq!(
+      raycast(
+        &map,
+        &block_registry,
+        start,
+        vec3(4.6, 1.0, 1.0),
+        false,
+        &[]
+      )
+      .len(),
+      1
+    );
+    assert!(raycast(
+      &map,
+      &block_registry,
+      start,
+      Vec3::new(0.0, 1.0, 1.0),
+      false,
+      &[]
+    )
+    .is_empty());
+  }
+}
@@ -0,0 +1,407 @@
+use std::collections::BTreeMap;
+
+use ahash::AHashMap;
+use glam::IVec3;
+
+use crate::{
+  file_utilities::{read_file_to_byte_vec, write_byte_slice_to_file},
+  game::byte_buffer::{ByteReader, ByteWriter},
+};
+
+use super::{
+  block_registry::{IGNORE_ID, IGNORE_NAME},
+  chunk::Node,
+  node_meta::NodeMeta,
+  Map,
+};
+
+const MAGIC: &[u8] = b"MTREGION";
+const SERIALIZATION_VERSION: u8 = 1;
+
+///
+/// The biggest Region that can be exported or imported. (256x256x256)
+///
+/// This keeps a typo in a position from eating all of the memory.
+///
+pub const MAX_REGION_VOLUME: i64 = 256 * 256 * 256;
+
+///
+/// A node coming out of a Region: (offset from the minimum corner, Node, NodeMeta)
+///
+pub type RegionNode<'a> = (IVec3, Node, Option<&'a NodeMeta>);
+
+///
+/// A box of nodes cut out of a Map, so a build can be moved between worlds.
+///
+/// Block IDs are different in every world, so a Region stores block names.
+/// They're looked up again in the world the Region gets imported into.
+///
+/// * size        - How many nodes it is on each axis.
+/// * block_names - The palette. Every Node's block_id is an index into this.
+/// * nodes       - X first, then Z, then Y. The same order as a Chunk.
+/// * node_meta   - NodeMeta by node index.
+///
+#[derive(Debug, PartialEq)]
+pub struct Region {
+  size: IVec3,
+  block_names: Vec<String>,
+  nodes: Vec<Node>,
+  node_meta: BTreeMap<u32, NodeMeta>,
+}
+
+impl Region {
+  ///
+  /// Copy every node between two corners (inclusive) out of a Map.
+  ///
+  /// block_names is the name of every block ID in the Map's world.
+  /// IGNORE_ID nodes (blocks whose mod is gone) are stored as "ignore".
+  ///
+  /// Every Chunk the Region touches must be in the Map.
+  ///
+  pub fn from_map(
+    map: &Map,
+    block_names: &[String],
+    pos1: IVec3,
+    pos2: IVec3,
+  ) -> Result<Self, String> {
+    let min = pos1.min(pos2);
+    let size = (pos1 - pos2).abs() + IVec3::ONE;
+    Self::check_size(size)?;
+
+    let mut region = Region {
+      size,
+      block_names: vec![],
+      nodes: Vec::with_capacity(Self::get_volume(size) as usize),
+      node_meta: BTreeMap::new(),
+    };
+
+    // World block ID -> palette index.
+    let mut palette: AHashMap<u16, u16> = AHashMap::new();
+
+    for y in 0..size.y {
+      for z in 0..size.z {
+        for x in 0..size.x {
+          let world_position = min + IVec3::new(x, y, z);
+
+          let node = match map.get_node(world_position) {
+            Some(node) => node,
+            None => return Err(format!("Region: [{}] is not loaded.", world_position)),
+          };
+
+          let palette_index = match palette.get(&node.block_id) {
+            Some(palette_index) => *palette_index,
+            None => {
+              let block_name = match block_names.get(node.block_id as usize) {
+                Some(block_name) => block_name.as_str(),
+                None if node.block_id == IGNORE_ID => IGNORE_NAME,
+                None => {
+                  return Err(format!(
+                    "Region: unknown block ID [{}] at [{}].",
+                    node.block_id, world_position
+                  ))
+                }
+              };
+              let palette_index = region.block_names.len() as u16;
+              region.block_names.push(block_name.to_string());
+              palette.insert(node.block_id, palette_index);
+              palette_index
+            }
+          };
+
+          if let Some(node_meta) = map.get_node_meta(world_position) {
+            if !node_meta.is_empty() {
+              region
+                .node_meta
+                .insert(region.nodes.len() as u32, node_meta.clone());
+            }
+          }
+
+          region.nodes.push(Node::new(palette_index, node.param2));
+        }
+      }
+    }
+
+    Ok(region)
+  }
+
+  fn get_volume(size: IVec3) -> i64 {
+    size.x as i64 * size.y as i64 * size.z as i64
+  }
+
+  fn check_size(size: IVec3) -> Result<(), String> {
+    if size.min_element() < 1 || Self::get_volume(size) > MAX_REGION_VOLUME {
+      return Err(format!(
+        "Region: size [{}] is invalid. It can be at most [{}] nodes.",
+        size, MAX_REGION_VOLUME
+      ));
+    }
+    Ok(())
+  }
+
+  pub fn get_size(&self) -> IVec3 {
+    self.size
+  }
+
+  pub fn get_node_count(&self) -> usize {
+    self.nodes.len()
+  }
+
+  fn position_from_index(&self, index: usize) -> IVec3 {
+    let index = index as i32;
+    IVec3::new(
+      index % self.size.x,
+      index / (self.size.x * self.size.z),
+      (index / self.size.x) % self.size.z,
+    )
+  }
+
+  ///
+  /// Turn the Region into (offset, Node, NodeMeta) for every node, with real block IDs.
+  ///
+  /// get_id looks up a block name in the world the Region is going into.
+  /// Fails listing every block that world doesn't have.
+  ///
+  /// "ignore" nodes are left out, so they don't overwrite anything.
+  ///
+  pub fn get_nodes<F: Fn(&str) -> Option<u16>>(
+    &self,
+    get_id: F,
+  ) -> Result<Vec<RegionNode<'_>>, String> {
+    let mut block_ids = vec![];
+    let mut unknown_blocks = vec![];
+    for block_name in &self.block_names {
+      if block_name == IGNORE_NAME {
+        block_ids.push(IGNORE_ID);
+        continue;
+      }
+      match get_id(block_name) {
+        Some(block_id) => block_ids.push(block_id),
+        None => unknown_blocks.push(block_name.as_str()),
+      }
+    }
+
+    if !unknown_blocks.is_empty() {
+      return Err(format!(
+        "Region: this world is missing blocks [{}].",
+        unknown_blocks.join(", ")
+      ));
+    }
+
+    Ok(
+      self
+        .nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| block_ids[node.block_id as usize] != IGNORE_ID)
+        .map(|(index, node)| {
+          (
+            self.position_from_index(index),
+            Node::new(block_ids[node.block_id as usize], node.param2),
+            self.node_meta.get(&(index as u32)),
+          )
+        })
+        .collect(),
+    )
+  }
+
+  ///
+  /// Turn the Region into raw bytes.
+  ///
+  /// * "MTREGION"
+  /// * u8                      - Serialization version.
+  /// * i32 x3                  - Size.
+  /// * u16                     - Palette length, then a string for each block name.
+  /// * (u16, u8) for each node - Palette index and param2.
+  /// * u32                     - NodeMeta count
+  /// * (u32 index, NodeMeta) for each NodeMeta
+  ///
+  pub fn serialize(&self) -> Vec<u8> {
+    let mut writer = ByteWriter::new();
+
+    writer.write_bytes(MAGIC);
+    writer.write_u8(SERIALIZATION_VERSION);
+    writer.write_i32(self.size.x);
+    writer.write_i32(self.size.y);
+    writer.write_i32(self.size.z);
+
+    writer.write_u16(self.block_names.len() as u16);
+    for block_name in &self.block_names {
+      writer.write_string(block_name);
+    }
+
+    for node in &self.nodes {
+      writer.write_u16(node.block_id);
+      writer.write_u8(node.param2);
+    }
+
+    writer.write_u32(self.node_meta.len() as u32);
+    for (index, node_meta) in &self.node_meta {
+      writer.write_u32(*index);
+      node_meta.serialize(&mut writer);
+    }
+
+    writer.into_bytes()
+  }
+
+  ///
+  /// Rebuild a Region out of raw bytes made by serialize().
+  ///
+  pub fn deserialize(bytes: &[u8]) -> Result<Self, String> {
+    Self::read_region(&mut ByteReader::new(bytes))
+      .map_err(|e| format!("Region: not a valid region file. {}", e))
+  }
+
+  fn read_region(reader: &mut ByteReader) -> Result<Self, String> {
+    if reader.read_bytes(MAGIC.len())? != MAGIC {
+      return Err("missing the MTREGION header.".to_string());
+    }
+
+    let version = reader.read_u8()?;
+    if version == 0 || version > SERIALIZATION_VERSION {
+      return Err(format!("unknown serialization version [{}].", version));
+    }
+
+    let size = IVec3::new(reader.read_i32()?, reader.read_i32()?, reader.read_i32()?);
+    Self::check_size(size)?;
+    let volume = Self::get_volume(size) as usize;
+
+    let mut block_names = vec![];
+    for _ in 0..reader.read_u16()? {
+      block_names.push(reader.read_string()?);
+    }
+
+    let mut nodes = Vec::with_capacity(volume);
+    for _ in 0..volume {
+      let node = Node::new(reader.read_u16()?, reader.read_u8()?);
+      if node.block_id as usize >= block_names.len() {
+        return Err(format!(
+          "palette index [{}] is out of bounds.",
+          node.block_id
+        ));
+      }
+      nodes.push(node);
+    }
+
+    let mut node_meta = BTreeMap::new();
+    for _ in 0..reader.read_u32()? {
+      let index = reader.read_u32()?;
+      if index as usize >= volume {
+        return Err(format!("NodeMeta index [{}] is out of bounds.", index));
+      }
+      node_meta.insert(index, NodeMeta::deserialize(reader)?);
+    }
+
+    if reader.get_remaining() != 0 {
+      return Err(format!(
+        "[{}] bytes of trailing data.",
+        reader.get_remaining()
+      ));
+    }
+
+    Ok(Region {
+      size,
+      block_names,
+      nodes,
+      node_meta,
+    })
+  }
+
+  pub fn save_to_file(&self, path: &str) -> Result<(), String> {
+    write_byte_slice_to_file(path, &self.serialize())
+      .map_err(|e| format!("Region: failed to save [{}]. {}", path, e))
+  }
+
+  pub fn load_from_file(path: &str) -> Result<Self, String> {
+    let bytes = read_file_to_byte_vec(path)
+      .map_err(|e| format!("Region: failed to load [{}]. {}", path, e))?;
+    Self::deserialize(&bytes)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, IVec3};
+
+  use super::Region;
+  use crate::game::map::{
+    block_registry::IGNORE_ID,
+    chunk::{Chunk, Node},
+    Map,
+  };
+
+  #[test]
+  fn regions_survive_moving_between_worlds() {
+    let mut map = Map::new();
+    map.insert_chunk(Chunk::new(IVec3::ZERO));
+    map.insert_chunk(Chunk::new(ivec3(-1, 0, 0)));
+    map.set_node(ivec3(-1, 0, 0), Node::new(2, 0));
+    map.set_node(ivec3(1, 1, 0), Node::new(3, 4));
+    if let Some(node_meta) = map.get_node_meta_mut(ivec3(1, 1, 0)) {
+      node_meta.set_string("owner", "singleplayer");
+    }
+
+    let block_names: Vec<String> = ["air", "stone", "dirt", "chest"]
+      .iter()
+      .map(|name| name.to_string())
+      .collect();
+
+    // Corners can be given in any order.
+    let region = Region::from_map(&map, &block_names, ivec3(1, 1, 1), ivec3(-1, 0, 0));
+    let region = match region {
+      Ok(region) => region,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(region.get_size(), ivec3(3, 2, 2));
+
+    let region = match Region::deserialize(&region.serialize()) {
+      Ok(loaded) if loaded == region => loaded,
+      other => panic!("round trip failed. {:?}", other),
+    };
+
+    // The other world has its IDs in a different order.
+    let nodes = region.get_nodes(|name| match name {
+      "air" => Some(0),
+      "chest" => Some(1),
+      "dirt" => Some(5),
+      _ => None,
+    });
+    let nodes = match nodes {
+      Ok(nodes) => nodes,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(nodes.len(), 12);
+    assert_eq!(nodes[0].0, IVec3::ZERO);
+    assert_eq!(nodes[0].1, Node::new(5, 0));
+
+    let chest = nodes
+      .iter()
+      .find(|(offset, _, _)| *offset == ivec3(2, 1, 0));
+    match chest {
+      Some((_, node, Some(node_meta))) => {
+        assert_eq!(*node, Node::new(1, 4));
+        assert_eq!(node_meta.get_string("owner"), "singleplayer");
+      }
+      other => panic!("chest was lost. {:?}", other),
+    }
+
+    assert!(region.get_nodes(|_| None).is_err());
+
+    // Blocks from a removed mod are left alone.
+    map.set_node(ivec3(0, 0, 0), Node::new(IGNORE_ID, 0));
+    let region = match Region::from_map(&map, &block_names, ivec3(0, 0, 0), ivec3(1, 0, 0)) {
+      Ok(region) => region,
+      Err(e) => panic!("{}", e),
+    };
+    match region.get_nodes(|name| {
+      block_names
+        .iter()
+        .position(|n| n == name)
+        .map(|id| id as u16)
+    }) {
+      Ok(nodes) => assert_eq!(nodes.len(), 1),
+      Err(e) => panic!("{}", e),
+    }
+
+    assert!(Region::from_map(&map, &block_names, ivec3(0, 0, 0), ivec3(16, 0, 0)).is_err());
+  }
+}
@@ -0,0 +1,724 @@
+// Schematics are pre-built structures. Trees, houses, dungeons, etc.
+//
+// Files store block names, so a schematic works in any game which has the
+// blocks it uses. Placing one can rotate it, mirror it and swap blocks out,
+// and every node can have a chance of not being placed at all.
+
+use std::collections::BTreeMap;
+
+use ahash::AHashMap;
+use glam::{ivec3, IVec3};
+use mlua::{Table, Value};
+use rand::Rng;
+
+use crate::{
+  file_utilities::{read_file_to_byte_vec, write_byte_slice_to_file},
+  game::{
+    byte_buffer::{ByteReader, ByteWriter},
+    lua_engine::lua_table_helpers::{get_field, get_field_or},
+  },
+};
+
+use super::{
+  block_registry::{BlockRegistry, IGNORE_NAME},
+  chunk::Node,
+  node_meta::NodeMeta,
+  Map,
+};
+
+const MAGIC: &[u8] = b"MTSCHEM";
+const SERIALIZATION_VERSION: u8 = 1;
+
+///
+/// The biggest schematic that can be created or loaded. (256x256x256)
+///
+pub const MAX_SCHEMATIC_VOLUME: i64 = 256 * 256 * 256;
+
+// Palette index of "ignore" in files.
+const IGNORE_INDEX: u16 = u16::MAX;
+
+///
+/// One node of a Schematic.
+///
+/// * probability - Chance out of 255 to be placed. 255 is always, 0 is never.
+/// * force_place - Replace whatever is there. Otherwise only air gets replaced.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SchematicNode {
+  pub block_id: u16,
+  pub param2: u8,
+  pub probability: u8,
+  pub force_place: bool,
+}
+
+///
+/// A box of nodes that can be placed anywhere in the world.
+///
+/// The nodes are ordered Z, then Y, then X. Just like C++ minetest.
+/// None is "ignore", it leaves whatever is already there.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schematic {
+  pub size: IVec3,
+  pub nodes: Vec<Option<SchematicNode>>,
+  pub node_meta: BTreeMap<u32, NodeMeta>,
+}
+
+///
+/// How far a Schematic gets turned around the Y axis, clockwise seen from above.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+  Degrees0,
+  Degrees90,
+  Degrees180,
+  Degrees270,
+  Random,
+}
+
+impl Rotation {
+  ///
+  /// Parse "0", "90", "180", "270" or "random".
+  ///
+  pub fn from_name(name: &str) -> Result<Self, String> {
+    match name {
+      "0" => Ok(Rotation::Degrees0),
+      "90" => Ok(Rotation::Degrees90),
+      "180" => Ok(Rotation::Degrees180),
+      "270" => Ok(Rotation::Degrees270),
+      "random" => Ok(Rotation::Random),
+      _ => Err(format!("unknown rotation [{}].", name)),
+    }
+  }
+
+  fn get_quarter_turns<R: Rng>(&self, rng: &mut R) -> i32 {
+    match self {
+      Rotation::Degrees0 => 0,
+      Rotation::Degrees90 => 1,
+      Rotation::Degrees180 => 2,
+      Rotation::Degrees270 => 3,
+      Rotation::Random => rng.gen_range(0..4),
+    }
+  }
+}
+
+///
+/// Everything about how a Schematic gets placed.
+///
+/// * replacements    - Block ID -> the block ID to place instead.
+/// * force_placement - Force place every node, not only the ones marked force_place.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchematicPlacement {
+  pub rotation: Rotation,
+  pub mirror_x: bool,
+  pub mirror_z: bool,
+  pub place_center_x: bool,
+  pub place_center_y: bool,
+  pub place_center_z: bool,
+  pub force_placement: bool,
+  pub replacements: AHashMap<u16, u16>,
+}
+
+impl SchematicPlacement {
+  pub fn new() -> Self {
+    SchematicPlacement {
+      rotation: Rotation::Degrees0,
+      mirror_x: false,
+      mirror_z: false,
+      place_center_x: false,
+      place_center_y: false,
+      place_center_z: false,
+      force_placement: false,
+      replacements: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Read placement options out of what Lua handed over.
+  ///
+  /// * rotation     - "0", "90", "180", "270" or "random". Defaults to "0".
+  /// * replacements - {["old"] = "new"} or {{"old", "new"}, ...}
+  /// * flags        - Comma separated: place_center_x, place_center_y, place_center_z, mirror_x, mirror_z
+  ///
+  pub fn from_lua(
+    rotation: Option<String>,
+    replacements: Option<Table>,
+    force_placement: bool,
+    flags: Option<String>,
+    block_registry: &BlockRegistry,
+    requester: &str,
+  ) -> Result<Self, String> {
+    let mut placement = SchematicPlacement::new();
+    placement.force_placement = force_placement;
+
+    if let Some(rotation) = rotation {
+      placement.rotation = Rotation::from_name(&rotation)?;
+    }
+
+    for flag in flags.unwrap_or_default().split(',').map(|flag| flag.trim()) {
+      match flag {
+        "" => (),
+        "place_center_x" => placement.place_center_x = true,
+        "place_center_y" => placement.place_center_y = true,
+        "place_center_z" => placement.place_center_z = true,
+        "mirror_x" => placement.mirror_x = true,
+        "mirror_z" => placement.mirror_z = true,
+        _ => return Err(format!("unknown schematic flag [{}].", flag)),
+      }
+    }
+
+    if let Some(replacements) = replacements {
+      for pair in replacements.pairs::<Value, Value>() {
+        let (old_name, new_name) = match pair {
+          Ok((Value::String(old_name), Value::String(new_name))) => (
+            old_name.to_string_lossy().to_string(),
+            new_name.to_string_lossy().to_string(),
+          ),
+          Ok((_, Value::Table(pair))) => (
+            pair.get::<_, String>(1).map_err(|e| e.to_string())?,
+            pair.get::<_, String>(2).map_err(|e| e.to_string())?,
+          ),
+          _ => {
+            return Err(
+              "replacements must be {[\"old\"] = \"new\"} or {{\"old\", \"new\"}} pairs."
+                .to_string(),
+            )
+          }
+        };
+
+        placement.replacements.insert(
+          block_registry.require_id(&old_name, requester)?,
+          block_registry.require_id(&new_name, requester)?,
+        );
+      }
+    }
+
+    Ok(placement)
+  }
+}
+
+impl Schematic {
+  fn get_volume(size: IVec3) -> i64 {
+    size.x as i64 * size.y as i64 * size.z as i64
+  }
+
+  fn check_size(size: IVec3) -> Result<(), String> {
+    if size.min_element() < 1 || Self::get_volume(size) > MAX_SCHEMATIC_VOLUME {
+      return Err(format!(
+        "schematic size [{}] is invalid. It can be at most [{}] nodes.",
+        size, MAX_SCHEMATIC_VOLUME
+      ));
+    }
+    Ok(())
+  }
+
+  fn position_from_index(&self, index: usize) -> IVec3 {
+    let index = index as i32;
+    ivec3(
+      index % self.size.x,
+      (index / self.size.x) % self.size.y,
+      index / (self.size.x * self.size.y),
+    )
+  }
+
+  ///
+  /// Parse a {size = {x, y, z}, data = {{name, prob?, param2?, force_place?}, ...}} table.
+  ///
+  /// "ignore" nodes are left out when placing.
+  ///
+  pub fn from_lua_table(
+    table: &Table,
+    block_registry: &BlockRegistry,
+    requester: &str,
+  ) -> Result<Self, String> {
+    let size_table: Table = get_field(table, "size")?;
+    let size = ivec3(
+      get_field(&size_table, "x")?,
+      get_field(&size_table, "y")?,
+      get_field(&size_table, "z")?,
+    );
+
+    if size.cmple(IVec3::ZERO).any() {
+      return Err(format!("{} schematic size must be positive.", requester));
+    }
+    Self::check_size(size).map_err(|e| format!("{} {}", requester, e))?;
+
+    let data_table: Table = get_field(table, "data")?;
+    let mut nodes = vec![];
+    for entry in data_table.sequence_values::<Table>() {
+      let entry = entry.map_err(|e| format!("{} malformed schematic data. {}", requester, e))?;
+      let name: String = get_field(&entry, "name")?;
+
+      if name == IGNORE_NAME {
+        nodes.push(None);
+        continue;
+      }
+
+      nodes.push(Some(SchematicNode {
+        block_id: block_registry.require_id(&name, requester)?,
+        param2: get_field_or(&entry, "param2", 0)?,
+        probability: get_field_or(&entry, "prob", 255)?,
+        force_place: get_field_or(&entry, "force_place", false)?,
+      }));
+    }
+
+    if nodes.len() as i64 != Self::get_volume(size) {
+      return Err(format!(
+        "{} schematic data has [{}] entries but size needs [{}].",
+        requester,
+        nodes.len(),
+        Self::get_volume(size)
+      ));
+    }
+
+    Ok(Schematic {
+      size,
+      nodes,
+      node_meta: BTreeMap::new(),
+    })
+  }
+
+  ///
+  /// Copy every node between two corners (inclusive) out of a Map.
+  ///
+  /// probabilities sets the chance of single nodes being placed, by world position.
+  /// Everything else is always placed.
+  ///
+  pub fn from_map(
+    map: &Map,
+    pos1: IVec3,
+    pos2: IVec3,
+    probabilities: &AHashMap<IVec3, u8>,
+  ) -> Result<Self, String> {
+    let min = pos1.min(pos2);
+    let size = (pos1 - pos2).abs() + IVec3::ONE;
+    Self::check_size(size)?;
+
+    let mut schematic = Schematic {
+      size,
+      nodes: Vec::with_capacity(Self::get_volume(size) as usize),
+      node_meta: BTreeMap::new(),
+    };
+
+    for z in 0..size.z {
+      for y in 0..size.y {
+        for x in 0..size.x {
+          let world_position = min + ivec3(x, y, z);
+
+          let node = match map.get_node(world_position) {
+            Some(node) => node,
+            None => return Err(format!("[{}] is not loaded.", world_position)),
+          };
+
+          if let Some(node_meta) = map.get_node_meta(world_position) {
+            if !node_meta.is_empty() {
+              schematic
+                .node_meta
+                .insert(schematic.nodes.len() as u32, node_meta.clone());
+            }
+          }
+
+          schematic.nodes.push(Some(SchematicNode {
+            block_id: node.block_id,
+            param2: node.param2,
+            probability: *probabilities.get(&world_position).unwrap_or(&255),
+            force_place: false,
+          }));
+        }
+      }
+    }
+
+    Ok(schematic)
+  }
+
+  ///
+  /// Walk every node that should be placed and where it goes.
+  ///
+  /// Probability is rolled, and rotation, mirroring and replacements are applied.
+  /// place gets (world position, Node, force place, NodeMeta) and decides
+  /// what actually happens, so this works on a Chunk or on the whole Map.
+  ///
+  /// todo: param2 is not rotated, blocks don't say what their param2 means yet.
+  ///
+  pub fn place<R: Rng, F: FnMut(IVec3, Node, bool, Option<&NodeMeta>)>(
+    &self,
+    position: IVec3,
+    placement: &SchematicPlacement,
+    rng: &mut R,
+    mut place: F,
+  ) {
+    let quarter_turns = placement.rotation.get_quarter_turns(rng);
+    let size = self.size;
+    let rotated_size = match quarter_turns % 2 {
+      0 => size,
+      _ => ivec3(size.z, size.y, size.x),
+    };
+
+    let mut corner = position;
+    if placement.place_center_x {
+      corner.x -= (rotated_size.x - 1) / 2;
+    }
+    if placement.place_center_y {
+      corner.y -= (rotated_size.y - 1) / 2;
+    }
+    if placement.place_center_z {
+      corner.z -= (rotated_size.z - 1) / 2;
+    }
+
+    for (index, node) in self.nodes.iter().enumerate() {
+      let node = match node {
+        Some(node) => node,
+        None => continue,
+      };
+
+      if node.probability < 255 && rng.gen::<u8>() >= node.probability {
+        continue;
+      }
+
+      let mut offset = self.position_from_index(index);
+      if placement.mirror_x {
+        offset.x = size.x - 1 - offset.x;
+      }
+      if placement.mirror_z {
+        offset.z = size.z - 1 - offset.z;
+      }
+      let offset = match quarter_turns {
+        1 => ivec3(offset.z, offset.y, size.x - 1 - offset.x),
+        2 => ivec3(size.x - 1 - offset.x, offset.y, size.z - 1 - offset.z),
+        3 => ivec3(size.z - 1 - offset.z, offset.y, offset.x),
+        _ => offset,
+      };
+
+      let block_id = *placement
+        .replacements
+        .get(&node.block_id)
+        .unwrap_or(&node.block_id);
+
+      place(
+        corner + offset,
+        Node::new(block_id, node.param2),
+        node.force_place || placement.force_placement,
+        self.node_meta.get(&(index as u32)),
+      );
+    }
+  }
+
+  ///
+  /// Turn the Schematic into raw bytes.
+  ///
+  /// Runs of the same node are stored once, so big empty areas cost next to nothing.
+  ///
+  /// * "MTSCHEM"
+  /// * u8     - Serialization version.
+  /// * i32 x3 - Size.
+  /// * u16    - Palette length, then a string for each block name.
+  /// * u32    - Run count
+  /// * (u32 length, u16 palette index, u8 param2, u8 probability, u8 force_place) for each run
+  /// * u32    - NodeMeta count
+  /// * (u32 index, NodeMeta) for each NodeMeta
+  ///
+  /// A palette index of u16::MAX is "ignore".
+  ///
+  pub fn serialize(&self, block_registry: &BlockRegistry) -> Vec<u8> {
+    let mut palette: AHashMap<u16, u16> = AHashMap::new();
+    let mut block_names: Vec<&str> = vec![];
+    let mut runs: Vec<(u32, u16, u8, u8, bool)> = vec![];
+
+    for node in &self.nodes {
+      let run = match node {
+        Some(node) => {
+          let palette_index = *palette.entry(node.block_id).or_insert_with(|| {
+            block_names.push(
+              block_registry
+                .get_name(node.block_id)
+                .unwrap_or(IGNORE_NAME),
+            );
+            (block_names.len() - 1) as u16
+          });
+          (
+            palette_index,
+            node.param2,
+            node.probability,
+            node.force_place,
+          )
+        }
+        None => (IGNORE_INDEX, 0, 0, false),
+      };
+
+      match runs.last_mut() {
+        Some(last) if (last.1, last.2, last.3, last.4) == run => last.0 += 1,
+        _ => runs.push((1, run.0, run.1, run.2, run.3)),
+      }
+    }
+
+    let mut writer = ByteWriter::new();
+
+    writer.write_bytes(MAGIC);
+    writer.write_u8(SERIALIZATION_VERSION);
+    writer.write_i32(self.size.x);
+    writer.write_i32(self.size.y);
+    writer.write_i32(self.size.z);
+
+    writer.write_u16(block_names.len() as u16);
+    for block_name in block_names {
+      writer.write_string(block_name);
+    }
+
+    writer.write_u32(runs.len() as u32);
+    for (length, palette_index, param2, probability, force_place) in runs {
+      writer.write_u32(length);
+      writer.write_u16(palette_index);
+      writer.write_u8(param2);
+      writer.write_u8(probability);
+      writer.write_u8(force_place as u8);
+    }
+
+    writer.write_u32(self.node_meta.len() as u32);
+    for (index, node_meta) in &self.node_meta {
+      writer.write_u32(*index);
+      node_meta.serialize(&mut writer);
+    }
+
+    writer.into_bytes()
+  }
+
+  ///
+  /// Rebuild a Schematic out of raw bytes made by serialize().
+  ///
+  /// Block names are looked up in block_registry. Fails listing every block it doesn't have.
+  ///
+  pub fn deserialize(bytes: &[u8], block_registry: &BlockRegistry) -> Result<Self, String> {
+    Self::read_schematic(&mut ByteReader::new(bytes), block_registry)
+  }
+
+  fn read_schematic(
+    reader: &mut ByteReader,
+    block_registry: &BlockRegistry,
+  ) -> Result<Self, String> {
+    if reader.read_bytes(MAGIC.len())? != MAGIC {
+      return Err("missing the MTSCHEM header.".to_string());
+    }
+
+    let version = reader.read_u8()?;
+    if version == 0 || version > SERIALIZATION_VERSION {
+      return Err(format!("unknown serialization version [{}].", version));
+    }
+
+    let size = ivec3(reader.read_i32()?, reader.read_i32()?, reader.read_i32()?);
+    Self::check_size(size)?;
+    let volume = Self::get_volume(size) as usize;
+
+    let mut block_ids = vec![];
+    let mut unknown_blocks = vec![];
+    for _ in 0..reader.read_u16()? {
+      let block_name = reader.read_string()?;
+      match block_registry.get_id(&block_name) {
+        Some(block_id) => block_ids.push(block_id),
+        None => unknown_blocks.push(block_name),
+      }
+    }
+    if !unknown_blocks.is_empty() {
+      return Err(format!(
+        "this game is missing blocks [{}].",
+        unknown_blocks.join(", ")
+      ));
+    }
+
+    let mut nodes = Vec::with_capacity(volume);
+    for _ in 0..reader.read_u32()? {
+      let length = reader.read_u32()? as usize;
+      let palette_index = reader.read_u16()?;
+      let param2 = reader.read_u8()?;
+      let probability = reader.read_u8()?;
+      let force_place = reader.read_u8()? != 0;
+
+      if nodes.len() + length > volume {
+        return Err("more nodes than the size holds.".to_string());
+      }
+
+      let node = match palette_index {
+        IGNORE_INDEX => None,
+        _ => match block_ids.get(palette_index as usize) {
+          Some(block_id) => Some(SchematicNode {
+            block_id: *block_id,
+            param2,
+            probability,
+            force_place,
+          }),
+          None => {
+            return Err(format!(
+              "palette index [{}] is out of bounds.",
+              palette_index
+            ))
+          }
+        },
+      };
+      nodes.extend(std::iter::repeat(node).take(length));
+    }
+
+    if nodes.len() != volume {
+      return Err(format!(
+        "[{}] nodes but the size needs [{}].",
+        nodes.len(),
+        volume
+      ));
+    }
+
+    let mut node_meta = BTreeMap::new();
+    for _ in 0..reader.read_u32()? {
+      let index = reader.read_u32()?;
+      if index as usize >= volume {
+        return Err(format!("NodeMeta index [{}] is out of bounds.", index));
+      }
+      node_meta.insert(index, NodeMeta::deserialize(reader)?);
+    }
+
+    if reader.get_remaining() != 0 {
+      return Err(format!(
+        "[{}] bytes of trailing data.",
+        reader.get_remaining()
+      ));
+    }
+
+    Ok(Schematic {
+      size,
+      nodes,
+      node_meta,
+    })
+  }
+
+  pub fn save_to_file(&self, path: &str, block_registry: &BlockRegistry) -> Result<(), String> {
+    write_byte_slice_to_file(path, &self.serialize(block_registry))
+      .map_err(|e| format!("failed to save schematic [{}]. {}", path, e))
+  }
+
+  pub fn load_from_file(path: &str, block_registry: &BlockRegistry) -> Result<Self, String> {
+    let bytes = read_file_to_byte_vec(path)
+      .map_err(|e| format!("failed to load schematic [{}]. {}", path, e))?;
+    Self::deserialize(&bytes, block_registry)
+      .map_err(|e| format!("schematic [{}] is not valid. {}", path, e))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use ahash::AHashMap;
+  use glam::{ivec3, IVec3};
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::{Rotation, Schematic, SchematicNode, SchematicPlacement};
+  use crate::game::map::{
+    block_registry::{BlockDefinition, BlockRegistry},
+    node_meta::NodeMeta,
+  };
+
+  fn test_node(block_id: u16) -> Option<SchematicNode> {
+    Some(SchematicNode {
+      block_id,
+      param2: 0,
+      probability: 255,
+      force_place: false,
+    })
+  }
+
+  fn place(schematic: &Schematic, placement: &SchematicPlacement) -> AHashMap<IVec3, u16> {
+    let mut placed = AHashMap::new();
+    schematic.place(
+      IVec3::ZERO,
+      placement,
+      &mut StdRng::seed_from_u64(0),
+      |position, node, _, _| {
+        placed.insert(position, node.block_id);
+      },
+    );
+    placed
+  }
+
+  #[test]
+  fn placement_rotates_mirrors_and_replaces() {
+    // 2 wide and 3 deep. The block ID at (x, 0, z) is 1 + x + z * 2.
+    let schematic = Schematic {
+      size: ivec3(2, 1, 3),
+      nodes: (1..=6).map(test_node).collect(),
+      node_meta: BTreeMap::new(),
+    };
+
+    let mut placement = SchematicPlacement::new();
+    let placed = place(&schematic, &placement);
+    assert_eq!(placed[&ivec3(1, 0, 2)], 6);
+
+    placement.rotation = Rotation::Degrees90;
+    let placed = place(&schematic, &placement);
+    assert_eq!(placed.len(), 6);
+    assert_eq!(placed[&ivec3(0, 0, 1)], 1);
+    assert_eq!(placed[&ivec3(0, 0, 0)], 2);
+    assert_eq!(placed[&ivec3(2, 0, 1)], 5);
+
+    // 4 quarter turns are back where it started.
+    placement.rotation = Rotation::Degrees270;
+    let placed_270 = place(&schematic, &placement);
+    placement.rotation = Rotation::Degrees180;
+    let placed_180 = place(&schematic, &placement);
+    assert_eq!(placed_180[&ivec3(1, 0, 2)], 1);
+    assert_eq!(placed_270[&ivec3(2, 0, 0)], 1);
+
+    placement.rotation = Rotation::Degrees0;
+    placement.mirror_x = true;
+    placement.place_center_z = true;
+    placement.replacements.insert(3, 9);
+    let placed = place(&schematic, &placement);
+    assert_eq!(placed[&ivec3(1, 0, -1)], 1);
+    assert_eq!(placed[&ivec3(1, 0, 0)], 9);
+  }
+
+  #[test]
+  fn schematic_files_round_trip() {
+    let mut block_registry = BlockRegistry::new();
+    let stone = block_registry
+      .register_block(BlockDefinition::test("test:stone"))
+      .unwrap_or(0);
+    let dirt = block_registry
+      .register_block(BlockDefinition::test("test:dirt"))
+      .unwrap_or(0);
+
+    let mut nodes = vec![None; 4 * 4 * 4];
+    for node in nodes.iter_mut().take(16) {
+      *node = test_node(stone);
+    }
+    nodes[20] = Some(SchematicNode {
+      block_id: dirt,
+      param2: 3,
+      probability: 100,
+      force_place: true,
+    });
+
+    let mut node_meta = NodeMeta::new();
+    node_meta.set_string("text", "hello");
+
+    let schematic = Schematic {
+      size: ivec3(4, 4, 4),
+      nodes,
+      node_meta: BTreeMap::from([(5, node_meta)]),
+    };
+
+    let bytes = schematic.serialize(&block_registry);
+    // 4 runs instead of 64 nodes, smaller than even 3 bytes a node.
+    assert!(bytes.len() < 64 * 3);
+
+    match Schematic::deserialize(&bytes, &block_registry) {
+      Ok(loaded) => assert_eq!(loaded, schematic),
+      Err(e) => panic!("{}", e),
+    }
+
+    // A game without dirt can't load it.
+    let mut other_registry = BlockRegistry::new();
+    let _ = other_registry.register_block(BlockDefinition::test("test:stone"));
+    match Schematic::deserialize(&bytes, &other_registry) {
+      Ok(_) => panic!("loaded a schematic with a missing block."),
+      Err(e) => assert!(e.contains("test:dirt")),
+    }
+  }
+}
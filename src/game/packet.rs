@@ -0,0 +1,425 @@
+use glam::{IVec3, Vec3};
+
+use super::{
+  byte_buffer::{ByteReader, ByteWriter},
+  inventory::{Inventory, InventoryLocation},
+  map::{chunk::Node, raycast::PointedThing},
+};
+
+///
+/// The first byte of every binary Packet.
+///
+/// 0xFF can never show up in UTF-8, so binary Packets can't be mixed up
+/// with the plain text messages (handshake, ping, etc).
+///
+pub const PACKET_MARKER: u8 = 0xFF;
+
+///
+/// How many entries a single list Packet carries.
+/// Keeps every Packet well under the UDP size limit.
+///
+pub const MAX_PACKET_ENTRIES: usize = 2048;
+
+///
+/// Binary messages between the Server and the Client.
+///
+/// Server -> Client:
+/// * ChunkData      - A whole Chunk from Chunk::serialize_for_client().
+/// * NodeChanges    - Nodes which changed in Chunks the Client already has.
+/// * ForgetChunks   - Chunks which left the Client's view range.
+/// * InventoryData  - A whole Inventory the Client can see. An empty one means it's gone.
+///
+/// Client -> Server:
+/// * PlayerPosition - Where the Client is, so the Server knows what to send.
+/// * DigStart       - The player started digging what they're pointing at, with the item in main[wield_index].
+/// * DigStop        - The player stopped digging before it finished.
+/// * Place          - The player placed the item in main[wield_index] against what they're pointing at.
+/// * InventoryMove  - The player moved items from one slot to another.
+/// * ChunksReceived - ChunkData which made it, so the Server doesn't send it again.
+///
+/// The Server decides if digging and placing actually happen. The Client
+/// finds out through NodeChanges like everyone else. The same goes for
+/// InventoryMove, the Client gets the result back as InventoryData.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+  ChunkData {
+    position: IVec3,
+    data: Vec<u8>,
+  },
+  NodeChanges(Vec<(IVec3, Node)>),
+  ForgetChunks(Vec<IVec3>),
+  PlayerPosition(Vec3),
+  DigStart {
+    pointed_thing: PointedThing,
+    wield_index: u32,
+  },
+  DigStop(PointedThing),
+  Place {
+    pointed_thing: PointedThing,
+    wield_index: u32,
+  },
+  InventoryData {
+    location: InventoryLocation,
+    inventory: Inventory,
+  },
+  InventoryMove {
+    from: InventoryLocation,
+    from_list: String,
+    from_index: u32,
+    to: InventoryLocation,
+    to_list: String,
+    to_index: u32,
+    count: u16,
+  },
+  // Typed by a player on the way to the Server, something to show on the way to a client.
+  ChatMessage(String),
+  ChunksReceived(Vec<IVec3>),
+}
+
+impl Packet {
+  fn get_id(&self) -> u8 {
+    match self {
+      Packet::ChunkData { .. } => 0,
+      Packet::NodeChanges(_) => 1,
+      Packet::ForgetChunks(_) => 2,
+      Packet::PlayerPosition(_) => 3,
+      Packet::DigStart { .. } => 4,
+      Packet::DigStop(_) => 5,
+      Packet::Place { .. } => 6,
+      Packet::InventoryData { .. } => 7,
+      Packet::InventoryMove { .. } => 8,
+      Packet::ChatMessage(_) => 9,
+      Packet::ChunksReceived(_) => 10,
+    }
+  }
+
+  ///
+  /// Check if raw network data is a binary Packet.
+  ///
+  pub fn is_packet(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&PACKET_MARKER)
+  }
+
+  pub fn serialize(&self) -> Vec<u8> {
+    let mut writer = ByteWriter::new();
+
+    writer.write_u8(PACKET_MARKER);
+    writer.write_u8(self.get_id());
+
+    match self {
+      Packet::ChunkData { position, data } => {
+        write_ivec3(&mut writer, *position);
+        writer.write_u32(data.len() as u32);
+        writer.write_bytes(data);
+      }
+      Packet::NodeChanges(node_changes) => {
+        writer.write_u32(node_changes.len() as u32);
+        for (position, node) in node_changes {
+          write_ivec3(&mut writer, *position);
+          writer.write_u16(node.block_id);
+          writer.write_u8(node.param2);
+        }
+      }
+      Packet::ForgetChunks(chunk_positions) | Packet::ChunksReceived(chunk_positions) => {
+        writer.write_u32(chunk_positions.len() as u32);
+        for chunk_position in chunk_positions {
+          write_ivec3(&mut writer, *chunk_position);
+        }
+      }
+      Packet::PlayerPosition(position) => write_vec3(&mut writer, *position),
+      Packet::DigStop(pointed_thing) => write_pointed_thing(&mut writer, pointed_thing),
+      Packet::DigStart {
+        pointed_thing,
+        wield_index,
+      }
+      | Packet::Place {
+        pointed_thing,
+        wield_index,
+      } => {
+        write_pointed_thing(&mut writer, pointed_thing);
+        writer.write_u32(*wield_index);
+      }
+      Packet::InventoryData {
+        location,
+        inventory,
+      } => {
+        location.serialize(&mut writer);
+        inventory.serialize(&mut writer);
+      }
+      Packet::InventoryMove {
+        from,
+        from_list,
+        from_index,
+        to,
+        to_list,
+        to_index,
+        count,
+      } => {
+        from.serialize(&mut writer);
+        writer.write_string(from_list);
+        writer.write_u32(*from_index);
+        to.serialize(&mut writer);
+        writer.write_string(to_list);
+        writer.write_u32(*to_index);
+        writer.write_u16(*count);
+      }
+      Packet::ChatMessage(message) => writer.write_string(message),
+    }
+
+    writer.into_bytes()
+  }
+
+  ///
+  /// Rebuild a Packet out of raw network data.
+  ///
+  /// This is data from the network, anything could be in here.
+  ///
+  pub fn deserialize(bytes: &[u8]) -> Result<Self, String> {
+    Self::read_packet(&mut ByteReader::new(bytes)).map_err(|e| format!("Packet: {}", e))
+  }
+
+  fn read_packet(reader: &mut ByteReader) -> Result<Self, String> {
+    if reader.read_u8()? != PACKET_MARKER {
+      return Err("missing packet marker.".to_string());
+    }
+
+    let packet = match reader.read_u8()? {
+      0 => {
+        let position = read_ivec3(reader)?;
+        let length = reader.read_u32()? as usize;
+        Packet::ChunkData {
+          position,
+          data: reader.read_bytes(length)?.to_vec(),
+        }
+      }
+      1 => {
+        let count = read_count(reader)?;
+        let mut node_changes = Vec::with_capacity(count);
+        for _ in 0..count {
+          let position = read_ivec3(reader)?;
+          let block_id = reader.read_u16()?;
+          let param2 = reader.read_u8()?;
+          node_changes.push((position, Node::new(block_id, param2)));
+        }
+        Packet::NodeChanges(node_changes)
+      }
+      2 => Packet::ForgetChunks(read_chunk_positions(reader)?),
+      3 => Packet::PlayerPosition(read_vec3(reader)?),
+      4 => Packet::DigStart {
+        pointed_thing: read_pointed_thing(reader)?,
+        wield_index: reader.read_u32()?,
+      },
+      5 => Packet::DigStop(read_pointed_thing(reader)?),
+      6 => Packet::Place {
+        pointed_thing: read_pointed_thing(reader)?,
+        wield_index: reader.read_u32()?,
+      },
+      7 => Packet::InventoryData {
+        location: InventoryLocation::deserialize(reader)?,
+        inventory: Inventory::deserialize(reader)?,
+      },
+      8 => Packet::InventoryMove {
+        from: InventoryLocation::deserialize(reader)?,
+        from_list: reader.read_string()?,
+        from_index: reader.read_u32()?,
+        to: InventoryLocation::deserialize(reader)?,
+        to_list: reader.read_string()?,
+        to_index: reader.read_u32()?,
+        count: reader.read_u16()?,
+      },
+      9 => Packet::ChatMessage(reader.read_string()?),
+      10 => Packet::ChunksReceived(read_chunk_positions(reader)?),
+      id => return Err(format!("unknown packet id [{}].", id)),
+    };
+
+    if reader.get_remaining() != 0 {
+      return Err(format!(
+        "[{}] bytes of trailing data.",
+        reader.get_remaining()
+      ));
+    }
+
+    Ok(packet)
+  }
+}
+
+fn write_ivec3(writer: &mut ByteWriter, value: IVec3) {
+  writer.write_i32(value.x);
+  writer.write_i32(value.y);
+  writer.write_i32(value.z);
+}
+
+fn read_ivec3(reader: &mut ByteReader) -> Result<IVec3, String> {
+  Ok(IVec3::new(
+    reader.read_i32()?,
+    reader.read_i32()?,
+    reader.read_i32()?,
+  ))
+}
+
+fn read_chunk_positions(reader: &mut ByteReader) -> Result<Vec<IVec3>, String> {
+  let count = read_count(reader)?;
+  let mut chunk_positions = Vec::with_capacity(count);
+  for _ in 0..count {
+    chunk_positions.push(read_ivec3(reader)?);
+  }
+  Ok(chunk_positions)
+}
+
+fn write_vec3(writer: &mut ByteWriter, value: Vec3) {
+  writer.write_f32(value.x);
+  writer.write_f32(value.y);
+  writer.write_f32(value.z);
+}
+
+fn read_vec3(reader: &mut ByteReader) -> Result<Vec3, String> {
+  Ok(Vec3::new(
+    reader.read_f32()?,
+    reader.read_f32()?,
+    reader.read_f32()?,
+  ))
+}
+
+fn write_pointed_thing(writer: &mut ByteWriter, pointed_thing: &PointedThing) {
+  match pointed_thing {
+    PointedThing::Node {
+      under,
+      above,
+      intersection_point,
+      intersection_normal,
+    } => {
+      writer.write_u8(0);
+      write_ivec3(writer, *under);
+      write_ivec3(writer, *above);
+      write_vec3(writer, *intersection_point);
+      write_ivec3(writer, *intersection_normal);
+    }
+    PointedThing::Entity {
+      id,
+      intersection_point,
+      intersection_normal,
+    } => {
+      writer.write_u8(1);
+      writer.write_u64(*id);
+      write_vec3(writer, *intersection_point);
+      write_ivec3(writer, *intersection_normal);
+    }
+  }
+}
+
+fn read_pointed_thing(reader: &mut ByteReader) -> Result<PointedThing, String> {
+  match reader.read_u8()? {
+    0 => Ok(PointedThing::Node {
+      under: read_ivec3(reader)?,
+      above: read_ivec3(reader)?,
+      intersection_point: read_vec3(reader)?,
+      intersection_normal: read_ivec3(reader)?,
+    }),
+    1 => Ok(PointedThing::Entity {
+      id: reader.read_u64()?,
+      intersection_point: read_vec3(reader)?,
+      intersection_normal: read_ivec3(reader)?,
+    }),
+    kind => Err(format!("unknown pointed thing type [{}].", kind)),
+  }
+}
+
+///
+/// Read a list length, refusing anything a real Packet would never have.
+///
+fn read_count(reader: &mut ByteReader) -> Result<usize, String> {
+  let count = reader.read_u32()? as usize;
+  if count > MAX_PACKET_ENTRIES {
+    return Err(format!(
+      "list of [{}] entries is over the limit of [{}].",
+      count, MAX_PACKET_ENTRIES
+    ));
+  }
+  Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{IVec3, Vec3};
+
+  use super::Packet;
+  use crate::game::{
+    inventory::{Inventory, InventoryLocation},
+    item_stack::ItemStack,
+    map::{chunk::Node, raycast::PointedThing},
+  };
+
+  #[test]
+  fn packets_survive_a_round_trip() {
+    let mut inventory = Inventory::new();
+    inventory.set_size("main", 2);
+    assert!(inventory
+      .set_stack("main", 1, ItemStack::new("minetest:dirt", 5))
+      .is_ok());
+
+    let packets = vec![
+      Packet::ChunkData {
+        position: IVec3::new(-1, 2, -3),
+        data: vec![1, 2, 3],
+      },
+      Packet::NodeChanges(vec![(IVec3::new(5, -6, 7), Node::new(4, 3))]),
+      Packet::ForgetChunks(vec![IVec3::ZERO, IVec3::NEG_ONE]),
+      Packet::ChunksReceived(vec![IVec3::ONE]),
+      Packet::PlayerPosition(Vec3::new(0.5, -1.5, 100.0)),
+      Packet::DigStart {
+        pointed_thing: PointedThing::Node {
+          under: IVec3::new(1, 2, 3),
+          above: IVec3::new(1, 3, 3),
+          intersection_point: Vec3::new(1.0, 2.5, 3.0),
+          intersection_normal: IVec3::Y,
+        },
+        wield_index: 7,
+      },
+      Packet::DigStop(PointedThing::Entity {
+        id: u64::MAX - 1,
+        intersection_point: Vec3::ZERO,
+        intersection_normal: IVec3::NEG_X,
+      }),
+      Packet::Place {
+        pointed_thing: PointedThing::Node {
+          under: IVec3::ZERO,
+          above: IVec3::Z,
+          intersection_point: Vec3::new(0.0, 0.0, 0.5),
+          intersection_normal: IVec3::Z,
+        },
+        wield_index: 3,
+      },
+      Packet::InventoryData {
+        location: InventoryLocation::Player("singleplayer".to_string()),
+        inventory,
+      },
+      Packet::InventoryMove {
+        from: InventoryLocation::Node(IVec3::new(1, -2, 3)),
+        from_list: "main".to_string(),
+        from_index: 0,
+        to: InventoryLocation::Detached("trash".to_string()),
+        to_list: "main".to_string(),
+        to_index: 7,
+        count: 99,
+      },
+      Packet::ChatMessage("<singleplayer> hi!".to_string()),
+    ];
+
+    for packet in packets {
+      let bytes = packet.serialize();
+      assert!(Packet::is_packet(&bytes));
+      assert_eq!(Packet::deserialize(&bytes), Ok(packet));
+    }
+  }
+
+  #[test]
+  fn broken_packets_are_errors() {
+    let bytes = Packet::ForgetChunks(vec![IVec3::ONE]).serialize();
+
+    assert!(Packet::deserialize(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Packet::deserialize(&[bytes.as_slice(), &[0]].concat()).is_err());
+    assert!(Packet::deserialize(b"MINETEST_HAND_SHAKE").is_err());
+    assert!(Packet::deserialize(&[0xFF, 200]).is_err());
+  }
+}
@@ -1,8 +1,78 @@
+mod abm;
+mod active_chunks;
+mod chat;
+mod chunk_streamer;
+mod console;
+mod emerge;
+mod falling_nodes;
+mod game_config;
+mod interaction;
+mod inventory_actions;
+mod lifecycle;
+mod liquids;
+mod lua_api;
+mod map_database;
+mod mapgen;
+mod node_timers;
+mod protection;
+mod rollback;
 mod server_connection;
+mod server_environment;
+pub mod world_tools;
 
-use self::server_connection::ServerConnection;
+use std::{cell::RefCell, rc::Rc, sync::Arc, thread, time::Duration};
 
-use super::lua_engine::LuaEngine;
+use glam::{ivec3, IVec3};
+use mlua::Function;
+
+use crate::file_utilities::create_dir;
+
+use self::{
+  abm::AbmRunner,
+  active_chunks::ActiveChunks,
+  chat::{send_chat_messages, Chat, ChatMessage},
+  chunk_streamer::ChunkStreamer,
+  console::{Console, ConsoleCommand, CONSOLE_HELP},
+  emerge::{Emerge, EmergeAction, EmergeAreaRequest, EmergeCallback},
+  falling_nodes::FallingNodeRunner,
+  game_config::GameConfig,
+  interaction::Interaction,
+  inventory_actions::{create_player_inventory, send_inventory, InventoryActions},
+  lifecycle::LifecycleCallbacks,
+  liquids::LiquidRunner,
+  map_database::MapDatabase,
+  mapgen::Mapgen,
+  node_timers::NodeTimerRunner,
+  protection::AreaProtection,
+  rollback::{
+    call_as_mod, get_player_actor, get_rollback_start_time, revert_node_actions, ABM_ACTOR,
+    CONSOLE_ACTOR, DEFAULT_ACTOR, FALLING_NODE_ACTOR, LIQUID_ACTOR, NODE_TIMER_ACTOR,
+    ROLLBACK_ACTOR,
+  },
+  server_connection::ServerConnection,
+  server_environment::{ServerEnvironment, PLAYER_HP_MAX},
+  world_tools::{export_region, get_default_backup_path},
+};
+
+use super::{
+  crafting::CraftRegistry,
+  inventory::InventoryLocation,
+  item_registry::ItemRegistry,
+  lua_engine::{
+    lua_item_stack::set_item_registry, lua_table_helpers::node_position_to_vector, LuaEngine,
+  },
+  map::{
+    block_registry::BlockRegistry,
+    chunk::{world_to_chunk_position, Chunk},
+    region::Region,
+  },
+};
+
+///
+/// How many Chunks can be unloaded in a single tick.
+/// Keeps a mass unload from stalling the Server.
+///
+const MAX_UNLOADS_PER_TICK: usize = 256;
 
 ///
 /// The Server component for the engine.
@@ -13,6 +83,7 @@ use super::lua_engine::LuaEngine;
 /// * 3.) [in the future] Be the main handler for ServerAuthentication.
 /// *  - ServerAuthentication does exactly what you think it does.
 /// *  - It handles the client auth for the server.
+///
 /// ? 4.) Handle GameConfig as a component to be utilized during runtime.
 /// ?  - Marked with ? because it's still being thought out at the moment.
 ///
@@ -20,20 +91,89 @@ pub struct Server {
   lua_engine: LuaEngine,
   connection: ServerConnection,
   shutdown_approved: bool,
+
+  mapgen: Arc<Mapgen>,
+
+  world_path: String,
+  database: MapDatabase,
+  environment: Rc<RefCell<ServerEnvironment>>,
+  emerge: Emerge,
+  game_config: GameConfig,
+  active_chunks: ActiveChunks,
+  chunk_streamer: ChunkStreamer,
+
+  abm_runner: AbmRunner,
+  node_timer_runner: NodeTimerRunner,
+  liquid_runner: LiquidRunner,
+  falling_node_runner: FallingNodeRunner,
+  interaction: Interaction,
+  inventory_actions: InventoryActions,
+  chat: Chat,
+  lifecycle: LifecycleCallbacks,
+  console: Console,
+
+  // minetest.emerge_area calls land in here until the next tick.
+  emerge_area_requests: Rc<RefCell<Vec<EmergeAreaRequest>>>,
+  // Chat waiting to go out at the end of the tick.
+  chat_messages: Rc<RefCell<Vec<ChatMessage>>>,
 }
 
 impl Server {
-  pub fn new(address: String, port: i32, game_name: String) -> Self {
+  pub fn new(address: String, port: i32, game_name: String, world_name: String) -> Self {
     // Create a connection.
     let connection = ServerConnection::new(address, port);
 
     // Create the base Luau virtual machine.
     let lua_engine = LuaEngine::new(true);
 
+    // Open up the world. New worlds get created automatically.
+    let world_path = Self::get_world_path(&world_name);
+    if let Err(e) = create_dir(&world_path) {
+      panic!("Server: {}", e);
+    }
+    let database = match MapDatabase::new(&Self::get_database_path(&world_path)) {
+      Ok(database) => database,
+      Err(e) => panic!("Server: {}", e),
+    };
+    let seed = match database.get_or_create_seed() {
+      Ok(seed) => seed,
+      Err(e) => panic!("Server: {}", e),
+    };
+    println!("Server: loaded world [{}] with seed [{}]", world_name, seed);
+
+    let mut environment = ServerEnvironment::new();
+    match database.load_protected_areas() {
+      Ok(areas) => environment.set_area_protection(AreaProtection::from_areas(areas)),
+      Err(e) => panic!("Server: {}", e),
+    }
+
     let mut new_server = Server {
       lua_engine,
       connection,
       shutdown_approved: false,
+
+      mapgen: Arc::new(Mapgen::new(seed)),
+
+      world_path,
+      database,
+      environment: Rc::new(RefCell::new(environment)),
+      emerge: Emerge::new(),
+      game_config: GameConfig::new(),
+      active_chunks: Self::create_active_chunks(&GameConfig::new()),
+      chunk_streamer: Self::create_chunk_streamer(&GameConfig::new()),
+
+      abm_runner: AbmRunner::new(),
+      node_timer_runner: NodeTimerRunner::new(),
+      liquid_runner: LiquidRunner::new(),
+      falling_node_runner: FallingNodeRunner::new(),
+      interaction: Interaction::new(),
+      inventory_actions: InventoryActions::new(),
+      chat: Chat::new(),
+      lifecycle: LifecycleCallbacks::new(),
+      console: Console::new(),
+
+      emerge_area_requests: Rc::new(RefCell::new(vec![])),
+      chat_messages: Rc::new(RefCell::new(vec![])),
     };
 
     // Automatically create a new Server LuaEngine.
@@ -45,19 +185,230 @@ impl Server {
     new_server
   }
 
+  ///
+  /// Where a world lives.
+  ///
+  pub fn get_world_path(world_name: &str) -> String {
+    format!("./worlds/{}", world_name)
+  }
+
+  ///
+  /// Where the map database lives inside of a world.
+  ///
+  pub fn get_database_path(world_path: &str) -> String {
+    format!("{}/map.sqlite", world_path)
+  }
+
+  ///
+  /// Set up active chunk tracking from the game's config.
+  ///
+  fn create_active_chunks(game_config: &GameConfig) -> ActiveChunks {
+    ActiveChunks::new(
+      game_config.hibernation_timeout,
+      game_config.active_chunk_range,
+      game_config.max_loaded_chunks,
+    )
+  }
+
+  ///
+  /// Set up chunk streaming from the game's config.
+  ///
+  fn create_chunk_streamer(game_config: &GameConfig) -> ChunkStreamer {
+    ChunkStreamer::new(game_config.view_range, game_config.chunk_sends_per_tick)
+  }
+
   ///
   /// Wipe the memory of the lua VM.
   /// Automatically regenerates a blank server VM.
   ///
   pub fn reset_lua_vm(&mut self) {
     self.lua_engine = LuaEngine::new(true);
+    self.emerge_area_requests.borrow_mut().clear();
+    self.chat_messages.borrow_mut().clear();
+
+    // These hold onto Lua functions from the old VM.
+    self.abm_runner = AbmRunner::new();
+    self.node_timer_runner = NodeTimerRunner::new();
+    self.interaction = Interaction::new();
+    self.inventory_actions = InventoryActions::new();
+    self.chat = Chat::new();
+    self.lifecycle = LifecycleCallbacks::new();
+    self.liquid_runner = LiquidRunner::new();
+    self.falling_node_runner = FallingNodeRunner::new();
+
+    self.register_lua_api();
+  }
+
+  ///
+  /// Hand the Rust side of the minetest API to the LuaEngine.
+  ///
+  fn register_lua_api(&self) {
+    lua_api::register_emerge_api(&self.lua_engine, self.emerge_area_requests.clone());
+    lua_api::register_node_api(&self.lua_engine, self.environment.clone());
+    lua_api::register_raycast_api(&self.lua_engine, self.environment.clone());
+    lua_api::register_schematic_api(&self.lua_engine, self.environment.clone(), &self.world_path);
+    lua_api::register_protection_api(&self.lua_engine, self.environment.clone());
+    lua_api::register_inventory_api(&self.lua_engine, self.environment.clone());
+    lua_api::register_craft_api(&self.lua_engine, self.environment.clone());
+    lua_api::register_chat_api(&self.lua_engine, self.chat_messages.clone());
+    lua_api::register_rollback_api(
+      &self.lua_engine,
+      self.environment.clone(),
+      &Self::get_database_path(&self.world_path),
+    );
   }
 
   ///
   /// Chain initial game load into LuaEngine to clean up new() implemenetation.
   ///
   pub fn load_game(&mut self, game_name: String) {
-    self.lua_engine.load_game(game_name)
+    self.lua_engine.load_game(game_name);
+
+    self.game_config = match GameConfig::from_game_conf(self.lua_engine.get_game_conf()) {
+      Ok(game_config) => game_config,
+      Err(e) => panic!("Server: {}", e),
+    };
+    self.active_chunks = Self::create_active_chunks(&self.game_config);
+    self.chunk_streamer = Self::create_chunk_streamer(&self.game_config);
+    self
+      .connection
+      .set_default_privileges(&self.game_config.default_privileges);
+    self
+      .environment
+      .borrow_mut()
+      .set_rollback_enabled(self.game_config.enable_rollback);
+
+    // Now that every mod has run, we can pick up what they registered.
+    self.load_definitions();
+    self
+      .lifecycle
+      .on_mods_loaded(self.lua_engine.get_lua(), &self.environment);
+
+    // The mapgen is finalized, the map can start emerging.
+    let block_registry = Arc::new(self.environment.borrow().get_block_registry().clone());
+    self.emerge.start_workers(
+      self.mapgen.clone(),
+      block_registry,
+      &Self::get_database_path(&self.world_path),
+    );
+  }
+
+  ///
+  /// Pull the registered blocks, items, ABMs and mapgen definitions out of the LuaEngine.
+  ///
+  /// Just like LuaEngine internals, the game should simply crash if this fails.
+  /// A map generated out of broken definitions is a broken map.
+  ///
+  fn load_definitions(&mut self) {
+    let get_table = |table_name: &str| match self.lua_engine.get_internal_table(table_name) {
+      Ok(table) => table,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    // Blocks keep the IDs the world gave them, so saved Chunks never change meaning.
+    let saved_block_names = match self.database.get_block_names() {
+      Ok(saved_block_names) => saved_block_names.unwrap_or_default(),
+      Err(e) => panic!("Server: {}", e),
+    };
+    let block_registry =
+      match BlockRegistry::from_lua_table(&get_table("blocks"), &saved_block_names) {
+        Ok(block_registry) => block_registry,
+        Err(e) => panic!("Server: {}", e),
+      };
+    if let Err(e) = self
+      .database
+      .set_block_names(block_registry.get_block_names())
+    {
+      panic!("Server: {}", e);
+    }
+
+    let item_registry =
+      match ItemRegistry::from_lua_tables(&get_table("blocks"), &get_table("items")) {
+        Ok(item_registry) => Rc::new(item_registry),
+        Err(e) => panic!("Server: {}", e),
+      };
+    set_item_registry(self.lua_engine.get_lua(), item_registry.clone());
+
+    let craft_registry = match CraftRegistry::from_lua_table(&get_table("crafts")) {
+      Ok(craft_registry) => craft_registry,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    self.mapgen = match Mapgen::from_lua_tables(
+      self.lua_engine.get_lua(),
+      self.mapgen.get_seed(),
+      &block_registry,
+      &get_table("biomes"),
+      &get_table("ores"),
+      &get_table("decorations"),
+    ) {
+      Ok(mapgen) => Arc::new(mapgen),
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    self.abm_runner = match AbmRunner::from_lua_table(
+      self.lua_engine.get_lua(),
+      &get_table("abms"),
+      &block_registry,
+    ) {
+      Ok(abm_runner) => abm_runner,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    self.node_timer_runner = match NodeTimerRunner::from_lua_table(
+      self.lua_engine.get_lua(),
+      &get_table("blocks"),
+      &block_registry,
+    ) {
+      Ok(node_timer_runner) => node_timer_runner,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    self.interaction = match Interaction::from_lua_tables(
+      self.lua_engine.get_lua(),
+      &get_table("blocks"),
+      &get_table("items"),
+      item_registry.clone(),
+      &block_registry,
+    ) {
+      Ok(interaction) => interaction,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    self.inventory_actions = match InventoryActions::from_lua_tables(
+      self.lua_engine.get_lua(),
+      &get_table("allow_player_inventory_action"),
+      &get_table("on_player_inventory_action"),
+    ) {
+      Ok(inventory_actions) => inventory_actions,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    self.lifecycle = match LifecycleCallbacks::from_lua_engine(&self.lua_engine) {
+      Ok(lifecycle) => lifecycle,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    self.chat = match Chat::from_lua_tables(
+      self.lua_engine.get_lua(),
+      &get_table("on_chat_message"),
+      &get_table("chatcommands"),
+    ) {
+      Ok(chat) => chat,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    self.liquid_runner = match LiquidRunner::from_block_registry(&block_registry) {
+      Ok(liquid_runner) => liquid_runner,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    self.falling_node_runner = FallingNodeRunner::from_block_registry(&block_registry);
+
+    let mut environment = self.environment.borrow_mut();
+    environment.set_block_registry(block_registry);
+    environment.set_item_registry(item_registry);
+    environment.set_craft_registry(craft_registry);
   }
 
   ///
@@ -88,6 +439,616 @@ impl Server {
     }
   }
 
+  ///
+  /// Feed the emerge queue and pick up what it finished.
+  ///
+  /// 1.) Pick up minetest.emerge_area calls from the last tick.
+  /// 2.) Sort the queue around the players.
+  /// 3.) Put finished Chunks into the Map and run the Lua callbacks.
+  ///
+  fn process_emerge(&mut self) {
+    let area_requests: Vec<EmergeAreaRequest> =
+      self.emerge_area_requests.borrow_mut().drain(..).collect();
+
+    for area_request in area_requests {
+      self
+        .emerge
+        .add_area(&area_request.chunk_positions, area_request.callback);
+
+      for chunk_position in area_request.chunk_positions {
+        let in_memory = self
+          .environment
+          .borrow()
+          .get_map()
+          .has_chunk(chunk_position);
+        if in_memory {
+          self.active_chunks.touch(chunk_position);
+          let callbacks = self
+            .emerge
+            .complete_chunk(chunk_position, EmergeAction::FromMemory);
+          self.run_emerge_callbacks(callbacks);
+        } else {
+          self.emerge.request_chunk(chunk_position);
+        }
+      }
+    }
+
+    self
+      .emerge
+      .update_priorities(&self.connection.get_client_positions());
+
+    for result in self.emerge.receive() {
+      if let Some(chunk) = result.chunk {
+        let mut environment = self.environment.borrow_mut();
+        if !environment.get_map().has_chunk(result.chunk_position) {
+          environment.add_emerged_chunk(chunk, result.action == EmergeAction::Generated);
+          self.active_chunks.touch(result.chunk_position);
+        }
+      }
+
+      let callbacks = self
+        .emerge
+        .complete_chunk(result.chunk_position, result.action);
+      self.run_emerge_callbacks(callbacks);
+    }
+  }
+
+  ///
+  /// Rebuild the active area around the players and emerge
+  /// any of it that isn't in memory yet.
+  ///
+  fn update_active_chunks(&mut self, delta: f64) {
+    let environment = self.environment.borrow();
+    self.active_chunks.update(
+      delta,
+      &self.connection.get_client_positions(),
+      environment.get_map(),
+    );
+
+    for chunk_position in self.active_chunks.get_active_chunks() {
+      if !environment.get_map().has_chunk(*chunk_position) {
+        self.emerge.request_chunk(*chunk_position);
+      }
+    }
+  }
+
+  ///
+  /// Save and drop Chunks which have been idle for too long.
+  ///
+  fn unload_idle_chunks(&mut self) {
+    let mut environment = self.environment.borrow_mut();
+    let map = environment.get_map_mut();
+
+    let unload_positions = self
+      .active_chunks
+      .get_unload_candidates(map, MAX_UNLOADS_PER_TICK);
+
+    if unload_positions.is_empty() {
+      return;
+    }
+
+    let mut unloaded_chunks = vec![];
+    for chunk_position in unload_positions {
+      if let Some(chunk) = map.remove_chunk(chunk_position) {
+        unloaded_chunks.push(chunk);
+      }
+      self.active_chunks.forget(chunk_position);
+    }
+
+    let modified_chunks: Vec<&Chunk> = unloaded_chunks
+      .iter()
+      .filter(|chunk| chunk.is_modified())
+      .collect();
+
+    if let Err(e) = self.database.save_chunks(&modified_chunks) {
+      panic!("Server: failed to save unloaded chunks. {}", e);
+    }
+  }
+
+  ///
+  /// Send clients the Chunks they're missing and everything that changed this tick.
+  ///
+  /// Liquids and falling nodes next to the changes get woken up too.
+  ///
+  fn stream_chunks(&mut self, delta: f64) {
+    let missing = {
+      let mut environment = self.environment.borrow_mut();
+      let node_changes = environment.take_node_changes();
+      self.liquid_runner.queue_node_changes(&node_changes);
+      self.falling_node_runner.queue_node_changes(&node_changes);
+      self
+        .chunk_streamer
+        .on_tick(delta, &mut self.connection, &environment, &node_changes)
+    };
+
+    for chunk_position in missing {
+      self.emerge.request_chunk(chunk_position);
+    }
+  }
+
+  ///
+  /// Move the rollback journal out of memory and into the database.
+  ///
+  fn save_node_actions(&mut self) {
+    let node_actions = self.environment.borrow_mut().take_node_actions();
+    if let Err(e) = self.database.save_node_actions(&node_actions) {
+      panic!("Server: failed to save the rollback journal. {}", e);
+    }
+  }
+
+  ///
+  /// Save the protected areas if they changed.
+  ///
+  fn save_protected_areas(&mut self) {
+    let mut environment = self.environment.borrow_mut();
+    let area_protection = environment.get_area_protection_mut();
+    if !area_protection.take_modified() {
+      return;
+    }
+
+    if let Err(e) = self
+      .database
+      .save_protected_areas(area_protection.get_areas())
+    {
+      panic!("Server: failed to save protected areas. {}", e);
+    }
+  }
+
+  ///
+  /// Let in the clients which asked to join, unless a mod turns them away.
+  ///
+  /// Players get their inventory out of the database if they've been here before.
+  ///
+  fn join_players(&mut self) {
+    let lua = self.lua_engine.get_lua();
+
+    for (end_point, name) in std::mem::take(&mut self.connection.join_requests) {
+      let ip = end_point.addr().ip().to_string();
+      if let Some(reason) = self
+        .lifecycle
+        .on_prejoinplayer(lua, &name, &ip, &self.environment)
+      {
+        println!("Server: turned [{}] away. {}", name, reason);
+        self.connection.reject_client(end_point, &reason);
+        continue;
+      }
+
+      let (inventory, new_player) = match self.database.load_player_inventory(&name) {
+        Ok(Some(inventory)) => (inventory, false),
+        Ok(None) => (create_player_inventory(), true),
+        Err(e) => panic!("Server: {}", e),
+      };
+      self.connection.accept_client(end_point, &name);
+
+      {
+        let mut environment = self.environment.borrow_mut();
+        environment.add_player(&name, inventory);
+
+        // The player inventory goes out with the rest of this tick's changes.
+        for location in environment.get_player_visible_inventories(&name) {
+          if let InventoryLocation::Detached(_) = location {
+            send_inventory(&self.connection, end_point, &environment, &location);
+          }
+        }
+      }
+
+      println!("Server: [{}] joined the game.", name);
+      self
+        .lifecycle
+        .on_joinplayer(lua, &name, new_player, &self.environment);
+    }
+  }
+
+  ///
+  /// Take the clients which left out of the world, saving their inventory.
+  ///
+  fn leave_players(&mut self) {
+    for (client, timed_out) in std::mem::take(&mut self.connection.left_clients) {
+      self.lifecycle.on_leaveplayer(
+        self.lua_engine.get_lua(),
+        &client.name,
+        timed_out,
+        &self.environment,
+      );
+
+      let inventory = self.environment.borrow_mut().remove_player(&client.name);
+      if let Some(inventory) = inventory {
+        if let Err(e) = self
+          .database
+          .save_player_inventory(&client.name, &inventory)
+        {
+          panic!("Server: failed to save player inventory. {}", e);
+        }
+      }
+
+      println!("Server: [{}] left the game.", client.name);
+    }
+  }
+
+  ///
+  /// Let the mods know about everyone who died this tick, then bring them back with full hp.
+  ///
+  /// todo: wait for the player to ask to respawn once the client has a death screen.
+  ///
+  fn respawn_players(&mut self) {
+    let lua = self.lua_engine.get_lua();
+
+    let deaths = self.environment.borrow_mut().take_player_deaths();
+    for (name, reason) in deaths {
+      self
+        .lifecycle
+        .on_dieplayer(lua, &name, &reason, &self.environment);
+
+      self
+        .environment
+        .borrow_mut()
+        .set_player_hp(&name, PLAYER_HP_MAX, "respawn");
+      self
+        .lifecycle
+        .on_respawnplayer(lua, &name, &self.environment);
+    }
+  }
+
+  ///
+  /// Save the player inventories that changed this tick and send every changed
+  /// Inventory to the clients that can see it.
+  ///
+  fn sync_inventories(&mut self) {
+    let mut environment = self.environment.borrow_mut();
+
+    for location in environment.take_modified_inventories() {
+      if let InventoryLocation::Player(name) = &location {
+        if let Some(inventory) = environment.get_inventory(&location) {
+          if let Err(e) = self.database.save_player_inventory(name, inventory) {
+            panic!("Server: failed to save player inventory. {}", e);
+          }
+        }
+      }
+
+      // Removed detached inventories get sent to everyone, nobody can see them anymore.
+      let removed = environment.get_inventory(&location).is_none();
+      for (end_point, client) in &self.connection.clients {
+        if removed || environment.can_player_see_inventory(&client.name, &location) {
+          send_inventory(&self.connection, *end_point, &environment, &location);
+        }
+      }
+    }
+  }
+
+  ///
+  /// Run every registered on_tick function, blaming each one's node changes on its mod.
+  ///
+  fn run_on_tick(&self, delta: f64) {
+    let lua = self.lua_engine.get_lua();
+    let on_tick = match self.lua_engine.get_internal_table("on_tick") {
+      Ok(on_tick) => on_tick,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    for function in on_tick.sequence_values::<Function>() {
+      match function {
+        Ok(function) => {
+          call_as_mod::<_, ()>(
+            lua,
+            &self.environment,
+            &function,
+            DEFAULT_ACTOR,
+            "on_tick",
+            delta,
+          );
+        }
+        Err(e) => panic!("Server: the internal on_tick is broken! {}", e),
+      }
+    }
+  }
+
+  ///
+  /// Set who gets the blame in the rollback journal for the next node changes.
+  ///
+  fn set_actor(&self, actor: &str) {
+    self.environment.borrow_mut().set_actor(actor);
+  }
+
+  ///
+  /// Save every modified Chunk in memory.
+  ///
+  pub fn save_map(&mut self) {
+    self.save_node_actions();
+    self.save_protected_areas();
+
+    let mut environment = self.environment.borrow_mut();
+    for (name, inventory) in environment.get_player_inventories() {
+      if let Err(e) = self.database.save_player_inventory(name, inventory) {
+        panic!("Server: failed to save player inventory. {}", e);
+      }
+    }
+
+    let map = environment.get_map_mut();
+
+    let modified_chunks: Vec<&Chunk> = map
+      .get_chunks()
+      .filter(|chunk| chunk.is_modified())
+      .collect();
+
+    if let Err(e) = self.database.save_chunks(&modified_chunks) {
+      panic!("Server: failed to save map. {}", e);
+    }
+
+    println!("Server: saved [{}] chunks.", modified_chunks.len());
+
+    for chunk_position in map.get_chunk_positions() {
+      if let Some(chunk) = map.get_chunk_mut(chunk_position) {
+        chunk.set_saved();
+      }
+    }
+  }
+
+  ///
+  /// Run everything typed into the server console.
+  ///
+  fn run_console_commands(&mut self) {
+    for line in self.console.take_commands() {
+      let result =
+        ConsoleCommand::parse(&line).and_then(|command| self.run_console_command(command));
+      match result {
+        Ok(message) => println!("{}", message),
+        Err(e) => println!("{}", e),
+      }
+    }
+  }
+
+  ///
+  /// Run a console command, from the console or a player's chat.
+  ///
+  /// Returns what to tell whoever ran it.
+  ///
+  fn run_console_command(&mut self, command: ConsoleCommand) -> Result<String, String> {
+    match command {
+      ConsoleCommand::Help => Ok(CONSOLE_HELP.to_string()),
+
+      ConsoleCommand::Backup { backup_path } => {
+        // Chunks in memory need to be in the database to be in the backup.
+        self.save_map();
+
+        let backup_path = match backup_path {
+          Some(backup_path) => backup_path,
+          None => get_default_backup_path(&self.world_path)?,
+        };
+        let message = format!("Server: backing up world to [{}]", backup_path);
+
+        // Copying a big world takes a while, the Server keeps ticking meanwhile.
+        // The backup API gives the copy its own consistent view of the database.
+        let database_path = Self::get_database_path(&self.world_path);
+        let backup = thread::Builder::new()
+          .name("backup".to_string())
+          .spawn(move || {
+            match MapDatabase::new(&database_path)
+              .and_then(|database| database.backup(&backup_path))
+            {
+              Ok(_) => println!("Server: backed up world to [{}]", backup_path),
+              Err(e) => println!("Server: backup failed. {}", e),
+            }
+          });
+        if let Err(e) = backup {
+          return Err(format!("Server: failed to spawn backup thread. {}", e));
+        }
+
+        Ok(message)
+      }
+
+      ConsoleCommand::Export {
+        pos1,
+        pos2,
+        region_path,
+      } => {
+        self.save_map();
+        let region = export_region(&self.database, pos1, pos2, &region_path)?;
+        Ok(format!(
+          "Server: exported [{}] nodes to [{}]",
+          region.get_node_count(),
+          region_path
+        ))
+      }
+
+      ConsoleCommand::Import {
+        region_path,
+        position,
+      } => {
+        let node_count = self.import_region(&region_path, position)?;
+        Ok(format!(
+          "Server: imported [{}] nodes from [{}]",
+          node_count, region_path
+        ))
+      }
+
+      ConsoleCommand::Rollback {
+        player_name,
+        seconds,
+      } => {
+        let (reverted, node_actions) = self.rollback_player(&player_name, seconds)?;
+        Ok(format!(
+          "Server: rolled back [{}] of [{}] node changes by [{}]",
+          reverted, node_actions, player_name
+        ))
+      }
+    }
+  }
+
+  ///
+  /// Handle this tick's chat, then send out everything mods and players said.
+  ///
+  /// Console commands run through chat answer the player who ran them.
+  ///
+  fn process_chat(&mut self) {
+    let console_commands = self.chat.on_tick(
+      self.lua_engine.get_lua(),
+      &self.environment,
+      &mut self.connection,
+      &self.chat_messages,
+    );
+
+    for (player_name, command) in console_commands {
+      let message = match self.run_console_command(command) {
+        Ok(message) => message,
+        Err(e) => e,
+      };
+      self
+        .chat_messages
+        .borrow_mut()
+        .push(ChatMessage::to_player(&player_name, &message));
+    }
+  }
+
+  ///
+  /// Paste a region file into the world with its minimum corner at position.
+  ///
+  /// The whole area has to be generated already. Chunks which aren't in memory
+  /// get loaded from the database first.
+  ///
+  fn import_region(&mut self, region_path: &str, position: IVec3) -> Result<usize, String> {
+    let region = Region::load_from_file(region_path)?;
+
+    let mut environment = self.environment.borrow_mut();
+    let nodes =
+      region.get_nodes(|block_name| environment.get_block_registry().get_id(block_name))?;
+
+    let min = world_to_chunk_position(position);
+    let max = world_to_chunk_position(position + region.get_size() - IVec3::ONE);
+    for x in min.x..=max.x {
+      for y in min.y..=max.y {
+        for z in min.z..=max.z {
+          let chunk_position = ivec3(x, y, z);
+          if !Self::load_chunk_from_database(
+            &self.database,
+            &mut self.active_chunks,
+            &mut environment,
+            chunk_position,
+          )? {
+            return Err(format!(
+              "Server: can't import into chunk [{}], it has not been generated yet.",
+              chunk_position
+            ));
+          }
+        }
+      }
+    }
+
+    environment.set_actor(CONSOLE_ACTOR);
+    for (offset, node, node_meta) in &nodes {
+      let world_position = position + *offset;
+      environment.set_node(world_position, *node);
+
+      let map = environment.get_map_mut();
+      match node_meta {
+        Some(node_meta) => {
+          if let Some(existing) = map.get_node_meta_mut(world_position) {
+            *existing = (*node_meta).clone();
+          }
+        }
+        None => map.remove_node_meta(world_position),
+      }
+    }
+
+    Ok(nodes.len())
+  }
+
+  ///
+  /// Make sure a Chunk is in memory, loading it from the database if it has to.
+  ///
+  /// Returns false if the Chunk has never been generated.
+  ///
+  fn load_chunk_from_database(
+    database: &MapDatabase,
+    active_chunks: &mut ActiveChunks,
+    environment: &mut ServerEnvironment,
+    chunk_position: IVec3,
+  ) -> Result<bool, String> {
+    if environment.get_map().has_chunk(chunk_position) {
+      return Ok(true);
+    }
+
+    let block_registry = environment.get_block_registry();
+    match database.load_chunk(chunk_position, |id| block_registry.is_registered(id))? {
+      Some(chunk) => {
+        environment.add_emerged_chunk(chunk, false);
+        active_chunks.touch(chunk_position);
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+
+  ///
+  /// Undo every node change a player made in the last few seconds.
+  ///
+  /// Returns (nodes put back, node changes found). Nodes which have been
+  /// changed again since then by someone else are left alone.
+  ///
+  fn rollback_player(&mut self, player_name: &str, seconds: u64) -> Result<(usize, usize), String> {
+    if !self.game_config.enable_rollback {
+      return Err(
+        "Server: rollback is disabled. Turn on enable_rollback in game.conf.".to_string(),
+      );
+    }
+
+    // Everything up to now has to be in the database to be found.
+    self.save_node_actions();
+
+    let node_actions = self.database.get_actor_node_actions(
+      &get_player_actor(player_name),
+      get_rollback_start_time(seconds)?,
+    )?;
+
+    let mut environment = self.environment.borrow_mut();
+    for node_action in &node_actions {
+      Self::load_chunk_from_database(
+        &self.database,
+        &mut self.active_chunks,
+        &mut environment,
+        world_to_chunk_position(node_action.position),
+      )?;
+    }
+
+    environment.set_actor(ROLLBACK_ACTOR);
+    let reverted = revert_node_actions(&mut environment, &node_actions);
+
+    Ok((reverted, node_actions.len()))
+  }
+
+  ///
+  /// Run minetest.emerge_area callbacks.
+  ///
+  /// Signature: callback(chunk_position, action, calls_remaining)
+  ///
+  fn run_emerge_callbacks(&self, callbacks: Vec<EmergeCallback>) {
+    let lua = self.lua_engine.get_lua();
+
+    for emerge_callback in callbacks {
+      let function: Function = match lua.registry_value(&emerge_callback.callback) {
+        Ok(function) => function,
+        Err(e) => panic!("Server: lost emerge_area callback. {}", e),
+      };
+
+      let chunk_position = match node_position_to_vector(lua, emerge_callback.chunk_position) {
+        Ok(chunk_position) => chunk_position,
+        Err(e) => panic!("Server: {}", e),
+      };
+
+      call_as_mod::<_, ()>(
+        lua,
+        &self.environment,
+        &function,
+        DEFAULT_ACTOR,
+        "emerge_area callback",
+        (
+          chunk_position,
+          emerge_callback.action as u8,
+          emerge_callback.calls_remaining,
+        ),
+      );
+    }
+  }
+
   ///
   /// Tick tock.
   ///
@@ -108,12 +1069,90 @@ impl Server {
       return;
     }
 
-    self.lua_engine.on_tick(delta);
+    self.run_console_commands();
+
+    // Players come and go first. New players need their inventory before they can do anything.
+    self.leave_players();
+    self.join_players();
+    self.inventory_actions.on_tick(
+      self.lua_engine.get_lua(),
+      &self.environment,
+      &mut self.connection,
+    );
+    self.process_chat();
+
+    // Players digging and placing. Interaction blames each player for their own changes.
+    self.interaction.on_tick(
+      delta,
+      self.lua_engine.get_lua(),
+      &self.environment,
+      &mut self.connection,
+    );
+
+    // Chunk loading and generation happens off thread. (non blocking)
+    self.set_actor(DEFAULT_ACTOR);
+    self.update_active_chunks(delta);
+    self.process_emerge();
+    self.unload_idle_chunks();
+
+    // World ticking, each gets its own slice of the tick.
+    self.set_actor(NODE_TIMER_ACTOR);
+    self.node_timer_runner.on_tick(
+      delta,
+      self.lua_engine.get_lua(),
+      &self.environment,
+      self.active_chunks.get_active_chunks(),
+      Duration::from_secs_f64(self.game_config.node_timer_budget),
+    );
+    self.set_actor(ABM_ACTOR);
+    self.abm_runner.on_tick(
+      delta,
+      self.lua_engine.get_lua(),
+      &self.environment,
+      self.active_chunks.get_active_chunks(),
+      Duration::from_secs_f64(self.game_config.abm_budget),
+    );
+    self.set_actor(LIQUID_ACTOR);
+    self.liquid_runner.on_tick(
+      delta,
+      &mut self.environment.borrow_mut(),
+      self.game_config.liquid_update,
+      Duration::from_secs_f64(self.game_config.liquid_budget),
+    );
+    self.set_actor(FALLING_NODE_ACTOR);
+    self
+      .falling_node_runner
+      .on_tick(delta, &mut self.environment.borrow_mut());
+
+    self.set_actor(DEFAULT_ACTOR);
+    self.run_on_tick(delta);
+    self.respawn_players();
+
+    // Everything that changed this tick goes out to the clients.
+    self.stream_chunks(delta);
+    self.sync_inventories();
+    send_chat_messages(&self.connection, &self.chat_messages);
+    self.save_node_actions();
+    self.save_protected_areas();
   }
 }
 
 impl Drop for Server {
   fn drop(&mut self) {
+    let lua = self.lua_engine.get_lua();
+    self.lifecycle.on_shutdown(lua, &self.environment);
+    // Everyone still here leaves with the Server. Their inventories get saved with the map.
+    for client in self.connection.clients.values() {
+      self
+        .lifecycle
+        .on_leaveplayer(lua, &client.name, false, &self.environment);
+    }
+
+    self.set_actor(FALLING_NODE_ACTOR);
+    self
+      .falling_node_runner
+      .land_all(&mut self.environment.borrow_mut());
+    self.save_map();
     println!("Server dropped!");
   }
 }
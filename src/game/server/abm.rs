@@ -0,0 +1,547 @@
+use std::{
+  cell::RefCell,
+  collections::VecDeque,
+  time::{Duration, Instant},
+};
+
+use ahash::AHashSet;
+use glam::{ivec3, IVec3};
+use mlua::{Function, Lua, RegistryKey, Table};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::game::{
+  lua_engine::lua_table_helpers::{
+    get_field, get_field_or, get_string_list, node_position_to_vector,
+  },
+  map::{
+    block_registry::BlockRegistry,
+    chunk::{chunk_to_world_position, CHUNK_SIZE},
+    Map,
+  },
+};
+
+use super::{
+  lua_api::node_to_table,
+  rollback::{call_as_mod, ABM_ACTOR},
+  server_environment::ServerEnvironment,
+};
+
+///
+/// An Active Block Modifier. (ABM in C++ minetest)
+///
+/// Every interval seconds, each node in the active area which is in nodenames
+/// (and touches one of neighbors, if there are any) has a 1 in chance
+/// shot of getting action(pos, node) run on it.
+///
+/// Block IDs are stored as lookup tables indexed by ID. A scan touches every
+/// node in every active Chunk, hashing names there would be far too slow.
+///
+struct Abm {
+  label: String,
+  nodenames: Vec<bool>,
+  neighbors: Option<Vec<bool>>,
+  interval: f64,
+  chance: u32,
+  action: RegistryKey,
+
+  timer: f64,
+  state: AbmState,
+  pending: VecDeque<IVec3>,
+}
+
+///
+/// Where an Abm is in its cycle.
+///
+/// An Abm's timer only runs while it's Idle. An Abm which can't finish its
+/// work inside of the time budget simply runs less often instead of piling up.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AbmState {
+  // Counting down the interval.
+  Idle,
+  // Waiting for the current scan to finish so it can join the next one.
+  WaitingForScan,
+  // Being checked against the Chunks in the scan queue.
+  Scanning,
+  // Has positions left in pending to run action on.
+  Running,
+}
+
+impl Abm {
+  fn from_lua_table(
+    lua: &Lua,
+    definition: &Table,
+    block_registry: &BlockRegistry,
+    index: usize,
+  ) -> Result<Self, String> {
+    let label: String = get_field_or(definition, "label", format!("{}", index))?;
+    let kind = format!("abm [{}]", label);
+
+    let nodenames = get_block_lookup(
+      &get_string_list(definition, "nodenames")?,
+      block_registry,
+      &kind,
+    )?;
+    if !nodenames.contains(&true) {
+      return Err(format!("{} has no nodenames.", kind));
+    }
+
+    let neighbors = get_string_list(definition, "neighbors")?;
+    let neighbors = match neighbors.is_empty() {
+      true => None,
+      false => Some(get_block_lookup(&neighbors, block_registry, &kind)?),
+    };
+
+    let interval: f64 = get_field_or(definition, "interval", 10.0)?;
+    if interval <= 0.0 || !interval.is_finite() {
+      return Err(format!("{} interval must be above 0.", kind));
+    }
+
+    let chance: u32 = get_field_or(definition, "chance", 50)?;
+    if chance == 0 {
+      return Err(format!("{} chance must be at least 1.", kind));
+    }
+
+    let action: Function = get_field(definition, "action")?;
+    let action = match lua.create_registry_value(action) {
+      Ok(action) => action,
+      Err(e) => return Err(format!("{} failed to store action. {}", kind, e)),
+    };
+
+    Ok(Abm {
+      label,
+      nodenames,
+      neighbors,
+      interval,
+      chance,
+      action,
+
+      timer: 0.0,
+      state: AbmState::Idle,
+      pending: VecDeque::new(),
+    })
+  }
+
+  fn matches(&self, block_id: u16) -> bool {
+    self
+      .nodenames
+      .get(block_id as usize)
+      .copied()
+      .unwrap_or(false)
+  }
+
+  ///
+  /// Check the 26 nodes around a position for one of neighbors.
+  ///
+  fn has_neighbor(&self, map: &Map, position: IVec3) -> bool {
+    let neighbors = match &self.neighbors {
+      Some(neighbors) => neighbors,
+      None => return true,
+    };
+
+    for x in -1..=1 {
+      for y in -1..=1 {
+        for z in -1..=1 {
+          if x == 0 && y == 0 && z == 0 {
+            continue;
+          }
+          if let Some(node) = map.get_node(position + ivec3(x, y, z)) {
+            if neighbors
+              .get(node.block_id as usize)
+              .copied()
+              .unwrap_or(false)
+            {
+              return true;
+            }
+          }
+        }
+      }
+    }
+
+    false
+  }
+}
+
+///
+/// Turn a list of block names into a lookup table indexed by block ID.
+///
+fn get_block_lookup(
+  names: &[String],
+  block_registry: &BlockRegistry,
+  kind: &str,
+) -> Result<Vec<bool>, String> {
+  let mut lookup = vec![false; block_registry.get_block_count()];
+  for name in names {
+    lookup[block_registry.require_id(name, kind)? as usize] = true;
+  }
+  Ok(lookup)
+}
+
+///
+/// Runs every registered Abm over the active Chunks.
+///
+/// Work is split up so no single tick goes over the time budget:
+/// 1.) Abms that are due get checked against the active Chunks, a few Chunks per tick.
+/// 2.) The positions that pass get their action run, taking turns between Abms
+///     so one heavy Abm can't starve the rest.
+///
+pub struct AbmRunner {
+  abms: Vec<Abm>,
+  scan_queue: Vec<IVec3>,
+  next_abm: usize,
+  random: StdRng,
+}
+
+impl AbmRunner {
+  pub fn new() -> Self {
+    AbmRunner {
+      abms: vec![],
+      scan_queue: vec![],
+      next_abm: 0,
+      random: StdRng::from_entropy(),
+    }
+  }
+
+  ///
+  /// Build the Abms out of the _G.abms table in a LuaEngine.
+  ///
+  pub fn from_lua_table(
+    lua: &Lua,
+    abm_table: &Table,
+    block_registry: &BlockRegistry,
+  ) -> Result<Self, String> {
+    let mut new_runner = AbmRunner::new();
+
+    for (index, definition) in abm_table.clone().sequence_values::<Table>().enumerate() {
+      let definition = definition.map_err(|e| format!("AbmRunner: malformed abm table. {}", e))?;
+      let abm = Abm::from_lua_table(lua, &definition, block_registry, index + 1)
+        .map_err(|e| format!("AbmRunner: {}", e))?;
+      new_runner.abms.push(abm);
+    }
+
+    println!("AbmRunner: loaded [{}] abms.", new_runner.abms.len());
+
+    Ok(new_runner)
+  }
+
+  ///
+  /// Do as much Abm work as fits into the budget.
+  ///
+  pub fn on_tick(
+    &mut self,
+    delta: f64,
+    lua: &Lua,
+    environment: &RefCell<ServerEnvironment>,
+    active_chunks: &AHashSet<IVec3>,
+    budget: Duration,
+  ) {
+    if self.abms.is_empty() {
+      return;
+    }
+
+    let deadline = Instant::now() + budget;
+
+    self.update_timers(delta);
+    self.start_scan(active_chunks);
+    self.scan(environment.borrow().get_map(), deadline);
+    self.run_actions(lua, environment, deadline);
+  }
+
+  fn update_timers(&mut self, delta: f64) {
+    for abm in self.abms.iter_mut() {
+      if abm.state != AbmState::Idle {
+        continue;
+      }
+      abm.timer += delta;
+      if abm.timer >= abm.interval {
+        abm.timer = 0.0;
+        abm.state = AbmState::WaitingForScan;
+      }
+    }
+  }
+
+  ///
+  /// Start a new scan for every waiting Abm, if the last scan is finished.
+  ///
+  fn start_scan(&mut self, active_chunks: &AHashSet<IVec3>) {
+    if !self.scan_queue.is_empty() {
+      return;
+    }
+
+    let mut any_waiting = false;
+    for abm in self.abms.iter_mut() {
+      if abm.state == AbmState::WaitingForScan {
+        abm.state = AbmState::Scanning;
+        any_waiting = true;
+      }
+    }
+
+    if any_waiting {
+      self.scan_queue = active_chunks.iter().copied().collect();
+    }
+  }
+
+  ///
+  /// Check Chunks out of the scan queue until the deadline.
+  ///
+  /// At least one Chunk is always scanned so the queue keeps moving.
+  ///
+  fn scan(&mut self, map: &Map, deadline: Instant) {
+    while let Some(chunk_position) = self.scan_queue.pop() {
+      if let Some(chunk) = map.get_chunk(chunk_position) {
+        let origin = chunk_to_world_position(chunk_position);
+
+        for y in 0..CHUNK_SIZE {
+          for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+              let local_position = ivec3(x, y, z);
+              let block_id = chunk.get_node(local_position).block_id;
+
+              for abm in self.abms.iter_mut() {
+                if abm.state != AbmState::Scanning || !abm.matches(block_id) {
+                  continue;
+                }
+                // The neighbor check is expensive, roll the dice first.
+                if self.random.gen_range(0..abm.chance) != 0 {
+                  continue;
+                }
+                let world_position = origin + local_position;
+                if abm.has_neighbor(map, world_position) {
+                  abm.pending.push_back(world_position);
+                }
+              }
+            }
+          }
+        }
+      }
+
+      if Instant::now() >= deadline {
+        break;
+      }
+    }
+
+    if self.scan_queue.is_empty() {
+      for abm in self.abms.iter_mut() {
+        if abm.state == AbmState::Scanning {
+          abm.state = AbmState::Running;
+        }
+      }
+    }
+  }
+
+  ///
+  /// Run pending actions until the deadline, one from each Abm in turn.
+  ///
+  /// At least one action is always run so the queue keeps moving.
+  ///
+  /// Signature: action(pos, node)
+  ///
+  fn run_actions(
+    &mut self,
+    lua: &Lua,
+    environment: &RefCell<ServerEnvironment>,
+    deadline: Instant,
+  ) {
+    let abm_count = self.abms.len();
+
+    loop {
+      let mut ran_any = false;
+
+      for offset in 0..abm_count {
+        let index = (self.next_abm + offset) % abm_count;
+        let abm = &mut self.abms[index];
+
+        let position = match abm.pending.pop_front() {
+          Some(position) => position,
+          None => {
+            if abm.state == AbmState::Running {
+              abm.state = AbmState::Idle;
+            }
+            continue;
+          }
+        };
+        ran_any = true;
+
+        // The node might have changed since it was scanned.
+        let node_table = {
+          let environment = environment.borrow();
+          match environment.get_node(position) {
+            Some(node) if abm.matches(node.block_id) => node_to_table(lua, &environment, position),
+            _ => continue,
+          }
+        };
+
+        let abm = &self.abms[index];
+        if let Err(e) = Self::run_action(lua, environment, abm, position, node_table) {
+          panic!("AbmRunner: abm [{}] failed. {}", abm.label, e);
+        }
+
+        if Instant::now() >= deadline {
+          self.next_abm = (index + 1) % abm_count;
+          return;
+        }
+      }
+
+      if !ran_any {
+        return;
+      }
+    }
+  }
+
+  fn run_action<'lua>(
+    lua: &'lua Lua,
+    environment: &RefCell<ServerEnvironment>,
+    abm: &Abm,
+    position: IVec3,
+    node_table: mlua::Result<Table<'lua>>,
+  ) -> mlua::Result<()> {
+    let action: Function = lua.registry_value(&abm.action)?;
+    let args = (node_position_to_vector(lua, position)?, node_table?);
+    let context = format!("abm [{}]", abm.label);
+    call_as_mod::<_, ()>(lua, environment, &action, ABM_ACTOR, &context, args);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, time::Duration};
+
+  use ahash::AHashSet;
+  use glam::{ivec3, IVec3};
+  use mlua::{Lua, Table};
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::AbmRunner;
+  use crate::game::{
+    map::{
+      block_registry::{BlockDefinition, BlockRegistry},
+      chunk::{Chunk, Node},
+    },
+    server::server_environment::ServerEnvironment,
+  };
+
+  ///
+  /// A Chunk with a dirt floor at y = 0 and one grass node on top of it at (8, 1, 8).
+  ///
+  /// Every abm counts its actions in _G.counts[label].
+  ///
+  fn test_world(lua: &Lua, abms: &str) -> (AbmRunner, RefCell<ServerEnvironment>) {
+    let mut block_registry = BlockRegistry::new();
+    let mut register = |name: &str| match block_registry.register_block(BlockDefinition::test(name))
+    {
+      Ok(block_id) => Node::new(block_id, 0),
+      Err(e) => panic!("{}", e),
+    };
+    let dirt = register("test:dirt");
+    let grass = register("test:grass");
+    register("test:stone");
+
+    let abm_table: Table = match lua
+      .load(format!(
+        r#"
+          counts = {{}}
+          local function count(label)
+            return function(pos, node)
+              assert(node.name == "test:dirt")
+              counts[label] = (counts[label] or 0) + 1
+            end
+          end
+          return {{ {} }}
+        "#,
+        abms
+      ))
+      .eval()
+    {
+      Ok(abm_table) => abm_table,
+      Err(e) => panic!("{}", e),
+    };
+
+    let mut abm_runner = match AbmRunner::from_lua_table(lua, &abm_table, &block_registry) {
+      Ok(abm_runner) => abm_runner,
+      Err(e) => panic!("{}", e),
+    };
+    abm_runner.random = StdRng::seed_from_u64(26);
+
+    let mut environment = ServerEnvironment::new();
+    environment.set_block_registry(block_registry);
+    environment.add_emerged_chunk(Chunk::new(IVec3::ZERO), true);
+    for x in 0..16 {
+      for z in 0..16 {
+        environment.set_node(ivec3(x, 0, z), dirt);
+      }
+    }
+    environment.set_node(ivec3(8, 1, 8), grass);
+
+    (abm_runner, RefCell::new(environment))
+  }
+
+  fn get_count(lua: &Lua, label: &str) -> u32 {
+    match lua.load(format!("return counts.{} or 0", label)).eval() {
+      Ok(count) => count,
+      Err(e) => panic!("{}", e),
+    }
+  }
+
+  #[test]
+  fn abms_pick_nodes_by_interval_chance_and_neighbors() {
+    let lua = Lua::new();
+    let (mut abm_runner, environment) = test_world(
+      &lua,
+      r#"
+        { label = "every", nodenames = { "test:dirt" }, interval = 2, chance = 1, action = count("every") },
+        { label = "near", nodenames = { "test:dirt" }, neighbors = { "test:grass" }, interval = 1, chance = 1, action = count("near") },
+        { label = "some", nodenames = { "test:dirt" }, interval = 1, chance = 4, action = count("some") },
+        { label = "none", nodenames = { "test:stone" }, interval = 1, chance = 1, action = count("none") },
+      "#,
+    );
+    let active_chunks = AHashSet::from([IVec3::ZERO]);
+    let budget = Duration::from_secs(10);
+
+    abm_runner.on_tick(1.0, &lua, &environment, &active_chunks, budget);
+    assert_eq!(get_count(&lua, "every"), 0);
+    assert_eq!(get_count(&lua, "near"), 9);
+
+    abm_runner.on_tick(1.0, &lua, &environment, &active_chunks, budget);
+    assert_eq!(get_count(&lua, "every"), 256);
+    assert_eq!(get_count(&lua, "near"), 18);
+    assert_eq!(get_count(&lua, "none"), 0);
+
+    // 1 in 4 of 256 nodes, twice.
+    let some = get_count(&lua, "some");
+    assert!(
+      (64..=192).contains(&some),
+      "chance picked [{}] nodes.",
+      some
+    );
+  }
+
+  #[test]
+  fn abms_take_turns_when_over_budget() {
+    let lua = Lua::new();
+    let (mut abm_runner, environment) = test_world(
+      &lua,
+      r#"
+        { label = "heavy", nodenames = { "test:dirt" }, interval = 1, chance = 1, action = count("heavy") },
+        { label = "idle", nodenames = { "test:stone" }, interval = 1, chance = 1, action = count("idle") },
+        { label = "light", nodenames = { "test:dirt" }, neighbors = { "test:grass" }, interval = 1, chance = 1, action = count("light") },
+      "#,
+    );
+    let active_chunks = AHashSet::from([IVec3::ZERO]);
+
+    // No budget at all still runs one action a tick, round robin.
+    for _ in 0..10 {
+      abm_runner.on_tick(1.0, &lua, &environment, &active_chunks, Duration::ZERO);
+    }
+    assert_eq!(get_count(&lua, "heavy"), 5);
+    assert_eq!(get_count(&lua, "light"), 5);
+
+    // Once light runs dry, heavy gets every turn.
+    for _ in 0..10 {
+      abm_runner.on_tick(1.0, &lua, &environment, &active_chunks, Duration::ZERO);
+    }
+    assert_eq!(get_count(&lua, "light"), 9);
+    assert_eq!(get_count(&lua, "heavy"), 11);
+    assert_eq!(get_count(&lua, "idle"), 0);
+  }
+}
@@ -0,0 +1,196 @@
+use ahash::{AHashMap, AHashSet};
+use glam::{ivec3, IVec3, Vec3};
+
+use crate::game::map::{chunk::world_to_chunk_position, Map};
+
+///
+/// Decides which Chunks matter right now.
+///
+/// Every Chunk within active_chunk_range of a player is active.
+/// Only active Chunks get ticked. (node timers, ABMs, liquids, etc)
+///
+/// Chunks outside of the active area are idle. Once a Chunk has been idle
+/// for longer than hibernation_timeout it gets unloaded. If the Map grows
+/// past max_loaded_chunks, the longest idle Chunks get unloaded early.
+///
+pub struct ActiveChunks {
+  hibernation_timeout: f64,
+  active_chunk_range: i32,
+  max_loaded_chunks: usize,
+
+  // Seconds since the Server started.
+  time: f64,
+
+  active: AHashSet<IVec3>,
+  last_active: AHashMap<IVec3, f64>,
+}
+
+impl ActiveChunks {
+  pub fn new(hibernation_timeout: f64, active_chunk_range: i32, max_loaded_chunks: usize) -> Self {
+    ActiveChunks {
+      hibernation_timeout,
+      active_chunk_range,
+      max_loaded_chunks,
+
+      time: 0.0,
+
+      active: AHashSet::new(),
+      last_active: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Rebuild the active area around the players.
+  ///
+  /// Only active Chunks which are in the Map get their idle timer reset.
+  /// The rest get touch()ed when they finish emerging.
+  ///
+  pub fn update(&mut self, delta: f64, player_positions: &[Vec3], map: &Map) {
+    self.time += delta;
+
+    self.active.clear();
+
+    let range = self.active_chunk_range;
+    for player_position in player_positions {
+      let center = world_to_chunk_position(player_position.floor().as_ivec3());
+
+      for x in -range..=range {
+        for y in -range..=range {
+          for z in -range..=range {
+            self.active.insert(center + ivec3(x, y, z));
+          }
+        }
+      }
+    }
+
+    for chunk_position in &self.active {
+      if map.has_chunk(*chunk_position) {
+        self.last_active.insert(*chunk_position, self.time);
+      }
+    }
+  }
+
+  ///
+  /// Check if a Chunk is inside of the active area.
+  ///
+  pub fn is_active(&self, chunk_position: IVec3) -> bool {
+    self.active.contains(&chunk_position)
+  }
+
+  ///
+  /// Get every Chunk position in the active area.
+  ///
+  /// ! Active Chunks are not always in memory yet. They might still be emerging.
+  ///
+  pub fn get_active_chunks(&self) -> &AHashSet<IVec3> {
+    &self.active
+  }
+
+  ///
+  /// Reset a Chunk's idle timer. Used when a Chunk gets loaded or touched.
+  ///
+  pub fn touch(&mut self, chunk_position: IVec3) {
+    self.last_active.insert(chunk_position, self.time);
+  }
+
+  ///
+  /// Stop tracking a Chunk. Used when a Chunk gets unloaded.
+  ///
+  pub fn forget(&mut self, chunk_position: IVec3) {
+    self.last_active.remove(&chunk_position);
+  }
+
+  ///
+  /// Find which Chunks in the Map should be unloaded.
+  ///
+  /// Idle Chunks past hibernation_timeout always get unloaded.
+  /// If that still leaves the Map over max_loaded_chunks, the longest
+  /// idle Chunks get unloaded too. Active Chunks are never unloaded.
+  ///
+  /// At most limit Chunks are returned so unloading can be spread out over ticks.
+  ///
+  pub fn get_unload_candidates(&self, map: &Map, limit: usize) -> Vec<IVec3> {
+    let mut idle: Vec<(IVec3, f64)> = map
+      .get_chunk_positions()
+      .into_iter()
+      .filter(|chunk_position| !self.is_active(*chunk_position))
+      .map(|chunk_position| {
+        let last_active = match self.last_active.get(&chunk_position) {
+          Some(last_active) => *last_active,
+          None => self.time,
+        };
+        (chunk_position, self.time - last_active)
+      })
+      .collect();
+
+    // Longest idle first.
+    idle.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let over_cap = map.get_chunk_count().saturating_sub(self.max_loaded_chunks);
+
+    idle
+      .into_iter()
+      .enumerate()
+      .take_while(|(index, (_, idle_time))| {
+        *idle_time > self.hibernation_timeout || *index < over_cap
+      })
+      .map(|(_, (chunk_position, _))| chunk_position)
+      .take(limit)
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, Vec3};
+
+  use super::ActiveChunks;
+  use crate::game::map::{chunk::Chunk, Map};
+
+  // A player at the origin, and three idle Chunks touched at 0, 5 and never.
+  fn run(max_loaded_chunks: usize) -> (ActiveChunks, Map) {
+    let mut map = Map::new();
+    for x in [0, 5, 6, 7] {
+      map.insert_chunk(Chunk::new(ivec3(x, 0, 0)));
+    }
+
+    let mut active_chunks = ActiveChunks::new(10.0, 1, max_loaded_chunks);
+    active_chunks.update(0.0, &[Vec3::ZERO], &map);
+    active_chunks.touch(ivec3(5, 0, 0));
+    active_chunks.update(5.0, &[Vec3::ZERO], &map);
+    active_chunks.touch(ivec3(6, 0, 0));
+    active_chunks.update(6.0, &[Vec3::ZERO], &map);
+
+    (active_chunks, map)
+  }
+
+  #[test]
+  fn idle_chunks_unload_after_hibernation_timeout() {
+    let (active_chunks, map) = run(100);
+
+    // The origin and the two touched Chunks. The other 26 active Chunks aren't loaded.
+    assert_eq!(active_chunks.last_active.len(), 3);
+
+    assert_eq!(
+      active_chunks.get_unload_candidates(&map, 8),
+      vec![ivec3(5, 0, 0)]
+    );
+    assert!(active_chunks.get_unload_candidates(&map, 0).is_empty());
+  }
+
+  #[test]
+  fn longest_idle_chunks_unload_when_over_the_cap() {
+    let (active_chunks, map) = run(2);
+    assert_eq!(
+      active_chunks.get_unload_candidates(&map, 8),
+      vec![ivec3(5, 0, 0), ivec3(6, 0, 0)]
+    );
+
+    // The active Chunk stays even if everything is over the cap.
+    let (active_chunks, map) = run(0);
+    assert_eq!(
+      active_chunks.get_unload_candidates(&map, 8),
+      vec![ivec3(5, 0, 0), ivec3(6, 0, 0), ivec3(7, 0, 0)]
+    );
+  }
+}
@@ -0,0 +1,530 @@
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use mlua::{Function, Lua, RegistryKey, Table};
+
+use crate::game::{
+  lua_engine::{
+    lua_error::call_mod_function,
+    lua_table_helpers::{get_field, get_field_or},
+  },
+  packet::Packet,
+};
+
+use super::{
+  console::ConsoleCommand,
+  rollback::get_player_actor,
+  server_connection::{ConnectedClient, ServerConnection},
+  server_environment::ServerEnvironment,
+};
+
+///
+/// The longest chat message a client can send. (in bytes)
+///
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 500;
+
+///
+/// A chat message waiting to go out.
+///
+/// player_name is who gets it, everyone does if it's None.
+///
+pub struct ChatMessage {
+  pub player_name: Option<String>,
+  pub message: String,
+}
+
+impl ChatMessage {
+  pub fn to_player(player_name: &str, message: &str) -> Self {
+    ChatMessage {
+      player_name: Some(player_name.to_string()),
+      message: message.to_string(),
+    }
+  }
+
+  pub fn to_all(message: &str) -> Self {
+    ChatMessage {
+      player_name: None,
+      message: message.to_string(),
+    }
+  }
+}
+
+///
+/// Send out every queued ChatMessage. Broadcasts show up in the server log too.
+///
+pub fn send_chat_messages(
+  connection: &ServerConnection,
+  chat_messages: &RefCell<Vec<ChatMessage>>,
+) {
+  for chat_message in chat_messages.borrow_mut().drain(..) {
+    if chat_message.player_name.is_none() {
+      println!("Chat: {}", chat_message.message);
+    }
+
+    let packet = Packet::ChatMessage(chat_message.message);
+    for (end_point, client) in &connection.clients {
+      if chat_message
+        .player_name
+        .as_ref()
+        .map_or(true, |player_name| *player_name == client.name)
+      {
+        connection.send_packet(*end_point, &packet);
+      }
+    }
+  }
+}
+
+///
+/// What runs a chat command.
+///
+/// * Help     - Built in. Lists the commands a player can run, or explains one.
+/// * Rollback - Built in. The rollback console command, the Server has to run it.
+/// * Lua      - A mod's func(name, param) -> (success?, message?)
+///
+enum ChatCommandHandler {
+  Help,
+  Rollback,
+  Lua(RegistryKey),
+}
+
+///
+/// What came of running a chat command.
+///
+/// * Reply   - Something to tell the player who ran it.
+/// * Console - A console command for the Server to run on the player's behalf.
+/// * Done    - Nothing to say.
+///
+enum CommandResult {
+  Reply(String),
+  Console(ConsoleCommand),
+  Done,
+}
+
+///
+/// A command players run by typing /name param into chat.
+///
+/// * params      - Shown in /help, like "<player> <seconds>".
+/// * description - Shown in /help.
+/// * privs       - Every privilege a player needs to run it.
+///
+struct ChatCommand {
+  params: String,
+  description: String,
+  privs: Vec<String>,
+  handler: ChatCommandHandler,
+}
+
+impl ChatCommand {
+  fn get_usage(&self, name: &str) -> String {
+    match self.params.is_empty() {
+      true => format!("/{}: {}", name, self.description),
+      false => format!("/{} {}: {}", name, self.params, self.description),
+    }
+  }
+}
+
+///
+/// Chat between players, and the chat commands.
+///
+/// Every message goes through the minetest.register_on_chat_message callbacks first,
+/// one returning true means the mod took care of it. What's left is either a /command
+/// or gets sent to everyone.
+///
+/// Mods send their own messages with minetest.chat_send_player and minetest.chat_send_all,
+/// those wait in the Server's queue until the end of the tick.
+///
+pub struct Chat {
+  on_chat_message: Vec<RegistryKey>,
+  commands: BTreeMap<String, ChatCommand>,
+}
+
+impl Chat {
+  pub fn new() -> Self {
+    let mut commands = BTreeMap::new();
+    commands.insert(
+      "help".to_string(),
+      ChatCommand {
+        params: "[command]".to_string(),
+        description: "List the commands you can run, or explain one.".to_string(),
+        privs: vec![],
+        handler: ChatCommandHandler::Help,
+      },
+    );
+    commands.insert(
+      "rollback".to_string(),
+      ChatCommand {
+        params: "<player> <seconds>".to_string(),
+        description: "Undo every node change a player made in the last few seconds.".to_string(),
+        privs: vec!["rollback".to_string()],
+        handler: ChatCommandHandler::Rollback,
+      },
+    );
+
+    Chat {
+      on_chat_message: vec![],
+      commands,
+    }
+  }
+
+  ///
+  /// Pick the callbacks and commands out of the on_chat_message and
+  /// chatcommands tables in a LuaEngine's internals.
+  ///
+  pub fn from_lua_tables(
+    lua: &Lua,
+    on_chat_message: &Table,
+    chatcommands: &Table,
+  ) -> Result<Self, String> {
+    let mut new_chat = Chat::new();
+
+    for callback in on_chat_message.clone().sequence_values::<Function>() {
+      let callback = match callback {
+        Ok(callback) => callback,
+        Err(e) => return Err(format!("Chat: malformed on_chat_message table. {}", e)),
+      };
+      match lua.create_registry_value(callback) {
+        Ok(callback) => new_chat.on_chat_message.push(callback),
+        Err(e) => return Err(format!("Chat: failed to store on_chat_message. {}", e)),
+      }
+    }
+
+    for pair in chatcommands.clone().pairs::<String, Table>() {
+      let (name, definition) = match pair {
+        Ok(pair) => pair,
+        Err(e) => return Err(format!("Chat: malformed chatcommands table. {}", e)),
+      };
+      if new_chat.commands.contains_key(&name) {
+        return Err(format!("Chat: chatcommand [/{}] is built in.", name));
+      }
+
+      let to_error = |e: String| format!("Chat: chatcommand [/{}] {}", name, e);
+      let privs: BTreeMap<String, bool> =
+        get_field_or(&definition, "privs", BTreeMap::new()).map_err(to_error)?;
+      let func: Function = get_field(&definition, "func").map_err(to_error)?;
+      let func = match lua.create_registry_value(func) {
+        Ok(func) => func,
+        Err(e) => return Err(to_error(format!("failed to store func. {}", e))),
+      };
+
+      new_chat.commands.insert(
+        name.clone(),
+        ChatCommand {
+          params: get_field_or(&definition, "params", String::new()).map_err(to_error)?,
+          description: get_field_or(&definition, "description", String::new()).map_err(to_error)?,
+          privs: privs
+            .into_iter()
+            .filter(|(_, granted)| *granted)
+            .map(|(privilege, _)| privilege)
+            .collect(),
+          handler: ChatCommandHandler::Lua(func),
+        },
+      );
+    }
+
+    Ok(new_chat)
+  }
+
+  ///
+  /// Handle the chat messages the clients sent.
+  ///
+  /// Returns the console commands players ran through chat, for the Server to run
+  /// and answer with a ChatMessage.
+  ///
+  pub fn on_tick(
+    &self,
+    lua: &Lua,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+    connection: &mut ServerConnection,
+    chat_messages: &RefCell<Vec<ChatMessage>>,
+  ) -> Vec<(String, ConsoleCommand)> {
+    let mut console_commands = vec![];
+
+    for (end_point, message) in std::mem::take(&mut connection.chat_requests) {
+      let client = match connection.clients.get(&end_point) {
+        Some(client) => client,
+        None => continue,
+      };
+
+      let message = message.trim();
+      if message.is_empty() {
+        continue;
+      }
+      if message.len() > MAX_CHAT_MESSAGE_LENGTH {
+        chat_messages.borrow_mut().push(ChatMessage::to_player(
+          &client.name,
+          "-!- Your message is too long.",
+        ));
+        continue;
+      }
+
+      if self.run_on_chat_message(lua, &client.name, message) {
+        continue;
+      }
+
+      match message.strip_prefix('/') {
+        Some(command) => {
+          let (name, param) = match command.split_once(char::is_whitespace) {
+            Some((name, param)) => (name, param.trim()),
+            None => (command, ""),
+          };
+          match self.run_command(lua, environment, client, name, param) {
+            CommandResult::Reply(reply) => chat_messages
+              .borrow_mut()
+              .push(ChatMessage::to_player(&client.name, &reply)),
+            CommandResult::Console(console_command) => {
+              console_commands.push((client.name.clone(), console_command))
+            }
+            CommandResult::Done => (),
+          }
+        }
+        None => chat_messages
+          .borrow_mut()
+          .push(ChatMessage::to_all(&format!(
+            "<{}> {}",
+            client.name, message
+          ))),
+      }
+    }
+
+    console_commands
+  }
+
+  ///
+  /// Ask the mods about a message. Returns true if one of them handled it.
+  ///
+  /// Signature: callback(name, message) -> boolean?
+  ///
+  fn run_on_chat_message(&self, lua: &Lua, player_name: &str, message: &str) -> bool {
+    for callback in &self.on_chat_message {
+      let function: Function = match lua.registry_value(callback) {
+        Ok(function) => function,
+        Err(e) => panic!("Chat: on_chat_message went missing. {}", e),
+      };
+
+      let handled = call_mod_function::<_, Option<bool>>(
+        lua,
+        &function,
+        "on_chat_message",
+        (player_name, message),
+      );
+      if let Some(Some(true)) = handled {
+        return true;
+      }
+    }
+
+    false
+  }
+
+  ///
+  /// Check a player may run a command, then run it.
+  ///
+  fn run_command(
+    &self,
+    lua: &Lua,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+    client: &ConnectedClient,
+    name: &str,
+    param: &str,
+  ) -> CommandResult {
+    let command = match self.commands.get(name) {
+      Some(command) => command,
+      None => {
+        return CommandResult::Reply(format!(
+          "-!- Invalid command: /{}. Type /help for a list of commands.",
+          name
+        ))
+      }
+    };
+
+    let missing_privs: Vec<&str> = command
+      .privs
+      .iter()
+      .filter(|privilege| !client.has_privilege(privilege))
+      .map(|privilege| privilege.as_str())
+      .collect();
+    if !missing_privs.is_empty() {
+      return CommandResult::Reply(format!(
+        "-!- You don't have permission to run /{}. Missing privileges: {}",
+        name,
+        missing_privs.join(", ")
+      ));
+    }
+
+    match &command.handler {
+      ChatCommandHandler::Help => CommandResult::Reply(self.get_help(client, param)),
+      ChatCommandHandler::Rollback => match ConsoleCommand::parse(&format!("rollback {}", param)) {
+        Ok(console_command) => CommandResult::Console(console_command),
+        Err(_) => CommandResult::Reply(format!("-!- Usage: /rollback {}", command.params)),
+      },
+      ChatCommandHandler::Lua(func) => {
+        let function: Function = match lua.registry_value(func) {
+          Ok(function) => function,
+          Err(e) => panic!("Chat: chatcommand [/{}] went missing. {}", name, e),
+        };
+
+        // Node changes made by the command are on the player.
+        environment
+          .borrow_mut()
+          .set_actor(&get_player_actor(&client.name));
+
+        let context = format!("chatcommand [/{}]", name);
+        match call_mod_function::<_, (Option<bool>, Option<String>)>(
+          lua,
+          &function,
+          &context,
+          (client.name.as_str(), param),
+        ) {
+          Some((_, Some(message))) => CommandResult::Reply(message),
+          Some((_, None)) => CommandResult::Done,
+          None => CommandResult::Reply(format!("-!- /{} failed. Check the server log.", name)),
+        }
+      }
+    }
+  }
+
+  ///
+  /// /help lists the commands a player has the privileges for, /help name explains one.
+  ///
+  fn get_help(&self, client: &ConnectedClient, param: &str) -> String {
+    if !param.is_empty() {
+      let name = param.trim_start_matches('/');
+      return match self.commands.get(name) {
+        Some(command) => command.get_usage(name),
+        None => format!("-!- Command not available: /{}", name),
+      };
+    }
+
+    let names: Vec<&str> = self
+      .commands
+      .iter()
+      .filter(|(_, command)| {
+        command
+          .privs
+          .iter()
+          .all(|privilege| client.has_privilege(privilege))
+      })
+      .map(|(name, _)| name.as_str())
+      .collect();
+
+    format!(
+      "Available commands: /{}\nType /help <command> for more information.",
+      names.join(", /")
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, rc::Rc};
+
+  use mlua::{Lua, Table};
+
+  use super::{Chat, CommandResult};
+  use crate::game::server::{
+    console::ConsoleCommand, server_connection::ConnectedClient,
+    server_environment::ServerEnvironment,
+  };
+
+  fn test_chat(lua: &Lua) -> Chat {
+    let tables = lua
+      .load(
+        r#"
+        local on_chat_message = {
+          function(name, message) return message:sub(1, 6) == "secret" end,
+          function(name, message) return nil end,
+        }
+        local chatcommands = {
+          ban = {
+            params = "<player>",
+            description = "Ban a player.",
+            privs = {ban = true, kick = false},
+            func = function(name, param) return true, name .. " banned " .. param end,
+          },
+          spawn = {
+            func = function(name, param) return true end,
+          },
+        }
+        return on_chat_message, chatcommands
+        "#,
+      )
+      .eval::<(Table, Table)>();
+    let (on_chat_message, chatcommands) = match tables {
+      Ok(tables) => tables,
+      Err(e) => panic!("{}", e),
+    };
+
+    match Chat::from_lua_tables(lua, &on_chat_message, &chatcommands) {
+      Ok(chat) => chat,
+      Err(e) => panic!("{}", e),
+    }
+  }
+
+  fn get_reply(result: CommandResult) -> String {
+    match result {
+      CommandResult::Reply(reply) => reply,
+      CommandResult::Console(console_command) => panic!("got {:?}", console_command),
+      CommandResult::Done => panic!("got no reply"),
+    }
+  }
+
+  #[test]
+  fn commands_need_their_privileges() {
+    let lua = Lua::new();
+    let chat = test_chat(&lua);
+    let environment = Rc::new(RefCell::new(ServerEnvironment::new()));
+
+    let player = ConnectedClient::new("player".to_string(), ["interact".to_string()].into());
+    let admin = ConnectedClient::new(
+      "admin".to_string(),
+      ["ban".to_string(), "rollback".to_string()].into(),
+    );
+
+    // /help only lists what each of them can run.
+    assert_eq!(
+      chat.get_help(&player, ""),
+      "Available commands: /help, /spawn\nType /help <command> for more information."
+    );
+    assert_eq!(
+      chat.get_help(&admin, ""),
+      "Available commands: /ban, /help, /rollback, /spawn\nType /help <command> for more information."
+    );
+    assert_eq!(
+      chat.get_help(&player, "/ban"),
+      "/ban <player>: Ban a player."
+    );
+
+    let reply = get_reply(chat.run_command(&lua, &environment, &player, "ban", "admin"));
+    assert_eq!(
+      reply,
+      "-!- You don't have permission to run /ban. Missing privileges: ban"
+    );
+    let reply = get_reply(chat.run_command(&lua, &environment, &admin, "ban", "player"));
+    assert_eq!(reply, "admin banned player");
+    assert!(matches!(
+      chat.run_command(&lua, &environment, &player, "spawn", ""),
+      CommandResult::Done
+    ));
+
+    // /rollback is handed over to the console, if it makes sense.
+    let reply = get_reply(chat.run_command(&lua, &environment, &admin, "rollback", "player"));
+    assert_eq!(reply, "-!- Usage: /rollback <player> <seconds>");
+    match chat.run_command(&lua, &environment, &admin, "rollback", "player 60") {
+      CommandResult::Console(console_command) => assert_eq!(
+        console_command,
+        ConsoleCommand::Rollback {
+          player_name: "player".to_string(),
+          seconds: 60,
+        }
+      ),
+      _ => panic!("/rollback didn't go to the console"),
+    }
+  }
+
+  #[test]
+  fn mods_can_swallow_chat_messages() {
+    let lua = Lua::new();
+    let chat = test_chat(&lua);
+
+    assert!(chat.run_on_chat_message(&lua, "player", "secret handshake"));
+    assert!(!chat.run_on_chat_message(&lua, "player", "hello everyone"));
+  }
+}
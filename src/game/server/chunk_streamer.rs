@@ -0,0 +1,373 @@
+use ahash::{AHashMap, AHashSet};
+use glam::IVec3;
+use message_io::network::Endpoint;
+
+use crate::game::{
+  map::{
+    chunk::{world_to_chunk_position, Node},
+    Map,
+  },
+  packet::{Packet, MAX_PACKET_ENTRIES},
+};
+
+use super::{server_connection::ServerConnection, server_environment::ServerEnvironment};
+
+///
+/// How many Chunks past view_range a Chunk has to be before a client forgets it.
+/// Walking back and forth over the edge of the view range shouldn't resend anything.
+///
+const FORGET_MARGIN: i32 = 1;
+
+///
+/// How many seconds a client has to acknowledge a Chunk before it gets sent again.
+///
+const RESEND_TIMEOUT: f64 = 2.0;
+
+///
+/// What a single client has in its chunk cache.
+///
+/// * known          - Chunks it has, or which are on the way. NodeChanges go out for these.
+/// * unacknowledged - Chunks on the way, and when they were sent.
+///
+#[derive(Default)]
+struct ClientChunks {
+  known: AHashSet<IVec3>,
+  unacknowledged: AHashMap<IVec3, f64>,
+}
+
+///
+/// What a client gets this tick.
+///
+/// * forget  - Chunks which left the view range.
+/// * send    - Chunks to send, nearest first.
+/// * missing - Chunks it's waiting on which aren't in memory.
+///
+struct ClientUpdate {
+  forget: Vec<IVec3>,
+  send: Vec<IVec3>,
+  missing: Vec<IVec3>,
+}
+
+///
+/// Sends the Map out to clients.
+///
+/// 1.) Each client gets the Chunks in its view range, nearest first,
+///     at most chunk_sends_per_tick per tick.
+/// 2.) After a client has a Chunk, it only gets NodeChanges for it.
+/// 3.) Chunks which fall out of the view range get forgotten by the client.
+///
+/// UDP can drop packets, so clients acknowledge every ChunkData with ChunksReceived.
+/// A Chunk that isn't acknowledged within RESEND_TIMEOUT gets sent again.
+///
+pub struct ChunkStreamer {
+  view_range: i32,
+  chunk_sends_per_tick: usize,
+
+  // Every Chunk offset inside of view_range, nearest first.
+  offsets: Vec<IVec3>,
+
+  // Seconds since the Server started.
+  time: f64,
+
+  client_chunks: AHashMap<Endpoint, ClientChunks>,
+}
+
+impl ChunkStreamer {
+  pub fn new(view_range: i32, chunk_sends_per_tick: usize) -> Self {
+    let mut offsets = vec![];
+    for x in -view_range..=view_range {
+      for y in -view_range..=view_range {
+        for z in -view_range..=view_range {
+          let offset = IVec3::new(x, y, z);
+          if offset.length_squared() <= view_range * view_range {
+            offsets.push(offset);
+          }
+        }
+      }
+    }
+    offsets.sort_by_key(|offset| offset.length_squared());
+
+    ChunkStreamer {
+      view_range,
+      chunk_sends_per_tick,
+
+      offsets,
+
+      time: 0.0,
+
+      client_chunks: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Bring every client up to date.
+  ///
+  /// node_changes comes from ServerEnvironment::take_node_changes().
+  ///
+  /// Returns the Chunks clients are waiting on which aren't in memory,
+  /// so the Server can emerge them.
+  ///
+  pub fn on_tick(
+    &mut self,
+    delta: f64,
+    connection: &mut ServerConnection,
+    environment: &ServerEnvironment,
+    node_changes: &[IVec3],
+  ) -> Vec<IVec3> {
+    self.time += delta;
+
+    // Clients that left don't need anything.
+    self
+      .client_chunks
+      .retain(|end_point, _| connection.clients.contains_key(end_point));
+
+    for (end_point, chunk_positions) in std::mem::take(&mut connection.chunk_acks) {
+      if let Some(client_chunks) = self.client_chunks.get_mut(&end_point) {
+        for chunk_position in chunk_positions {
+          client_chunks.unacknowledged.remove(&chunk_position);
+        }
+      }
+    }
+
+    let node_changes: AHashSet<IVec3> = node_changes.iter().copied().collect();
+
+    let mut missing = vec![];
+
+    for (end_point, client) in &connection.clients {
+      let mut client_chunks = self.client_chunks.remove(end_point).unwrap_or_default();
+      let center = world_to_chunk_position(client.position.floor().as_ivec3());
+
+      let update = self.update_client(&mut client_chunks, center, environment.get_map());
+
+      for batch in update.forget.chunks(MAX_PACKET_ENTRIES) {
+        connection.send_packet(*end_point, &Packet::ForgetChunks(batch.to_vec()));
+      }
+
+      // Chunks going out this tick already have the changes in them.
+      Self::send_node_changes(
+        connection,
+        *end_point,
+        &client_chunks.known,
+        &update.send,
+        environment,
+        &node_changes,
+      );
+
+      for chunk_position in update.send {
+        if let Some(chunk) = environment.get_map().get_chunk(chunk_position) {
+          connection.send_packet(
+            *end_point,
+            &Packet::ChunkData {
+              position: chunk_position,
+              data: chunk.serialize_for_client(),
+            },
+          );
+        }
+      }
+
+      missing.extend(update.missing);
+      self.client_chunks.insert(*end_point, client_chunks);
+    }
+
+    missing
+  }
+
+  ///
+  /// Work out what a client gets this tick, and mark it as sent.
+  ///
+  /// 1.) Far Chunks get forgotten.
+  /// 2.) Chunks which were never acknowledged get sent again.
+  /// 3.) The nearest Chunks the client doesn't have get sent,
+  ///     at most chunk_sends_per_tick of them.
+  ///
+  fn update_client(
+    &self,
+    client_chunks: &mut ClientChunks,
+    center: IVec3,
+    map: &Map,
+  ) -> ClientUpdate {
+    let forget_range = self.view_range + FORGET_MARGIN;
+    let forget: Vec<IVec3> = client_chunks
+      .known
+      .iter()
+      .filter(|chunk_position| {
+        (**chunk_position - center).length_squared() > forget_range * forget_range
+      })
+      .copied()
+      .collect();
+
+    for chunk_position in &forget {
+      client_chunks.known.remove(chunk_position);
+      client_chunks.unacknowledged.remove(chunk_position);
+    }
+
+    // Lost on the way. They go back in line with everything else.
+    client_chunks.unacknowledged.retain(|chunk_position, sent| {
+      let lost = self.time - *sent >= RESEND_TIMEOUT;
+      if lost {
+        client_chunks.known.remove(chunk_position);
+      }
+      !lost
+    });
+
+    let mut send = vec![];
+    let mut missing = vec![];
+    for offset in &self.offsets {
+      if send.len() >= self.chunk_sends_per_tick {
+        break;
+      }
+
+      let chunk_position = center + *offset;
+      if client_chunks.known.contains(&chunk_position) {
+        continue;
+      }
+
+      if map.has_chunk(chunk_position) {
+        send.push(chunk_position);
+      } else if missing.len() < self.chunk_sends_per_tick {
+        // Don't flood the emerge queue, it only needs to stay ahead of what gets sent.
+        missing.push(chunk_position);
+      }
+    }
+
+    for chunk_position in &send {
+      client_chunks.known.insert(*chunk_position);
+      client_chunks
+        .unacknowledged
+        .insert(*chunk_position, self.time);
+    }
+
+    ClientUpdate {
+      forget,
+      send,
+      missing,
+    }
+  }
+
+  fn send_node_changes(
+    connection: &ServerConnection,
+    end_point: Endpoint,
+    known_chunks: &AHashSet<IVec3>,
+    sending: &[IVec3],
+    environment: &ServerEnvironment,
+    node_changes: &AHashSet<IVec3>,
+  ) {
+    let changes: Vec<(IVec3, Node)> = node_changes
+      .iter()
+      .filter(|position| {
+        let chunk_position = world_to_chunk_position(**position);
+        known_chunks.contains(&chunk_position) && !sending.contains(&chunk_position)
+      })
+      .filter_map(|position| {
+        environment
+          .get_node(*position)
+          .map(|node| (*position, node))
+      })
+      .collect();
+
+    for batch in changes.chunks(MAX_PACKET_ENTRIES) {
+      connection.send_packet(end_point, &Packet::NodeChanges(batch.to_vec()));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, IVec3};
+
+  use super::{ChunkStreamer, ClientChunks, RESEND_TIMEOUT};
+  use crate::game::map::{chunk::Chunk, Map};
+
+  ///
+  /// Every Chunk within 3 of the origin, except for (0, 0, 2).
+  ///
+  fn test_map() -> Map {
+    let mut map = Map::new();
+    for x in -3..=3 {
+      for y in -3..=3 {
+        for z in -3..=3 {
+          if ivec3(x, y, z) != ivec3(0, 0, 2) {
+            map.insert_chunk(Chunk::new(ivec3(x, y, z)));
+          }
+        }
+      }
+    }
+    map
+  }
+
+  #[test]
+  fn nearest_chunks_go_first_up_to_the_cap() {
+    let map = test_map();
+    let chunk_streamer = ChunkStreamer::new(2, 4);
+    let mut client_chunks = ClientChunks::default();
+
+    let update = chunk_streamer.update_client(&mut client_chunks, IVec3::ZERO, &map);
+    assert_eq!(update.send.len(), 4);
+    assert_eq!(update.send[0], IVec3::ZERO);
+    assert!(update.send[1..]
+      .iter()
+      .all(|chunk_position| chunk_position.length_squared() == 1));
+    assert!(update.missing.is_empty());
+
+    // Everything in range goes out eventually, the hole gets asked for instead.
+    let mut sent = update.send.len();
+    let mut missing = vec![];
+    for _ in 0..100 {
+      let update = chunk_streamer.update_client(&mut client_chunks, IVec3::ZERO, &map);
+      assert!(update.send.len() <= 4);
+      sent += update.send.len();
+      missing.extend(update.missing);
+    }
+    assert_eq!(sent, chunk_streamer.offsets.len() - 1);
+    assert_eq!(client_chunks.known.len(), sent);
+    assert!(missing
+      .iter()
+      .all(|chunk_position| *chunk_position == ivec3(0, 0, 2)));
+    assert!(!missing.is_empty());
+  }
+
+  #[test]
+  fn far_chunks_are_forgotten() {
+    let map = test_map();
+    let chunk_streamer = ChunkStreamer::new(1, 100);
+    let mut client_chunks = ClientChunks::default();
+    chunk_streamer.update_client(&mut client_chunks, IVec3::ZERO, &map);
+    assert!(client_chunks.known.contains(&ivec3(-1, 0, 0)));
+
+    // One step is inside of the margin.
+    let update = chunk_streamer.update_client(&mut client_chunks, ivec3(1, 0, 0), &map);
+    assert!(update.forget.is_empty());
+
+    let update = chunk_streamer.update_client(&mut client_chunks, ivec3(2, 0, 0), &map);
+    assert!(update.forget.contains(&ivec3(-1, 0, 0)));
+    assert!(update
+      .forget
+      .iter()
+      .all(|chunk_position| (*chunk_position - ivec3(2, 0, 0)).length_squared() > 4));
+    assert!(!client_chunks.known.contains(&ivec3(-1, 0, 0)));
+    assert!(!client_chunks.unacknowledged.contains_key(&ivec3(-1, 0, 0)));
+  }
+
+  #[test]
+  fn unacknowledged_chunks_are_sent_again() {
+    let map = test_map();
+    let mut chunk_streamer = ChunkStreamer::new(1, 100);
+    let mut client_chunks = ClientChunks::default();
+    let update = chunk_streamer.update_client(&mut client_chunks, IVec3::ZERO, &map);
+    assert_eq!(update.send.len(), 7);
+
+    // Everything but the origin made it.
+    for chunk_position in &update.send[1..] {
+      client_chunks.unacknowledged.remove(chunk_position);
+    }
+
+    chunk_streamer.time += RESEND_TIMEOUT / 2.0;
+    let update = chunk_streamer.update_client(&mut client_chunks, IVec3::ZERO, &map);
+    assert!(update.send.is_empty());
+
+    chunk_streamer.time += RESEND_TIMEOUT / 2.0;
+    let update = chunk_streamer.update_client(&mut client_chunks, IVec3::ZERO, &map);
+    assert_eq!(update.send, vec![IVec3::ZERO]);
+    assert!(client_chunks.known.contains(&IVec3::ZERO));
+  }
+}
@@ -0,0 +1,122 @@
+// The server console. Commands typed into the terminal the server is running in.
+
+use std::{
+  io::stdin,
+  sync::mpsc::{self, Receiver},
+  thread,
+};
+
+use glam::IVec3;
+
+use crate::command_line::parse_position;
+
+pub const CONSOLE_HELP: &str = "Server console commands:
+  help                          - Show this.
+  backup [file]                 - Snapshot the world database. Defaults to the world's backups folder.
+  export <x,y,z> <x,y,z> <file> - Export the nodes between two corners into a region file.
+  import <file> <x,y,z>         - Import a region file with its minimum corner at a position.
+  rollback <player> <seconds>   - Undo every node change a player made in the last few seconds.";
+
+///
+/// A command typed into the server console.
+///
+#[derive(Debug, PartialEq)]
+pub enum ConsoleCommand {
+  Help,
+  Backup {
+    backup_path: Option<String>,
+  },
+  Export {
+    pos1: IVec3,
+    pos2: IVec3,
+    region_path: String,
+  },
+  Import {
+    region_path: String,
+    position: IVec3,
+  },
+  Rollback {
+    player_name: String,
+    seconds: u64,
+  },
+}
+
+impl ConsoleCommand {
+  pub fn parse(line: &str) -> Result<Self, String> {
+    let arguments: Vec<&str> = line.split_whitespace().collect();
+
+    let position = |argument: &str| parse_position(argument).map_err(|e| format!("Console: {}", e));
+
+    match arguments[..] {
+      ["help"] => Ok(ConsoleCommand::Help),
+      ["backup"] => Ok(ConsoleCommand::Backup { backup_path: None }),
+      ["backup", backup_path] => Ok(ConsoleCommand::Backup {
+        backup_path: Some(backup_path.to_string()),
+      }),
+      ["export", pos1, pos2, region_path] => Ok(ConsoleCommand::Export {
+        pos1: position(pos1)?,
+        pos2: position(pos2)?,
+        region_path: region_path.to_string(),
+      }),
+      ["import", region_path, position_argument] => Ok(ConsoleCommand::Import {
+        region_path: region_path.to_string(),
+        position: position(position_argument)?,
+      }),
+      ["rollback", player_name, seconds] => Ok(ConsoleCommand::Rollback {
+        player_name: player_name.to_string(),
+        seconds: seconds
+          .parse()
+          .map_err(|_| format!("Console: [{}] is not a number of seconds.", seconds))?,
+      }),
+      _ => Err(format!(
+        "Console: unknown command [{}]. Type help for a list of commands.",
+        line.trim()
+      )),
+    }
+  }
+}
+
+///
+/// Reads lines out of stdin on its own thread, so the tick loop never waits on it.
+///
+pub struct Console {
+  receiver: Receiver<String>,
+}
+
+impl Console {
+  pub fn new() -> Self {
+    let (sender, receiver) = mpsc::channel();
+
+    let reader = thread::Builder::new()
+      .name("console".to_string())
+      .spawn(move || {
+        for line in stdin().lines() {
+          match line {
+            Ok(line) => {
+              if sender.send(line).is_err() {
+                break;
+              }
+            }
+            Err(_) => break,
+          }
+        }
+      });
+
+    if let Err(e) = reader {
+      panic!("Console: failed to spawn reader thread. {}", e);
+    }
+
+    Console { receiver }
+  }
+
+  ///
+  /// Get every non-empty line typed since the last call.
+  ///
+  pub fn take_commands(&self) -> Vec<String> {
+    self
+      .receiver
+      .try_iter()
+      .filter(|line| !line.trim().is_empty())
+      .collect()
+  }
+}
@@ -0,0 +1,627 @@
+use std::{
+  cmp::Ordering,
+  collections::BinaryHeap,
+  rc::Rc,
+  sync::{
+    mpsc::{self, Receiver, Sender},
+    Arc, Condvar, Mutex,
+  },
+  thread::{self, JoinHandle},
+};
+
+use ahash::{AHashMap, AHashSet};
+use glam::{IVec3, Vec3};
+use mlua::RegistryKey;
+use unique_64::Unique64;
+
+use crate::game::map::{
+  block_registry::BlockRegistry,
+  chunk::{chunk_to_world_position, world_to_chunk_position, Chunk, CHUNK_SIZE},
+};
+
+use super::{map_database::MapDatabase, mapgen::Mapgen};
+
+///
+/// The most Chunks a single minetest.emerge_area can ask for.
+/// That's a cube 256 nodes to a side.
+///
+pub const MAX_EMERGE_AREA_CHUNKS: i64 = 16 * 16 * 16;
+
+///
+/// What happened to an emerged Chunk.
+///
+/// These are the exact same numbers as minetest.EMERGE_* in C++ minetest.
+/// They are exposed to Lua as minetest.emerge_action.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmergeAction {
+  Cancelled = 0,
+  Errored = 1,
+  FromMemory = 2,
+  FromDisk = 3,
+  Generated = 4,
+}
+
+///
+/// A finished Chunk coming back from an emerge worker.
+///
+/// chunk is None if the action is Cancelled or Errored.
+///
+pub struct EmergeResult {
+  pub chunk_position: IVec3,
+  pub chunk: Option<Chunk>,
+  pub action: EmergeAction,
+}
+
+///
+/// A call to minetest.emerge_area which the Server has not picked up yet.
+///
+pub struct EmergeAreaRequest {
+  pub chunk_positions: Vec<IVec3>,
+  pub callback: Option<RegistryKey>,
+}
+
+///
+/// Get every Chunk position between two node positions. (inclusive)
+///
+/// Fails if that's more than MAX_EMERGE_AREA_CHUNKS, before anything gets allocated.
+///
+pub fn get_area_chunk_positions(pos1: IVec3, pos2: IVec3) -> Result<Vec<IVec3>, String> {
+  let min = world_to_chunk_position(pos1.min(pos2));
+  let max = world_to_chunk_position(pos1.max(pos2));
+
+  // Worst case is i32::MIN to i32::MAX on every axis, that only fits into an i64 one axis at a time.
+  let mut volume: i64 = 1;
+  for (min, max) in [(min.x, max.x), (min.y, max.y), (min.z, max.z)] {
+    volume = volume.saturating_mul(max as i64 - min as i64 + 1);
+  }
+  if volume > MAX_EMERGE_AREA_CHUNKS {
+    return Err(format!(
+      "area is [{}] chunks, the most is [{}].",
+      volume, MAX_EMERGE_AREA_CHUNKS
+    ));
+  }
+
+  let mut chunk_positions = Vec::with_capacity(volume as usize);
+  for x in min.x..=max.x {
+    for y in min.y..=max.y {
+      for z in min.z..=max.z {
+        chunk_positions.push(IVec3::new(x, y, z));
+      }
+    }
+  }
+
+  Ok(chunk_positions)
+}
+
+///
+/// A Lua callback which is waiting on an area to emerge.
+///
+/// * callback  - The function from minetest.emerge_area, if it had one.
+/// * remaining - How many Chunks in the area have not finished yet.
+///
+struct EmergeArea {
+  callback: Option<Rc<RegistryKey>>,
+  remaining: usize,
+}
+
+///
+/// A callback that the Server needs to run because a Chunk in its
+/// area has finished emerging.
+///
+pub struct EmergeCallback {
+  pub callback: Rc<RegistryKey>,
+  pub chunk_position: IVec3,
+  pub action: EmergeAction,
+  pub calls_remaining: usize,
+}
+
+///
+/// A Chunk waiting in line.
+///
+/// The BinaryHeap is a max heap, so the ordering is reversed.
+/// The closest Chunk to a player is the "biggest".
+///
+#[derive(PartialEq, Eq)]
+struct EmergeRequest {
+  distance_squared: i64,
+  chunk_position: IVec3,
+}
+
+impl Ord for EmergeRequest {
+  fn cmp(&self, other: &Self) -> Ordering {
+    other
+      .distance_squared
+      .cmp(&self.distance_squared)
+      .then_with(|| {
+        self
+          .chunk_position
+          .to_array()
+          .cmp(&other.chunk_position.to_array())
+      })
+  }
+}
+
+impl PartialOrd for EmergeRequest {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+///
+/// The data that the workers and the Server thread share.
+///
+/// * requests        - Chunks waiting for a worker.
+/// * interest_points - Player positions. Chunks closest to these go first.
+/// * shutting_down   - Tells the workers to stop.
+///
+struct EmergeQueue {
+  requests: BinaryHeap<EmergeRequest>,
+  interest_points: Vec<Vec3>,
+  shutting_down: bool,
+}
+
+impl EmergeQueue {
+  ///
+  /// Squared distance from the center of a Chunk to the nearest interest point.
+  ///
+  /// If there are no players, the world origin is used instead.
+  ///
+  fn get_distance_squared(&self, chunk_position: IVec3) -> i64 {
+    let center = (chunk_to_world_position(chunk_position) + IVec3::splat(CHUNK_SIZE / 2)).as_vec3();
+
+    let distance = match self.interest_points.is_empty() {
+      true => center.length_squared(),
+      false => self
+        .interest_points
+        .iter()
+        .map(|point| point.distance_squared(center))
+        .fold(f32::MAX, f32::min),
+    };
+
+    distance as i64
+  }
+
+  fn push(&mut self, chunk_position: IVec3) {
+    let distance_squared = self.get_distance_squared(chunk_position);
+    self.requests.push(EmergeRequest {
+      distance_squared,
+      chunk_position,
+    });
+  }
+}
+
+///
+/// The emerge subsystem.
+///
+/// Emerging a Chunk means getting it into memory. Either by loading
+/// it out of the MapDatabase or by generating it with the Mapgen.
+///
+/// This is all done on a pool of worker threads so the Server's tick
+/// loop never stalls on the disk or mapgen. The Server requests Chunks
+/// and then picks up the results with receive() every tick.
+///
+/// ! Only the Server thread touches Emerge. The workers only see the queue.
+///
+pub struct Emerge {
+  queue: Arc<(Mutex<EmergeQueue>, Condvar)>,
+  result_sender: Sender<EmergeResult>,
+  result_receiver: Receiver<EmergeResult>,
+  workers: Vec<JoinHandle<()>>,
+
+  // Chunks which have been requested but have not come back yet.
+  in_flight: AHashSet<IVec3>,
+
+  // Lua emerge_area bookkeeping.
+  area_ids: Unique64,
+  areas: AHashMap<u64, EmergeArea>,
+  chunk_waiters: AHashMap<IVec3, Vec<u64>>,
+}
+
+impl Emerge {
+  pub fn new() -> Self {
+    let (result_sender, result_receiver) = mpsc::channel();
+
+    Emerge {
+      queue: Arc::new((
+        Mutex::new(EmergeQueue {
+          requests: BinaryHeap::new(),
+          interest_points: vec![],
+          shutting_down: false,
+        }),
+        Condvar::new(),
+      )),
+      result_sender,
+      result_receiver,
+      workers: vec![],
+
+      in_flight: AHashSet::new(),
+
+      area_ids: Unique64::new(),
+      areas: AHashMap::new(),
+      chunk_waiters: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Spin up the worker threads.
+  ///
+  /// One thread is left free for the Server. Each worker opens its own
+  /// connection to the MapDatabase.
+  ///
+  /// The BlockRegistry is only there to turn blocks that aren't registered anymore into ignore.
+  ///
+  pub fn start_workers(
+    &mut self,
+    mapgen: Arc<Mapgen>,
+    block_registry: Arc<BlockRegistry>,
+    database_path: &str,
+  ) {
+    if !self.workers.is_empty() {
+      panic!("Emerge: tried to start workers twice!");
+    }
+
+    let worker_count = match thread::available_parallelism() {
+      Ok(count) => (count.get() - 1).max(1),
+      Err(_) => 1,
+    };
+
+    for worker_id in 0..worker_count {
+      let database = match MapDatabase::new(database_path) {
+        Ok(database) => database,
+        Err(e) => panic!("Emerge: {}", e),
+      };
+      let queue = self.queue.clone();
+      let mapgen = mapgen.clone();
+      let block_registry = block_registry.clone();
+      let result_sender = self.result_sender.clone();
+
+      let worker = thread::Builder::new()
+        .name(format!("emerge_{}", worker_id))
+        .spawn(move || emerge_worker(queue, mapgen, block_registry, database, result_sender));
+
+      match worker {
+        Ok(worker) => self.workers.push(worker),
+        Err(e) => panic!("Emerge: failed to spawn worker thread. {}", e),
+      }
+    }
+
+    println!("Emerge: started [{}] worker threads.", worker_count);
+  }
+
+  ///
+  /// Put a Chunk in line to be emerged.
+  ///
+  /// Requesting a Chunk which is already in line does nothing.
+  ///
+  pub fn request_chunk(&mut self, chunk_position: IVec3) {
+    if !self.in_flight.insert(chunk_position) {
+      return;
+    }
+
+    let (lock, condvar) = &*self.queue;
+    match lock.lock() {
+      Ok(mut queue) => queue.push(chunk_position),
+      Err(e) => panic!("Emerge: queue lock poisoned. {}", e),
+    }
+    condvar.notify_one();
+  }
+
+  ///
+  /// Check if a Chunk has been requested but not received yet.
+  ///
+  pub fn is_in_flight(&self, chunk_position: IVec3) -> bool {
+    self.in_flight.contains(&chunk_position)
+  }
+
+  ///
+  /// Update where the players are and resort the queue around them.
+  ///
+  pub fn update_priorities(&mut self, interest_points: &[Vec3]) {
+    let (lock, _) = &*self.queue;
+    let mut queue = match lock.lock() {
+      Ok(queue) => queue,
+      Err(e) => panic!("Emerge: queue lock poisoned. {}", e),
+    };
+
+    if queue.interest_points == interest_points {
+      return;
+    }
+    queue.interest_points = interest_points.to_vec();
+
+    let old_requests = std::mem::take(&mut queue.requests);
+    for request in old_requests {
+      queue.push(request.chunk_position);
+    }
+  }
+
+  ///
+  /// Non-blocking. Pick up every Chunk the workers have finished.
+  ///
+  pub fn receive(&mut self) -> Vec<EmergeResult> {
+    let results: Vec<EmergeResult> = self.result_receiver.try_iter().collect();
+
+    for result in &results {
+      self.in_flight.remove(&result.chunk_position);
+    }
+
+    results
+  }
+
+  ///
+  /// Track an area from minetest.emerge_area.
+  ///
+  /// Every Chunk in the area must then be completed with complete_chunk().
+  ///
+  pub fn add_area(&mut self, chunk_positions: &[IVec3], callback: Option<RegistryKey>) {
+    if chunk_positions.is_empty() {
+      return;
+    }
+
+    let area_id = self.area_ids.get_next();
+
+    self.areas.insert(
+      area_id,
+      EmergeArea {
+        callback: callback.map(Rc::new),
+        remaining: chunk_positions.len(),
+      },
+    );
+
+    for chunk_position in chunk_positions {
+      self
+        .chunk_waiters
+        .entry(*chunk_position)
+        .or_default()
+        .push(area_id);
+    }
+  }
+
+  ///
+  /// Mark a Chunk as finished for every area waiting on it.
+  ///
+  /// Returns the Lua callbacks that need to be run.
+  ///
+  pub fn complete_chunk(
+    &mut self,
+    chunk_position: IVec3,
+    action: EmergeAction,
+  ) -> Vec<EmergeCallback> {
+    let mut callbacks = vec![];
+
+    let area_ids = match self.chunk_waiters.remove(&chunk_position) {
+      Some(area_ids) => area_ids,
+      None => return callbacks,
+    };
+
+    for area_id in area_ids {
+      let area = match self.areas.get_mut(&area_id) {
+        Some(area) => area,
+        None => continue,
+      };
+
+      area.remaining -= 1;
+
+      if let Some(callback) = &area.callback {
+        callbacks.push(EmergeCallback {
+          callback: callback.clone(),
+          chunk_position,
+          action,
+          calls_remaining: area.remaining,
+        });
+      }
+
+      if area.remaining == 0 {
+        self.areas.remove(&area_id);
+        self.area_ids.remove(area_id);
+      }
+    }
+
+    callbacks
+  }
+}
+
+impl Drop for Emerge {
+  fn drop(&mut self) {
+    let (lock, condvar) = &*self.queue;
+    match lock.lock() {
+      Ok(mut queue) => {
+        queue.shutting_down = true;
+        queue.requests.clear();
+      }
+      Err(e) => println!("Emerge: queue lock poisoned during shutdown. {}", e),
+    }
+    condvar.notify_all();
+
+    for worker in self.workers.drain(..) {
+      if worker.join().is_err() {
+        println!("Emerge: a worker thread panicked.");
+      }
+    }
+
+    println!("Emerge dropped!");
+  }
+}
+
+///
+/// The body of an emerge worker thread.
+///
+/// Sleeps until there is work, then loads the Chunk from the database.
+/// If the Chunk was never saved, it gets generated and saved.
+///
+fn emerge_worker(
+  queue: Arc<(Mutex<EmergeQueue>, Condvar)>,
+  mapgen: Arc<Mapgen>,
+  block_registry: Arc<BlockRegistry>,
+  database: MapDatabase,
+  result_sender: Sender<EmergeResult>,
+) {
+  let (lock, condvar) = &*queue;
+
+  loop {
+    let chunk_position = {
+      let mut queue = match lock.lock() {
+        Ok(queue) => queue,
+        Err(_) => return,
+      };
+
+      loop {
+        if queue.shutting_down {
+          return;
+        }
+        if let Some(request) = queue.requests.pop() {
+          break request.chunk_position;
+        }
+        queue = match condvar.wait(queue) {
+          Ok(queue) => queue,
+          Err(_) => return,
+        };
+      }
+    };
+
+    let result = match database.load_chunk(chunk_position, |id| block_registry.is_registered(id)) {
+      Ok(Some(chunk)) => EmergeResult {
+        chunk_position,
+        chunk: Some(chunk),
+        action: EmergeAction::FromDisk,
+      },
+      Ok(None) => {
+        let mut chunk = mapgen.generate(chunk_position);
+        match database.save_chunk(&chunk) {
+          Ok(_) => {
+            chunk.set_saved();
+            EmergeResult {
+              chunk_position,
+              chunk: Some(chunk),
+              action: EmergeAction::Generated,
+            }
+          }
+          Err(e) => {
+            println!("Emerge: {}", e);
+            EmergeResult {
+              chunk_position,
+              chunk: None,
+              action: EmergeAction::Errored,
+            }
+          }
+        }
+      }
+      Err(e) => {
+        println!("Emerge: {}", e);
+        EmergeResult {
+          chunk_position,
+          chunk: None,
+          action: EmergeAction::Errored,
+        }
+      }
+    };
+
+    // The Server is gone, nobody is listening anymore.
+    if result_sender.send(result).is_err() {
+      return;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    collections::BinaryHeap,
+    sync::Arc,
+    time::{Duration, Instant},
+  };
+
+  use glam::{ivec3, vec3, IVec3};
+  use mlua::Lua;
+
+  use super::{
+    get_area_chunk_positions, Emerge, EmergeAction, EmergeQueue, MAX_EMERGE_AREA_CHUNKS,
+  };
+  use crate::game::{map::block_registry::BlockRegistry, server::mapgen::Mapgen};
+
+  #[test]
+  fn emerge_areas_are_capped() {
+    let get_count =
+      |pos1: IVec3, pos2: IVec3| get_area_chunk_positions(pos1, pos2).map(|p| p.len());
+
+    assert_eq!(get_count(ivec3(47, 15, 0), ivec3(-1, 0, 15)), Ok(4));
+    assert_eq!(
+      get_count(IVec3::ZERO, IVec3::splat(255)),
+      Ok(MAX_EMERGE_AREA_CHUNKS as usize)
+    );
+    assert!(get_count(IVec3::ZERO, ivec3(256, 255, 255)).is_err());
+    assert!(get_count(IVec3::splat(-1_000_000), IVec3::splat(1_000_000)).is_err());
+    assert!(get_count(IVec3::splat(i32::MIN), IVec3::splat(i32::MAX)).is_err());
+  }
+
+  #[test]
+  fn closest_chunks_emerge_first() {
+    let mut queue = EmergeQueue {
+      requests: BinaryHeap::new(),
+      interest_points: vec![vec3(100.0, 8.0, 8.0)],
+      shutting_down: false,
+    };
+    for x in [0, 7, 5, 6] {
+      queue.push(ivec3(x, 0, 0));
+    }
+
+    let order: Vec<i32> = std::iter::from_fn(|| queue.requests.pop())
+      .map(|request| request.chunk_position.x)
+      .collect();
+    assert_eq!(order, vec![6, 5, 7, 0]);
+  }
+
+  #[test]
+  fn workers_emerge_every_chunk_in_an_area() {
+    let lua = Lua::new();
+    let callback = match lua
+      .create_function(|_, ()| Ok(()))
+      .and_then(|callback| lua.create_registry_value(callback))
+    {
+      Ok(callback) => callback,
+      Err(e) => panic!("{}", e),
+    };
+
+    // Every worker gets its own in memory database, so everything gets generated.
+    let mut emerge = Emerge::new();
+    emerge.start_workers(
+      Arc::new(Mapgen::new(1)),
+      Arc::new(BlockRegistry::new()),
+      ":memory:",
+    );
+
+    let chunk_positions = match get_area_chunk_positions(IVec3::ZERO, ivec3(47, 15, 31)) {
+      Ok(chunk_positions) => chunk_positions,
+      Err(e) => panic!("{}", e),
+    };
+    emerge.add_area(&chunk_positions, Some(callback));
+    for chunk_position in &chunk_positions {
+      emerge.request_chunk(*chunk_position);
+      emerge.request_chunk(*chunk_position);
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut calls_remaining = vec![];
+    while calls_remaining.len() < chunk_positions.len() {
+      assert!(Instant::now() < deadline, "emerge workers took too long.");
+      for result in emerge.receive() {
+        assert_eq!(result.action, EmergeAction::Generated);
+        assert!(result.chunk.is_some());
+        for callback in emerge.complete_chunk(result.chunk_position, result.action) {
+          calls_remaining.push(callback.calls_remaining);
+        }
+      }
+      std::thread::sleep(Duration::from_millis(1));
+    }
+
+    // Asking twice still only emerges each Chunk once.
+    calls_remaining.sort();
+    assert_eq!(
+      calls_remaining,
+      (0..chunk_positions.len()).collect::<Vec<_>>()
+    );
+    assert!(chunk_positions
+      .iter()
+      .all(|chunk_position| !emerge.is_in_flight(*chunk_position)));
+  }
+}
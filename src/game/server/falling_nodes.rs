@@ -0,0 +1,387 @@
+// Block physics. Sand and gravel fall, torches and flowers drop off.
+//
+// Blocks opt in with groups:
+// * falling_node = 1  - Turns into a falling entity when the node under it is open.
+// * attached_node = 1 - Gets removed when the node under it is open.
+//
+// "Open" means nothing is holding it up. Air, or a liquid.
+// Only nodes next to a change get checked, just like liquids.
+
+use ahash::AHashSet;
+use glam::{ivec3, vec3, IVec3, Vec3};
+
+use crate::game::map::{
+  block_registry::{BlockRegistry, LiquidType, AIR_ID},
+  chunk::Node,
+  node_meta::NodeMeta,
+};
+
+use super::server_environment::ServerEnvironment;
+
+///
+/// How fast falling nodes speed up. (nodes per second per second)
+///
+const GRAVITY: f32 = 9.81;
+
+///
+/// The fastest a falling node can go. (nodes per second)
+///
+/// Keeps a falling node from skipping through a whole column in one tick.
+///
+const MAX_FALL_SPEED: f32 = 40.0;
+
+///
+/// How far up a falling node looks for room when it lands somewhere that filled in.
+///
+const MAX_LANDING_SEARCH: i32 = 16;
+
+const NEIGHBORS: [IVec3; 7] = [
+  ivec3(0, 0, 0),
+  ivec3(1, 0, 0),
+  ivec3(-1, 0, 0),
+  ivec3(0, 1, 0),
+  ivec3(0, -1, 0),
+  ivec3(0, 0, 1),
+  ivec3(0, 0, -1),
+];
+
+///
+/// A node on its way down.
+///
+/// * position - The bottom corner. x and z always sit on the node grid.
+/// * velocity - How fast it's falling. (nodes per second, down is positive)
+/// * node     - What gets placed when it lands.
+/// * node_meta - The NodeMeta it had before it fell. It lands with it.
+///
+/// todo: clients don't see falling nodes until entities get sent over the network.
+/// todo: they only see the node disappear, and then show up where it lands.
+///
+#[derive(Clone, Debug)]
+pub struct FallingNode {
+  pub position: Vec3,
+  pub velocity: f32,
+  pub node: Node,
+  pub node_meta: Option<NodeMeta>,
+}
+
+impl FallingNode {
+  fn get_node_position(&self) -> IVec3 {
+    self.position.floor().as_ivec3()
+  }
+}
+
+///
+/// Runs the falling_node and attached_node groups.
+///
+/// Block IDs are stored as lookup tables indexed by ID, the same as ABMs.
+///
+pub struct FallingNodeRunner {
+  falling: Vec<bool>,
+  attached: Vec<bool>,
+  open: Vec<bool>,
+
+  checks: AHashSet<IVec3>,
+  falling_nodes: Vec<FallingNode>,
+}
+
+impl FallingNodeRunner {
+  pub fn new() -> Self {
+    FallingNodeRunner {
+      falling: vec![],
+      attached: vec![],
+      open: vec![],
+
+      checks: AHashSet::new(),
+      falling_nodes: vec![],
+    }
+  }
+
+  ///
+  /// Find every block in the falling_node and attached_node groups.
+  ///
+  pub fn from_block_registry(block_registry: &BlockRegistry) -> Self {
+    let mut falling_node_runner = FallingNodeRunner::new();
+
+    for id in 0..block_registry.get_block_count() as u16 {
+      let (falling, attached, open) = match block_registry.get_definition(id) {
+        Some(definition) => (
+          definition.get_group("falling_node") != 0,
+          definition.get_group("attached_node") != 0,
+          id == AIR_ID || definition.liquid_type != LiquidType::None,
+        ),
+        None => (false, false, id == AIR_ID),
+      };
+      falling_node_runner.falling.push(falling);
+      falling_node_runner.attached.push(attached);
+      falling_node_runner.open.push(open);
+    }
+
+    falling_node_runner
+  }
+
+  fn is_falling(&self, block_id: u16) -> bool {
+    self
+      .falling
+      .get(block_id as usize)
+      .copied()
+      .unwrap_or(false)
+  }
+
+  fn is_attached(&self, block_id: u16) -> bool {
+    self
+      .attached
+      .get(block_id as usize)
+      .copied()
+      .unwrap_or(false)
+  }
+
+  ///
+  /// If there's nothing to hold something up. Unloaded nodes hold everything up.
+  ///
+  fn is_open(&self, node: Option<Node>) -> bool {
+    match node {
+      Some(node) => self
+        .open
+        .get(node.block_id as usize)
+        .copied()
+        .unwrap_or(false),
+      None => false,
+    }
+  }
+
+  ///
+  /// Queue up every changed node and its neighbors to be checked on the next tick.
+  ///
+  pub fn queue_node_changes(&mut self, node_changes: &[IVec3]) {
+    if !self.falling.contains(&true) && !self.attached.contains(&true) {
+      return;
+    }
+
+    for position in node_changes {
+      for offset in NEIGHBORS {
+        self.checks.insert(*position + offset);
+      }
+    }
+  }
+
+  pub fn get_falling_nodes(&self) -> &[FallingNode] {
+    &self.falling_nodes
+  }
+
+  ///
+  /// Check everything that was queued, then move the falling nodes.
+  ///
+  pub fn on_tick(&mut self, delta: f64, environment: &mut ServerEnvironment) {
+    let checks: Vec<IVec3> = self.checks.drain().collect();
+    for position in checks {
+      self.check_node(environment, position);
+    }
+
+    let mut still_falling = Vec::with_capacity(self.falling_nodes.len());
+    for mut falling_node in std::mem::take(&mut self.falling_nodes) {
+      if !self.fall(environment, &mut falling_node, delta as f32) {
+        still_falling.push(falling_node);
+      }
+    }
+    self.falling_nodes = still_falling;
+  }
+
+  ///
+  /// Knock a node loose if nothing is holding it up.
+  ///
+  fn check_node(&mut self, environment: &mut ServerEnvironment, position: IVec3) {
+    let node = match environment.get_node(position) {
+      Some(node) => node,
+      None => return,
+    };
+
+    let falling = self.is_falling(node.block_id);
+    if !falling && !self.is_attached(node.block_id) {
+      return;
+    }
+
+    if !self.is_open(environment.get_node(position - IVec3::Y)) {
+      return;
+    }
+
+    // Setting it to air clears out its NodeMeta.
+    let node_meta = environment.get_map().get_node_meta(position).cloned();
+    environment.set_node(position, Node::air());
+
+    // todo: attached nodes should drop as items once items can exist in the world.
+    if falling {
+      self.falling_nodes.push(FallingNode {
+        position: position.as_vec3(),
+        velocity: 0.0,
+        node,
+        node_meta: node_meta.filter(|node_meta| !node_meta.is_empty()),
+      });
+    }
+  }
+
+  ///
+  /// Move a falling node down. Returns true once it has landed.
+  ///
+  /// Every node it passes through gets checked, so it can't tunnel through a floor.
+  /// It waits in the air if the area under it isn't loaded.
+  ///
+  fn fall(
+    &self,
+    environment: &mut ServerEnvironment,
+    falling_node: &mut FallingNode,
+    delta: f32,
+  ) -> bool {
+    falling_node.velocity = (falling_node.velocity + GRAVITY * delta).min(MAX_FALL_SPEED);
+    let new_y = falling_node.position.y - falling_node.velocity * delta;
+
+    let current = falling_node.get_node_position();
+    let lowest = new_y.floor() as i32;
+
+    for y in (lowest..current.y).rev() {
+      let below = environment.get_node(ivec3(current.x, y, current.z));
+      if below.is_none() {
+        falling_node.position.y = (y + 1) as f32;
+        falling_node.velocity = 0.0;
+        return false;
+      }
+      if !self.is_open(below) {
+        self.land(
+          environment,
+          falling_node,
+          ivec3(current.x, y + 1, current.z),
+        );
+        return true;
+      }
+    }
+
+    falling_node.position = vec3(falling_node.position.x, new_y, falling_node.position.z);
+    false
+  }
+
+  ///
+  /// Place a falling node back into the Map.
+  ///
+  /// If something got put where it was going to land it stacks on top.
+  ///
+  fn land(&self, environment: &mut ServerEnvironment, falling_node: &FallingNode, position: IVec3) {
+    for y in position.y..position.y + MAX_LANDING_SEARCH {
+      let landing_position = ivec3(position.x, y, position.z);
+      if !self.is_open(environment.get_node(landing_position)) {
+        continue;
+      }
+
+      environment.set_node(landing_position, falling_node.node);
+      if let Some(node_meta) = &falling_node.node_meta {
+        if let Some(existing) = environment
+          .get_map_mut()
+          .get_node_meta_mut(landing_position)
+        {
+          *existing = node_meta.clone();
+        }
+      }
+      return;
+    }
+
+    println!(
+      "FallingNodeRunner: no room for a falling node to land at [{}]. It was lost.",
+      position
+    );
+  }
+
+  ///
+  /// Put every falling node down wherever it is right now.
+  ///
+  /// Falling nodes only live in memory, this keeps them from being lost on shutdown.
+  ///
+  pub fn land_all(&mut self, environment: &mut ServerEnvironment) {
+    for falling_node in std::mem::take(&mut self.falling_nodes) {
+      self.land(environment, &falling_node, falling_node.get_node_position());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, IVec3};
+
+  use super::FallingNodeRunner;
+  use crate::game::{
+    map::{
+      block_registry::{BlockDefinition, BlockRegistry},
+      chunk::{Chunk, Node},
+    },
+    server::server_environment::ServerEnvironment,
+  };
+
+  fn test_block(name: &str, group: Option<&str>) -> BlockDefinition {
+    BlockDefinition {
+      groups: group
+        .map(|group| (group.to_string(), 1))
+        .into_iter()
+        .collect(),
+      ..BlockDefinition::test(name)
+    }
+  }
+
+  ///
+  /// Run ticks the way the Server does, feeding changes back in.
+  ///
+  fn run_ticks(environment: &mut ServerEnvironment, runner: &mut FallingNodeRunner, ticks: usize) {
+    for _ in 0..ticks {
+      let node_changes = environment.take_node_changes();
+      runner.queue_node_changes(&node_changes);
+      runner.on_tick(0.05, environment);
+    }
+  }
+
+  #[test]
+  fn unsupported_nodes_fall_and_drop() {
+    let mut block_registry = BlockRegistry::new();
+    let mut register = |definition| match block_registry.register_block(definition) {
+      Ok(id) => Node::new(id, 0),
+      Err(e) => panic!("{}", e),
+    };
+    let stone = register(test_block("test:stone", None));
+    let sand = register(test_block("test:sand", Some("falling_node")));
+    let torch = register(test_block("test:torch", Some("attached_node")));
+
+    let mut runner = FallingNodeRunner::from_block_registry(&block_registry);
+    let mut environment = ServerEnvironment::new();
+    environment.set_block_registry(block_registry);
+    environment.add_emerged_chunk(Chunk::new(IVec3::ZERO), true);
+
+    // A sand column on a stone pillar, and a torch on top.
+    environment.set_node(ivec3(4, 0, 4), stone);
+    environment.set_node(ivec3(4, 1, 4), stone);
+    environment.set_node(ivec3(4, 2, 4), sand);
+    environment.set_node(ivec3(4, 3, 4), sand);
+    environment.set_node(ivec3(4, 4, 4), torch);
+    if let Some(node_meta) = environment.get_map_mut().get_node_meta_mut(ivec3(4, 3, 4)) {
+      node_meta.set_string("label", "sand");
+    }
+    run_ticks(&mut environment, &mut runner, 5);
+    assert_eq!(environment.get_node(ivec3(4, 3, 4)), Some(sand));
+    assert!(runner.get_falling_nodes().is_empty());
+
+    // Knock out the pillar. The sand falls onto the stone that's left.
+    environment.set_node(ivec3(4, 1, 4), Node::air());
+    run_ticks(&mut environment, &mut runner, 3);
+    assert_eq!(environment.get_node(ivec3(4, 2, 4)), Some(Node::air()));
+    assert_eq!(runner.get_falling_nodes().len(), 2);
+
+    // The torch lost its support once the sand under it left.
+    assert_eq!(environment.get_node(ivec3(4, 4, 4)), Some(Node::air()));
+
+    run_ticks(&mut environment, &mut runner, 40);
+    assert!(runner.get_falling_nodes().is_empty());
+    assert_eq!(environment.get_node(ivec3(4, 1, 4)), Some(sand));
+    assert_eq!(environment.get_node(ivec3(4, 2, 4)), Some(sand));
+    assert_eq!(environment.get_node(ivec3(4, 3, 4)), Some(Node::air()));
+
+    // It landed with its NodeMeta.
+    match environment.get_map().get_node_meta(ivec3(4, 2, 4)) {
+      Some(node_meta) => assert_eq!(node_meta.get_string("label"), "sand"),
+      None => panic!("the falling node lost its NodeMeta."),
+    }
+  }
+}
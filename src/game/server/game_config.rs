@@ -0,0 +1,167 @@
+use configparser::ini::Ini;
+
+///
+/// The runtime settings of a game, read out of the [config] section of game.conf.
+///
+/// * hibernation_timeout - Seconds a Chunk outside of the active area stays in memory.
+/// * active_chunk_range  - Radius in Chunks around each player which is kept active.
+/// * max_loaded_chunks   - Hard cap on Chunks in memory. Idle Chunks get evicted early past this.
+/// * abm_budget          - Seconds per tick ABMs are allowed to use.
+/// * node_timer_budget   - Seconds per tick node timers are allowed to use.
+/// * liquid_budget       - Seconds per tick liquid flow is allowed to use.
+/// * liquid_update       - Seconds between each step of liquid flow.
+/// * view_range          - Radius in Chunks around each player which gets sent to their client.
+/// * chunk_sends_per_tick - How many Chunks each client can be sent per tick.
+/// * default_privileges  - Privileges every player gets when they join. (comma separated)
+/// * enable_rollback     - Record every node change in the rollback journal.
+///
+/// The budgets keep world ticking from dragging the Server under its tps.
+/// Work that doesn't fit gets carried over to the next tick.
+///
+pub struct GameConfig {
+  pub hibernation_timeout: f64,
+  pub active_chunk_range: i32,
+  pub max_loaded_chunks: usize,
+  pub abm_budget: f64,
+  pub node_timer_budget: f64,
+  pub liquid_budget: f64,
+  pub liquid_update: f64,
+  pub view_range: i32,
+  pub chunk_sends_per_tick: usize,
+  pub default_privileges: Vec<String>,
+  pub enable_rollback: bool,
+}
+
+impl GameConfig {
+  ///
+  /// The defaults, used for anything game.conf leaves out.
+  ///
+  pub fn new() -> Self {
+    GameConfig {
+      hibernation_timeout: 30.0,
+      active_chunk_range: 3,
+      max_loaded_chunks: 4096,
+      abm_budget: 0.01,
+      node_timer_budget: 0.01,
+      liquid_budget: 0.01,
+      liquid_update: 1.0,
+      view_range: 6,
+      chunk_sends_per_tick: 8,
+      default_privileges: vec!["interact".to_string()],
+      enable_rollback: false,
+    }
+  }
+
+  ///
+  /// Read the [config] section out of a parsed game.conf.
+  ///
+  pub fn from_game_conf(game_conf: &Ini) -> Result<Self, String> {
+    let mut game_config = GameConfig::new();
+
+    if let Some(hibernation_timeout) = get_config_value(
+      game_conf.getfloat("config", "hibernation_timeout"),
+      "hibernation_timeout",
+    )? {
+      if hibernation_timeout < 0.0 {
+        return Err("GameConfig: [hibernation_timeout] cannot be negative.".to_string());
+      }
+      game_config.hibernation_timeout = hibernation_timeout;
+    }
+
+    if let Some(active_chunk_range) = get_config_value(
+      game_conf.getuint("config", "active_chunk_range"),
+      "active_chunk_range",
+    )? {
+      game_config.active_chunk_range = active_chunk_range as i32;
+    }
+
+    if let Some(max_loaded_chunks) = get_config_value(
+      game_conf.getuint("config", "max_loaded_chunks"),
+      "max_loaded_chunks",
+    )? {
+      game_config.max_loaded_chunks = max_loaded_chunks as usize;
+    }
+
+    if let Some(abm_budget) =
+      get_config_value(game_conf.getfloat("config", "abm_budget"), "abm_budget")?
+    {
+      if abm_budget < 0.0 {
+        return Err("GameConfig: [abm_budget] cannot be negative.".to_string());
+      }
+      game_config.abm_budget = abm_budget;
+    }
+
+    if let Some(node_timer_budget) = get_config_value(
+      game_conf.getfloat("config", "node_timer_budget"),
+      "node_timer_budget",
+    )? {
+      if node_timer_budget < 0.0 {
+        return Err("GameConfig: [node_timer_budget] cannot be negative.".to_string());
+      }
+      game_config.node_timer_budget = node_timer_budget;
+    }
+
+    if let Some(liquid_budget) = get_config_value(
+      game_conf.getfloat("config", "liquid_budget"),
+      "liquid_budget",
+    )? {
+      if liquid_budget < 0.0 {
+        return Err("GameConfig: [liquid_budget] cannot be negative.".to_string());
+      }
+      game_config.liquid_budget = liquid_budget;
+    }
+
+    if let Some(liquid_update) = get_config_value(
+      game_conf.getfloat("config", "liquid_update"),
+      "liquid_update",
+    )? {
+      if liquid_update < 0.0 {
+        return Err("GameConfig: [liquid_update] cannot be negative.".to_string());
+      }
+      game_config.liquid_update = liquid_update;
+    }
+
+    if let Some(view_range) =
+      get_config_value(game_conf.getuint("config", "view_range"), "view_range")?
+    {
+      game_config.view_range = view_range as i32;
+    }
+
+    if let Some(chunk_sends_per_tick) = get_config_value(
+      game_conf.getuint("config", "chunk_sends_per_tick"),
+      "chunk_sends_per_tick",
+    )? {
+      if chunk_sends_per_tick == 0 {
+        return Err("GameConfig: [chunk_sends_per_tick] must be at least 1.".to_string());
+      }
+      game_config.chunk_sends_per_tick = chunk_sends_per_tick as usize;
+    }
+
+    if let Some(default_privileges) = game_conf.get("config", "default_privileges") {
+      game_config.default_privileges = default_privileges
+        .split(',')
+        .map(|privilege| privilege.trim().to_string())
+        .filter(|privilege| !privilege.is_empty())
+        .collect();
+    }
+
+    if let Some(enable_rollback) = get_config_value(
+      game_conf.getboolcoerce("config", "enable_rollback"),
+      "enable_rollback",
+    )? {
+      game_config.enable_rollback = enable_rollback;
+    }
+
+    Ok(game_config)
+  }
+}
+
+///
+/// Attach the key name to configparser's error.
+///
+fn get_config_value<T>(value: Result<Option<T>, String>, key: &str) -> Result<Option<T>, String> {
+  match value {
+    Ok(value) => Ok(value),
+    Err(e) => Err(format!("GameConfig: [{}] is malformed. {}", key, e)),
+  }
+}
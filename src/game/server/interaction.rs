@@ -0,0 +1,860 @@
+use std::{cell::RefCell, rc::Rc};
+
+use ahash::AHashMap;
+use glam::IVec3;
+use message_io::network::Endpoint;
+use mlua::{Function, Lua, RegistryKey, Table, Value};
+
+use crate::game::{
+  inventory::InventoryLocation,
+  item_registry::{ItemRegistry, DEFAULT_ITEM_RANGE},
+  item_stack::ItemStack,
+  lua_engine::{
+    lua_error::{call_mod_function, report_error},
+    lua_item_stack::{get_item_stack, LuaItemStack},
+    lua_table_helpers::node_position_to_vector,
+  },
+  map::{block_registry::BlockRegistry, chunk::Node, raycast::PointedThing},
+  packet::Packet,
+  tool_capabilities::ToolCapabilities,
+};
+
+use super::{
+  lua_api::{check_protection, node_to_table, player_ref::PlayerRef, pointed_thing_to_table},
+  rollback::get_player_actor,
+  server_connection::{ConnectedClient, ServerConnection},
+  server_environment::ServerEnvironment,
+};
+
+///
+/// Extra reach the Server allows on top of the item's range.
+/// Client positions are always a little behind, this keeps that from rejecting fair digs.
+///
+const REACH_MARGIN: f32 = 1.0;
+
+///
+/// A dig in progress.
+///
+struct Dig {
+  position: IVec3,
+  wield_index: usize,
+  block_id: u16,
+  elapsed: f32,
+  dig_time: f32,
+}
+
+///
+/// Digging and placing, with the Server having the final say.
+///
+/// 1.) Clients send DigStart, DigStop and Place with what they're pointing at.
+///     DigStart and Place name a slot in the player's main list, the item comes out of there.
+/// 2.) Each one is checked against the player's privileges, reach and minetest.is_protected.
+///     Reach is the wielded item's range.
+/// 3.) Digs take as long as the wielded item's tool_capabilities say, timed on the Server.
+///     Items without tool_capabilities dig like the hand. (the item named "")
+/// 4.) Finished digs run the block's on_dig, placing runs the wielded item's on_place.
+///     The defaults for those live in api.lua. (minetest.node_dig, minetest.item_place)
+///
+/// Changed nodes go out to everyone through the ChunkStreamer. Rejected actions
+/// resend the nodes involved to the player, in case their client got ahead of itself.
+///
+pub struct Interaction {
+  item_registry: Rc<ItemRegistry>,
+  hand_capabilities: ToolCapabilities,
+
+  // Block ID -> the block's on_dig.
+  on_dig: AHashMap<u16, RegistryKey>,
+  // Item name -> the item's on_place. Blocks are items too.
+  on_place: AHashMap<String, RegistryKey>,
+
+  digs: AHashMap<Endpoint, Dig>,
+}
+
+impl Interaction {
+  pub fn new() -> Self {
+    Interaction {
+      item_registry: Rc::new(ItemRegistry::new()),
+      hand_capabilities: ToolCapabilities::new(),
+
+      on_dig: AHashMap::new(),
+      on_place: AHashMap::new(),
+
+      digs: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Pick the callbacks out of the blocks and items tables in a LuaEngine's internals.
+  ///
+  pub fn from_lua_tables(
+    lua: &Lua,
+    blocks: &Table,
+    items: &Table,
+    item_registry: Rc<ItemRegistry>,
+    block_registry: &BlockRegistry,
+  ) -> Result<Self, String> {
+    let mut new_interaction = Interaction::new();
+
+    // The hand is the item named "".
+    if let Some(tool_capabilities) = item_registry
+      .get_definition("")
+      .and_then(|hand| hand.tool_capabilities.as_ref())
+    {
+      new_interaction.hand_capabilities = tool_capabilities.clone();
+    }
+    new_interaction.item_registry = item_registry;
+
+    // Only blocks get dug.
+    let tables: [(&str, &Table, &[&str]); 2] = [
+      ("block", blocks, &["on_dig", "on_place"]),
+      ("item", items, &["on_place"]),
+    ];
+
+    for (kind, table, fields) in tables {
+      for pair in table.clone().pairs::<String, Table>() {
+        let (name, definition) =
+          pair.map_err(|e| format!("Interaction: malformed {} table. {}", kind, e))?;
+
+        for field in fields {
+          let callback = match definition.get::<_, Option<Function>>(*field) {
+            Ok(Some(callback)) => callback,
+            Ok(None) => continue,
+            Err(e) => {
+              return Err(format!(
+                "Interaction: {} [{}] {} must be a function. {}",
+                kind, name, field, e
+              ))
+            }
+          };
+
+          let callback = match lua.create_registry_value(callback) {
+            Ok(callback) => callback,
+            Err(e) => {
+              return Err(format!(
+                "Interaction: {} [{}] failed to store {}. {}",
+                kind, name, field, e
+              ))
+            }
+          };
+
+          match *field {
+            "on_dig" => {
+              let block_id = block_registry.require_id(&name, "Interaction")?;
+              new_interaction.on_dig.insert(block_id, callback);
+            }
+            _ => {
+              new_interaction.on_place.insert(name.clone(), callback);
+            }
+          }
+        }
+      }
+    }
+
+    Ok(new_interaction)
+  }
+
+  ///
+  /// What a client digs with and how far it reaches, going by the item in main[wield_index].
+  ///
+  /// Empty slots, unknown items and items without tool_capabilities dig like the hand.
+  ///
+  fn get_wielded(
+    &self,
+    client: &ConnectedClient,
+    wield_index: usize,
+    environment: &ServerEnvironment,
+  ) -> (&ToolCapabilities, f32) {
+    let location = InventoryLocation::Player(client.name.clone());
+    let item_stack = match environment.get_inventory(&location) {
+      Some(inventory) => inventory.get_stack("main", wield_index),
+      None => ItemStack::default(),
+    };
+
+    let hand = self.item_registry.get_definition("");
+    let definition = match self.item_registry.get_definition(item_stack.get_name()) {
+      Some(definition) if !item_stack.is_empty() => Some(definition),
+      _ => hand,
+    };
+
+    let tool_capabilities =
+      match definition.and_then(|definition| definition.tool_capabilities.as_ref()) {
+        Some(tool_capabilities) => tool_capabilities,
+        None => &self.hand_capabilities,
+      };
+    let range = match definition {
+      Some(definition) => definition.range,
+      None => DEFAULT_ITEM_RANGE,
+    };
+
+    (tool_capabilities, range)
+  }
+
+  ///
+  /// Handle what the clients sent and move the digs in progress forward.
+  ///
+  pub fn on_tick(
+    &mut self,
+    delta: f64,
+    lua: &Lua,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+    connection: &mut ServerConnection,
+  ) {
+    for (end_point, packet) in std::mem::take(&mut connection.interaction_requests) {
+      match packet {
+        Packet::DigStart {
+          pointed_thing,
+          wield_index,
+        } => self.start_dig(
+          end_point,
+          &pointed_thing,
+          wield_index as usize,
+          lua,
+          environment,
+          connection,
+        ),
+        Packet::DigStop(_) => {
+          self.digs.remove(&end_point);
+        }
+        Packet::Place {
+          pointed_thing,
+          wield_index,
+        } => self.place(
+          end_point,
+          &pointed_thing,
+          wield_index as usize,
+          lua,
+          environment,
+          connection,
+        ),
+        _ => (),
+      }
+    }
+
+    // Clients that left can't finish digging.
+    self
+      .digs
+      .retain(|end_point, _| connection.clients.contains_key(end_point));
+
+    let mut finished = vec![];
+    for (end_point, dig) in &mut self.digs {
+      dig.elapsed += delta as f32;
+      if dig.elapsed >= dig.dig_time {
+        finished.push(*end_point);
+      }
+    }
+
+    for end_point in finished {
+      if let Some(dig) = self.digs.remove(&end_point) {
+        self.finish_dig(end_point, dig, lua, environment, connection);
+      }
+    }
+  }
+
+  fn start_dig(
+    &mut self,
+    end_point: Endpoint,
+    pointed_thing: &PointedThing,
+    wield_index: usize,
+    lua: &Lua,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+    connection: &ServerConnection,
+  ) {
+    let client = match connection.clients.get(&end_point) {
+      Some(client) => client,
+      None => return,
+    };
+
+    // todo: punching objects.
+    let position = match pointed_thing {
+      PointedThing::Node { under, .. } => *under,
+      PointedThing::Entity { .. } => return,
+    };
+
+    // Starting a new dig drops the old one.
+    self.digs.remove(&end_point);
+
+    let checked = self.check_dig(client, position, wield_index, &environment.borrow());
+    match checked {
+      Ok(dig) if dig.dig_time <= 0.0 => {
+        self.finish_dig(end_point, dig, lua, environment, connection)
+      }
+      Ok(dig) => {
+        self.digs.insert(end_point, dig);
+      }
+      Err(e) => {
+        println!("Interaction: [{}] can't dig. {}", client.name, e);
+        resend_nodes(connection, end_point, &environment.borrow(), &[position]);
+      }
+    }
+  }
+
+  ///
+  /// Make sure a client is allowed to dig a node, and find out how long it takes.
+  ///
+  fn check_dig(
+    &self,
+    client: &ConnectedClient,
+    position: IVec3,
+    wield_index: usize,
+    environment: &ServerEnvironment,
+  ) -> Result<Dig, String> {
+    let (tool_capabilities, range) = self.get_wielded(client, wield_index, environment);
+    check_reach(client, position, range)?;
+
+    let node = match environment.get_node(position) {
+      Some(node) => node,
+      None => return Err(format!("[{}] is not loaded.", position)),
+    };
+    let definition = match environment
+      .get_block_registry()
+      .get_definition(node.block_id)
+    {
+      Some(definition) => definition,
+      None => return Err(format!("[{}] is an unknown block.", position)),
+    };
+    if !definition.pointable {
+      return Err(format!("[{}] is not pointable.", definition.name));
+    }
+
+    match tool_capabilities.get_dig_time(definition) {
+      Some(dig_time) => Ok(Dig {
+        position,
+        wield_index,
+        block_id: node.block_id,
+        elapsed: 0.0,
+        dig_time,
+      }),
+      None => Err(format!(
+        "[{}] can't be dug with what they're holding.",
+        definition.name
+      )),
+    }
+  }
+
+  ///
+  /// The dig timer ran out. Check everything again, the world
+  /// and the player could have changed since it started.
+  ///
+  /// Signature: on_dig(pos, node, digger)
+  ///
+  fn finish_dig(
+    &self,
+    end_point: Endpoint,
+    dig: Dig,
+    lua: &Lua,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+    connection: &ServerConnection,
+  ) {
+    let client = match connection.clients.get(&end_point) {
+      Some(client) => client,
+      None => return,
+    };
+
+    let checked = self.check_dig(client, dig.position, dig.wield_index, &environment.borrow());
+    match checked {
+      Ok(checked) if checked.block_id == dig.block_id => (),
+      Ok(_) => return,
+      Err(e) => {
+        println!("Interaction: [{}] can't dig. {}", client.name, e);
+        return;
+      }
+    }
+
+    if is_protected(lua, dig.position, &client.name) {
+      println!(
+        "Interaction: [{}] can't dig at [{}], it is protected.",
+        client.name, dig.position
+      );
+      resend_nodes(
+        connection,
+        end_point,
+        &environment.borrow(),
+        &[dig.position],
+      );
+      return;
+    }
+
+    environment
+      .borrow_mut()
+      .set_actor(&get_player_actor(&client.name));
+
+    let on_dig = match self.on_dig.get(&dig.block_id) {
+      Some(on_dig) => on_dig,
+      None => {
+        environment.borrow_mut().set_node(dig.position, Node::air());
+        return;
+      }
+    };
+
+    // on_dig is going to borrow the environment right back.
+    let node = node_to_table(lua, &environment.borrow(), dig.position);
+    let result = node.and_then(|node| {
+      let on_dig: Function = lua.registry_value(on_dig)?;
+      let args = (
+        node_position_to_vector(lua, dig.position)?,
+        node,
+        PlayerRef::new(client.name.clone(), environment.clone()),
+      );
+      let context = format!("on_dig at [{}]", dig.position);
+      call_mod_function::<_, ()>(lua, &on_dig, &context, args);
+      Ok(())
+    });
+
+    if let Err(e) = result {
+      panic!("Interaction: on_dig at [{}] failed. {}", dig.position, e);
+    }
+  }
+
+  ///
+  /// Place the stack in main[wield_index].
+  ///
+  /// Signature: on_place(itemstack, placer, pointed_thing) -> leftover itemstack
+  ///
+  /// The leftover goes back into the slot. nil leaves the slot alone.
+  ///
+  fn place(
+    &self,
+    end_point: Endpoint,
+    pointed_thing: &PointedThing,
+    wield_index: usize,
+    lua: &Lua,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+    connection: &ServerConnection,
+  ) {
+    let client = match connection.clients.get(&end_point) {
+      Some(client) => client,
+      None => return,
+    };
+
+    let location = InventoryLocation::Player(client.name.clone());
+    let item_stack = match environment.borrow().get_inventory(&location) {
+      Some(inventory) => inventory.get_stack("main", wield_index),
+      None => ItemStack::default(),
+    };
+    let item = item_stack.get_name().to_string();
+
+    let (under, above) = match pointed_thing {
+      PointedThing::Node { under, above, .. } => (*under, *above),
+      PointedThing::Entity { .. } => return,
+    };
+
+    let checked = self.check_place(
+      client,
+      under,
+      above,
+      &item,
+      wield_index,
+      &environment.borrow(),
+    );
+    let on_place = match checked {
+      Ok(on_place) => on_place,
+      Err(e) => {
+        println!("Interaction: [{}] can't place. {}", client.name, e);
+        resend_nodes(
+          connection,
+          end_point,
+          &environment.borrow(),
+          &[under, above],
+        );
+        return;
+      }
+    };
+
+    if is_protected(lua, above, &client.name) {
+      println!(
+        "Interaction: [{}] can't place at [{}], it is protected.",
+        client.name, above
+      );
+      resend_nodes(
+        connection,
+        end_point,
+        &environment.borrow(),
+        &[under, above],
+      );
+      return;
+    }
+
+    environment
+      .borrow_mut()
+      .set_actor(&get_player_actor(&client.name));
+
+    let context = format!("on_place of [{}]", item);
+    let result = pointed_thing_to_table(lua, pointed_thing).and_then(|pointed_thing| {
+      let on_place: Function = lua.registry_value(on_place)?;
+      let args = (
+        LuaItemStack::new(item_stack),
+        PlayerRef::new(client.name.clone(), environment.clone()),
+        pointed_thing,
+      );
+      Ok(call_mod_function::<_, Value>(
+        lua, &on_place, &context, args,
+      ))
+    });
+
+    let leftover = match result {
+      Ok(Some(Value::Nil)) | Ok(None) => return,
+      Ok(Some(leftover)) => leftover,
+      Err(e) => panic!("Interaction: on_place of [{}] failed. {}", item, e),
+    };
+
+    match get_item_stack(&leftover) {
+      Ok(leftover) => {
+        if let Some(inventory) = environment.borrow_mut().get_inventory_mut(&location) {
+          // on_place could have resized the list out from under the slot.
+          if let Err(e) = inventory.set_stack("main", wield_index, leftover) {
+            println!("Interaction: [{}] lost the leftover. {}", context, e);
+          }
+        }
+      }
+      Err(e) => {
+        report_error(lua, &context, &e);
+      }
+    }
+  }
+
+  ///
+  /// Make sure a client is allowed to place an item where it says it's pointing.
+  ///
+  /// Returns the item's on_place.
+  ///
+  fn check_place(
+    &self,
+    client: &ConnectedClient,
+    under: IVec3,
+    above: IVec3,
+    item: &str,
+    wield_index: usize,
+    environment: &ServerEnvironment,
+  ) -> Result<&RegistryKey, String> {
+    let (_, range) = self.get_wielded(client, wield_index, environment);
+    check_reach(client, under, range)?;
+    check_reach(client, above, range)?;
+
+    // Above is always the face in front of under.
+    if (above - under).abs().dot(IVec3::ONE) > 1 {
+      return Err(format!("[{}] is not next to [{}].", above, under));
+    }
+
+    if environment.get_node(under).is_none() {
+      return Err(format!("[{}] is not loaded.", under));
+    }
+
+    if item.is_empty() {
+      return Err("there is nothing to place.".to_string());
+    }
+
+    match self.on_place.get(item) {
+      Some(on_place) => Ok(on_place),
+      None => Err(format!("[{}] is not a placeable item.", item)),
+    }
+  }
+}
+
+///
+/// Privileges first, then distance.
+///
+fn check_reach(client: &ConnectedClient, position: IVec3, range: f32) -> Result<(), String> {
+  if !client.has_privilege("interact") {
+    return Err("missing privilege [interact].".to_string());
+  }
+
+  let distance = client.position.distance(position.as_vec3());
+  if distance > range + REACH_MARGIN {
+    return Err(format!(
+      "[{}] is out of reach. ({:.1} nodes away)",
+      position, distance
+    ));
+  }
+
+  Ok(())
+}
+
+///
+/// Ask minetest.is_protected about a position.
+///
+/// A broken is_protected gets reported, and the position is treated as protected.
+///
+fn is_protected(lua: &Lua, position: IVec3, player_name: &str) -> bool {
+  match check_protection(lua, position, player_name) {
+    Ok(protected) => protected,
+    Err(e) => {
+      report_error(lua, "minetest.is_protected", &e);
+      true
+    }
+  }
+}
+
+///
+/// Tell a client what's really at some positions.
+///
+fn resend_nodes(
+  connection: &ServerConnection,
+  end_point: Endpoint,
+  environment: &ServerEnvironment,
+  positions: &[IVec3],
+) {
+  let node_changes: Vec<(IVec3, Node)> = positions
+    .iter()
+    .filter_map(|position| {
+      environment
+        .get_node(*position)
+        .map(|node| (*position, node))
+    })
+    .collect();
+
+  if !node_changes.is_empty() {
+    connection.send_packet(end_point, &Packet::NodeChanges(node_changes));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+  use ahash::AHashSet;
+  use glam::{ivec3, vec3, IVec3, Vec3};
+  use message_io::network::Endpoint;
+  use mlua::{Function, Lua};
+
+  use super::Interaction;
+  use crate::game::{
+    inventory::Inventory,
+    item_stack::ItemStack,
+    lua_engine::LuaEngine,
+    map::{
+      block_registry::{BlockDefinition, BlockRegistry, AIR_ID},
+      chunk::{Chunk, Node},
+      raycast::PointedThing,
+    },
+    packet::Packet,
+    server::{
+      lua_api::register_protection_api,
+      server_connection::{ConnectedClient, ServerConnection},
+      server_environment::ServerEnvironment,
+    },
+    tool_capabilities::GroupCap,
+  };
+
+  ///
+  /// A Chunk with stone at (2, 0, 0) and (12, 0, 0), and a player named digger at the origin.
+  ///
+  /// The hand digs stone in one second. Placing stone counts in _G.placed.
+  ///
+  struct TestWorld {
+    lua_engine: LuaEngine,
+    environment: Rc<RefCell<ServerEnvironment>>,
+    connection: ServerConnection,
+    end_point: Endpoint,
+    interaction: Interaction,
+    stone: u16,
+  }
+
+  impl TestWorld {
+    fn new() -> Self {
+      let mut block_registry = BlockRegistry::new();
+      let stone = match block_registry.register_block(BlockDefinition {
+        groups: BTreeMap::from([("cracky".to_string(), 1)]),
+        ..BlockDefinition::test("test:stone")
+      }) {
+        Ok(stone) => stone,
+        Err(e) => panic!("{}", e),
+      };
+
+      let mut environment = ServerEnvironment::new();
+      environment.set_block_registry(block_registry);
+      environment.add_emerged_chunk(Chunk::new(IVec3::ZERO), true);
+      environment.set_node(ivec3(2, 0, 0), Node::new(stone, 0));
+      environment.set_node(ivec3(12, 0, 0), Node::new(stone, 0));
+      let mut inventory = Inventory::new();
+      inventory.set_size("main", 2);
+      if let Err(e) = inventory.set_stack("main", 0, ItemStack::new("test:stone", 10)) {
+        panic!("{}", e);
+      }
+      environment.add_player("digger", inventory);
+      let environment = Rc::new(RefCell::new(environment));
+
+      let lua_engine = LuaEngine::new(true);
+      register_protection_api(&lua_engine, environment.clone());
+
+      let mut connection = ServerConnection::new("127.0.0.1".to_string(), 0);
+      let end_point = connection.get_test_end_point(40001);
+      let privileges = AHashSet::from(["interact".to_string()]);
+      connection.clients.insert(
+        end_point,
+        ConnectedClient::new("digger".to_string(), privileges),
+      );
+
+      let mut interaction = Interaction::new();
+      interaction.hand_capabilities.groupcaps.insert(
+        "cracky".to_string(),
+        GroupCap {
+          times: vec![1.0],
+          maxlevel: 0,
+        },
+      );
+      let lua = lua_engine.get_lua();
+      let on_place = match lua
+        .load("return function() placed = (placed or 0) + 1 end")
+        .eval::<Function>()
+        .and_then(|on_place| lua.create_registry_value(on_place))
+      {
+        Ok(on_place) => on_place,
+        Err(e) => panic!("{}", e),
+      };
+      interaction
+        .on_place
+        .insert("test:stone".to_string(), on_place);
+
+      TestWorld {
+        lua_engine,
+        environment,
+        connection,
+        end_point,
+        interaction,
+        stone,
+      }
+    }
+
+    fn send(&mut self, packet: Packet) {
+      self
+        .connection
+        .interaction_requests
+        .push((self.end_point, packet));
+    }
+
+    fn dig(&mut self, under: IVec3) {
+      self.send(Packet::DigStart {
+        pointed_thing: pointed_node(under, under + IVec3::Y),
+        wield_index: 0,
+      });
+    }
+
+    fn place(&mut self, under: IVec3, above: IVec3, wield_index: u32) {
+      self.send(Packet::Place {
+        pointed_thing: pointed_node(under, above),
+        wield_index,
+      });
+    }
+
+    fn tick(&mut self, delta: f64) {
+      self.interaction.on_tick(
+        delta,
+        self.lua_engine.get_lua(),
+        &self.environment,
+        &mut self.connection,
+      );
+    }
+
+    fn get_client(&mut self) -> &mut ConnectedClient {
+      match self.connection.clients.get_mut(&self.end_point) {
+        Some(client) => client,
+        None => panic!("digger went missing"),
+      }
+    }
+
+    fn protect(&mut self, position: IVec3) {
+      self
+        .environment
+        .borrow_mut()
+        .get_area_protection_mut()
+        .protect("owner", position, position);
+    }
+
+    fn get_block_id(&self, position: IVec3) -> u16 {
+      match self.environment.borrow().get_node(position) {
+        Some(node) => node.block_id,
+        None => panic!("[{}] is not loaded", position),
+      }
+    }
+
+    fn get_placed(&self) -> u32 {
+      get_placed(self.lua_engine.get_lua())
+    }
+  }
+
+  fn pointed_node(under: IVec3, above: IVec3) -> PointedThing {
+    PointedThing::Node {
+      under,
+      above,
+      intersection_point: under.as_vec3(),
+      intersection_normal: above - under,
+    }
+  }
+
+  fn get_placed(lua: &Lua) -> u32 {
+    match lua.globals().get::<_, Option<u32>>("placed") {
+      Ok(placed) => placed.unwrap_or(0),
+      Err(e) => panic!("{}", e),
+    }
+  }
+
+  #[test]
+  fn digs_are_checked_and_timed_on_the_server() {
+    let mut world = TestWorld::new();
+    let stone = world.stone;
+
+    // The dig only finishes once the hand's dig time has passed.
+    world.dig(ivec3(2, 0, 0));
+    world.tick(0.5);
+    assert_eq!(world.get_block_id(ivec3(2, 0, 0)), stone);
+    world.tick(0.6);
+    assert_eq!(world.get_block_id(ivec3(2, 0, 0)), AIR_ID);
+
+    // Out of reach.
+    world.dig(ivec3(12, 0, 0));
+    world.tick(2.0);
+    assert_eq!(world.get_block_id(ivec3(12, 0, 0)), stone);
+
+    // Without interact.
+    world.get_client().position = vec3(10.0, 0.0, 0.0);
+    world.get_client().privileges.clear();
+    world.dig(ivec3(12, 0, 0));
+    world.tick(2.0);
+    assert_eq!(world.get_block_id(ivec3(12, 0, 0)), stone);
+    world.get_client().privileges.insert("interact".to_string());
+
+    // Everything gets checked again once the timer runs out.
+    world.dig(ivec3(12, 0, 0));
+    world.tick(0.5);
+    world.get_client().position = Vec3::ZERO;
+    world.tick(1.0);
+    assert_eq!(world.get_block_id(ivec3(12, 0, 0)), stone);
+
+    // Protected.
+    world.get_client().position = vec3(10.0, 0.0, 0.0);
+    world.protect(ivec3(12, 0, 0));
+    world.dig(ivec3(12, 0, 0));
+    world.tick(2.0);
+    assert_eq!(world.get_block_id(ivec3(12, 0, 0)), stone);
+  }
+
+  #[test]
+  fn places_are_checked_before_on_place_runs() {
+    let mut world = TestWorld::new();
+
+    world.place(ivec3(2, 0, 0), ivec3(1, 0, 0), 0);
+    world.tick(0.0);
+    assert_eq!(world.get_placed(), 1);
+
+    // Not next to under, out of reach, an empty slot.
+    world.place(ivec3(2, 0, 0), ivec3(1, 1, 1), 0);
+    world.place(ivec3(12, 0, 0), ivec3(11, 0, 0), 0);
+    world.place(ivec3(2, 0, 0), ivec3(1, 0, 0), 1);
+    world.tick(0.0);
+    assert_eq!(world.get_placed(), 1);
+
+    // Without interact.
+    world.get_client().privileges.clear();
+    world.place(ivec3(2, 0, 0), ivec3(1, 0, 0), 0);
+    world.tick(0.0);
+    assert_eq!(world.get_placed(), 1);
+    world.get_client().privileges.insert("interact".to_string());
+
+    // Protected.
+    world.protect(ivec3(1, 0, 0));
+    world.place(ivec3(2, 0, 0), ivec3(1, 0, 0), 0);
+    world.place(ivec3(2, 0, 0), ivec3(2, 1, 0), 0);
+    world.tick(0.0);
+    assert_eq!(world.get_placed(), 2);
+  }
+}
@@ -0,0 +1,648 @@
+use std::{cell::RefCell, rc::Rc};
+
+use message_io::network::Endpoint;
+use mlua::{Function, IntoLuaMulti, Lua, MultiValue, RegistryKey, Table};
+
+use crate::game::{
+  inventory::{move_stack, Inventory, InventoryLocation},
+  item_stack::ItemStack,
+  lua_engine::{
+    lua_error::{call_mod_function, report_error},
+    lua_item_stack::LuaItemStack,
+  },
+  packet::Packet,
+};
+
+use super::{
+  lua_api::{get_detached_inventory_callbacks, inventory_ref::InvRef, player_ref::PlayerRef},
+  server_connection::{ConnectedClient, ServerConnection},
+  server_environment::ServerEnvironment,
+};
+
+///
+/// The lists every new player starts out with. (name, size, width)
+///
+const PLAYER_LISTS: [(&str, usize, u32); 3] = [("main", 32, 8), ("craft", 9, 3), ("hand", 1, 1)];
+
+///
+/// The Inventory a player who has never joined before gets.
+///
+pub fn create_player_inventory() -> Inventory {
+  let mut inventory = Inventory::new();
+  for (list_name, size, width) in PLAYER_LISTS {
+    inventory.set_size(list_name, size);
+    if let Err(e) = inventory.set_width(list_name, width) {
+      panic!("InventoryActions: {}", e);
+    }
+  }
+  inventory
+}
+
+///
+/// Send an Inventory to a client. Inventories which don't exist get sent empty,
+/// so the client forgets them.
+///
+pub fn send_inventory(
+  connection: &ServerConnection,
+  end_point: Endpoint,
+  environment: &ServerEnvironment,
+  location: &InventoryLocation,
+) {
+  let inventory = match environment.get_inventory(location) {
+    Some(inventory) => inventory.clone(),
+    None => Inventory::new(),
+  };
+
+  connection.send_packet(
+    end_point,
+    &Packet::InventoryData {
+      location: location.clone(),
+      inventory,
+    },
+  );
+}
+
+///
+/// A client asking to move items from one slot to another.
+///
+struct ItemMove {
+  from: InventoryLocation,
+  from_list: String,
+  from_index: usize,
+  to: InventoryLocation,
+  to_list: String,
+  to_index: usize,
+  // 0 is the whole stack.
+  count: u16,
+}
+
+///
+/// What a move looks like to the mods of one Inventory.
+///
+/// Moves inside of one Inventory are a Move. Moves between two are a
+/// Take out of one and a Put into the other.
+///
+enum Action<'a> {
+  Move(&'a ItemMove, u16),
+  Take(&'a str, usize, ItemStack),
+  Put(&'a str, usize, ItemStack),
+}
+
+impl Action<'_> {
+  fn get_name(&self) -> &'static str {
+    match self {
+      Action::Move(..) => "move",
+      Action::Take(..) => "take",
+      Action::Put(..) => "put",
+    }
+  }
+}
+
+///
+/// Players moving items around in the inventories they can see.
+///
+/// Mods get the final say on every move, in the same places as C++ minetest:
+/// * Player inventories   - minetest.register_allow_player_inventory_action
+///   and minetest.register_on_player_inventory_action.
+/// * Detached inventories - The callbacks given to minetest.create_detached_inventory.
+///
+/// allow callbacks return how many of the items may move, the lowest answer wins.
+/// on callbacks run once the items have moved.
+///
+/// Rejected moves resend the inventories involved, in case the client got ahead of itself.
+///
+/// todo: node inventories need formspecs before players can get to them.
+///
+pub struct InventoryActions {
+  allow_player_inventory_action: Vec<RegistryKey>,
+  on_player_inventory_action: Vec<RegistryKey>,
+}
+
+impl InventoryActions {
+  pub fn new() -> Self {
+    InventoryActions {
+      allow_player_inventory_action: vec![],
+      on_player_inventory_action: vec![],
+    }
+  }
+
+  ///
+  /// Pick the callbacks out of the allow_player_inventory_action and
+  /// on_player_inventory_action tables in a LuaEngine's internals.
+  ///
+  pub fn from_lua_tables(
+    lua: &Lua,
+    allow_player_inventory_action: &Table,
+    on_player_inventory_action: &Table,
+  ) -> Result<Self, String> {
+    let mut new_inventory_actions = InventoryActions::new();
+
+    for (kind, table, callbacks) in [
+      (
+        "allow_player_inventory_action",
+        allow_player_inventory_action,
+        &mut new_inventory_actions.allow_player_inventory_action,
+      ),
+      (
+        "on_player_inventory_action",
+        on_player_inventory_action,
+        &mut new_inventory_actions.on_player_inventory_action,
+      ),
+    ] {
+      for callback in table.clone().sequence_values::<Function>() {
+        let callback = match callback {
+          Ok(callback) => callback,
+          Err(e) => return Err(format!("InventoryActions: malformed {} table. {}", kind, e)),
+        };
+        match lua.create_registry_value(callback) {
+          Ok(callback) => callbacks.push(callback),
+          Err(e) => return Err(format!("InventoryActions: failed to store {}. {}", kind, e)),
+        }
+      }
+    }
+
+    Ok(new_inventory_actions)
+  }
+
+  ///
+  /// Handle the InventoryMoves the clients sent.
+  ///
+  pub fn on_tick(
+    &self,
+    lua: &Lua,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+    connection: &mut ServerConnection,
+  ) {
+    for (end_point, packet) in std::mem::take(&mut connection.inventory_requests) {
+      let item_move = match packet {
+        Packet::InventoryMove {
+          from,
+          from_list,
+          from_index,
+          to,
+          to_list,
+          to_index,
+          count,
+        } => ItemMove {
+          from,
+          from_list,
+          from_index: from_index as usize,
+          to,
+          to_list,
+          to_index: to_index as usize,
+          count,
+        },
+        _ => continue,
+      };
+
+      let client = match connection.clients.get(&end_point) {
+        Some(client) => client,
+        None => continue,
+      };
+
+      if let Err(e) = self.move_items(client, &item_move, lua, environment) {
+        println!(
+          "InventoryActions: [{}] can't move items. {}",
+          client.name, e
+        );
+
+        let environment = environment.borrow();
+        for location in [&item_move.from, &item_move.to] {
+          if environment.can_player_see_inventory(&client.name, location) {
+            send_inventory(connection, end_point, &environment, location);
+          }
+        }
+      }
+    }
+  }
+
+  ///
+  /// Check a move, ask the mods about it, then do it.
+  ///
+  fn move_items(
+    &self,
+    client: &ConnectedClient,
+    item_move: &ItemMove,
+    lua: &Lua,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+  ) -> Result<(), String> {
+    if !client.has_privilege("interact") {
+      return Err("missing privilege [interact].".to_string());
+    }
+
+    let same_inventory = item_move.from == item_move.to;
+    if same_inventory
+      && item_move.from_list == item_move.to_list
+      && item_move.from_index == item_move.to_index
+    {
+      return Err("items can't move onto the slot they're in.".to_string());
+    }
+
+    let (from_stack, to_stack) = get_slots(client, item_move, &environment.borrow())?;
+    if from_stack.is_empty() {
+      return Err("there is nothing to move.".to_string());
+    }
+    // A swap would put items into the source the mods never got asked about.
+    if !same_inventory && !to_stack.is_empty() && !to_stack.stacks_with(&from_stack) {
+      return Err("items can't be swapped between inventories.".to_string());
+    }
+
+    let count = match item_move.count {
+      0 => from_stack.get_count(),
+      count => count.min(from_stack.get_count()),
+    };
+
+    let mut allowed = count;
+    for (location, action) in get_actions(item_move, &from_stack.peek_item(count)) {
+      allowed = allowed.min(self.get_allowed_count(
+        lua,
+        environment,
+        &client.name,
+        location,
+        &action,
+        allowed,
+      ));
+    }
+    if allowed == 0 {
+      return Err("a mod didn't allow it.".to_string());
+    }
+
+    // The allow callbacks could have changed anything, so check everything again.
+    let (from_stack, to_stack) = get_slots(client, item_move, &environment.borrow())?;
+    let stack_max = environment
+      .borrow()
+      .get_item_registry()
+      .get_stack_max(from_stack.get_name());
+    let (from_after, to_after) = match move_stack(&from_stack, &to_stack, allowed, stack_max) {
+      Some(stacks) => stacks,
+      None => return Err("the items don't fit.".to_string()),
+    };
+
+    let moved_count = match from_after.stacks_with(&from_stack) {
+      true => from_stack.get_count() - from_after.get_count(),
+      false => from_stack.get_count(),
+    };
+
+    {
+      let mut environment = environment.borrow_mut();
+      for (location, list_name, index, stack) in [
+        (
+          &item_move.from,
+          &item_move.from_list,
+          item_move.from_index,
+          from_after,
+        ),
+        (
+          &item_move.to,
+          &item_move.to_list,
+          item_move.to_index,
+          to_after,
+        ),
+      ] {
+        if let Some(inventory) = environment.get_inventory_mut(location) {
+          inventory.set_stack(list_name, index, stack)?;
+        }
+      }
+    }
+
+    for (location, action) in get_actions(item_move, &from_stack.peek_item(moved_count)) {
+      self.run_on_callbacks(lua, environment, &client.name, location, &action);
+    }
+
+    Ok(())
+  }
+
+  ///
+  /// Get the allow callbacks or on callbacks for an action in an Inventory.
+  ///
+  /// Detached inventories keep theirs in the table they were created with,
+  /// as allow_move, on_put, etc.
+  ///
+  fn get_callbacks<'lua>(
+    &self,
+    lua: &'lua Lua,
+    location: &InventoryLocation,
+    kind: &str,
+    action: &Action,
+  ) -> mlua::Result<Vec<Function<'lua>>> {
+    match location {
+      InventoryLocation::Player(_) => {
+        let callbacks = match kind {
+          "allow" => &self.allow_player_inventory_action,
+          _ => &self.on_player_inventory_action,
+        };
+        callbacks
+          .iter()
+          .map(|callback| lua.registry_value(callback))
+          .collect()
+      }
+      InventoryLocation::Detached(name) => match get_detached_inventory_callbacks(lua, name)? {
+        Some(callbacks) => {
+          let field = format!("{}_{}", kind, action.get_name());
+          Ok(
+            callbacks
+              .get::<_, Option<Function>>(field)?
+              .into_iter()
+              .collect(),
+          )
+        }
+        None => Ok(vec![]),
+      },
+      InventoryLocation::Node(_) => Ok(vec![]),
+    }
+  }
+
+  ///
+  /// Ask the allow callbacks how many of count items can move.
+  ///
+  /// nil means no objection. Errors and anything that isn't a number mean 0.
+  ///
+  fn get_allowed_count(
+    &self,
+    lua: &Lua,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+    player_name: &str,
+    location: &InventoryLocation,
+    action: &Action,
+    count: u16,
+  ) -> u16 {
+    let context = format!("allow_{} of {}", action.get_name(), location);
+
+    let result = self
+      .get_callbacks(lua, location, "allow", action)
+      .and_then(|callbacks| {
+        let mut allowed = count;
+        for callback in callbacks {
+          let args = get_callback_args(lua, environment, player_name, location, action)?;
+          allowed = match call_mod_function::<_, Option<i64>>(lua, &callback, &context, args) {
+            Some(Some(answer)) => allowed.min(answer.clamp(0, u16::MAX as i64) as u16),
+            Some(None) => allowed,
+            None => 0,
+          };
+        }
+        Ok(allowed)
+      });
+
+    match result {
+      Ok(allowed) => allowed,
+      Err(e) => {
+        report_error(lua, &context, &e);
+        0
+      }
+    }
+  }
+
+  fn run_on_callbacks(
+    &self,
+    lua: &Lua,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+    player_name: &str,
+    location: &InventoryLocation,
+    action: &Action,
+  ) {
+    let context = format!("on_{} of {}", action.get_name(), location);
+
+    let result = self
+      .get_callbacks(lua, location, "on", action)
+      .and_then(|callbacks| {
+        for callback in callbacks {
+          let args = get_callback_args(lua, environment, player_name, location, action)?;
+          call_mod_function::<_, ()>(lua, &callback, &context, args);
+        }
+        Ok(())
+      });
+
+    if let Err(e) = result {
+      report_error(lua, &context, &e);
+    }
+  }
+}
+
+///
+/// Get what's in the two slots of a move, making sure the player can get to them.
+///
+fn get_slots(
+  client: &ConnectedClient,
+  item_move: &ItemMove,
+  environment: &ServerEnvironment,
+) -> Result<(ItemStack, ItemStack), String> {
+  let get_slot = |location: &InventoryLocation, list_name: &str, index: usize| {
+    if !environment.can_player_see_inventory(&client.name, location) {
+      return Err(format!("{} is out of reach.", location));
+    }
+    let inventory = match environment.get_inventory(location) {
+      Some(inventory) => inventory,
+      None => return Err(format!("{} does not exist.", location)),
+    };
+    if index >= inventory.get_size(list_name) {
+      return Err(format!(
+        "slot [{}] of list [{}] in {} does not exist.",
+        index, list_name, location
+      ));
+    }
+    Ok(inventory.get_stack(list_name, index))
+  };
+
+  Ok((
+    get_slot(&item_move.from, &item_move.from_list, item_move.from_index)?,
+    get_slot(&item_move.to, &item_move.to_list, item_move.to_index)?,
+  ))
+}
+
+///
+/// Split a move up into what each Inventory's mods get asked about.
+///
+fn get_actions<'a>(
+  item_move: &'a ItemMove,
+  stack: &ItemStack,
+) -> Vec<(&'a InventoryLocation, Action<'a>)> {
+  if item_move.from == item_move.to {
+    return vec![(&item_move.from, Action::Move(item_move, stack.get_count()))];
+  }
+
+  vec![
+    (
+      &item_move.from,
+      Action::Take(&item_move.from_list, item_move.from_index, stack.clone()),
+    ),
+    (
+      &item_move.to,
+      Action::Put(&item_move.to_list, item_move.to_index, stack.clone()),
+    ),
+  ]
+}
+
+///
+/// Signatures, same as C++ minetest. Slots start at 1 in Lua.
+///
+/// Player inventories:
+/// * callback(player, action, inventory, inventory_info)
+/// * inventory_info is {from_list, from_index, to_list, to_index, count} for "move",
+///   and {listname, index, stack} for "take" and "put".
+///
+/// Detached inventories:
+/// * allow_move/on_move(inventory, from_list, from_index, to_list, to_index, count, player)
+/// * allow_take/on_take/allow_put/on_put(inventory, listname, index, stack, player)
+///
+fn get_callback_args<'lua>(
+  lua: &'lua Lua,
+  environment: &Rc<RefCell<ServerEnvironment>>,
+  player_name: &str,
+  location: &InventoryLocation,
+  action: &Action,
+) -> mlua::Result<MultiValue<'lua>> {
+  let inventory = InvRef::new(location.clone(), environment.clone());
+  let player = PlayerRef::new(player_name.to_string(), environment.clone());
+
+  if let InventoryLocation::Player(_) = location {
+    let inventory_info = lua.create_table()?;
+    match action {
+      Action::Move(item_move, count) => {
+        inventory_info.set("from_list", item_move.from_list.as_str())?;
+        inventory_info.set("from_index", item_move.from_index + 1)?;
+        inventory_info.set("to_list", item_move.to_list.as_str())?;
+        inventory_info.set("to_index", item_move.to_index + 1)?;
+        inventory_info.set("count", *count)?;
+      }
+      Action::Take(list_name, index, stack) | Action::Put(list_name, index, stack) => {
+        inventory_info.set("listname", *list_name)?;
+        inventory_info.set("index", index + 1)?;
+        inventory_info.set("stack", LuaItemStack::new(stack.clone()))?;
+      }
+    }
+    return (player, action.get_name(), inventory, inventory_info).into_lua_multi(lua);
+  }
+
+  match action {
+    Action::Move(item_move, count) => (
+      inventory,
+      item_move.from_list.as_str(),
+      item_move.from_index + 1,
+      item_move.to_list.as_str(),
+      item_move.to_index + 1,
+      *count,
+      player,
+    )
+      .into_lua_multi(lua),
+    Action::Take(list_name, index, stack) | Action::Put(list_name, index, stack) => (
+      inventory,
+      *list_name,
+      index + 1,
+      LuaItemStack::new(stack.clone()),
+      player,
+    )
+      .into_lua_multi(lua),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, rc::Rc};
+
+  use ahash::AHashSet;
+
+  use super::{create_player_inventory, InventoryActions};
+  use crate::game::{
+    inventory::InventoryLocation,
+    item_stack::ItemStack,
+    lua_engine::LuaEngine,
+    packet::Packet,
+    server::{
+      server_connection::{ConnectedClient, ServerConnection},
+      server_environment::ServerEnvironment,
+    },
+  };
+
+  fn item_move(from_index: u32, to_list: &str, to_index: u32, count: u16) -> Packet {
+    Packet::InventoryMove {
+      from: InventoryLocation::Player("mover".to_string()),
+      from_list: "main".to_string(),
+      from_index,
+      to: InventoryLocation::Player("mover".to_string()),
+      to_list: to_list.to_string(),
+      to_index,
+      count,
+    }
+  }
+
+  #[test]
+  fn mods_decide_how_many_items_move() {
+    let lua_engine = LuaEngine::new(true);
+    let lua = lua_engine.get_lua();
+    if let Err(e) = lua
+      .load(
+        r#"
+        log = {}
+        minetest.register_allow_player_inventory_action(function(player, action, inventory, info)
+          if info.to_list == "hand" then return 0 end
+          if info.to_index == 3 then return 3 end
+        end)
+        minetest.register_on_player_inventory_action(function(player, action, inventory, info)
+          table.insert(log, player:get_player_name() .. " " .. action .. " " .. info.count)
+        end)
+        "#,
+      )
+      .exec()
+    {
+      panic!("{}", e);
+    }
+
+    let inventory_actions = match lua_engine
+      .get_internal_table("allow_player_inventory_action")
+      .and_then(|allow| {
+        let on = lua_engine.get_internal_table("on_player_inventory_action")?;
+        InventoryActions::from_lua_tables(lua, &allow, &on)
+      }) {
+      Ok(inventory_actions) => inventory_actions,
+      Err(e) => panic!("{}", e),
+    };
+
+    let mut inventory = create_player_inventory();
+    if let Err(e) = inventory.set_stack("main", 0, ItemStack::new("test:stone", 10)) {
+      panic!("{}", e);
+    }
+    let mut environment = ServerEnvironment::new();
+    environment.add_player("mover", inventory);
+    environment.take_modified_inventories();
+    let environment = Rc::new(RefCell::new(environment));
+
+    let mut connection = ServerConnection::new("127.0.0.1".to_string(), 0);
+    let end_point = connection.get_test_end_point(40001);
+    let privileges = AHashSet::from(["interact".to_string()]);
+    connection.clients.insert(
+      end_point,
+      ConnectedClient::new("mover".to_string(), privileges),
+    );
+
+    let mut run = |packet: Packet| {
+      connection.inventory_requests.push((end_point, packet));
+      inventory_actions.on_tick(lua, &environment, &mut connection);
+      environment.borrow_mut().take_modified_inventories()
+    };
+    let location = InventoryLocation::Player("mover".to_string());
+
+    // Moved items get synced, rejected moves leave the inventory alone.
+    assert_eq!(run(item_move(0, "main", 1, 0)), vec![location.clone()]);
+    assert_eq!(run(item_move(1, "main", 2, 0)), vec![location.clone()]);
+    assert_eq!(run(item_move(1, "hand", 0, 0)), vec![]);
+    assert_eq!(run(item_move(0, "main", 3, 0)), vec![]);
+    assert_eq!(run(item_move(1, "main", 1, 0)), vec![]);
+    assert_eq!(run(item_move(1, "main", 99, 0)), vec![]);
+
+    let get_count = |index: usize| {
+      environment
+        .borrow()
+        .get_inventory(&location)
+        .map(|inventory| inventory.get_stack("main", index).get_count())
+    };
+    assert_eq!(get_count(0), Some(0));
+    assert_eq!(get_count(1), Some(7));
+    assert_eq!(get_count(2), Some(3));
+
+    let log: Vec<String> = match lua.globals().get("log") {
+      Ok(log) => log,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(log, vec!["mover move 10", "mover move 3"]);
+  }
+}
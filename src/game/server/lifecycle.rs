@@ -0,0 +1,402 @@
+use std::{cell::RefCell, rc::Rc};
+
+use ahash::AHashMap;
+use mlua::{FromLuaMulti, Function, IntoLuaMulti, Lua, RegistryKey};
+
+use crate::game::lua_engine::LuaEngine;
+
+use super::{
+  lua_api::player_ref::PlayerRef,
+  rollback::{call_as_mod, DEFAULT_ACTOR},
+  server_environment::ServerEnvironment,
+};
+
+///
+/// The moments in a player's, or the Server's, life mods can hook into.
+///
+/// Each one has an internals table of the same name, filled by minetest.register_<name>.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum LifecycleEvent {
+  PreJoinPlayer,
+  JoinPlayer,
+  NewPlayer,
+  LeavePlayer,
+  DiePlayer,
+  RespawnPlayer,
+  Shutdown,
+  ModsLoaded,
+}
+
+const LIFECYCLE_EVENTS: [LifecycleEvent; 8] = [
+  LifecycleEvent::PreJoinPlayer,
+  LifecycleEvent::JoinPlayer,
+  LifecycleEvent::NewPlayer,
+  LifecycleEvent::LeavePlayer,
+  LifecycleEvent::DiePlayer,
+  LifecycleEvent::RespawnPlayer,
+  LifecycleEvent::Shutdown,
+  LifecycleEvent::ModsLoaded,
+];
+
+impl LifecycleEvent {
+  fn get_name(&self) -> &'static str {
+    match self {
+      LifecycleEvent::PreJoinPlayer => "on_prejoinplayer",
+      LifecycleEvent::JoinPlayer => "on_joinplayer",
+      LifecycleEvent::NewPlayer => "on_newplayer",
+      LifecycleEvent::LeavePlayer => "on_leaveplayer",
+      LifecycleEvent::DiePlayer => "on_dieplayer",
+      LifecycleEvent::RespawnPlayer => "on_respawnplayer",
+      LifecycleEvent::Shutdown => "on_shutdown",
+      LifecycleEvent::ModsLoaded => "on_mods_loaded",
+    }
+  }
+}
+
+///
+/// The minetest.register_on_joinplayer family of callbacks.
+///
+/// The Server calls these wherever the matching thing happens:
+/// * on_prejoinplayer(name, ip) -> string? - A client asked to join. Any string turns it away.
+/// * on_newplayer(player)                  - First time joining this world, right before on_joinplayer.
+/// * on_joinplayer(player)                 - In the world with their inventory.
+/// * on_leaveplayer(player, timed_out)     - Disconnected, or the Server is shutting down.
+/// * on_dieplayer(player, reason)          - hp hit 0. reason is {type = "set_hp"}.
+/// * on_respawnplayer(player) -> boolean?  - Back up with full hp.
+/// * on_shutdown()                         - The Server is about to save and stop.
+/// * on_mods_loaded()                      - Every mod has run and everything registered is loaded.
+///
+/// Callbacks which error get blamed on the mod which defined them.
+///
+/// todo: true from on_respawnplayer should skip moving the player to spawn,
+/// todo: once the Server is able to move players.
+///
+pub struct LifecycleCallbacks {
+  callbacks: AHashMap<LifecycleEvent, Vec<RegistryKey>>,
+}
+
+impl LifecycleCallbacks {
+  pub fn new() -> Self {
+    LifecycleCallbacks {
+      callbacks: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Pick the callbacks out of the on_joinplayer, on_leaveplayer, etc. tables in a LuaEngine's internals.
+  ///
+  pub fn from_lua_engine(lua_engine: &LuaEngine) -> Result<Self, String> {
+    let mut new_callbacks = LifecycleCallbacks::new();
+
+    for event in LIFECYCLE_EVENTS {
+      let table = lua_engine.get_internal_table(event.get_name())?;
+
+      let mut callbacks = vec![];
+      for callback in table.sequence_values::<Function>() {
+        let callback = match callback {
+          Ok(callback) => callback,
+          Err(e) => {
+            return Err(format!(
+              "LifecycleCallbacks: malformed {} table. {}",
+              event.get_name(),
+              e
+            ))
+          }
+        };
+        match lua_engine.get_lua().create_registry_value(callback) {
+          Ok(callback) => callbacks.push(callback),
+          Err(e) => {
+            return Err(format!(
+              "LifecycleCallbacks: failed to store {}. {}",
+              event.get_name(),
+              e
+            ))
+          }
+        }
+      }
+      new_callbacks.callbacks.insert(event, callbacks);
+    }
+
+    Ok(new_callbacks)
+  }
+
+  ///
+  /// Run every callback of an event in the order they were registered,
+  /// until one gives back something stop agrees with.
+  ///
+  fn run<'lua, A, R>(
+    &self,
+    lua: &'lua Lua,
+    environment: &RefCell<ServerEnvironment>,
+    event: LifecycleEvent,
+    args: A,
+    stop: impl Fn(&R) -> bool,
+  ) -> Option<R>
+  where
+    A: IntoLuaMulti<'lua> + Clone,
+    R: FromLuaMulti<'lua>,
+  {
+    for callback in self.callbacks.get(&event).into_iter().flatten() {
+      let function: Function = match lua.registry_value(callback) {
+        Ok(function) => function,
+        Err(e) => panic!(
+          "LifecycleCallbacks: {} went missing. {}",
+          event.get_name(),
+          e
+        ),
+      };
+
+      let context = event.get_name();
+      if let Some(result) = call_as_mod(
+        lua,
+        environment,
+        &function,
+        DEFAULT_ACTOR,
+        context,
+        args.clone(),
+      ) {
+        if stop(&result) {
+          return Some(result);
+        }
+      }
+    }
+
+    None
+  }
+
+  ///
+  /// Returns why the player can't join, if a mod says they can't.
+  ///
+  pub fn on_prejoinplayer(
+    &self,
+    lua: &Lua,
+    name: &str,
+    ip: &str,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+  ) -> Option<String> {
+    self
+      .run::<_, Option<String>>(
+        lua,
+        environment,
+        LifecycleEvent::PreJoinPlayer,
+        (name, ip),
+        |reason| reason.is_some(),
+      )
+      .flatten()
+  }
+
+  ///
+  /// A player made it into the world. New players get on_newplayer first.
+  ///
+  pub fn on_joinplayer(
+    &self,
+    lua: &Lua,
+    name: &str,
+    new_player: bool,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+  ) {
+    let player = PlayerRef::new(name.to_string(), environment.clone());
+    if new_player {
+      self.run::<_, ()>(
+        lua,
+        environment,
+        LifecycleEvent::NewPlayer,
+        player.clone(),
+        |_| false,
+      );
+    }
+    self.run::<_, ()>(lua, environment, LifecycleEvent::JoinPlayer, player, |_| {
+      false
+    });
+  }
+
+  pub fn on_leaveplayer(
+    &self,
+    lua: &Lua,
+    name: &str,
+    timed_out: bool,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+  ) {
+    let player = PlayerRef::new(name.to_string(), environment.clone());
+    self.run::<_, ()>(
+      lua,
+      environment,
+      LifecycleEvent::LeavePlayer,
+      (player, timed_out),
+      |_| false,
+    );
+  }
+
+  pub fn on_dieplayer(
+    &self,
+    lua: &Lua,
+    name: &str,
+    reason: &str,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+  ) {
+    let reason = match lua
+      .create_table()
+      .and_then(|table| table.set("type", reason).map(|_| table))
+    {
+      Ok(reason) => reason,
+      Err(e) => panic!("LifecycleCallbacks: failed to create death reason. {}", e),
+    };
+    let player = PlayerRef::new(name.to_string(), environment.clone());
+    self.run::<_, ()>(
+      lua,
+      environment,
+      LifecycleEvent::DiePlayer,
+      (player, reason),
+      |_| false,
+    );
+  }
+
+  pub fn on_respawnplayer(
+    &self,
+    lua: &Lua,
+    name: &str,
+    environment: &Rc<RefCell<ServerEnvironment>>,
+  ) {
+    let player = PlayerRef::new(name.to_string(), environment.clone());
+    self.run::<_, Option<bool>>(
+      lua,
+      environment,
+      LifecycleEvent::RespawnPlayer,
+      player,
+      |_| false,
+    );
+  }
+
+  pub fn on_shutdown(&self, lua: &Lua, environment: &Rc<RefCell<ServerEnvironment>>) {
+    self.run::<_, ()>(lua, environment, LifecycleEvent::Shutdown, (), |_| false);
+  }
+
+  pub fn on_mods_loaded(&self, lua: &Lua, environment: &Rc<RefCell<ServerEnvironment>>) {
+    self.run::<_, ()>(lua, environment, LifecycleEvent::ModsLoaded, (), |_| false);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, rc::Rc};
+
+  use super::LifecycleCallbacks;
+  use crate::game::{
+    lua_engine::{lua_error::add_mod, LuaEngine},
+    server::server_environment::ServerEnvironment,
+  };
+
+  fn load_mod(lua_engine: &LuaEngine, mod_name: &str, raw_code: &str) {
+    let lua = lua_engine.get_lua();
+    add_mod(lua, mod_name, &format!("./mods/{}", mod_name));
+    if let Err(e) = lua
+      .load(raw_code)
+      .set_name(format!("@./mods/{}/main.lua", mod_name))
+      .exec()
+    {
+      panic!("{}", e);
+    }
+  }
+
+  fn get_log(lua_engine: &LuaEngine) -> Vec<String> {
+    match lua_engine.get_lua().globals().get("log") {
+      Ok(log) => log,
+      Err(e) => panic!("{}", e),
+    }
+  }
+
+  #[test]
+  fn players_join_in_order_unless_turned_away() {
+    let lua_engine = LuaEngine::new(true);
+    load_mod(
+      &lua_engine,
+      "greeter",
+      r#"
+      log = {}
+      minetest.register_on_prejoinplayer(function(name, ip)
+        if name == "griefer" then return "You're banned." end
+      end)
+      minetest.register_on_joinplayer(function(player)
+        table.insert(log, "join " .. player:get_player_name())
+      end)
+      minetest.register_on_newplayer(function(player)
+        table.insert(log, "new " .. player:get_player_name())
+      end)
+      "#,
+    );
+
+    // Callbacks get checked when they're registered.
+    match lua_engine
+      .get_lua()
+      .load("minetest.register_on_joinplayer(\"greet\")")
+      .exec()
+    {
+      Ok(_) => panic!("a string got registered as on_joinplayer"),
+      Err(e) => assert!(e
+        .to_string()
+        .contains("on_joinplayer callback must be a function, got string")),
+    }
+
+    let lifecycle = match LifecycleCallbacks::from_lua_engine(&lua_engine) {
+      Ok(lifecycle) => lifecycle,
+      Err(e) => panic!("{}", e),
+    };
+    let lua = lua_engine.get_lua();
+    let environment = Rc::new(RefCell::new(ServerEnvironment::new()));
+
+    assert_eq!(
+      lifecycle.on_prejoinplayer(lua, "griefer", "127.0.0.1", &environment),
+      Some("You're banned.".to_string())
+    );
+    assert_eq!(
+      lifecycle.on_prejoinplayer(lua, "newcomer", "127.0.0.1", &environment),
+      None
+    );
+
+    lifecycle.on_joinplayer(lua, "newcomer", true, &environment);
+    lifecycle.on_joinplayer(lua, "regular", false, &environment);
+    assert_eq!(
+      get_log(&lua_engine),
+      vec!["new newcomer", "join newcomer", "join regular"]
+    );
+  }
+
+  #[test]
+  fn broken_callbacks_get_blamed_on_their_mod() {
+    let lua_engine = LuaEngine::new(true);
+    load_mod(
+      &lua_engine,
+      "broken",
+      r#"
+      log = {}
+      minetest.register_on_joinplayer(function(player)
+        table.insert(log, "broken")
+        local t = nil
+        return t.x
+      end)
+      "#,
+    );
+    load_mod(
+      &lua_engine,
+      "greeter",
+      r#"
+      minetest.register_on_joinplayer(function(player)
+        table.insert(log, "greeter")
+      end)
+      "#,
+    );
+
+    let lifecycle = match LifecycleCallbacks::from_lua_engine(&lua_engine) {
+      Ok(lifecycle) => lifecycle,
+      Err(e) => panic!("{}", e),
+    };
+    let lua = lua_engine.get_lua();
+    let environment = Rc::new(RefCell::new(ServerEnvironment::new()));
+
+    // The broken mod gets disabled, everyone else carries on.
+    lifecycle.on_joinplayer(lua, "first", false, &environment);
+    lifecycle.on_joinplayer(lua, "second", false, &environment);
+    assert_eq!(get_log(&lua_engine), vec!["broken", "greeter", "greeter"]);
+  }
+}
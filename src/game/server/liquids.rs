@@ -0,0 +1,487 @@
+// Flowing liquids. Water, lava, etc.
+//
+// A liquid is a pair of blocks. The source stays put, the flowing block
+// spreads out from it. A flowing liquid's param2 holds its level and if it's
+// falling down. Positions which might need to flow get queued up, then they're
+// worked through a generation at a time, so liquids move one node per update.
+
+use std::{
+  collections::VecDeque,
+  time::{Duration, Instant},
+};
+
+use ahash::{AHashMap, AHashSet};
+use glam::{ivec3, IVec3};
+
+use crate::game::map::{
+  block_registry::{BlockRegistry, LiquidType, AIR_ID, LIQUID_LEVEL_MAX},
+  chunk::Node,
+};
+
+use super::server_environment::ServerEnvironment;
+
+///
+/// The level bits in a flowing liquid's param2.
+///
+pub const LIQUID_LEVEL_MASK: u8 = 0x07;
+
+///
+/// Set in a flowing liquid's param2 when it's falling. (the node under it is open)
+///
+/// Falling liquid does not spread out to the sides.
+///
+pub const LIQUID_FLOW_DOWN_MASK: u8 = 0x08;
+
+const SIDES: [IVec3; 4] = [
+  ivec3(1, 0, 0),
+  ivec3(-1, 0, 0),
+  ivec3(0, 0, 1),
+  ivec3(0, 0, -1),
+];
+
+const NEIGHBORS: [IVec3; 6] = [
+  ivec3(1, 0, 0),
+  ivec3(-1, 0, 0),
+  ivec3(0, 1, 0),
+  ivec3(0, -1, 0),
+  ivec3(0, 0, 1),
+  ivec3(0, 0, -1),
+];
+
+///
+/// A liquid's definition, resolved into block IDs.
+///
+#[derive(Clone, Copy, Debug)]
+struct Liquid {
+  source: u16,
+  flowing: u16,
+  viscosity: i32,
+  range: i32,
+  renewable: bool,
+}
+
+///
+/// Spreads and drains liquids.
+///
+/// * liquids              - Both the source and flowing block ID of every liquid.
+/// * queue                - Positions that might need to flow.
+/// * generation_remaining - How much of the queue is left in the current update.
+///
+/// Everything queued while an update is running waits for the next one.
+///
+pub struct LiquidRunner {
+  liquids: AHashMap<u16, Liquid>,
+  queue: VecDeque<IVec3>,
+  queued: AHashSet<IVec3>,
+  generation_remaining: usize,
+  timer: f64,
+}
+
+impl LiquidRunner {
+  pub fn new() -> Self {
+    LiquidRunner {
+      liquids: AHashMap::new(),
+      queue: VecDeque::new(),
+      queued: AHashSet::new(),
+      generation_remaining: 0,
+      timer: 0.0,
+    }
+  }
+
+  ///
+  /// Find every liquid in the BlockRegistry.
+  ///
+  /// Every liquid block needs a liquid_alternative_source and liquid_alternative_flowing
+  /// which are really a source and a flowing block.
+  ///
+  pub fn from_block_registry(block_registry: &BlockRegistry) -> Result<Self, String> {
+    let mut liquid_runner = LiquidRunner::new();
+
+    for id in 0..block_registry.get_block_count() as u16 {
+      let definition = match block_registry.get_definition(id) {
+        Some(definition) if definition.liquid_type != LiquidType::None => definition,
+        _ => continue,
+      };
+
+      let requester = format!("LiquidRunner: block [{}]", definition.name);
+
+      let get_alternative = |name: &str, liquid_type: LiquidType, field: &str| {
+        let alternative = block_registry.require_id(name, &requester)?;
+        match block_registry.get_definition(alternative) {
+          Some(alternative_definition) if alternative_definition.liquid_type == liquid_type => {
+            Ok(alternative)
+          }
+          _ => Err(format!(
+            "{} {} [{}] is not a {:?} liquid.",
+            requester, field, name, liquid_type
+          )),
+        }
+      };
+
+      let source = get_alternative(
+        &definition.liquid_alternative_source,
+        LiquidType::Source,
+        "liquid_alternative_source",
+      )?;
+      let flowing = get_alternative(
+        &definition.liquid_alternative_flowing,
+        LiquidType::Flowing,
+        "liquid_alternative_flowing",
+      )?;
+
+      // How the liquid behaves comes from the flowing block. That's the one that moves.
+      let flowing_definition = match block_registry.get_definition(flowing) {
+        Some(flowing_definition) => flowing_definition,
+        None => continue,
+      };
+
+      liquid_runner.liquids.insert(
+        id,
+        Liquid {
+          source,
+          flowing,
+          viscosity: flowing_definition.liquid_viscosity as i32,
+          range: flowing_definition.liquid_range as i32,
+          renewable: flowing_definition.liquid_renewable,
+        },
+      );
+    }
+
+    Ok(liquid_runner)
+  }
+
+  ///
+  /// Queue up a position to be checked on the next update.
+  ///
+  pub fn queue(&mut self, position: IVec3) {
+    if self.queued.insert(position) {
+      self.queue.push_back(position);
+    }
+  }
+
+  ///
+  /// Queue up every changed node and its neighbors.
+  ///
+  /// Anything next to a change might be able to flow now, or might have lost its source.
+  ///
+  pub fn queue_node_changes(&mut self, node_changes: &[IVec3]) {
+    if self.liquids.is_empty() {
+      return;
+    }
+
+    for position in node_changes {
+      self.queue(*position);
+      for offset in NEIGHBORS {
+        self.queue(*position + offset);
+      }
+    }
+  }
+
+  ///
+  /// Run liquid updates every update_interval seconds, only using up to budget each tick.
+  ///
+  /// An update that doesn't fit into the budget is finished on the next tick.
+  ///
+  pub fn on_tick(
+    &mut self,
+    delta: f64,
+    environment: &mut ServerEnvironment,
+    update_interval: f64,
+    budget: Duration,
+  ) {
+    self.timer += delta;
+
+    if self.generation_remaining == 0 {
+      if self.timer < update_interval || self.queue.is_empty() {
+        return;
+      }
+      self.timer = 0.0;
+      self.generation_remaining = self.queue.len();
+    }
+
+    let deadline = Instant::now() + budget;
+    while self.generation_remaining > 0 && Instant::now() < deadline {
+      self.transform_next(environment);
+    }
+  }
+
+  ///
+  /// Run one whole liquid update, no matter how long it takes.
+  ///
+  /// Liquids move a single node per update.
+  ///
+  pub fn step(&mut self, environment: &mut ServerEnvironment) {
+    if self.generation_remaining == 0 {
+      self.generation_remaining = self.queue.len();
+    }
+    while self.generation_remaining > 0 {
+      self.transform_next(environment);
+    }
+  }
+
+  fn transform_next(&mut self, environment: &mut ServerEnvironment) {
+    self.generation_remaining -= 1;
+    if let Some(position) = self.queue.pop_front() {
+      self.queued.remove(&position);
+      self.transform(environment, position);
+    }
+  }
+
+  ///
+  /// Work out what a single node should be now, based on its neighbors.
+  ///
+  /// * A liquid above makes a full, falling node.
+  /// * A source to the side makes a full node. Flowing liquid to the side makes one level less.
+  /// * Two sources to the side of a renewable liquid make a new source, if there's a floor.
+  /// * Sources never change on their own.
+  ///
+  fn transform(&mut self, environment: &mut ServerEnvironment, position: IVec3) {
+    let node = match environment.get_node(position) {
+      Some(node) => node,
+      None => return,
+    };
+
+    let mut liquid = self.liquids.get(&node.block_id).copied();
+    let current_level = match liquid {
+      Some(liquid) if node.block_id == liquid.source => return,
+      Some(_) => (node.param2 & LIQUID_LEVEL_MASK) as i32,
+      None if node.block_id == AIR_ID => -1,
+      None => return,
+    };
+
+    let mut max_level = -1;
+    let mut source_count = 0;
+
+    if let Some(above) = environment.get_node(position + IVec3::Y) {
+      if let Some(above_liquid) = self.liquids.get(&above.block_id) {
+        if liquid.map_or(true, |liquid| liquid.source == above_liquid.source) {
+          liquid = Some(*above_liquid);
+          max_level = LIQUID_LEVEL_MAX as i32;
+        }
+      }
+    }
+
+    for offset in SIDES {
+      let side = match environment.get_node(position + offset) {
+        Some(side) => side,
+        None => continue,
+      };
+      let side_liquid = match self.liquids.get(&side.block_id) {
+        Some(side_liquid) => *side_liquid,
+        None => continue,
+      };
+      if liquid.is_some_and(|liquid| liquid.source != side_liquid.source) {
+        continue;
+      }
+      liquid = Some(side_liquid);
+
+      if side.block_id == side_liquid.source {
+        source_count += 1;
+        max_level = LIQUID_LEVEL_MAX as i32;
+      } else if side.param2 & LIQUID_FLOW_DOWN_MASK == 0 {
+        max_level = max_level.max((side.param2 & LIQUID_LEVEL_MASK) as i32 - 1);
+      }
+    }
+
+    // Plain air with no liquid next to it.
+    let liquid = match liquid {
+      Some(liquid) => liquid,
+      None => return,
+    };
+
+    let below = environment.get_node(position - IVec3::Y);
+    let below_is_open =
+      below.is_some_and(|below| below.block_id == AIR_ID || below.block_id == liquid.flowing);
+
+    if liquid.renewable && source_count >= 2 && below.is_some() && !below_is_open {
+      environment.set_node(position, Node::new(liquid.source, 0));
+      return;
+    }
+
+    let mut new_level = max_level;
+    if new_level < LIQUID_LEVEL_MAX as i32 + 1 - liquid.range {
+      new_level = -1;
+    }
+
+    // Thick liquids creep toward their new level, then come back to keep going.
+    if liquid.viscosity > 1 && new_level != current_level {
+      let target_level = new_level;
+      let level_change = new_level - current_level;
+      new_level = match level_change {
+        _ if level_change.abs() > liquid.viscosity => {
+          current_level + level_change / liquid.viscosity
+        }
+        _ if level_change < 0 => current_level - 1,
+        _ => current_level + 1,
+      };
+      if new_level != target_level {
+        self.queue(position);
+      }
+    }
+
+    let new_node = match new_level {
+      -1 => Node::air(),
+      _ => {
+        let falling = match below_is_open {
+          true => LIQUID_FLOW_DOWN_MASK,
+          false => 0,
+        };
+        Node::new(liquid.flowing, new_level as u8 | falling)
+      }
+    };
+
+    if new_node != node {
+      environment.set_node(position, new_node);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, IVec3};
+
+  use super::{LiquidRunner, LIQUID_FLOW_DOWN_MASK};
+  use crate::game::{
+    map::{
+      block_registry::{BlockDefinition, BlockRegistry, LiquidType},
+      chunk::{Chunk, Node},
+    },
+    server::server_environment::ServerEnvironment,
+  };
+
+  fn test_block(
+    name: &str,
+    liquid_type: LiquidType,
+    range: u8,
+    renewable: bool,
+  ) -> BlockDefinition {
+    BlockDefinition {
+      pointable: liquid_type == LiquidType::None,
+      liquid_type,
+      liquid_alternative_source: "test:water_source".to_string(),
+      liquid_alternative_flowing: "test:water_flowing".to_string(),
+      liquid_range: range,
+      liquid_renewable: renewable,
+      ..BlockDefinition::test(name)
+    }
+  }
+
+  ///
+  /// A single Chunk of air with a stone floor at y = 0.
+  ///
+  fn test_world(range: u8, renewable: bool) -> (ServerEnvironment, LiquidRunner, Node, Node) {
+    let mut block_registry = BlockRegistry::new();
+    let stone = block_registry
+      .register_block(test_block("test:stone", LiquidType::None, 8, true))
+      .unwrap_or(0);
+    let source = block_registry
+      .register_block(test_block(
+        "test:water_source",
+        LiquidType::Source,
+        range,
+        renewable,
+      ))
+      .unwrap_or(0);
+    let flowing = block_registry
+      .register_block(test_block(
+        "test:water_flowing",
+        LiquidType::Flowing,
+        range,
+        renewable,
+      ))
+      .unwrap_or(0);
+
+    let liquid_runner = match LiquidRunner::from_block_registry(&block_registry) {
+      Ok(liquid_runner) => liquid_runner,
+      Err(e) => panic!("{}", e),
+    };
+
+    let mut environment = ServerEnvironment::new();
+    environment.set_block_registry(block_registry);
+    environment.add_emerged_chunk(Chunk::new(IVec3::ZERO), true);
+    for x in 0..16 {
+      for z in 0..16 {
+        environment.set_node(ivec3(x, 0, z), Node::new(stone, 0));
+      }
+    }
+    environment.take_node_changes();
+
+    (
+      environment,
+      liquid_runner,
+      Node::new(source, 0),
+      Node::new(flowing, 0),
+    )
+  }
+
+  ///
+  /// Run liquid updates the way the Server does, feeding changes back into the queue.
+  ///
+  fn run_steps(
+    environment: &mut ServerEnvironment,
+    liquid_runner: &mut LiquidRunner,
+    steps: usize,
+  ) {
+    for _ in 0..steps {
+      let node_changes = environment.take_node_changes();
+      liquid_runner.queue_node_changes(&node_changes);
+      liquid_runner.step(environment);
+    }
+  }
+
+  fn get_level(environment: &ServerEnvironment, flowing: Node, position: IVec3) -> Option<u8> {
+    match environment.get_node(position) {
+      Some(node) if node.block_id == flowing.block_id => Some(node.param2),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn liquids_spread_fall_and_drain() {
+    let (mut environment, mut liquid_runner, source, flowing) = test_world(3, false);
+
+    environment.set_node(ivec3(8, 1, 8), source);
+    run_steps(&mut environment, &mut liquid_runner, 1);
+    assert_eq!(get_level(&environment, flowing, ivec3(9, 1, 8)), Some(7));
+
+    run_steps(&mut environment, &mut liquid_runner, 10);
+    assert_eq!(get_level(&environment, flowing, ivec3(10, 1, 8)), Some(6));
+    assert_eq!(get_level(&environment, flowing, ivec3(11, 1, 8)), Some(5));
+    // Out of range.
+    assert_eq!(get_level(&environment, flowing, ivec3(12, 1, 8)), None);
+
+    // Liquid in the air falls instead of spreading.
+    environment.set_node(ivec3(2, 6, 2), source);
+    run_steps(&mut environment, &mut liquid_runner, 1);
+    assert_eq!(
+      get_level(&environment, flowing, ivec3(2, 5, 2)),
+      Some(7 | LIQUID_FLOW_DOWN_MASK)
+    );
+    run_steps(&mut environment, &mut liquid_runner, 10);
+    assert_eq!(get_level(&environment, flowing, ivec3(2, 1, 2)), Some(7));
+    assert_eq!(get_level(&environment, flowing, ivec3(4, 1, 2)), Some(6));
+    assert_eq!(get_level(&environment, flowing, ivec3(4, 5, 2)), None);
+
+    // Take the source away and it all dries up.
+    environment.set_node(ivec3(8, 1, 8), Node::air());
+    run_steps(&mut environment, &mut liquid_runner, 10);
+    assert_eq!(get_level(&environment, flowing, ivec3(9, 1, 8)), None);
+    assert_eq!(get_level(&environment, flowing, ivec3(11, 1, 8)), None);
+  }
+
+  #[test]
+  fn renewable_liquids_fill_gaps() {
+    for renewable in [true, false] {
+      let (mut environment, mut liquid_runner, source, _) = test_world(8, renewable);
+
+      environment.set_node(ivec3(4, 1, 4), source);
+      environment.set_node(ivec3(6, 1, 4), source);
+      run_steps(&mut environment, &mut liquid_runner, 2);
+
+      assert_eq!(
+        environment.get_node(ivec3(5, 1, 4)) == Some(source),
+        renewable
+      );
+    }
+  }
+}
@@ -0,0 +1,994 @@
+// The Rust side of the server's minetest Lua API.
+//
+// api.lua handles registration and anything that can be done in pure Lua.
+// Anything that needs to touch the engine gets added to the minetest table from here.
+
+pub mod inventory_ref;
+mod node_meta_ref;
+mod node_timer_ref;
+pub mod player_ref;
+mod raycast_ref;
+
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+use ahash::AHashMap;
+use glam::IVec3;
+use mlua::{Function, Lua, Table, Value};
+
+use crate::{
+  file_utilities::create_dir,
+  game::{
+    crafting::{CraftInput, CraftMethod, CraftRecipe},
+    inventory::InventoryLocation,
+    item_stack::ItemStack,
+    lua_engine::{
+      lua_error::{call_mod_function, get_calling_mod},
+      lua_item_stack::{get_item_stack, LuaItemStack},
+      lua_table_helpers::{
+        get_field, get_field_or, get_node_position, get_position, node_position_to_vector,
+        position_to_vector,
+      },
+      module_loader::{get_mod_file_path, is_valid_mod_file_path},
+      LuaEngine,
+    },
+    map::{
+      block_registry::{AIR_ID, IGNORE_NAME},
+      chunk::Node,
+      raycast::{PointedThing, Raycast},
+      schematic::{Schematic, SchematicPlacement},
+    },
+  },
+};
+
+use self::{
+  inventory_ref::InvRef, node_meta_ref::NodeMetaRef, node_timer_ref::NodeTimerRef,
+  raycast_ref::RaycastRef,
+};
+
+use super::{
+  chat::ChatMessage,
+  emerge::{get_area_chunk_positions, EmergeAreaRequest},
+  map_database::MapDatabase,
+  protection::ProtectedArea,
+  rollback::{get_unix_time, NodeAction, RollbackNode},
+  server_environment::ServerEnvironment,
+};
+
+///
+/// The Lua registry table of detached inventory callbacks, by inventory name.
+///
+const DETACHED_CALLBACKS_KEY: &str = "minetest_detached_inventory_callbacks";
+
+///
+/// Turn a position argument into a node position, or a Lua error naming the function.
+///
+fn get_position_argument(value: &Value, function_name: &str) -> mlua::Result<IVec3> {
+  get_node_position(value)
+    .map_err(|e| mlua::Error::runtime(format!("minetest.{}: {}", function_name, e)))
+}
+
+///
+/// Ask minetest.is_protected if a player is kept out of a position.
+///
+/// If they are, minetest.record_protection_violation lets mods react to it.
+/// Both get looked up on every call, mods are allowed to override them.
+///
+/// Returns true if the player must not change the position.
+///
+/// ! The ServerEnvironment must not be borrowed while calling this.
+///
+pub fn check_protection(lua: &Lua, position: IVec3, player_name: &str) -> mlua::Result<bool> {
+  let minetest: Table = lua.globals().get("minetest")?;
+
+  let is_protected: Function = minetest.get("is_protected")?;
+  let protected: bool =
+    is_protected.call((node_position_to_vector(lua, position)?, player_name))?;
+
+  if protected {
+    let record_protection_violation: Function = minetest.get("record_protection_violation")?;
+    record_protection_violation
+      .call::<_, ()>((node_position_to_vector(lua, position)?, player_name))?;
+  }
+
+  Ok(protected)
+}
+
+///
+/// check_protection() for whoever is making changes right now, if that's a player.
+///
+/// This covers NodeMeta and Inventory writes made by mods from a player's dig or place.
+///
+fn check_player_actor_protection(
+  lua: &Lua,
+  environment: &RefCell<ServerEnvironment>,
+  position: IVec3,
+) -> mlua::Result<bool> {
+  let player_name = environment
+    .borrow()
+    .get_player_actor_name()
+    .map(|player_name| player_name.to_string());
+
+  match player_name {
+    Some(player_name) => check_protection(lua, position, &player_name),
+    None => Ok(false),
+  }
+}
+
+///
+/// Turn the node at a position into a {name, param1, param2} table for Lua.
+///
+/// Unloaded nodes are named "ignore".
+///
+pub fn node_to_table<'lua>(
+  lua: &'lua Lua,
+  environment: &ServerEnvironment,
+  position: IVec3,
+) -> mlua::Result<Table<'lua>> {
+  let table = lua.create_table()?;
+  match environment.get_node(position) {
+    Some(node) => {
+      let name = environment
+        .get_block_registry()
+        .get_name(node.block_id)
+        .unwrap_or(IGNORE_NAME);
+      table.set("name", name)?;
+      table.set(
+        "param1",
+        environment.get_map().get_light(position).unwrap_or(0),
+      )?;
+      table.set("param2", node.param2)?;
+    }
+    None => {
+      table.set("name", IGNORE_NAME)?;
+      table.set("param1", 0)?;
+      table.set("param2", 0)?;
+    }
+  }
+  Ok(table)
+}
+
+///
+/// Turn a PointedThing into a pointed_thing table for Lua.
+///
+/// todo: "object" pointed_things need a ref to the object once entities exist.
+///
+pub fn pointed_thing_to_table<'lua>(
+  lua: &'lua Lua,
+  pointed_thing: &PointedThing,
+) -> mlua::Result<Table<'lua>> {
+  let table = lua.create_table()?;
+  match pointed_thing {
+    PointedThing::Node {
+      under,
+      above,
+      intersection_point,
+      intersection_normal,
+    } => {
+      table.set("type", "node")?;
+      table.set("under", node_position_to_vector(lua, *under)?)?;
+      table.set("above", node_position_to_vector(lua, *above)?)?;
+      table.set(
+        "intersection_point",
+        position_to_vector(lua, *intersection_point)?,
+      )?;
+      table.set(
+        "intersection_normal",
+        node_position_to_vector(lua, *intersection_normal)?,
+      )?;
+    }
+    PointedThing::Entity {
+      id,
+      intersection_point,
+      intersection_normal,
+    } => {
+      table.set("type", "object")?;
+      table.set("id", *id)?;
+      table.set(
+        "intersection_point",
+        position_to_vector(lua, *intersection_point)?,
+      )?;
+      table.set(
+        "intersection_normal",
+        node_position_to_vector(lua, *intersection_normal)?,
+      )?;
+    }
+  }
+  Ok(table)
+}
+
+///
+/// minetest.emerge_area(pos1, pos2, callback)
+///
+/// The request gets handed to the Server on the next tick.
+/// Areas over MAX_EMERGE_AREA_CHUNKS are an error.
+///
+pub fn register_emerge_api(
+  lua_engine: &LuaEngine,
+  emerge_area_requests: Rc<RefCell<Vec<EmergeAreaRequest>>>,
+) {
+  lua_engine.register_api_function(
+    "emerge_area",
+    move |lua, (pos1, pos2, callback): (Value, Value, Option<Function>)| {
+      let pos1 = get_position_argument(&pos1, "emerge_area")?;
+      let pos2 = get_position_argument(&pos2, "emerge_area")?;
+      let chunk_positions = get_area_chunk_positions(pos1, pos2)
+        .map_err(|e| mlua::Error::runtime(format!("minetest.emerge_area: {}", e)))?;
+
+      let callback = match callback {
+        Some(callback) => Some(lua.create_registry_value(callback)?),
+        None => None,
+      };
+
+      emerge_area_requests.borrow_mut().push(EmergeAreaRequest {
+        chunk_positions,
+        callback,
+      });
+
+      Ok(())
+    },
+  );
+}
+
+///
+/// minetest.get_node, minetest.set_node, minetest.remove_node, minetest.get_meta
+/// and minetest.get_node_timer.
+///
+pub fn register_node_api(lua_engine: &LuaEngine, environment: Rc<RefCell<ServerEnvironment>>) {
+  let get_node_environment = environment.clone();
+  lua_engine.register_api_function("get_node", move |lua, position: Value| {
+    let position = get_position_argument(&position, "get_node")?;
+    node_to_table(lua, &get_node_environment.borrow(), position)
+  });
+
+  // Returns false if the node is not loaded.
+  let set_node_environment = environment.clone();
+  lua_engine.register_api_function("set_node", move |_, (position, node): (Value, Table)| {
+    let position = get_position_argument(&position, "set_node")?;
+    let mut environment = set_node_environment.borrow_mut();
+
+    let name: String = get_field(&node, "name")
+      .map_err(|e| mlua::Error::runtime(format!("minetest.set_node: {}", e)))?;
+    let param2: u8 = get_field_or(&node, "param2", 0)
+      .map_err(|e| mlua::Error::runtime(format!("minetest.set_node: {}", e)))?;
+
+    let block_id = match environment.get_block_registry().get_id(&name) {
+      Some(block_id) => block_id,
+      None => {
+        return Err(mlua::Error::runtime(format!(
+          "minetest.set_node: unknown block [{}]",
+          name
+        )))
+      }
+    };
+
+    Ok(environment.set_node(position, Node::new(block_id, param2)))
+  });
+
+  let remove_node_environment = environment.clone();
+  lua_engine.register_api_function("remove_node", move |_, position: Value| {
+    let position = get_position_argument(&position, "remove_node")?;
+    Ok(
+      remove_node_environment
+        .borrow_mut()
+        .set_node(position, Node::air()),
+    )
+  });
+
+  let get_meta_environment = environment.clone();
+  lua_engine.register_api_function("get_meta", move |_, position: Value| {
+    let position = get_position_argument(&position, "get_meta")?;
+    Ok(NodeMetaRef::new(position, get_meta_environment.clone()))
+  });
+
+  lua_engine.register_api_function("get_node_timer", move |_, position: Value| {
+    let position = get_position_argument(&position, "get_node_timer")?;
+    Ok(NodeTimerRef::new(position, environment.clone()))
+  });
+}
+
+///
+/// minetest.raycast(pos1, pos2, objects, liquids)
+///
+/// * objects - Point at objects too. Defaults to true.
+/// * liquids - Point at liquids even if they aren't pointable. Defaults to false.
+///
+pub fn register_raycast_api(lua_engine: &LuaEngine, environment: Rc<RefCell<ServerEnvironment>>) {
+  lua_engine.register_api_function(
+    "raycast",
+    move |_, (pos1, pos2, objects, liquids): (Value, Value, Option<bool>, Option<bool>)| {
+      let pos1 = get_position(&pos1)
+        .map_err(|e| mlua::Error::runtime(format!("minetest.raycast: {}", e)))?;
+      let pos2 = get_position(&pos2)
+        .map_err(|e| mlua::Error::runtime(format!("minetest.raycast: {}", e)))?;
+
+      // todo: there are no objects yet. Hand their hitboxes in here once there are.
+      let _objects = objects.unwrap_or(true);
+
+      Ok(RaycastRef::new(
+        Raycast::new(pos1, pos2, liquids.unwrap_or(false), &[]),
+        environment.clone(),
+      ))
+    },
+  );
+}
+
+///
+/// Schematic files are read out of the calling mod's folder.
+///
+fn get_schematic_read_path(lua: &Lua, file: &str) -> Result<String, String> {
+  match get_calling_mod(lua) {
+    Some((mod_name, _)) => get_mod_file_path(lua, &mod_name, file),
+    None => Err(format!(
+      "can't read [{}], it wasn't asked for by a mod.",
+      file
+    )),
+  }
+}
+
+///
+/// Schematic files are written into the world's schematics folder.
+///
+fn get_schematic_write_path(world_path: &str, file: &str) -> Result<String, String> {
+  if !is_valid_mod_file_path(file) {
+    return Err(format!(
+      "[{}] is not a valid schematic file. It has to be a path inside the schematics folder.",
+      file
+    ));
+  }
+
+  let path = format!("{}/schematics/{}", world_path, file);
+  if let Some(folder) = Path::new(&path).parent() {
+    create_dir(&folder.to_string_lossy())?;
+  }
+  Ok(path)
+}
+
+///
+/// minetest.create_schematic(pos1, pos2, probability_list, filename)
+///
+/// * probability_list - {{pos = position, prob = 0-255}, ...} or nil. Every other node is always placed.
+/// * filename         - Where to save it, inside <world>/schematics.
+///
+/// Every node in the area has to be loaded.
+///
+pub fn register_schematic_api(
+  lua_engine: &LuaEngine,
+  environment: Rc<RefCell<ServerEnvironment>>,
+  world_path: &str,
+) {
+  let create_environment = environment.clone();
+  let world_path = world_path.to_string();
+  lua_engine.register_api_function(
+    "create_schematic",
+    move |_, (pos1, pos2, probability_list, filename): (Value, Value, Option<Table>, String)| {
+      let pos1 = get_position_argument(&pos1, "create_schematic")?;
+      let pos2 = get_position_argument(&pos2, "create_schematic")?;
+
+      let error = |e: String| mlua::Error::runtime(format!("minetest.create_schematic: {}", e));
+
+      let mut probabilities = AHashMap::new();
+      if let Some(probability_list) = probability_list {
+        for entry in probability_list.sequence_values::<Table>() {
+          let entry = entry?;
+          let position =
+            get_node_position(&get_field(&entry, "pos").map_err(error)?).map_err(error)?;
+          let probability: u8 = get_field(&entry, "prob").map_err(error)?;
+          probabilities.insert(position, probability);
+        }
+      }
+
+      let path = get_schematic_write_path(&world_path, &filename).map_err(error)?;
+
+      let environment = create_environment.borrow();
+      let schematic =
+        Schematic::from_map(environment.get_map(), pos1, pos2, &probabilities).map_err(error)?;
+      schematic
+        .save_to_file(&path, environment.get_block_registry())
+        .map_err(error)?;
+
+      Ok(true)
+    },
+  );
+
+  // Returns false if part of it landed in unloaded chunks.
+  lua_engine.register_api_function(
+    "place_schematic",
+    move |lua,
+          (position, schematic, rotation, replacements, force_placement, flags): (
+      Value,
+      Value,
+      Option<String>,
+      Option<Table>,
+      Option<bool>,
+      Option<String>,
+    )| {
+      let position = get_position_argument(&position, "place_schematic")?;
+
+      let error = |e: String| mlua::Error::runtime(format!("minetest.place_schematic: {}", e));
+
+      let (schematic, placement) = {
+        let environment = environment.borrow();
+        let block_registry = environment.get_block_registry();
+
+        let schematic = match schematic {
+          Value::String(file) => {
+            let path = get_schematic_read_path(lua, &file.to_string_lossy()).map_err(error)?;
+            Schematic::load_from_file(&path, block_registry).map_err(error)?
+          }
+          Value::Table(table) => {
+            Schematic::from_lua_table(&table, block_registry, "schematic").map_err(error)?
+          }
+          other => {
+            return Err(error(format!(
+              "schematic must be a file path or a table, got [{}].",
+              other.type_name()
+            )))
+          }
+        };
+
+        let placement = SchematicPlacement::from_lua(
+          rotation,
+          replacements,
+          force_placement.unwrap_or(false),
+          flags,
+          block_registry,
+          "schematic",
+        )
+        .map_err(error)?;
+
+        (schematic, placement)
+      };
+
+      let mut environment = environment.borrow_mut();
+      let mut fully_placed = true;
+
+      schematic.place(
+        position,
+        &placement,
+        &mut rand::thread_rng(),
+        |world_position, node, force_place, node_meta| {
+          let existing = match environment.get_node(world_position) {
+            Some(existing) => existing,
+            None => {
+              fully_placed = false;
+              return;
+            }
+          };
+          if !force_place && existing.block_id != AIR_ID {
+            return;
+          }
+
+          environment.set_node(world_position, node);
+          if let Some(node_meta) = node_meta {
+            if let Some(existing) = environment.get_map_mut().get_node_meta_mut(world_position) {
+              *existing = node_meta.clone();
+            }
+          }
+        },
+      );
+
+      Ok(fully_placed)
+    },
+  );
+}
+
+fn rollback_node_to_table<'lua>(lua: &'lua Lua, node: &RollbackNode) -> mlua::Result<Table<'lua>> {
+  let table = lua.create_table()?;
+  table.set("name", node.name.as_str())?;
+  table.set("param2", node.param2)?;
+  Ok(table)
+}
+
+fn node_action_to_table<'lua>(
+  lua: &'lua Lua,
+  node_action: &NodeAction,
+) -> mlua::Result<Table<'lua>> {
+  let table = lua.create_table()?;
+  table.set("actor", node_action.actor.as_str())?;
+  table.set("pos", node_position_to_vector(lua, node_action.position)?)?;
+  table.set("time", node_action.time)?;
+  table.set(
+    "oldnode",
+    rollback_node_to_table(lua, &node_action.old_node)?,
+  )?;
+  table.set(
+    "newnode",
+    rollback_node_to_table(lua, &node_action.new_node)?,
+  )?;
+  Ok(table)
+}
+
+///
+/// minetest.rollback_get_node_actions(pos, range, seconds)
+///
+/// Returns {{actor, pos, time, oldnode, newnode}, ...} newest first for every node change
+/// within range nodes of pos in the last few seconds. Empty if the rollback journal is off.
+///
+/// The journal is read through its own database connection, like the emerge workers.
+///
+pub fn register_rollback_api(
+  lua_engine: &LuaEngine,
+  environment: Rc<RefCell<ServerEnvironment>>,
+  database_path: &str,
+) {
+  let database = match MapDatabase::new(database_path) {
+    Ok(database) => database,
+    Err(e) => panic!("minetest.rollback_get_node_actions: {}", e),
+  };
+
+  lua_engine.register_api_function(
+    "rollback_get_node_actions",
+    move |lua, (position, range, seconds): (Value, i32, i64)| {
+      let position = get_position_argument(&position, "rollback_get_node_actions")?;
+      let min = position - IVec3::splat(range.abs());
+      let max = position + IVec3::splat(range.abs());
+      let since = get_unix_time() - seconds;
+
+      // Whatever happened this tick hasn't made it into the database yet.
+      let mut node_actions: Vec<NodeAction> = environment
+        .borrow()
+        .get_node_actions()
+        .iter()
+        .rev()
+        .filter(|node_action| {
+          node_action.time >= since
+            && node_action.position.cmpge(min).all()
+            && node_action.position.cmple(max).all()
+        })
+        .cloned()
+        .collect();
+
+      node_actions.extend(
+        database
+          .get_node_actions_in_area(min, max, since)
+          .map_err(|e| {
+            mlua::Error::runtime(format!("minetest.rollback_get_node_actions: {}", e))
+          })?,
+      );
+
+      let table = lua.create_table()?;
+      for (index, node_action) in node_actions.iter().enumerate() {
+        table.set(index + 1, node_action_to_table(lua, node_action)?)?;
+      }
+      Ok(table)
+    },
+  );
+}
+
+fn protected_area_to_table<'lua>(
+  lua: &'lua Lua,
+  id: u32,
+  area: &ProtectedArea,
+) -> mlua::Result<Table<'lua>> {
+  let table = lua.create_table()?;
+  table.set("id", id)?;
+  table.set("owner", area.owner.as_str())?;
+  table.set("min", node_position_to_vector(lua, area.min)?)?;
+  table.set("max", node_position_to_vector(lua, area.max)?)?;
+  Ok(table)
+}
+
+///
+/// The built-in area protection.
+///
+/// minetest.is_protected(pos, name), minetest.protect_area(pos1, pos2, owner),
+/// minetest.unprotect_area(id) and minetest.get_protected_areas(pos).
+///
+/// minetest.is_protected is only the default. Mods can replace it with their own.
+///
+/// minetest.record_protection_violation(pos, name) runs the on_protection_violation callbacks.
+/// A broken callback is blamed on the mod which registered it, not on whoever got turned away.
+///
+pub fn register_protection_api(
+  lua_engine: &LuaEngine,
+  environment: Rc<RefCell<ServerEnvironment>>,
+) {
+  let on_protection_violation = match lua_engine
+    .get_internal_table("on_protection_violation")
+    .and_then(|table| {
+      lua_engine
+        .get_lua()
+        .create_registry_value(table)
+        .map_err(|e| e.to_string())
+    }) {
+    Ok(on_protection_violation) => on_protection_violation,
+    Err(e) => panic!("minetest.record_protection_violation: {}", e),
+  };
+  lua_engine.register_api_function(
+    "record_protection_violation",
+    move |lua, (position, name): (Value, String)| {
+      let position = get_position_argument(&position, "record_protection_violation")?;
+      let callbacks: Table = lua.registry_value(&on_protection_violation)?;
+      for callback in callbacks.sequence_values::<Function>() {
+        call_mod_function::<_, ()>(
+          lua,
+          &callback?,
+          "on_protection_violation",
+          (node_position_to_vector(lua, position)?, name.as_str()),
+        );
+      }
+      Ok(())
+    },
+  );
+
+  let is_protected_environment = environment.clone();
+  lua_engine.register_api_function(
+    "is_protected",
+    move |_, (position, name): (Value, String)| {
+      let position = get_position_argument(&position, "is_protected")?;
+      Ok(
+        is_protected_environment
+          .borrow()
+          .get_area_protection()
+          .is_protected(position, &name),
+      )
+    },
+  );
+
+  let protect_environment = environment.clone();
+  lua_engine.register_api_function(
+    "protect_area",
+    move |_, (pos1, pos2, owner): (Value, Value, String)| {
+      let pos1 = get_position_argument(&pos1, "protect_area")?;
+      let pos2 = get_position_argument(&pos2, "protect_area")?;
+      Ok(
+        protect_environment
+          .borrow_mut()
+          .get_area_protection_mut()
+          .protect(&owner, pos1, pos2),
+      )
+    },
+  );
+
+  let unprotect_environment = environment.clone();
+  lua_engine.register_api_function("unprotect_area", move |_, id: u32| {
+    Ok(
+      unprotect_environment
+        .borrow_mut()
+        .get_area_protection_mut()
+        .unprotect(id),
+    )
+  });
+
+  lua_engine.register_api_function("get_protected_areas", move |lua, position: Value| {
+    let position = get_position_argument(&position, "get_protected_areas")?;
+    let environment = environment.borrow();
+
+    let table = lua.create_table()?;
+    for (index, (id, area)) in environment
+      .get_area_protection()
+      .get_areas_at(position)
+      .into_iter()
+      .enumerate()
+    {
+      table.set(index + 1, protected_area_to_table(lua, id, area)?)?;
+    }
+    Ok(table)
+  });
+}
+
+///
+/// Get the callbacks a detached inventory was created with.
+///
+pub fn get_detached_inventory_callbacks<'lua>(
+  lua: &'lua Lua,
+  name: &str,
+) -> mlua::Result<Option<Table<'lua>>> {
+  match lua.named_registry_value::<Option<Table>>(DETACHED_CALLBACKS_KEY)? {
+    Some(callbacks) => callbacks.get(name),
+    None => Ok(None),
+  }
+}
+
+///
+/// minetest.create_detached_inventory, minetest.remove_detached_inventory
+/// and minetest.get_inventory.
+///
+/// Detached inventories live until they're removed or the Server stops, they're never saved.
+///
+pub fn register_inventory_api(lua_engine: &LuaEngine, environment: Rc<RefCell<ServerEnvironment>>) {
+  let lua = lua_engine.get_lua();
+  if let Err(e) = lua
+    .create_table()
+    .and_then(|callbacks| lua.set_named_registry_value(DETACHED_CALLBACKS_KEY, callbacks))
+  {
+    panic!("minetest.create_detached_inventory: {}", e);
+  }
+
+  // callbacks is {allow_move, allow_put, allow_take, on_move, on_put, on_take}, all optional.
+  // player_name limits who can see it, everyone can if it's nil.
+  let create_environment = environment.clone();
+  lua_engine.register_api_function(
+    "create_detached_inventory",
+    move |lua, (name, callbacks, player_name): (String, Option<Table>, Option<String>)| {
+      let detached_callbacks: Table = lua.named_registry_value(DETACHED_CALLBACKS_KEY)?;
+      detached_callbacks.set(name.as_str(), callbacks)?;
+
+      create_environment
+        .borrow_mut()
+        .create_detached_inventory(&name, player_name);
+      Ok(InvRef::new(
+        InventoryLocation::Detached(name),
+        create_environment.clone(),
+      ))
+    },
+  );
+
+  let remove_environment = environment.clone();
+  lua_engine.register_api_function("remove_detached_inventory", move |lua, name: String| {
+    let detached_callbacks: Table = lua.named_registry_value(DETACHED_CALLBACKS_KEY)?;
+    detached_callbacks.set(name.as_str(), Value::Nil)?;
+
+    Ok(
+      remove_environment
+        .borrow_mut()
+        .remove_detached_inventory(&name),
+    )
+  });
+
+  // location is {type = "player", name}, {type = "detached", name} or {type = "node", pos}.
+  // Returns nil for player and detached inventories that don't exist.
+  lua_engine.register_api_function("get_inventory", move |_, location: Table| {
+    let kind: String = get_field(&location, "type")
+      .map_err(|e| mlua::Error::runtime(format!("minetest.get_inventory: {}", e)))?;
+
+    let location = match kind.as_str() {
+      "node" => InventoryLocation::Node(get_position_argument(
+        &location.get("pos")?,
+        "get_inventory",
+      )?),
+      "player" | "detached" => {
+        let name: String = get_field(&location, "name")
+          .map_err(|e| mlua::Error::runtime(format!("minetest.get_inventory: {}", e)))?;
+        let location = match kind.as_str() {
+          "player" => InventoryLocation::Player(name),
+          _ => InventoryLocation::Detached(name),
+        };
+        if environment.borrow().get_inventory(&location).is_none() {
+          return Ok(None);
+        }
+        location
+      }
+      _ => {
+        return Err(mlua::Error::runtime(format!(
+          "minetest.get_inventory: unknown inventory type [{}].",
+          kind
+        )))
+      }
+    };
+
+    Ok(Some(InvRef::new(location, environment.clone())))
+  });
+}
+
+///
+/// Let mods ask the CraftRegistry what a grid makes, and what makes an item.
+///
+pub fn register_craft_api(lua_engine: &LuaEngine, environment: Rc<RefCell<ServerEnvironment>>) {
+  // input is {method = "normal" | "cooking" | "fuel", width, items}, items being a list of ItemStacks.
+  // Returns ({item, time}, {method, width, items}). item is empty if nothing matched,
+  // the second table is the input after crafting.
+  let result_environment = environment.clone();
+  lua_engine.register_api_function("get_craft_result", move |lua, input: Table| {
+    let to_error = |e: String| mlua::Error::runtime(format!("minetest.get_craft_result: {}", e));
+
+    let method = CraftMethod::from_name(
+      &get_field_or(&input, "method", "normal".to_string()).map_err(to_error)?,
+    )
+    .map_err(to_error)?;
+    let width: usize = get_field_or(&input, "width", 1).map_err(to_error)?;
+    let item_table: Table = get_field(&input, "items").map_err(to_error)?;
+
+    // Empty slots may be nil, so the list can have holes in it.
+    let mut items = vec![];
+    for pair in item_table.pairs::<usize, Value>() {
+      let (index, value) = pair?;
+      if index == 0 {
+        continue;
+      }
+      if items.len() < index {
+        items.resize(index, ItemStack::default());
+      }
+      items[index - 1] = get_item_stack(&value)?;
+    }
+
+    let input = CraftInput {
+      method,
+      width,
+      items,
+    };
+    let result = {
+      let environment = result_environment.borrow();
+      environment
+        .get_craft_registry()
+        .get_craft_result(&input, &environment.get_item_registry())
+    };
+
+    let output = lua.create_table()?;
+    let decremented = lua.create_table()?;
+    decremented.set("method", method.get_name())?;
+    decremented.set("width", width)?;
+    match result {
+      Some(result) => {
+        output.set("item", LuaItemStack::new(result.output))?;
+        output.set("time", result.time)?;
+        decremented.set(
+          "items",
+          lua.create_sequence_from(result.decremented_input.into_iter().map(LuaItemStack::new))?,
+        )?;
+      }
+      None => {
+        output.set("item", LuaItemStack::new(ItemStack::default()))?;
+        output.set("time", 0)?;
+        decremented.set(
+          "items",
+          lua.create_sequence_from(input.items.into_iter().map(LuaItemStack::new))?,
+        )?;
+      }
+    }
+
+    Ok((output, decremented))
+  });
+
+  // Returns {method, type, width, output, items} for the first recipe making output,
+  // or an empty table if there isn't one. Empty slots are left out of items.
+  lua_engine.register_api_function("get_craft_recipe", move |lua, output: String| {
+    let recipe = lua.create_table()?;
+
+    let environment = environment.borrow();
+    let definition = match environment.get_craft_registry().get_craft_recipe(&output) {
+      Some(definition) => definition,
+      None => return Ok(recipe),
+    };
+
+    let items = lua.create_table()?;
+    let (kind, width) = match &definition.recipe {
+      CraftRecipe::Shaped {
+        width, ingredients, ..
+      } => {
+        for (index, ingredient) in ingredients.iter().enumerate() {
+          if !ingredient.is_empty() {
+            items.set(index + 1, ingredient.to_string())?;
+          }
+        }
+        ("shaped", *width)
+      }
+      CraftRecipe::Shapeless(ingredients) => {
+        for (index, ingredient) in ingredients.iter().enumerate() {
+          items.set(index + 1, ingredient.to_string())?;
+        }
+        ("shapeless", 0)
+      }
+      CraftRecipe::Cooking { ingredient, .. } => {
+        items.set(1, ingredient.to_string())?;
+        ("cooking", 1)
+      }
+      CraftRecipe::Fuel { ingredient, .. } => {
+        items.set(1, ingredient.to_string())?;
+        ("fuel", 1)
+      }
+    };
+
+    recipe.set("method", definition.get_method().get_name())?;
+    recipe.set("type", kind)?;
+    recipe.set("width", width)?;
+    recipe.set("output", definition.output.to_string())?;
+    recipe.set("items", items)?;
+
+    Ok(recipe)
+  });
+}
+
+///
+/// minetest.chat_send_player and minetest.chat_send_all.
+///
+/// The messages go out at the end of the tick.
+///
+pub fn register_chat_api(lua_engine: &LuaEngine, chat_messages: Rc<RefCell<Vec<ChatMessage>>>) {
+  let player_messages = chat_messages.clone();
+  lua_engine.register_api_function(
+    "chat_send_player",
+    move |_, (name, message): (String, String)| {
+      player_messages
+        .borrow_mut()
+        .push(ChatMessage::to_player(&name, &message));
+      Ok(())
+    },
+  );
+
+  lua_engine.register_api_function("chat_send_all", move |_, message: String| {
+    chat_messages
+      .borrow_mut()
+      .push(ChatMessage::to_all(&message));
+    Ok(())
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, rc::Rc};
+
+  use glam::ivec3;
+
+  use super::{check_protection, register_protection_api};
+  use crate::game::{
+    lua_engine::{lua_error::add_mod, LuaEngine},
+    server::server_environment::ServerEnvironment,
+  };
+
+  fn load_mod(lua_engine: &LuaEngine, mod_name: &str, raw_code: &str) {
+    let lua = lua_engine.get_lua();
+    add_mod(lua, mod_name, &format!("./mods/{}", mod_name));
+    if let Err(e) = lua
+      .load(raw_code)
+      .set_name(format!("@./mods/{}/main.lua", mod_name))
+      .exec()
+    {
+      panic!("{}", e);
+    }
+  }
+
+  #[test]
+  fn broken_violation_callbacks_get_blamed_on_their_mod() {
+    let lua_engine = LuaEngine::new(true);
+    let environment = Rc::new(RefCell::new(ServerEnvironment::new()));
+    environment.borrow_mut().get_area_protection_mut().protect(
+      "owner",
+      ivec3(0, 0, 0),
+      ivec3(4, 4, 4),
+    );
+    register_protection_api(&lua_engine, environment);
+
+    load_mod(
+      &lua_engine,
+      "alarm",
+      r#"
+      log = {}
+      minetest.register_on_protection_violation(function(pos, name)
+        table.insert(log, "alarm")
+        local t = nil
+        return t.x
+      end)
+      "#,
+    );
+    load_mod(
+      &lua_engine,
+      "guard",
+      r#"
+      minetest.register_on_protection_violation(function(pos, name)
+        table.insert(log, "guard " .. name .. " " .. pos.x)
+      end)
+      "#,
+    );
+
+    // Callbacks get checked when they're registered.
+    let lua = lua_engine.get_lua();
+    match lua
+      .load("minetest.register_on_protection_violation(true)")
+      .exec()
+    {
+      Ok(_) => panic!("a boolean got registered as on_protection_violation"),
+      Err(e) => assert!(e
+        .to_string()
+        .contains("on_protection_violation callback must be a function, got boolean")),
+    }
+
+    // The broken mod gets disabled, the position stays protected and everyone else carries on.
+    for _ in 0..2 {
+      match check_protection(lua, ivec3(2, 2, 2), "griefer") {
+        Ok(protected) => assert!(protected),
+        Err(e) => panic!("{}", e),
+      }
+    }
+    match check_protection(lua, ivec3(2, 2, 2), "owner") {
+      Ok(protected) => assert!(!protected),
+      Err(e) => panic!("{}", e),
+    }
+
+    let log: Vec<String> = match lua.globals().get("log") {
+      Ok(log) => log,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(log, vec!["alarm", "guard griefer 2", "guard griefer 2"]);
+  }
+}
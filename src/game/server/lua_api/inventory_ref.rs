@@ -0,0 +1,225 @@
+use std::{cell::RefCell, rc::Rc};
+
+use mlua::{Lua, Table, UserData, UserDataMethods, Value};
+
+use crate::game::{
+  inventory::{Inventory, InventoryList, InventoryLocation},
+  lua_engine::{
+    lua_item_stack::{get_item_stack, LuaItemStack},
+    lua_table_helpers::node_position_to_vector,
+  },
+  server::server_environment::ServerEnvironment,
+};
+
+use super::check_player_actor_protection;
+
+///
+/// The Lua handle to an Inventory. (InvRef in C++ minetest)
+///
+/// Just like NodeMetaRef, it only holds the location and goes to the
+/// Inventory fresh on every call.
+///
+/// Writes to node inventories made on behalf of a player are held to minetest.is_protected.
+/// Protected writes are skipped and report failure.
+///
+/// ! Slots in Lua start at 1. They start at 0 in Rust.
+///
+pub struct InvRef {
+  location: InventoryLocation,
+  environment: Rc<RefCell<ServerEnvironment>>,
+}
+
+impl InvRef {
+  pub fn new(location: InventoryLocation, environment: Rc<RefCell<ServerEnvironment>>) -> Self {
+    InvRef {
+      location,
+      environment,
+    }
+  }
+
+  fn read<R>(&self, reader: impl FnOnce(&Inventory) -> R) -> R {
+    let environment = self.environment.borrow();
+    match environment.get_inventory(&self.location) {
+      Some(inventory) => reader(inventory),
+      None => reader(&Inventory::new()),
+    }
+  }
+
+  fn write<R>(
+    &self,
+    lua: &Lua,
+    writer: impl FnOnce(&mut Inventory) -> R,
+  ) -> mlua::Result<Option<R>> {
+    if let InventoryLocation::Node(position) = self.location {
+      if check_player_actor_protection(lua, &self.environment, position)? {
+        return Ok(None);
+      }
+    }
+
+    let mut environment = self.environment.borrow_mut();
+    match environment.get_inventory_mut(&self.location) {
+      Some(inventory) => Ok(Some(writer(inventory))),
+      None => Err(mlua::Error::runtime(format!(
+        "InvRef: inventory {} does not exist.",
+        self.location
+      ))),
+    }
+  }
+}
+
+///
+/// Turn a 1 based Lua slot into a 0 based Rust slot.
+///
+fn to_slot(index: i64) -> mlua::Result<usize> {
+  match usize::try_from(index - 1) {
+    Ok(slot) => Ok(slot),
+    Err(_) => Err(mlua::Error::runtime(format!(
+      "InvRef: slot [{}] is invalid, slots start at 1.",
+      index
+    ))),
+  }
+}
+
+impl UserData for InvRef {
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    methods.add_method("is_empty", |_, this, list_name: String| {
+      Ok(this.read(|inventory| inventory.is_list_empty(&list_name)))
+    });
+
+    methods.add_method("get_size", |_, this, list_name: String| {
+      Ok(this.read(|inventory| inventory.get_size(&list_name)))
+    });
+
+    methods.add_method(
+      "set_size",
+      |lua, this, (list_name, size): (String, usize)| {
+        Ok(
+          this
+            .write(lua, |inventory| inventory.set_size(&list_name, size))?
+            .is_some(),
+        )
+      },
+    );
+
+    methods.add_method("get_width", |_, this, list_name: String| {
+      Ok(this.read(|inventory| inventory.get_width(&list_name)))
+    });
+
+    methods.add_method(
+      "set_width",
+      |lua, this, (list_name, width): (String, u32)| {
+        Ok(matches!(
+          this.write(lua, |inventory| inventory.set_width(&list_name, width))?,
+          Some(Ok(_))
+        ))
+      },
+    );
+
+    methods.add_method("get_stack", |_, this, (list_name, index): (String, i64)| {
+      let slot = to_slot(index)?;
+      Ok(LuaItemStack::new(
+        this.read(|inventory| inventory.get_stack(&list_name, slot)),
+      ))
+    });
+
+    methods.add_method(
+      "set_stack",
+      |lua, this, (list_name, index, stack): (String, i64, Value)| {
+        let slot = to_slot(index)?;
+        let stack = get_item_stack(&stack)?;
+        Ok(matches!(
+          this.write(lua, |inventory| inventory
+            .set_stack(&list_name, slot, stack))?,
+          Some(Ok(_))
+        ))
+      },
+    );
+
+    methods.add_method("get_list", |lua, this, list_name: String| {
+      match this.read(|inventory| inventory.get_list(&list_name).cloned()) {
+        Some(list) => Ok(Some(
+          lua.create_sequence_from(list.stacks.into_iter().map(LuaItemStack::new))?,
+        )),
+        None => Ok(None),
+      }
+    });
+
+    methods.add_method(
+      "set_list",
+      |lua, this, (list_name, stacks): (String, Table)| {
+        let mut list = InventoryList::new(0);
+        for stack in stacks.sequence_values::<Value>() {
+          list.stacks.push(get_item_stack(&stack?)?);
+        }
+        this.write(lua, |inventory| {
+          list.width = inventory.get_width(&list_name);
+          inventory.set_list(&list_name, list)
+        })?;
+        Ok(())
+      },
+    );
+
+    methods.add_method("get_lists", |lua, this, ()| {
+      lua.create_sequence_from(this.read(|inventory| inventory.get_list_names()))
+    });
+
+    // Returns what didn't fit.
+    methods.add_method(
+      "add_item",
+      |lua, this, (list_name, item): (String, Value)| {
+        let item = get_item_stack(&item)?;
+        let item_registry = this.environment.borrow().get_item_registry();
+        let leftover = this.write(lua, |inventory| {
+          inventory.add_item(&list_name, item.clone(), &item_registry)
+        })?;
+        Ok(LuaItemStack::new(leftover.unwrap_or(item)))
+      },
+    );
+
+    methods.add_method(
+      "room_for_item",
+      |_, this, (list_name, item): (String, Value)| {
+        let item = get_item_stack(&item)?;
+        let item_registry = this.environment.borrow().get_item_registry();
+        Ok(this.read(|inventory| inventory.room_for_item(&list_name, &item, &item_registry)))
+      },
+    );
+
+    methods.add_method(
+      "contains_item",
+      |_, this, (list_name, item): (String, Value)| {
+        let item = get_item_stack(&item)?;
+        Ok(this.read(|inventory| inventory.contains_item(&list_name, &item)))
+      },
+    );
+
+    // Returns what was taken.
+    methods.add_method(
+      "remove_item",
+      |lua, this, (list_name, item): (String, Value)| {
+        let item = get_item_stack(&item)?;
+        let removed = this.write(lua, |inventory| inventory.remove_item(&list_name, &item))?;
+        Ok(LuaItemStack::new(removed.unwrap_or_default()))
+      },
+    );
+
+    methods.add_method("get_location", |lua, this, ()| {
+      let table = lua.create_table()?;
+      match &this.location {
+        InventoryLocation::Node(position) => {
+          table.set("type", "node")?;
+          table.set("pos", node_position_to_vector(lua, *position)?)?;
+        }
+        InventoryLocation::Player(name) => {
+          table.set("type", "player")?;
+          table.set("name", name.as_str())?;
+        }
+        InventoryLocation::Detached(name) => {
+          table.set("type", "detached")?;
+          table.set("name", name.as_str())?;
+        }
+      }
+      Ok(table)
+    });
+  }
+}
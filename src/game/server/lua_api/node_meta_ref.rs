@@ -0,0 +1,147 @@
+use std::{cell::RefCell, rc::Rc};
+
+use glam::IVec3;
+use mlua::{Lua, UserData, UserDataMethods};
+
+use crate::game::{
+  inventory::InventoryLocation, lua_engine::lua_table_helpers::node_position_to_vector,
+  map::node_meta::NodeMeta, server::server_environment::ServerEnvironment,
+};
+
+use super::{check_player_actor_protection, inventory_ref::InvRef};
+
+///
+/// The Lua handle to a node's NodeMeta. Made by minetest.get_meta(pos).
+///
+/// It only holds the position. Every call goes straight to the Map,
+/// so it never goes stale.
+///
+/// Reading a node in an unloaded Chunk gives the defaults.
+/// Writing to one is a Lua error.
+///
+/// Writes made on behalf of a player are held to minetest.is_protected.
+/// Protected writes are skipped.
+///
+pub struct NodeMetaRef {
+  position: IVec3,
+  environment: Rc<RefCell<ServerEnvironment>>,
+}
+
+impl NodeMetaRef {
+  pub fn new(position: IVec3, environment: Rc<RefCell<ServerEnvironment>>) -> Self {
+    NodeMetaRef {
+      position,
+      environment,
+    }
+  }
+
+  fn read<R>(&self, reader: impl FnOnce(&NodeMeta) -> R) -> R {
+    let environment = self.environment.borrow();
+    match environment.get_map().get_node_meta(self.position) {
+      Some(node_meta) => reader(node_meta),
+      None => reader(&NodeMeta::new()),
+    }
+  }
+
+  fn write(&self, lua: &Lua, writer: impl FnOnce(&mut NodeMeta)) -> mlua::Result<()> {
+    if check_player_actor_protection(lua, &self.environment, self.position)? {
+      return Ok(());
+    }
+
+    let mut environment = self.environment.borrow_mut();
+    let map = environment.get_map_mut();
+
+    let is_empty = match map.get_node_meta_mut(self.position) {
+      Some(node_meta) => {
+        writer(node_meta);
+        node_meta.is_empty()
+      }
+      None => {
+        return Err(mlua::Error::runtime(format!(
+          "NodeMetaRef: position [{}] is not loaded.",
+          self.position
+        )))
+      }
+    };
+
+    // Don't leave empty NodeMeta lying around in the Chunk.
+    if is_empty {
+      map.remove_node_meta(self.position);
+    }
+
+    Ok(())
+  }
+}
+
+impl UserData for NodeMetaRef {
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    methods.add_method("contains", |_, this, key: String| {
+      Ok(this.read(|node_meta| node_meta.contains(&key)))
+    });
+
+    methods.add_method("get_string", |_, this, key: String| {
+      Ok(this.read(|node_meta| node_meta.get_string(&key)))
+    });
+
+    methods.add_method(
+      "set_string",
+      |lua, this, (key, value): (String, Option<String>)| {
+        let value = value.unwrap_or_default();
+        this.write(lua, |node_meta| node_meta.set_string(&key, &value))
+      },
+    );
+
+    methods.add_method("get_int", |_, this, key: String| {
+      Ok(this.read(|node_meta| node_meta.get_int(&key)))
+    });
+
+    methods.add_method("set_int", |lua, this, (key, value): (String, i64)| {
+      this.write(lua, |node_meta| node_meta.set_int(&key, value))
+    });
+
+    methods.add_method("get_float", |_, this, key: String| {
+      Ok(this.read(|node_meta| node_meta.get_float(&key)))
+    });
+
+    methods.add_method("set_float", |lua, this, (key, value): (String, f64)| {
+      this.write(lua, |node_meta| node_meta.set_float(&key, value))
+    });
+
+    methods.add_method("get_inventory", |_, this, ()| {
+      Ok(InvRef::new(
+        InventoryLocation::Node(this.position),
+        this.environment.clone(),
+      ))
+    });
+
+    methods.add_method("get_pos", |lua, this, ()| {
+      node_position_to_vector(lua, this.position)
+    });
+
+    // Mirrors C++ minetest, {fields = {...}, inventory = {list_name = {...}}}
+    methods.add_method("to_table", |lua, this, ()| {
+      let table = lua.create_table()?;
+      let fields = lua.create_table()?;
+      let inventory = lua.create_table()?;
+
+      this.read(|node_meta| -> mlua::Result<()> {
+        for (key, value) in node_meta.get_fields() {
+          fields.set(key.as_str(), value.as_str())?;
+        }
+        for list_name in node_meta.get_inventory().get_list_names() {
+          if let Some(list) = node_meta.get_inventory().get_list(&list_name) {
+            inventory.set(
+              list_name,
+              lua.create_sequence_from(list.stacks.iter().map(|stack| stack.to_string()))?,
+            )?;
+          }
+        }
+        Ok(())
+      })?;
+
+      table.set("fields", fields)?;
+      table.set("inventory", inventory)?;
+      Ok(table)
+    });
+  }
+}
@@ -0,0 +1,97 @@
+use std::{cell::RefCell, rc::Rc};
+
+use glam::IVec3;
+use mlua::{UserData, UserDataMethods};
+
+use crate::game::{map::node_timer::NodeTimer, server::server_environment::ServerEnvironment};
+
+///
+/// The Lua handle to a node's NodeTimer. Made by minetest.get_node_timer(pos).
+///
+/// Just like NodeMetaRef, it only holds the position and goes to the Map
+/// fresh on every call.
+///
+/// When the timer goes off, the block's on_timer(pos, elapsed) gets run.
+/// If on_timer returns true, the timer starts over with the same timeout.
+///
+pub struct NodeTimerRef {
+  position: IVec3,
+  environment: Rc<RefCell<ServerEnvironment>>,
+}
+
+impl NodeTimerRef {
+  pub fn new(position: IVec3, environment: Rc<RefCell<ServerEnvironment>>) -> Self {
+    NodeTimerRef {
+      position,
+      environment,
+    }
+  }
+
+  fn get(&self) -> Option<NodeTimer> {
+    self
+      .environment
+      .borrow()
+      .get_map()
+      .get_node_timer(self.position)
+  }
+
+  fn set(&self, timeout: f32, elapsed: f32) -> mlua::Result<()> {
+    if !timeout.is_finite() || !elapsed.is_finite() {
+      return Err(mlua::Error::runtime(
+        "NodeTimerRef: timeout and elapsed must be finite numbers.",
+      ));
+    }
+
+    match self
+      .environment
+      .borrow_mut()
+      .get_map_mut()
+      .set_node_timer(self.position, NodeTimer::new(timeout, elapsed))
+    {
+      true => Ok(()),
+      false => Err(mlua::Error::runtime(format!(
+        "NodeTimerRef: position [{}] is not loaded.",
+        self.position
+      ))),
+    }
+  }
+}
+
+impl UserData for NodeTimerRef {
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    methods.add_method("set", |_, this, (timeout, elapsed): (f32, f32)| {
+      this.set(timeout, elapsed)
+    });
+
+    methods.add_method("start", |_, this, timeout: f32| this.set(timeout, 0.0));
+
+    methods.add_method("stop", |_, this, ()| {
+      this
+        .environment
+        .borrow_mut()
+        .get_map_mut()
+        .remove_node_timer(this.position);
+      Ok(())
+    });
+
+    methods.add_method("get_timeout", |_, this, ()| {
+      Ok(
+        this
+          .get()
+          .map(|node_timer| node_timer.timeout)
+          .unwrap_or(0.0),
+      )
+    });
+
+    methods.add_method("get_elapsed", |_, this, ()| {
+      Ok(
+        this
+          .get()
+          .map(|node_timer| node_timer.elapsed)
+          .unwrap_or(0.0),
+      )
+    });
+
+    methods.add_method("is_started", |_, this, ()| Ok(this.get().is_some()));
+  }
+}
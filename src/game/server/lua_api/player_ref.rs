@@ -0,0 +1,69 @@
+use std::{cell::RefCell, rc::Rc};
+
+use mlua::{Table, UserData, UserDataMethods};
+
+use crate::game::{
+  inventory::InventoryLocation, lua_engine::lua_table_helpers::get_field_or,
+  server::server_environment::ServerEnvironment,
+};
+
+use super::inventory_ref::InvRef;
+
+///
+/// The Lua handle to a player. Handed to callbacks as digger, placer, etc.
+///
+/// todo: this only knows the name, hp and inventory for now. Position and the rest
+/// todo: come with a real player object.
+///
+#[derive(Clone)]
+pub struct PlayerRef {
+  name: String,
+  environment: Rc<RefCell<ServerEnvironment>>,
+}
+
+impl PlayerRef {
+  pub fn new(name: String, environment: Rc<RefCell<ServerEnvironment>>) -> Self {
+    PlayerRef { name, environment }
+  }
+}
+
+impl UserData for PlayerRef {
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    methods.add_method("get_player_name", |_, this, ()| Ok(this.name.clone()));
+
+    methods.add_method("is_player", |_, _, ()| Ok(true));
+
+    methods.add_method("get_inventory", |_, this, ()| {
+      Ok(InvRef::new(
+        InventoryLocation::Player(this.name.clone()),
+        this.environment.clone(),
+      ))
+    });
+
+    // 0 once the player has left.
+    methods.add_method("get_hp", |_, this, ()| {
+      Ok(
+        this
+          .environment
+          .borrow()
+          .get_player_hp(&this.name)
+          .unwrap_or(0),
+      )
+    });
+
+    // reason is {type = "..."}, it's handed to on_dieplayer if this kills them. type defaults to "set_hp".
+    methods.add_method("set_hp", |_, this, (hp, reason): (f64, Option<Table>)| {
+      let reason = match reason {
+        Some(reason) => get_field_or(&reason, "type", "set_hp".to_string())
+          .map_err(|e| mlua::Error::runtime(format!("PlayerRef:set_hp: {}", e)))?,
+        None => "set_hp".to_string(),
+      };
+      let hp = hp.clamp(0.0, u16::MAX as f64) as u16;
+      this
+        .environment
+        .borrow_mut()
+        .set_player_hp(&this.name, hp, &reason);
+      Ok(())
+    });
+  }
+}
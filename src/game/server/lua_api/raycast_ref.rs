@@ -0,0 +1,54 @@
+use std::{cell::RefCell, rc::Rc};
+
+use mlua::{Lua, MetaMethod, Table, UserData, UserDataMethods};
+
+use crate::game::{map::raycast::Raycast, server::server_environment::ServerEnvironment};
+
+use super::pointed_thing_to_table;
+
+///
+/// The Lua handle to a Raycast. Made by minetest.raycast(pos1, pos2, objects, liquids).
+///
+/// Works as an iterator, each call hands back the next pointed_thing or nil:
+///
+/// for pointed_thing in minetest.raycast(pos1, pos2) do ... end
+///
+/// The ray is walked lazily. The Map is looked at fresh on every call, so
+/// changing nodes in the middle of a loop affects what comes up next.
+///
+pub struct RaycastRef {
+  raycast: Raycast,
+  environment: Rc<RefCell<ServerEnvironment>>,
+}
+
+impl RaycastRef {
+  pub fn new(raycast: Raycast, environment: Rc<RefCell<ServerEnvironment>>) -> Self {
+    RaycastRef {
+      raycast,
+      environment,
+    }
+  }
+
+  fn next<'lua>(&mut self, lua: &'lua Lua) -> mlua::Result<Option<Table<'lua>>> {
+    let pointed_thing = {
+      let environment = self.environment.borrow();
+      self
+        .raycast
+        .next(environment.get_map(), environment.get_block_registry())
+    };
+
+    match pointed_thing {
+      Some(pointed_thing) => Ok(Some(pointed_thing_to_table(lua, &pointed_thing)?)),
+      None => Ok(None),
+    }
+  }
+}
+
+impl UserData for RaycastRef {
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    methods.add_method_mut("next", |lua, this, ()| this.next(lua));
+
+    // Lets a RaycastRef be used directly in a generic for loop.
+    methods.add_meta_method_mut(MetaMethod::Call, |lua, this, ()| this.next(lua));
+  }
+}
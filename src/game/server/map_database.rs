@@ -0,0 +1,766 @@
+use std::{collections::BTreeMap, thread, time::Duration};
+
+use glam::{ivec3, IVec3};
+use rusqlite::{
+  backup::{Backup, StepResult},
+  params, Connection, OptionalExtension,
+};
+
+use crate::file_utilities::file_exists;
+
+use crate::game::{
+  byte_buffer::{ByteReader, ByteWriter},
+  inventory::Inventory,
+  map::chunk::Chunk,
+};
+
+use super::{
+  protection::ProtectedArea,
+  rollback::{NodeAction, RollbackNode},
+};
+
+///
+/// The SQLite3 database which holds a world's map.
+///
+/// Every thread which touches the database opens its own MapDatabase.
+/// SQLite3 handles the locking between them. WAL mode lets the emerge
+/// workers read while the Server thread writes.
+///
+pub struct MapDatabase {
+  connection: Connection,
+}
+
+impl MapDatabase {
+  ///
+  /// Open (or create) the map database at a path.
+  ///
+  pub fn new(database_path: &str) -> Result<Self, String> {
+    let connection = match Connection::open(database_path) {
+      Ok(connection) => connection,
+      Err(e) => {
+        return Err(format!(
+          "MapDatabase: failed to open [{}]. {}",
+          database_path, e
+        ))
+      }
+    };
+
+    // Workers and the Server thread will be fighting over this file.
+    // Setting up the tables can already run into them, so this goes first.
+    if let Err(e) = connection.busy_timeout(std::time::Duration::from_secs(5)) {
+      return Err(format!("MapDatabase: failed to set busy timeout. {}", e));
+    }
+
+    let setup = "
+      PRAGMA journal_mode = WAL;
+      PRAGMA synchronous = NORMAL;
+      CREATE TABLE IF NOT EXISTS chunks (
+        x INTEGER NOT NULL,
+        y INTEGER NOT NULL,
+        z INTEGER NOT NULL,
+        data BLOB NOT NULL,
+        PRIMARY KEY (x, y, z)
+      );
+      CREATE TABLE IF NOT EXISTS map_meta (
+        key TEXT PRIMARY KEY NOT NULL,
+        value TEXT NOT NULL
+      );
+      CREATE TABLE IF NOT EXISTS rollback (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        time INTEGER NOT NULL,
+        actor TEXT NOT NULL,
+        x INTEGER NOT NULL,
+        y INTEGER NOT NULL,
+        z INTEGER NOT NULL,
+        old_name TEXT NOT NULL,
+        old_param2 INTEGER NOT NULL,
+        new_name TEXT NOT NULL,
+        new_param2 INTEGER NOT NULL
+      );
+      CREATE INDEX IF NOT EXISTS rollback_position ON rollback (x, z, y);
+      CREATE INDEX IF NOT EXISTS rollback_actor ON rollback (actor, time);
+      CREATE TABLE IF NOT EXISTS protected_areas (
+        id INTEGER PRIMARY KEY NOT NULL,
+        owner TEXT NOT NULL,
+        min_x INTEGER NOT NULL,
+        min_y INTEGER NOT NULL,
+        min_z INTEGER NOT NULL,
+        max_x INTEGER NOT NULL,
+        max_y INTEGER NOT NULL,
+        max_z INTEGER NOT NULL
+      );
+      CREATE TABLE IF NOT EXISTS players (
+        name TEXT PRIMARY KEY NOT NULL,
+        inventory BLOB NOT NULL
+      );
+    ";
+
+    if let Err(e) = connection.execute_batch(setup) {
+      return Err(format!(
+        "MapDatabase: failed to set up [{}]. {}",
+        database_path, e
+      ));
+    }
+
+    Ok(MapDatabase { connection })
+  }
+
+  ///
+  /// Load a Chunk out of the database.
+  ///
+  /// Nodes of blocks is_registered turns down load as ignore.
+  /// Returns None if the Chunk was never saved.
+  ///
+  pub fn load_chunk<F: Fn(u16) -> bool>(
+    &self,
+    chunk_position: IVec3,
+    is_registered: F,
+  ) -> Result<Option<Chunk>, String> {
+    let data: Option<Vec<u8>> = match self
+      .connection
+      .query_row(
+        "SELECT data FROM chunks WHERE x = ?1 AND y = ?2 AND z = ?3",
+        params![chunk_position.x, chunk_position.y, chunk_position.z],
+        |row| row.get(0),
+      )
+      .optional()
+    {
+      Ok(data) => data,
+      Err(e) => {
+        return Err(format!(
+          "MapDatabase: failed to load chunk [{}]. {}",
+          chunk_position, e
+        ))
+      }
+    };
+
+    match data {
+      Some(bytes) => Ok(Some(Chunk::deserialize(
+        chunk_position,
+        &bytes,
+        is_registered,
+      )?)),
+      None => Ok(None),
+    }
+  }
+
+  ///
+  /// Save a Chunk into the database. Overwrites what was there.
+  ///
+  pub fn save_chunk(&self, chunk: &Chunk) -> Result<(), String> {
+    let chunk_position = chunk.get_position();
+
+    match self.connection.execute(
+      "INSERT OR REPLACE INTO chunks (x, y, z, data) VALUES (?1, ?2, ?3, ?4)",
+      params![
+        chunk_position.x,
+        chunk_position.y,
+        chunk_position.z,
+        chunk.serialize()
+      ],
+    ) {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!(
+        "MapDatabase: failed to save chunk [{}]. {}",
+        chunk_position, e
+      )),
+    }
+  }
+
+  ///
+  /// Save many Chunks at once inside of a single transaction.
+  ///
+  /// This is much faster than calling save_chunk() in a loop.
+  ///
+  pub fn save_chunks(&self, chunks: &[&Chunk]) -> Result<(), String> {
+    let transaction = match self.connection.unchecked_transaction() {
+      Ok(transaction) => transaction,
+      Err(e) => return Err(format!("MapDatabase: failed to begin transaction. {}", e)),
+    };
+
+    for chunk in chunks {
+      self.save_chunk(chunk)?;
+    }
+
+    match transaction.commit() {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!("MapDatabase: failed to commit chunks. {}", e)),
+    }
+  }
+
+  ///
+  /// Get every Chunk position stored in the database.
+  ///
+  pub fn get_chunk_positions(&self) -> Result<Vec<IVec3>, String> {
+    let mut statement = match self.connection.prepare("SELECT x, y, z FROM chunks") {
+      Ok(statement) => statement,
+      Err(e) => return Err(format!("MapDatabase: failed to list chunks. {}", e)),
+    };
+
+    let rows = match statement.query_map([], |row| Ok(ivec3(row.get(0)?, row.get(1)?, row.get(2)?)))
+    {
+      Ok(rows) => rows,
+      Err(e) => return Err(format!("MapDatabase: failed to list chunks. {}", e)),
+    };
+
+    let mut positions = vec![];
+    for row in rows {
+      match row {
+        Ok(position) => positions.push(position),
+        Err(e) => return Err(format!("MapDatabase: failed to read chunk row. {}", e)),
+      }
+    }
+
+    Ok(positions)
+  }
+
+  ///
+  /// Get a value out of the map_meta table.
+  ///
+  pub fn get_meta(&self, key: &str) -> Result<Option<String>, String> {
+    match self
+      .connection
+      .query_row(
+        "SELECT value FROM map_meta WHERE key = ?1",
+        params![key],
+        |row| row.get(0),
+      )
+      .optional()
+    {
+      Ok(value) => Ok(value),
+      Err(e) => Err(format!("MapDatabase: failed to get meta [{}]. {}", key, e)),
+    }
+  }
+
+  ///
+  /// Set a value in the map_meta table.
+  ///
+  pub fn set_meta(&self, key: &str, value: &str) -> Result<(), String> {
+    match self.connection.execute(
+      "INSERT OR REPLACE INTO map_meta (key, value) VALUES (?1, ?2)",
+      params![key, value],
+    ) {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!("MapDatabase: failed to set meta [{}]. {}", key, e)),
+    }
+  }
+
+  ///
+  /// Remember which block name goes with each block ID.
+  ///
+  /// Chunks only store IDs. The Server hands this back to the BlockRegistry
+  /// so IDs never move, and anything reading the database without the game
+  /// loaded (region exports, etc) needs it to make sense of them.
+  ///
+  pub fn set_block_names(&self, block_names: &[String]) -> Result<(), String> {
+    self.set_meta("block_names", &block_names.join("\n"))
+  }
+
+  ///
+  /// Get the block names saved by set_block_names(), indexed by block ID.
+  ///
+  /// Returns None if the world has never been loaded by a Server.
+  ///
+  pub fn get_block_names(&self) -> Result<Option<Vec<String>>, String> {
+    Ok(self.get_meta("block_names")?.map(|block_names| {
+      block_names
+        .split('\n')
+        .map(|name| name.to_string())
+        .collect()
+    }))
+  }
+
+  ///
+  /// Copy the whole database into a new file with SQLite's online backup API.
+  ///
+  /// This is safe while the Server is running. The copy is done in one
+  /// step, so it's a consistent snapshot of the database at that moment.
+  ///
+  /// Refuses to overwrite an existing file.
+  ///
+  pub fn backup(&self, backup_path: &str) -> Result<(), String> {
+    if file_exists(backup_path) {
+      return Err(format!(
+        "MapDatabase: backup [{}] already exists.",
+        backup_path
+      ));
+    }
+
+    let mut destination = match Connection::open(backup_path) {
+      Ok(destination) => destination,
+      Err(e) => {
+        return Err(format!(
+          "MapDatabase: failed to create backup [{}]. {}",
+          backup_path, e
+        ))
+      }
+    };
+
+    let backup = match Backup::new(&self.connection, &mut destination) {
+      Ok(backup) => backup,
+      Err(e) => return Err(format!("MapDatabase: failed to start backup. {}", e)),
+    };
+
+    // -1 copies every page in a single step.
+    // Busy or locked means another connection is writing, just try again.
+    loop {
+      match backup.step(-1) {
+        Ok(StepResult::Done) => return Ok(()),
+        Ok(_) => thread::sleep(Duration::from_millis(100)),
+        Err(e) => {
+          return Err(format!(
+            "MapDatabase: backup to [{}] failed. {}",
+            backup_path, e
+          ))
+        }
+      }
+    }
+  }
+
+  ///
+  /// Append node actions to the rollback journal inside of a single transaction.
+  ///
+  pub fn save_node_actions(&self, node_actions: &[NodeAction]) -> Result<(), String> {
+    if node_actions.is_empty() {
+      return Ok(());
+    }
+
+    let transaction = match self.connection.unchecked_transaction() {
+      Ok(transaction) => transaction,
+      Err(e) => return Err(format!("MapDatabase: failed to begin transaction. {}", e)),
+    };
+
+    for node_action in node_actions {
+      if let Err(e) = self.connection.execute(
+        "INSERT INTO rollback (time, actor, x, y, z, old_name, old_param2, new_name, new_param2)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+          node_action.time,
+          node_action.actor,
+          node_action.position.x,
+          node_action.position.y,
+          node_action.position.z,
+          node_action.old_node.name,
+          node_action.old_node.param2,
+          node_action.new_node.name,
+          node_action.new_node.param2
+        ],
+      ) {
+        return Err(format!("MapDatabase: failed to save node action. {}", e));
+      }
+    }
+
+    match transaction.commit() {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!("MapDatabase: failed to commit node actions. {}", e)),
+    }
+  }
+
+  ///
+  /// Get the node actions between two corners (inclusive) since a unix time, newest first.
+  ///
+  pub fn get_node_actions_in_area(
+    &self,
+    min: IVec3,
+    max: IVec3,
+    since: i64,
+  ) -> Result<Vec<NodeAction>, String> {
+    self.query_node_actions(
+      "WHERE x BETWEEN ?1 AND ?2 AND z BETWEEN ?3 AND ?4 AND y BETWEEN ?5 AND ?6 AND time >= ?7",
+      params![min.x, max.x, min.z, max.z, min.y, max.y, since],
+    )
+  }
+
+  ///
+  /// Get every node action by an actor since a unix time, newest first.
+  ///
+  pub fn get_actor_node_actions(&self, actor: &str, since: i64) -> Result<Vec<NodeAction>, String> {
+    self.query_node_actions("WHERE actor = ?1 AND time >= ?2", params![actor, since])
+  }
+
+  fn query_node_actions(
+    &self,
+    filter: &str,
+    parameters: &[&dyn rusqlite::ToSql],
+  ) -> Result<Vec<NodeAction>, String> {
+    let query = format!(
+      "SELECT time, actor, x, y, z, old_name, old_param2, new_name, new_param2
+      FROM rollback {} ORDER BY id DESC",
+      filter
+    );
+
+    let mut statement = match self.connection.prepare(&query) {
+      Ok(statement) => statement,
+      Err(e) => return Err(format!("MapDatabase: failed to query node actions. {}", e)),
+    };
+
+    let rows = statement.query_map(parameters, |row| {
+      Ok(NodeAction {
+        time: row.get(0)?,
+        actor: row.get(1)?,
+        position: ivec3(row.get(2)?, row.get(3)?, row.get(4)?),
+        old_node: RollbackNode {
+          name: row.get(5)?,
+          param2: row.get(6)?,
+        },
+        new_node: RollbackNode {
+          name: row.get(7)?,
+          param2: row.get(8)?,
+        },
+      })
+    });
+    let rows = match rows {
+      Ok(rows) => rows,
+      Err(e) => return Err(format!("MapDatabase: failed to query node actions. {}", e)),
+    };
+
+    let mut node_actions = vec![];
+    for row in rows {
+      match row {
+        Ok(node_action) => node_actions.push(node_action),
+        Err(e) => {
+          return Err(format!(
+            "MapDatabase: failed to read node action row. {}",
+            e
+          ))
+        }
+      }
+    }
+
+    Ok(node_actions)
+  }
+
+  ///
+  /// Replace every protected area in the database inside of a single transaction.
+  ///
+  pub fn save_protected_areas(&self, areas: &BTreeMap<u32, ProtectedArea>) -> Result<(), String> {
+    let transaction = match self.connection.unchecked_transaction() {
+      Ok(transaction) => transaction,
+      Err(e) => return Err(format!("MapDatabase: failed to begin transaction. {}", e)),
+    };
+
+    if let Err(e) = self.connection.execute("DELETE FROM protected_areas", []) {
+      return Err(format!(
+        "MapDatabase: failed to clear protected areas. {}",
+        e
+      ));
+    }
+
+    for (id, area) in areas {
+      if let Err(e) = self.connection.execute(
+        "INSERT INTO protected_areas (id, owner, min_x, min_y, min_z, max_x, max_y, max_z)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+          id, area.owner, area.min.x, area.min.y, area.min.z, area.max.x, area.max.y, area.max.z
+        ],
+      ) {
+        return Err(format!(
+          "MapDatabase: failed to save protected area [{}]. {}",
+          id, e
+        ));
+      }
+    }
+
+    match transaction.commit() {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!(
+        "MapDatabase: failed to commit protected areas. {}",
+        e
+      )),
+    }
+  }
+
+  ///
+  /// Get every protected area in the database by ID.
+  ///
+  pub fn load_protected_areas(&self) -> Result<BTreeMap<u32, ProtectedArea>, String> {
+    let mut statement = match self
+      .connection
+      .prepare("SELECT id, owner, min_x, min_y, min_z, max_x, max_y, max_z FROM protected_areas")
+    {
+      Ok(statement) => statement,
+      Err(e) => {
+        return Err(format!(
+          "MapDatabase: failed to load protected areas. {}",
+          e
+        ))
+      }
+    };
+
+    let rows = statement.query_map([], |row| {
+      Ok((
+        row.get::<_, u32>(0)?,
+        ProtectedArea {
+          owner: row.get(1)?,
+          min: ivec3(row.get(2)?, row.get(3)?, row.get(4)?),
+          max: ivec3(row.get(5)?, row.get(6)?, row.get(7)?),
+        },
+      ))
+    });
+    let rows = match rows {
+      Ok(rows) => rows,
+      Err(e) => {
+        return Err(format!(
+          "MapDatabase: failed to load protected areas. {}",
+          e
+        ))
+      }
+    };
+
+    let mut areas = BTreeMap::new();
+    for row in rows {
+      match row {
+        Ok((id, area)) => {
+          areas.insert(id, area);
+        }
+        Err(e) => {
+          return Err(format!(
+            "MapDatabase: failed to read protected area row. {}",
+            e
+          ))
+        }
+      }
+    }
+
+    Ok(areas)
+  }
+
+  ///
+  /// Load a player's Inventory out of the database.
+  ///
+  /// Returns None if the player has never been saved.
+  ///
+  pub fn load_player_inventory(&self, name: &str) -> Result<Option<Inventory>, String> {
+    let data: Option<Vec<u8>> = match self
+      .connection
+      .query_row(
+        "SELECT inventory FROM players WHERE name = ?1",
+        params![name],
+        |row| row.get(0),
+      )
+      .optional()
+    {
+      Ok(data) => data,
+      Err(e) => {
+        return Err(format!(
+          "MapDatabase: failed to load player [{}]. {}",
+          name, e
+        ))
+      }
+    };
+
+    match data {
+      Some(bytes) => match Inventory::deserialize(&mut ByteReader::new(&bytes)) {
+        Ok(inventory) => Ok(Some(inventory)),
+        Err(e) => Err(format!(
+          "MapDatabase: player [{}] inventory is corrupted. {}",
+          name, e
+        )),
+      },
+      None => Ok(None),
+    }
+  }
+
+  ///
+  /// Save a player's Inventory into the database. Overwrites what was there.
+  ///
+  pub fn save_player_inventory(&self, name: &str, inventory: &Inventory) -> Result<(), String> {
+    let mut writer = ByteWriter::new();
+    inventory.serialize(&mut writer);
+
+    match self.connection.execute(
+      "INSERT OR REPLACE INTO players (name, inventory) VALUES (?1, ?2)",
+      params![name, writer.into_bytes()],
+    ) {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!(
+        "MapDatabase: failed to save player [{}]. {}",
+        name, e
+      )),
+    }
+  }
+
+  ///
+  /// Get the world's map seed. A new random one is created for new worlds.
+  ///
+  pub fn get_or_create_seed(&self) -> Result<u64, String> {
+    if let Some(seed) = self.get_meta("seed")? {
+      return match seed.parse::<u64>() {
+        Ok(seed) => Ok(seed),
+        Err(e) => Err(format!("MapDatabase: seed [{}] is corrupted. {}", seed, e)),
+      };
+    }
+
+    let seed = rand::random::<u64>();
+    self.set_meta("seed", &seed.to_string())?;
+
+    Ok(seed)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, IVec3};
+
+  use super::MapDatabase;
+  use crate::game::{
+    item_stack::ItemStack,
+    map::{
+      block_registry::IGNORE_ID,
+      chunk::{Chunk, Node},
+    },
+    server::inventory_actions::create_player_inventory,
+  };
+
+  #[test]
+  fn chunks_and_block_names_round_trip() {
+    let database = match MapDatabase::new(":memory:") {
+      Ok(database) => database,
+      Err(e) => panic!("{}", e),
+    };
+    assert!(matches!(
+      database.load_chunk(IVec3::ZERO, |_| true),
+      Ok(None)
+    ));
+    assert!(matches!(database.get_block_names(), Ok(None)));
+
+    let mut chunk = Chunk::new(ivec3(1, -2, 3));
+    chunk.set_node(ivec3(1, 2, 3), Node::new(2, 5));
+    chunk.set_node(ivec3(3, 2, 1), Node::new(1, 0));
+    chunk
+      .get_node_meta_mut(ivec3(1, 2, 3))
+      .set_string("text", "hello");
+    if let Err(e) = database.save_chunks(&[&chunk, &Chunk::new(IVec3::ZERO)]) {
+      panic!("{}", e);
+    }
+
+    let loaded = match database.load_chunk(ivec3(1, -2, 3), |_| true) {
+      Ok(Some(loaded)) => loaded,
+      other => panic!("{:?}", other.map(|chunk| chunk.is_some())),
+    };
+    assert_eq!(loaded.serialize(), chunk.serialize());
+
+    let mut chunk_positions = match database.get_chunk_positions() {
+      Ok(chunk_positions) => chunk_positions,
+      Err(e) => panic!("{}", e),
+    };
+    chunk_positions.sort_by_key(|position| position.to_array());
+    assert_eq!(chunk_positions, vec![IVec3::ZERO, ivec3(1, -2, 3)]);
+
+    // Block 2's mod is gone.
+    match database.load_chunk(ivec3(1, -2, 3), |id| id < 2) {
+      Ok(Some(loaded)) => {
+        assert_eq!(loaded.get_node(ivec3(1, 2, 3)), Node::new(IGNORE_ID, 5));
+        assert_eq!(loaded.get_node(ivec3(3, 2, 1)), Node::new(1, 0));
+      }
+      other => panic!("{:?}", other.map(|chunk| chunk.is_some())),
+    }
+
+    let block_names = vec!["air".to_string(), "test:stone".to_string()];
+    if let Err(e) = database.set_block_names(&block_names) {
+      panic!("{}", e);
+    }
+    assert_eq!(database.get_block_names(), Ok(Some(block_names)));
+
+    let seed = database.get_or_create_seed();
+    assert!(seed.is_ok());
+    assert_eq!(database.get_or_create_seed(), seed);
+  }
+
+  #[test]
+  fn player_inventories_round_trip() {
+    let database = match MapDatabase::new(":memory:") {
+      Ok(database) => database,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(database.load_player_inventory("alice"), Ok(None));
+
+    let mut inventory = create_player_inventory();
+    for (list_name, index, item_string) in
+      [("main", 0, "test:stone 99"), ("craft", 8, "test:stick")]
+    {
+      match ItemStack::from_string(item_string) {
+        Ok(stack) => {
+          if let Err(e) = inventory.set_stack(list_name, index, stack) {
+            panic!("{}", e);
+          }
+        }
+        Err(e) => panic!("{}", e),
+      }
+    }
+    if let Err(e) = database.save_player_inventory("alice", &inventory) {
+      panic!("{}", e);
+    }
+    assert_eq!(
+      database.load_player_inventory("alice"),
+      Ok(Some(inventory.clone()))
+    );
+
+    // Saving again replaces the old Inventory.
+    if let Err(e) = inventory.set_stack("main", 0, ItemStack::default()) {
+      panic!("{}", e);
+    }
+    if let Err(e) = database.save_player_inventory("alice", &inventory) {
+      panic!("{}", e);
+    }
+    assert_eq!(database.load_player_inventory("alice"), Ok(Some(inventory)));
+    assert_eq!(database.load_player_inventory("bob"), Ok(None));
+  }
+
+  #[test]
+  fn backups_are_copies_which_never_overwrite() {
+    let world_dir =
+      std::env::temp_dir().join(format!("minetest_backup_test_{}", std::process::id()));
+    if let Err(e) = std::fs::create_dir_all(&world_dir) {
+      panic!("{}", e);
+    }
+    let database_path = world_dir.join("map.sqlite").to_string_lossy().to_string();
+    let backup_path = world_dir
+      .join("backup.sqlite")
+      .to_string_lossy()
+      .to_string();
+
+    let database = match MapDatabase::new(&database_path) {
+      Ok(database) => database,
+      Err(e) => panic!("{}", e),
+    };
+    let mut chunk = Chunk::new(ivec3(0, 1, 0));
+    chunk.set_node(ivec3(4, 4, 4), Node::new(1, 0));
+    if let Err(e) = database.save_chunks(&[&chunk]) {
+      panic!("{}", e);
+    }
+    if let Err(e) = database.backup(&backup_path) {
+      panic!("{}", e);
+    }
+
+    // Changes after the backup stay out of it.
+    if let Err(e) = database.save_chunks(&[&Chunk::new(IVec3::ZERO)]) {
+      panic!("{}", e);
+    }
+    let backup = match MapDatabase::new(&backup_path) {
+      Ok(backup) => backup,
+      Err(e) => panic!("{}", e),
+    };
+    match backup.load_chunk(ivec3(0, 1, 0), |_| true) {
+      Ok(Some(loaded)) => assert_eq!(loaded.serialize(), chunk.serialize()),
+      other => panic!("{:?}", other.map(|chunk| chunk.is_some())),
+    }
+    assert_eq!(backup.get_chunk_positions(), Ok(vec![ivec3(0, 1, 0)]));
+    drop(backup);
+
+    // An existing file is never overwritten.
+    match database.backup(&backup_path) {
+      Ok(_) => panic!("the backup got overwritten"),
+      Err(e) => assert!(e.contains("already exists")),
+    }
+    let backup = match MapDatabase::new(&backup_path) {
+      Ok(backup) => backup,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(backup.get_chunk_positions(), Ok(vec![ivec3(0, 1, 0)]));
+
+    if let Err(e) = std::fs::remove_dir_all(world_dir) {
+      panic!("{}", e);
+    }
+  }
+}
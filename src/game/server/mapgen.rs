@@ -0,0 +1,287 @@
+mod biome;
+mod decoration;
+mod noise;
+mod ore;
+
+use glam::{ivec3, vec3, IVec3};
+use mlua::{Lua, Table};
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::game::map::{
+  block_registry::BlockRegistry,
+  chunk::{Chunk, Node, CHUNK_SIZE},
+};
+
+use self::{
+  biome::{select_biome, Biome},
+  decoration::Decoration,
+  noise::{Noise, NoiseParams},
+  ore::Ore,
+};
+
+///
+/// Anything at or below this height which would be air becomes
+/// the biome's node_water instead. (If it has one)
+///
+const WATER_LEVEL: i32 = 1;
+
+///
+/// The map generator.
+///
+/// Mods shape the map with minetest.register_biome, minetest.register_ore
+/// and minetest.register_decoration. After all mods are loaded the Server
+/// hands those definitions in here.
+///
+/// Generation happens in 3 passes per Chunk:
+/// 1.) Terrain - A height map filled in with each column's biome blocks.
+/// 2.) Ores - Replaces underground blocks.
+/// 3.) Decorations - Placed on top of the surface.
+///
+/// Mapgen is immutable after creation so it can be shared between threads.
+///
+pub struct Mapgen {
+  seed: u64,
+  terrain_noise: Noise,
+  heat_noise: Noise,
+  humidity_noise: Noise,
+  biomes: Vec<Biome>,
+  ores: Vec<Ore>,
+  decorations: Vec<Decoration>,
+}
+
+impl Mapgen {
+  ///
+  /// Create a Mapgen with no definitions.
+  ///
+  /// This will generate nothing but air.
+  ///
+  pub fn new(seed: u64) -> Self {
+    Mapgen {
+      seed,
+      terrain_noise: Noise::new(
+        NoiseParams::new(4.0, 20.0, vec3(250.0, 250.0, 250.0), 82341, 5, 0.6),
+        seed,
+      ),
+      heat_noise: Noise::new(
+        NoiseParams::new(50.0, 50.0, vec3(1000.0, 1000.0, 1000.0), 5349, 3, 0.5),
+        seed,
+      ),
+      humidity_noise: Noise::new(
+        NoiseParams::new(50.0, 50.0, vec3(1000.0, 1000.0, 1000.0), 842, 3, 0.5),
+        seed,
+      ),
+      biomes: vec![],
+      ores: vec![],
+      decorations: vec![],
+    }
+  }
+
+  ///
+  /// Create a Mapgen out of the _G.biomes, _G.ores and _G.decorations tables.
+  ///
+  /// Every block name is resolved into an ID here, so any typo in a mod
+  /// is caught when the game loads instead of when a Chunk generates.
+  ///
+  pub fn from_lua_tables(
+    lua: &Lua,
+    seed: u64,
+    block_registry: &BlockRegistry,
+    biome_table: &Table,
+    ore_table: &Table,
+    decoration_table: &Table,
+  ) -> Result<Self, String> {
+    let mut new_mapgen = Mapgen::new(seed);
+
+    for pair in biome_table.clone().pairs::<String, Table>() {
+      let (_, definition) = pair.map_err(|e| format!("Mapgen: malformed biome table. {}", e))?;
+      let biome =
+        Biome::from_lua_table(&definition, block_registry).map_err(|e| format!("Mapgen: {}", e))?;
+      new_mapgen.biomes.push(biome);
+    }
+
+    // Biome selection ties go to the first one, so keep this stable.
+    new_mapgen.biomes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for definition in ore_table.clone().sequence_values::<Table>() {
+      let definition = definition.map_err(|e| format!("Mapgen: malformed ore table. {}", e))?;
+      let ore = Ore::from_lua_table(&definition, block_registry, seed)
+        .map_err(|e| format!("Mapgen: {}", e))?;
+      new_mapgen.ores.push(ore);
+    }
+
+    for (index, definition) in decoration_table
+      .clone()
+      .sequence_values::<Table>()
+      .enumerate()
+    {
+      let definition =
+        definition.map_err(|e| format!("Mapgen: malformed decoration table. {}", e))?;
+      let decoration = Decoration::from_lua_table(
+        lua,
+        &definition,
+        block_registry,
+        &new_mapgen.biomes,
+        index + 1,
+      )
+      .map_err(|e| format!("Mapgen: {}", e))?;
+      new_mapgen.decorations.push(decoration);
+    }
+
+    println!(
+      "Mapgen: loaded [{}] biomes, [{}] ores, [{}] decorations.",
+      new_mapgen.biomes.len(),
+      new_mapgen.ores.len(),
+      new_mapgen.decorations.len()
+    );
+
+    Ok(new_mapgen)
+  }
+
+  ///
+  /// Get the seed the Mapgen was created with.
+  ///
+  pub fn get_seed(&self) -> u64 {
+    self.seed
+  }
+
+  ///
+  /// Generate a brand new Chunk.
+  ///
+  /// The same seed, definitions and position will always make the same Chunk.
+  ///
+  pub fn generate(&self, chunk_position: IVec3) -> Chunk {
+    let mut chunk = Chunk::new(chunk_position);
+
+    if self.biomes.is_empty() {
+      return chunk;
+    }
+
+    let column_biomes = self.generate_terrain(&mut chunk);
+
+    let mut rng = StdRng::seed_from_u64(self.get_chunk_seed(chunk_position));
+
+    for ore in &self.ores {
+      ore.generate(&mut chunk, &mut rng);
+    }
+
+    for decoration in &self.decorations {
+      decoration.generate(&mut chunk, &column_biomes, &mut rng);
+    }
+
+    chunk
+  }
+
+  ///
+  /// Fill in the height map with each column's biome.
+  ///
+  /// Returns the biome index of each column. (X + Z * CHUNK_SIZE)
+  ///
+  fn generate_terrain(&self, chunk: &mut Chunk) -> Vec<Option<usize>> {
+    let origin = chunk.get_world_origin();
+    let mut column_biomes = vec![None; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+
+    for x in 0..CHUNK_SIZE {
+      for z in 0..CHUNK_SIZE {
+        let world_x = (origin.x + x) as f32;
+        let world_z = (origin.z + z) as f32;
+
+        let surface = self.terrain_noise.get_2d(world_x, world_z).floor() as i32;
+        let heat = self.heat_noise.get_2d(world_x, world_z);
+        let humidity = self.humidity_noise.get_2d(world_x, world_z);
+
+        let biome_index = match select_biome(&self.biomes, heat, humidity, surface) {
+          Some(biome_index) => biome_index,
+          None => continue,
+        };
+        column_biomes[(x + z * CHUNK_SIZE) as usize] = Some(biome_index);
+
+        let biome = &self.biomes[biome_index];
+
+        for y in 0..CHUNK_SIZE {
+          let world_y = origin.y + y;
+
+          let block_id = if world_y > surface {
+            match biome.node_water {
+              Some(water) if world_y <= WATER_LEVEL => water,
+              _ => continue,
+            }
+          } else if world_y > surface - biome.depth_top {
+            biome.node_top
+          } else if world_y > surface - biome.depth_top - biome.depth_filler {
+            biome.node_filler
+          } else {
+            biome.node_stone
+          };
+
+          chunk.set_node(ivec3(x, y, z), Node::new(block_id, 0));
+        }
+      }
+    }
+
+    column_biomes
+  }
+
+  ///
+  /// Mix the world seed with the chunk position.
+  ///
+  fn get_chunk_seed(&self, chunk_position: IVec3) -> u64 {
+    let mixed = (chunk_position.x as i64).wrapping_mul(73856093)
+      ^ (chunk_position.y as i64).wrapping_mul(19349663)
+      ^ (chunk_position.z as i64).wrapping_mul(83492791);
+    self.seed ^ (mixed as u64)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, IVec3};
+
+  use super::{biome::Biome, Mapgen};
+  use crate::game::map::chunk::CHUNK_SIZE;
+
+  #[test]
+  fn terrain_is_layered_and_deterministic() {
+    let mut mapgen = Mapgen::new(42);
+    mapgen.biomes.push(Biome {
+      name: "test:grassland".to_string(),
+      node_top: 1,
+      depth_top: 1,
+      node_filler: 2,
+      depth_filler: 3,
+      node_stone: 3,
+      node_water: Some(4),
+      y_min: -31000,
+      y_max: 31000,
+      heat_point: 50.0,
+      humidity_point: 50.0,
+    });
+
+    // Every column goes stone, filler, top, then air or water, from the bottom up.
+    for chunk_position in [ivec3(0, -1, 0), ivec3(0, 0, 0), ivec3(3, 0, -2)] {
+      let chunk = mapgen.generate(chunk_position);
+      for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+          let column: Vec<u16> = (0..CHUNK_SIZE)
+            .map(|y| chunk.get_node(ivec3(x, y, z)).block_id)
+            .collect();
+          let mut sorted = column.clone();
+          sorted.sort_by_key(|block_id| match *block_id {
+            3 => 0,
+            2 => 1,
+            1 => 2,
+            _ => 3,
+          });
+          assert_eq!(column, sorted);
+        }
+      }
+
+      assert_eq!(
+        mapgen.generate(chunk_position).serialize(),
+        chunk.serialize()
+      );
+    }
+
+    // Without biomes there's nothing to generate.
+    assert!(Mapgen::new(42).generate(IVec3::ZERO).is_empty());
+  }
+}
@@ -0,0 +1,136 @@
+use mlua::Table;
+
+use crate::game::{
+  lua_engine::lua_table_helpers::{get_field, get_field_or},
+  map::block_registry::BlockRegistry,
+};
+
+///
+/// A biome from minetest.register_biome, with every block
+/// name resolved into a BlockRegistry ID.
+///
+/// Biomes are picked per column by whichever biome's heat and humidity
+/// point is the closest to the heat and humidity noise at that column.
+///
+#[derive(Clone, Debug)]
+pub struct Biome {
+  pub name: String,
+  pub node_top: u16,
+  pub depth_top: i32,
+  pub node_filler: u16,
+  pub depth_filler: i32,
+  pub node_stone: u16,
+  pub node_water: Option<u16>,
+  pub y_min: i32,
+  pub y_max: i32,
+  pub heat_point: f32,
+  pub humidity_point: f32,
+}
+
+impl Biome {
+  ///
+  /// Parse and resolve a Biome out of a Lua table.
+  ///
+  pub fn from_lua_table(table: &Table, block_registry: &BlockRegistry) -> Result<Self, String> {
+    let name: String = get_field(table, "name")?;
+    let requester = format!("biome [{}]", name);
+
+    let resolve = |field: &str| -> Result<u16, String> {
+      let block_name: String = get_field(table, field)?;
+      block_registry.require_id(&block_name, &requester)
+    };
+
+    let node_water = match get_field_or::<Option<String>>(table, "node_water", None)? {
+      Some(block_name) => Some(block_registry.require_id(&block_name, &requester)?),
+      None => None,
+    };
+
+    let y_min = get_field_or(table, "y_min", -31000)?;
+    let y_max = get_field_or(table, "y_max", 31000)?;
+    if y_min > y_max {
+      return Err(format!("{} has y_min greater than y_max.", requester));
+    }
+
+    Ok(Biome {
+      node_top: resolve("node_top")?,
+      depth_top: get_field_or(table, "depth_top", 1)?,
+      node_filler: resolve("node_filler")?,
+      depth_filler: get_field_or(table, "depth_filler", 3)?,
+      node_stone: resolve("node_stone")?,
+      node_water,
+      y_min,
+      y_max,
+      heat_point: get_field_or(table, "heat_point", 50.0)?,
+      humidity_point: get_field_or(table, "humidity_point", 50.0)?,
+      name,
+    })
+  }
+}
+
+///
+/// Pick the closest biome in heat/humidity space which is
+/// allowed to exist at this height.
+///
+/// Returns the biome's index.
+///
+pub fn select_biome(biomes: &[Biome], heat: f32, humidity: f32, y: i32) -> Option<usize> {
+  let mut closest: Option<(usize, f32)> = None;
+
+  for (index, biome) in biomes.iter().enumerate() {
+    if y < biome.y_min || y > biome.y_max {
+      continue;
+    }
+
+    let heat_distance = heat - biome.heat_point;
+    let humidity_distance = humidity - biome.humidity_point;
+    let distance = heat_distance * heat_distance + humidity_distance * humidity_distance;
+
+    match closest {
+      Some((_, closest_distance)) if closest_distance <= distance => (),
+      _ => closest = Some((index, distance)),
+    }
+  }
+
+  closest.map(|(index, _)| index)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{select_biome, Biome};
+
+  fn test_biome(name: &str, heat_point: f32, humidity_point: f32, y_min: i32) -> Biome {
+    Biome {
+      name: name.to_string(),
+      node_top: 1,
+      depth_top: 1,
+      node_filler: 2,
+      depth_filler: 3,
+      node_stone: 3,
+      node_water: None,
+      y_min,
+      y_max: 31000,
+      heat_point,
+      humidity_point,
+    }
+  }
+
+  #[test]
+  fn closest_biome_in_range_wins() {
+    let biomes = [
+      test_biome("test:desert", 90.0, 10.0, -31000),
+      test_biome("test:grassland", 50.0, 50.0, -31000),
+      test_biome("test:tundra", 10.0, 40.0, 20),
+      // Same point as grassland, it loses the tie.
+      test_biome("test:meadow", 50.0, 50.0, -31000),
+    ];
+
+    assert_eq!(select_biome(&biomes, 85.0, 20.0, 0), Some(0));
+    assert_eq!(select_biome(&biomes, 55.0, 45.0, 0), Some(1));
+    assert_eq!(select_biome(&biomes, 10.0, 40.0, 30), Some(2));
+    // Too low for the tundra, the next closest one takes over.
+    assert_eq!(select_biome(&biomes, 10.0, 40.0, 0), Some(1));
+
+    assert_eq!(select_biome(&[], 50.0, 50.0, 0), None);
+    assert_eq!(select_biome(&biomes[2..3], 50.0, 50.0, 0), None);
+  }
+}
@@ -0,0 +1,325 @@
+use glam::{ivec3, IVec3};
+use mlua::{Lua, Table, Value};
+use rand::{rngs::StdRng, Rng};
+
+use crate::game::{
+  lua_engine::{
+    lua_table_helpers::{get_field, get_field_or, get_string_list},
+    module_loader::get_mod_file_path,
+  },
+  map::{
+    block_registry::{BlockRegistry, AIR_ID},
+    chunk::{Chunk, Node, CHUNK_SIZE},
+    schematic::{Schematic, SchematicPlacement},
+  },
+};
+
+use super::biome::Biome;
+
+///
+/// What a decoration actually places.
+///
+#[derive(Clone, Debug)]
+pub enum DecorationType {
+  Simple {
+    decoration: Vec<u16>,
+    height: i32,
+    height_max: i32,
+  },
+  Schematic {
+    schematic: Schematic,
+    placement: SchematicPlacement,
+  },
+}
+
+///
+/// A decoration from minetest.register_decoration, with every block
+/// name resolved into a BlockRegistry ID.
+///
+/// Decorations are placed on top of surface nodes after terrain and ores.
+///
+#[derive(Clone, Debug)]
+pub struct Decoration {
+  pub deco_type: DecorationType,
+  pub place_on: Vec<u16>,
+  pub fill_ratio: f32,
+  pub biomes: Vec<usize>,
+  pub y_min: i32,
+  pub y_max: i32,
+}
+
+impl Decoration {
+  ///
+  /// Parse and resolve a Decoration out of a Lua table.
+  ///
+  /// Biome names are resolved into indices of the biomes slice.
+  /// Schematic files are read out of the folder of the mod which registered the decoration.
+  ///
+  pub fn from_lua_table(
+    lua: &Lua,
+    table: &Table,
+    block_registry: &BlockRegistry,
+    biomes: &[Biome],
+    index: usize,
+  ) -> Result<Self, String> {
+    let requester = format!("decoration [{}]", index);
+
+    let place_on_names = get_string_list(table, "place_on")?;
+    if place_on_names.is_empty() {
+      return Err(format!("{} has no [place_on] blocks.", requester));
+    }
+    let mut place_on = vec![];
+    for name in place_on_names {
+      place_on.push(block_registry.require_id(&name, &requester)?);
+    }
+
+    let mut biome_indices = vec![];
+    for biome_name in get_string_list(table, "biomes")? {
+      match biomes.iter().position(|biome| biome.name == biome_name) {
+        Some(biome_index) => biome_indices.push(biome_index),
+        None => {
+          return Err(format!(
+            "{} references unknown biome [{}].",
+            requester, biome_name
+          ))
+        }
+      }
+    }
+
+    let deco_type_name: String = get_field(table, "deco_type")?;
+    let deco_type = match deco_type_name.as_str() {
+      "simple" => {
+        let mut decoration = vec![];
+        for name in get_string_list(table, "decoration")? {
+          decoration.push(block_registry.require_id(&name, &requester)?);
+        }
+        if decoration.is_empty() {
+          return Err(format!("{} has no [decoration] blocks.", requester));
+        }
+
+        let height: i32 = get_field_or(table, "height", 1)?;
+        let height_max: i32 = get_field_or(table, "height_max", height)?;
+
+        DecorationType::Simple {
+          decoration,
+          height: height.max(1),
+          height_max: height_max.max(height.max(1)),
+        }
+      }
+      "schematic" => {
+        // Either a schematic file or a schematic table.
+        let schematic = match get_field(table, "schematic")? {
+          Value::String(file) => {
+            let mod_name: String = get_field(table, "schematic_mod")?;
+            let path = get_mod_file_path(lua, &mod_name, &file.to_string_lossy())
+              .map_err(|e| format!("{} {}", requester, e))?;
+            Schematic::load_from_file(&path, block_registry)
+              .map_err(|e| format!("{} {}", requester, e))?
+          }
+          Value::Table(schematic_table) => {
+            Schematic::from_lua_table(&schematic_table, block_registry, &requester)?
+          }
+          other => {
+            return Err(format!(
+              "{} schematic must be a file path or a table, got [{}].",
+              requester,
+              other.type_name()
+            ))
+          }
+        };
+
+        let placement = SchematicPlacement::from_lua(
+          get_field_or(table, "rotation", None)?,
+          get_field_or(table, "replacements", None)?,
+          get_field_or(table, "force_placement", false)?,
+          get_field_or(table, "flags", None)?,
+          block_registry,
+          &requester,
+        )
+        .map_err(|e| format!("{} {}", requester, e))?;
+
+        DecorationType::Schematic {
+          schematic,
+          placement,
+        }
+      }
+      _ => {
+        return Err(format!(
+          "{} has unknown deco_type [{}].",
+          requester, deco_type_name
+        ))
+      }
+    };
+
+    Ok(Decoration {
+      deco_type,
+      place_on,
+      fill_ratio: get_field_or(table, "fill_ratio", 0.02)?,
+      biomes: biome_indices,
+      y_min: get_field_or(table, "y_min", -31000)?,
+      y_max: get_field_or(table, "y_max", 31000)?,
+    })
+  }
+
+  ///
+  /// Place this decoration into a Chunk.
+  ///
+  /// column_biomes is the biome index of each X/Z column. (X + Z * CHUNK_SIZE)
+  ///
+  /// ! Decorations are clipped to the Chunk they are generated in.
+  ///
+  pub fn generate(&self, chunk: &mut Chunk, column_biomes: &[Option<usize>], rng: &mut StdRng) {
+    let origin = chunk.get_world_origin();
+
+    for x in 0..CHUNK_SIZE {
+      for z in 0..CHUNK_SIZE {
+        // Roll the dice first so every column eats the same amount of rng.
+        // This keeps generation stable when biomes change.
+        if rng.gen::<f32>() >= self.fill_ratio {
+          continue;
+        }
+
+        if !self.biomes.is_empty() {
+          match column_biomes[(x + z * CHUNK_SIZE) as usize] {
+            Some(biome) if self.biomes.contains(&biome) => (),
+            _ => continue,
+          }
+        }
+
+        // Find the top surface node in this column which has air above it.
+        let surface = (0..CHUNK_SIZE - 1).rev().find(|y| {
+          self
+            .place_on
+            .contains(&chunk.get_node(ivec3(x, *y, z)).block_id)
+            && chunk.get_node(ivec3(x, *y + 1, z)).block_id == AIR_ID
+        });
+
+        let surface_y = match surface {
+          Some(y) => y,
+          None => continue,
+        };
+
+        let world_y = origin.y + surface_y + 1;
+        if world_y < self.y_min || world_y > self.y_max {
+          continue;
+        }
+
+        self.place(chunk, ivec3(x, surface_y + 1, z), rng);
+      }
+    }
+  }
+
+  fn place(&self, chunk: &mut Chunk, base: IVec3, rng: &mut StdRng) {
+    match &self.deco_type {
+      DecorationType::Simple {
+        decoration,
+        height,
+        height_max,
+      } => {
+        let block_id = decoration[rng.gen_range(0..decoration.len())];
+        let height = rng.gen_range(*height..=*height_max);
+
+        for y in 0..height {
+          let position = base + ivec3(0, y, 0);
+          if !Chunk::is_in_bounds(position) || chunk.get_node(position).block_id != AIR_ID {
+            break;
+          }
+          chunk.set_node(position, Node::new(block_id, 0));
+        }
+      }
+      DecorationType::Schematic {
+        schematic,
+        placement,
+      } => {
+        schematic.place(
+          base,
+          placement,
+          rng,
+          |position, node, force_place, node_meta| {
+            if !Chunk::is_in_bounds(position) {
+              return;
+            }
+            if !force_place && chunk.get_node(position).block_id != AIR_ID {
+              return;
+            }
+
+            chunk.set_node(position, node);
+            if let Some(node_meta) = node_meta {
+              *chunk.get_node_meta_mut(position) = node_meta.clone();
+            }
+          },
+        );
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, IVec3};
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::{Decoration, DecorationType};
+  use crate::game::map::{
+    block_registry::AIR_ID,
+    chunk::{Chunk, Node, CHUNK_SIZE},
+  };
+
+  const STONE: u16 = 1;
+  const GRASS: u16 = 2;
+  const FLOWER: u16 = 3;
+
+  #[test]
+  fn simple_decorations_grow_on_place_on_in_their_biome() {
+    // Grass on top of stone at y = 4, except stone reaches the surface where x = 0.
+    let mut chunk = Chunk::new(IVec3::ZERO);
+    for x in 0..CHUNK_SIZE {
+      for z in 0..CHUNK_SIZE {
+        for y in 0..4 {
+          chunk.set_node(ivec3(x, y, z), Node::new(STONE, 0));
+        }
+        let top = if x == 0 { STONE } else { GRASS };
+        chunk.set_node(ivec3(x, 4, z), Node::new(top, 0));
+      }
+    }
+
+    // Biome 0 on the z < 8 half, biome 1 on the other.
+    let column_biomes: Vec<Option<usize>> = (0..CHUNK_SIZE * CHUNK_SIZE)
+      .map(|index| Some(if index / CHUNK_SIZE < 8 { 0 } else { 1 }))
+      .collect();
+
+    let decoration = Decoration {
+      deco_type: DecorationType::Simple {
+        decoration: vec![FLOWER],
+        height: 2,
+        height_max: 2,
+      },
+      place_on: vec![GRASS],
+      fill_ratio: 1.0,
+      biomes: vec![0],
+      y_min: -31000,
+      y_max: 31000,
+    };
+    decoration.generate(&mut chunk, &column_biomes, &mut StdRng::seed_from_u64(0));
+
+    let get_block_id = |x, y, z| chunk.get_node(ivec3(x, y, z)).block_id;
+    assert_eq!(get_block_id(3, 5, 3), FLOWER);
+    assert_eq!(get_block_id(3, 6, 3), FLOWER);
+    assert_eq!(get_block_id(3, 7, 3), AIR_ID);
+    // Wrong biome.
+    assert_eq!(get_block_id(3, 5, 12), AIR_ID);
+    // Not on place_on.
+    assert_eq!(get_block_id(0, 5, 3), AIR_ID);
+
+    // Above y_max nothing grows.
+    let mut chunk = Chunk::new(IVec3::ZERO);
+    chunk.set_node(ivec3(3, 4, 3), Node::new(GRASS, 0));
+    let decoration = Decoration {
+      biomes: vec![],
+      y_max: 4,
+      ..decoration
+    };
+    decoration.generate(&mut chunk, &column_biomes, &mut StdRng::seed_from_u64(0));
+    assert_eq!(chunk.get_node(ivec3(3, 5, 3)).block_id, AIR_ID);
+  }
+}
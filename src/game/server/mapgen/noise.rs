@@ -0,0 +1,207 @@
+use glam::Vec3;
+use mlua::Table;
+
+use crate::game::lua_engine::lua_table_helpers::{get_field, get_field_or};
+
+///
+/// The exact same layout as NoiseParams in C++ minetest.
+///
+/// * offset      - Added onto the final result.
+/// * scale       - Multiplied onto the summed octaves.
+/// * spread      - How many nodes one wave of the first octave covers.
+/// * seed        - Added onto the world seed so each noise is unique.
+/// * octaves     - How many layers of detail to sum.
+/// * persistence - How much each octave's amplitude shrinks by.
+/// * lacunarity  - How much each octave's frequency grows by.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct NoiseParams {
+  pub offset: f32,
+  pub scale: f32,
+  pub spread: Vec3,
+  pub seed: i32,
+  pub octaves: u32,
+  pub persistence: f32,
+  pub lacunarity: f32,
+}
+
+impl NoiseParams {
+  pub fn new(
+    offset: f32,
+    scale: f32,
+    spread: Vec3,
+    seed: i32,
+    octaves: u32,
+    persistence: f32,
+  ) -> Self {
+    NoiseParams {
+      offset,
+      scale,
+      spread,
+      seed,
+      octaves,
+      persistence,
+      lacunarity: 2.0,
+    }
+  }
+
+  ///
+  /// Parse NoiseParams out of a Lua table.
+  ///
+  /// Spread is a {x = number, y = number, z = number} table.
+  ///
+  pub fn from_lua_table(table: &Table) -> Result<Self, String> {
+    let spread_table: Table = get_field(table, "spread")?;
+
+    let spread = Vec3::new(
+      get_field(&spread_table, "x")?,
+      get_field(&spread_table, "y")?,
+      get_field(&spread_table, "z")?,
+    );
+
+    if spread.cmple(Vec3::ZERO).any() {
+      return Err("noise spread must be greater than 0 on all axes.".to_string());
+    }
+
+    let octaves: u32 = get_field_or(table, "octaves", 1)?;
+    if octaves == 0 {
+      return Err("noise octaves must be at least 1.".to_string());
+    }
+
+    Ok(NoiseParams {
+      offset: get_field_or(table, "offset", 0.0)?,
+      scale: get_field_or(table, "scale", 1.0)?,
+      spread,
+      seed: get_field_or(table, "seed", 0)?,
+      octaves,
+      persistence: get_field_or(table, "persistence", 0.5)?,
+      lacunarity: get_field_or(table, "lacunarity", 2.0)?,
+    })
+  }
+}
+
+///
+/// Fractal value noise. This is what C++ minetest uses for mapgen.
+///
+/// Noise is completely stateless, so it can be shared between threads.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct Noise {
+  params: NoiseParams,
+  seed: i32,
+}
+
+impl Noise {
+  ///
+  /// The world seed is mixed in with the NoiseParams seed.
+  ///
+  pub fn new(params: NoiseParams, world_seed: u64) -> Self {
+    Noise {
+      params,
+      seed: params.seed.wrapping_add(world_seed as i32),
+    }
+  }
+
+  ///
+  /// Sample the noise in 2D. Uses the X and Z spread.
+  ///
+  pub fn get_2d(&self, x: f32, z: f32) -> f32 {
+    let mut frequency = 1.0;
+    let mut amplitude = 1.0;
+    let mut result = 0.0;
+
+    for octave in 0..self.params.octaves {
+      result += amplitude
+        * value_noise_2d(
+          x / self.params.spread.x * frequency,
+          z / self.params.spread.z * frequency,
+          self.seed.wrapping_add(octave as i32),
+        );
+      frequency *= self.params.lacunarity;
+      amplitude *= self.params.persistence;
+    }
+
+    self.params.offset + self.params.scale * result
+  }
+
+  ///
+  /// Sample the noise in 3D.
+  ///
+  pub fn get_3d(&self, x: f32, y: f32, z: f32) -> f32 {
+    let mut frequency = 1.0;
+    let mut amplitude = 1.0;
+    let mut result = 0.0;
+
+    for octave in 0..self.params.octaves {
+      result += amplitude
+        * value_noise_3d(
+          x / self.params.spread.x * frequency,
+          y / self.params.spread.y * frequency,
+          z / self.params.spread.z * frequency,
+          self.seed.wrapping_add(octave as i32),
+        );
+      frequency *= self.params.lacunarity;
+      amplitude *= self.params.persistence;
+    }
+
+    self.params.offset + self.params.scale * result
+  }
+}
+
+///
+/// Integer hash into the range of -1.0 to 1.0.
+///
+fn hash_3d(x: i32, y: i32, z: i32, seed: i32) -> f32 {
+  let mut n = x
+    .wrapping_mul(1619)
+    .wrapping_add(y.wrapping_mul(31337))
+    .wrapping_add(z.wrapping_mul(52591))
+    .wrapping_add(seed.wrapping_mul(1013))
+    & 0x7fffffff;
+  n = (n >> 13) ^ n;
+  let hashed = n
+    .wrapping_mul(n.wrapping_mul(n).wrapping_mul(60493).wrapping_add(19990303))
+    .wrapping_add(1376312589)
+    & 0x7fffffff;
+  1.0 - (hashed as f32 / 1073741824.0)
+}
+
+///
+/// Smooth step so the lattice isn't visible.
+///
+fn ease_curve(t: f32) -> f32 {
+  t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+  a + (b - a) * t
+}
+
+fn value_noise_2d(x: f32, z: f32, seed: i32) -> f32 {
+  value_noise_3d(x, 0.0, z, seed)
+}
+
+fn value_noise_3d(x: f32, y: f32, z: f32, seed: i32) -> f32 {
+  let x0 = x.floor();
+  let y0 = y.floor();
+  let z0 = z.floor();
+
+  let tx = ease_curve(x - x0);
+  let ty = ease_curve(y - y0);
+  let tz = ease_curve(z - z0);
+
+  let (x0, y0, z0) = (x0 as i32, y0 as i32, z0 as i32);
+
+  let corner = |dx: i32, dy: i32, dz: i32| hash_3d(x0 + dx, y0 + dy, z0 + dz, seed);
+
+  let bottom_front = lerp(corner(0, 0, 0), corner(1, 0, 0), tx);
+  let bottom_back = lerp(corner(0, 0, 1), corner(1, 0, 1), tx);
+  let top_front = lerp(corner(0, 1, 0), corner(1, 1, 0), tx);
+  let top_back = lerp(corner(0, 1, 1), corner(1, 1, 1), tx);
+
+  lerp(
+    lerp(bottom_front, bottom_back, tz),
+    lerp(top_front, top_back, tz),
+    ty,
+  )
+}
@@ -0,0 +1,365 @@
+use glam::{ivec3, IVec3};
+use mlua::Table;
+use rand::{rngs::StdRng, Rng};
+
+use crate::game::{
+  lua_engine::lua_table_helpers::{get_field, get_field_or, get_string_list},
+  map::{
+    block_registry::BlockRegistry,
+    chunk::{Chunk, Node, CHUNK_SIZE, CHUNK_VOLUME},
+  },
+};
+
+use super::noise::{Noise, NoiseParams};
+
+///
+/// How an ore is distributed through the ground.
+///
+/// * Scatter - Small random clusters. (coal, iron)
+/// * Blob    - Noise warped spheres. (clay, gravel)
+/// * Vein    - Long snaking tunnels where two noises cross zero.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OreType {
+  Scatter,
+  Blob,
+  Vein,
+}
+
+impl OreType {
+  pub fn from_name(ore_type: &str) -> Result<Self, String> {
+    match ore_type {
+      "scatter" => Ok(OreType::Scatter),
+      "blob" => Ok(OreType::Blob),
+      "vein" => Ok(OreType::Vein),
+      _ => Err(format!("unknown ore_type [{}]", ore_type)),
+    }
+  }
+}
+
+///
+/// An ore from minetest.register_ore, with every block
+/// name resolved into a BlockRegistry ID.
+///
+#[derive(Clone, Debug)]
+pub struct Ore {
+  pub ore_type: OreType,
+  pub ore: u16,
+  pub wherein: Vec<u16>,
+  pub clust_scarcity: u32,
+  pub clust_num_ores: u32,
+  pub clust_size: i32,
+  pub y_min: i32,
+  pub y_max: i32,
+  pub noise: Option<Noise>,
+  pub noise_secondary: Option<Noise>,
+  pub noise_threshold: f32,
+}
+
+impl Ore {
+  ///
+  /// Parse and resolve an Ore out of a Lua table.
+  ///
+  pub fn from_lua_table(
+    table: &Table,
+    block_registry: &BlockRegistry,
+    world_seed: u64,
+  ) -> Result<Self, String> {
+    let ore_name: String = get_field(table, "ore")?;
+    let requester = format!("ore [{}]", ore_name);
+
+    let ore_type = OreType::from_name(&get_field::<String>(table, "ore_type")?)
+      .map_err(|e| format!("{} {}", requester, e))?;
+
+    let ore = block_registry.require_id(&ore_name, &requester)?;
+
+    let wherein_names = get_string_list(table, "wherein")?;
+    if wherein_names.is_empty() {
+      return Err(format!("{} has no [wherein] blocks.", requester));
+    }
+    let mut wherein = vec![];
+    for name in wherein_names {
+      wherein.push(block_registry.require_id(&name, &requester)?);
+    }
+
+    let noise_params = match get_field_or::<Option<Table>>(table, "noise_params", None)? {
+      Some(noise_table) => {
+        Some(NoiseParams::from_lua_table(&noise_table).map_err(|e| format!("{} {}", requester, e))?)
+      }
+      None => None,
+    };
+
+    if ore_type == OreType::Vein && noise_params.is_none() {
+      return Err(format!(
+        "{} is a vein but has no [noise_params].",
+        requester
+      ));
+    }
+
+    // Veins need a second noise to cross with. It's the same noise shifted over.
+    let noise_secondary = match (ore_type, noise_params) {
+      (OreType::Vein, Some(params)) => {
+        let mut secondary_params = params;
+        secondary_params.seed = secondary_params.seed.wrapping_add(1);
+        Some(Noise::new(secondary_params, world_seed))
+      }
+      _ => None,
+    };
+
+    let default_threshold = match ore_type {
+      OreType::Vein => 0.1,
+      _ => 0.0,
+    };
+
+    let clust_scarcity: u32 = get_field_or(table, "clust_scarcity", 8 * 8 * 8)?;
+    if clust_scarcity == 0 {
+      return Err(format!("{} clust_scarcity must be at least 1.", requester));
+    }
+
+    Ok(Ore {
+      ore_type,
+      ore,
+      wherein,
+      clust_scarcity,
+      clust_num_ores: get_field_or(table, "clust_num_ores", 8)?,
+      clust_size: get_field_or::<i32>(table, "clust_size", 3)?.clamp(1, CHUNK_SIZE),
+      y_min: get_field_or(table, "y_min", -31000)?,
+      y_max: get_field_or(table, "y_max", 31000)?,
+      noise: noise_params.map(|params| Noise::new(params, world_seed)),
+      noise_secondary,
+      noise_threshold: get_field_or(table, "noise_threshold", default_threshold)?,
+    })
+  }
+
+  ///
+  /// Place this ore into a Chunk.
+  ///
+  /// The rng is seeded per chunk so this is deterministic.
+  ///
+  pub fn generate(&self, chunk: &mut Chunk, rng: &mut StdRng) {
+    let origin = chunk.get_world_origin();
+
+    // Completely out of range, don't waste time.
+    if origin.y + CHUNK_SIZE - 1 < self.y_min || origin.y > self.y_max {
+      return;
+    }
+
+    match self.ore_type {
+      OreType::Scatter => self.generate_scatter(chunk, rng),
+      OreType::Blob => self.generate_blob(chunk, rng),
+      OreType::Vein => self.generate_vein(chunk),
+    }
+  }
+
+  ///
+  /// Replace the node if it's a wherein block and in the y range.
+  ///
+  fn try_place(&self, chunk: &mut Chunk, local_position: IVec3) {
+    if !Chunk::is_in_bounds(local_position) {
+      return;
+    }
+
+    let y = chunk.get_world_origin().y + local_position.y;
+    if y < self.y_min || y > self.y_max {
+      return;
+    }
+
+    if self
+      .wherein
+      .contains(&chunk.get_node(local_position).block_id)
+    {
+      chunk.set_node(local_position, Node::new(self.ore, 0));
+    }
+  }
+
+  ///
+  /// How many clusters go into this chunk.
+  ///
+  /// Scarcity is "1 cluster per this many nodes". When that's bigger than a chunk
+  /// the leftover fraction becomes a chance.
+  ///
+  fn get_cluster_count(&self, rng: &mut StdRng) -> u32 {
+    let clusters = CHUNK_VOLUME as f32 / self.clust_scarcity as f32;
+    let mut count = clusters.floor() as u32;
+    if rng.gen::<f32>() < clusters.fract() {
+      count += 1;
+    }
+    count
+  }
+
+  ///
+  /// Get a random cluster corner where the whole cluster fits into the chunk.
+  ///
+  fn get_cluster_corner(&self, rng: &mut StdRng) -> IVec3 {
+    let max = CHUNK_SIZE - self.clust_size;
+    ivec3(
+      rng.gen_range(0..=max),
+      rng.gen_range(0..=max),
+      rng.gen_range(0..=max),
+    )
+  }
+
+  fn generate_scatter(&self, chunk: &mut Chunk, rng: &mut StdRng) {
+    for _ in 0..self.get_cluster_count(rng) {
+      let corner = self.get_cluster_corner(rng);
+
+      for _ in 0..self.clust_num_ores {
+        let offset = ivec3(
+          rng.gen_range(0..self.clust_size),
+          rng.gen_range(0..self.clust_size),
+          rng.gen_range(0..self.clust_size),
+        );
+        self.try_place(chunk, corner + offset);
+      }
+    }
+  }
+
+  fn generate_blob(&self, chunk: &mut Chunk, rng: &mut StdRng) {
+    let origin = chunk.get_world_origin();
+    let radius = self.clust_size as f32 / 2.0;
+
+    for _ in 0..self.get_cluster_count(rng) {
+      let corner = self.get_cluster_corner(rng);
+      let center = corner.as_vec3() + radius;
+
+      for x in 0..self.clust_size {
+        for y in 0..self.clust_size {
+          for z in 0..self.clust_size {
+            let local_position = corner + ivec3(x, y, z);
+            let distance = (local_position.as_vec3() + 0.5 - center) / radius;
+
+            // Noise warps the sphere into a blob.
+            let warp = match &self.noise {
+              Some(noise) => {
+                let world = (origin + local_position).as_vec3();
+                noise.get_3d(world.x, world.y, world.z) * 0.5
+              }
+              None => 0.0,
+            };
+
+            if distance.length_squared() <= 1.0 + warp {
+              self.try_place(chunk, local_position);
+            }
+          }
+        }
+      }
+    }
+  }
+
+  fn generate_vein(&self, chunk: &mut Chunk) {
+    let (noise, noise_secondary) = match (&self.noise, &self.noise_secondary) {
+      (Some(noise), Some(noise_secondary)) => (noise, noise_secondary),
+      _ => return,
+    };
+
+    let origin = chunk.get_world_origin();
+
+    for x in 0..CHUNK_SIZE {
+      for y in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+          let local_position = ivec3(x, y, z);
+          let world = (origin + local_position).as_vec3();
+
+          if noise.get_3d(world.x, world.y, world.z).abs() >= self.noise_threshold {
+            continue;
+          }
+          if noise_secondary.get_3d(world.x, world.y, world.z).abs() >= self.noise_threshold {
+            continue;
+          }
+
+          self.try_place(chunk, local_position);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, IVec3};
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::{Ore, OreType};
+  use crate::game::map::chunk::{Chunk, Node, CHUNK_SIZE};
+
+  const STONE: u16 = 1;
+  const DIRT: u16 = 2;
+  const COAL: u16 = 3;
+
+  ///
+  /// Stone on the bottom half, dirt on the top half.
+  ///
+  fn test_chunk() -> Chunk {
+    let mut chunk = Chunk::new(IVec3::ZERO);
+    for x in 0..CHUNK_SIZE {
+      for y in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+          let block_id = if y < CHUNK_SIZE / 2 { STONE } else { DIRT };
+          chunk.set_node(ivec3(x, y, z), Node::new(block_id, 0));
+        }
+      }
+    }
+    chunk
+  }
+
+  fn get_ore_positions(chunk: &Chunk) -> Vec<IVec3> {
+    let mut positions = vec![];
+    for x in 0..CHUNK_SIZE {
+      for y in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+          if chunk.get_node(ivec3(x, y, z)).block_id == COAL {
+            positions.push(ivec3(x, y, z));
+          }
+        }
+      }
+    }
+    positions
+  }
+
+  #[test]
+  fn scatter_ores_stay_in_wherein_and_range() {
+    let ore = Ore {
+      ore_type: OreType::Scatter,
+      ore: COAL,
+      wherein: vec![STONE, DIRT],
+      clust_scarcity: 64,
+      clust_num_ores: 8,
+      clust_size: 3,
+      y_min: -31000,
+      y_max: 5,
+      noise: None,
+      noise_secondary: None,
+      noise_threshold: 0.0,
+    };
+
+    let mut chunk = test_chunk();
+    ore.generate(&mut chunk, &mut StdRng::seed_from_u64(7));
+    let positions = get_ore_positions(&chunk);
+    assert!(!positions.is_empty());
+    assert!(positions.iter().all(|position| position.y <= 5));
+
+    // The same seed places the same ore.
+    let mut again = test_chunk();
+    ore.generate(&mut again, &mut StdRng::seed_from_u64(7));
+    assert_eq!(get_ore_positions(&again), positions);
+
+    // Dirt isn't wherein anymore, so only the stone half gets any.
+    let ore = Ore {
+      wherein: vec![STONE],
+      y_max: 31000,
+      ..ore
+    };
+    let mut chunk = test_chunk();
+    ore.generate(&mut chunk, &mut StdRng::seed_from_u64(7));
+    let positions = get_ore_positions(&chunk);
+    assert!(!positions.is_empty());
+    assert!(positions.iter().all(|position| position.y < CHUNK_SIZE / 2));
+
+    // A chunk completely out of the y range is left alone.
+    let mut high_chunk = Chunk::new(ivec3(0, 10, 0));
+    high_chunk.fill(Node::new(STONE, 0));
+    let ore = Ore { y_max: 100, ..ore };
+    ore.generate(&mut high_chunk, &mut StdRng::seed_from_u64(7));
+    assert!(get_ore_positions(&high_chunk).is_empty());
+  }
+}
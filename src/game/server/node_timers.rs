@@ -0,0 +1,267 @@
+use std::{
+  cell::RefCell,
+  time::{Duration, Instant},
+};
+
+use ahash::{AHashMap, AHashSet};
+use glam::IVec3;
+use mlua::{Function, Lua, RegistryKey, Table};
+
+use crate::game::{
+  lua_engine::lua_table_helpers::node_position_to_vector,
+  map::{block_registry::BlockRegistry, chunk::chunk_to_world_position, node_timer::NodeTimer},
+};
+
+use super::{
+  rollback::{call_as_mod, NODE_TIMER_ACTOR},
+  server_environment::ServerEnvironment,
+};
+
+///
+/// Ticks the NodeTimers in the active Chunks and runs on_timer when they go off.
+///
+/// Expired timers that don't fit into the time budget stay in the Map and
+/// get picked up on the next tick. The most overdue timers always go first,
+/// so nothing gets starved.
+///
+pub struct NodeTimerRunner {
+  // Block ID -> the block's on_timer callback.
+  on_timer: AHashMap<u16, RegistryKey>,
+}
+
+impl NodeTimerRunner {
+  pub fn new() -> Self {
+    NodeTimerRunner {
+      on_timer: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Pick the on_timer callbacks out of the _G.blocks table in a LuaEngine.
+  ///
+  pub fn from_lua_table(
+    lua: &Lua,
+    blocks: &Table,
+    block_registry: &BlockRegistry,
+  ) -> Result<Self, String> {
+    let mut new_runner = NodeTimerRunner::new();
+
+    for pair in blocks.clone().pairs::<String, Table>() {
+      let (name, definition) =
+        pair.map_err(|e| format!("NodeTimerRunner: malformed block table. {}", e))?;
+
+      let on_timer = match definition.get::<_, Option<Function>>("on_timer") {
+        Ok(Some(on_timer)) => on_timer,
+        Ok(None) => continue,
+        Err(e) => {
+          return Err(format!(
+            "NodeTimerRunner: block [{}] on_timer must be a function. {}",
+            name, e
+          ))
+        }
+      };
+
+      let block_id = block_registry.require_id(&name, "NodeTimerRunner")?;
+      let on_timer = match lua.create_registry_value(on_timer) {
+        Ok(on_timer) => on_timer,
+        Err(e) => {
+          return Err(format!(
+            "NodeTimerRunner: block [{}] failed to store on_timer. {}",
+            name, e
+          ))
+        }
+      };
+
+      new_runner.on_timer.insert(block_id, on_timer);
+    }
+
+    Ok(new_runner)
+  }
+
+  ///
+  /// Move the NodeTimers forward and run as many expired ones as fit into the budget.
+  ///
+  /// At least one timer is always run so they keep moving.
+  ///
+  /// Signature: on_timer(pos, elapsed) -> boolean
+  /// Returning true starts the timer over with the same timeout.
+  ///
+  pub fn on_tick(
+    &mut self,
+    delta: f64,
+    lua: &Lua,
+    environment: &RefCell<ServerEnvironment>,
+    active_chunks: &AHashSet<IVec3>,
+    budget: Duration,
+  ) {
+    let deadline = Instant::now() + budget;
+
+    let mut expired: Vec<(IVec3, NodeTimer)> = vec![];
+    {
+      let mut environment = environment.borrow_mut();
+      let map = environment.get_map_mut();
+      for chunk_position in active_chunks {
+        if let Some(chunk) = map.get_chunk_mut(*chunk_position) {
+          let origin = chunk_to_world_position(*chunk_position);
+          for (local_position, node_timer) in chunk.step_node_timers(delta as f32) {
+            expired.push((origin + local_position, node_timer));
+          }
+        }
+      }
+    }
+
+    // Most overdue first.
+    expired.sort_by(|a, b| b.1.get_overdue().total_cmp(&a.1.get_overdue()));
+
+    for (position, node_timer) in expired {
+      // The timer goes away before on_timer runs, so on_timer can start a new one.
+      let block_id = {
+        let mut environment = environment.borrow_mut();
+        environment.get_map_mut().remove_node_timer(position);
+        match environment.get_node(position) {
+          Some(node) => node.block_id,
+          None => continue,
+        }
+      };
+
+      // Blocks without on_timer simply lose the timer.
+      if let Some(on_timer) = self.on_timer.get(&block_id) {
+        let restart =
+          match Self::run_on_timer(lua, environment, on_timer, position, node_timer.elapsed) {
+            Ok(restart) => restart,
+            Err(e) => panic!("NodeTimerRunner: on_timer at [{}] failed. {}", position, e),
+          };
+
+        if restart {
+          environment
+            .borrow_mut()
+            .get_map_mut()
+            .set_node_timer(position, NodeTimer::new(node_timer.timeout, 0.0));
+        }
+      }
+
+      if Instant::now() >= deadline {
+        break;
+      }
+    }
+  }
+
+  fn run_on_timer(
+    lua: &Lua,
+    environment: &RefCell<ServerEnvironment>,
+    on_timer: &RegistryKey,
+    position: IVec3,
+    elapsed: f32,
+  ) -> mlua::Result<bool> {
+    let on_timer: Function = lua.registry_value(on_timer)?;
+    let args = (node_position_to_vector(lua, position)?, elapsed);
+
+    // A broken on_timer doesn't get restarted.
+    let context = format!("on_timer at [{}]", position);
+    let restart = call_as_mod::<_, Option<bool>>(
+      lua,
+      environment,
+      &on_timer,
+      NODE_TIMER_ACTOR,
+      &context,
+      args,
+    );
+    Ok(restart.flatten() == Some(true))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, time::Duration};
+
+  use ahash::AHashSet;
+  use glam::{ivec3, IVec3};
+  use mlua::{Lua, Table};
+
+  use super::NodeTimerRunner;
+  use crate::game::{
+    map::{
+      block_registry::{BlockDefinition, BlockRegistry},
+      chunk::{Chunk, Node},
+      node_timer::NodeTimer,
+    },
+    server::server_environment::ServerEnvironment,
+  };
+
+  #[test]
+  fn most_overdue_timers_go_first() {
+    let mut block_registry = BlockRegistry::new();
+    let mut register = |name: &str| match block_registry.register_block(BlockDefinition::test(name))
+    {
+      Ok(block_id) => Node::new(block_id, 0),
+      Err(e) => panic!("{}", e),
+    };
+    let clock = register("test:clock");
+    let stone = register("test:stone");
+
+    // Only the clock at x = 1 restarts itself.
+    let lua = Lua::new();
+    let blocks: Table = match lua
+      .load(
+        r#"
+          calls = ""
+          return {
+            ["test:clock"] = {
+              on_timer = function(pos, elapsed)
+                calls = calls .. pos.x .. "@" .. elapsed .. " "
+                return pos.x == 1
+              end,
+            },
+            ["test:stone"] = {},
+          }
+        "#,
+      )
+      .eval()
+    {
+      Ok(blocks) => blocks,
+      Err(e) => panic!("{}", e),
+    };
+    let mut node_timer_runner =
+      match NodeTimerRunner::from_lua_table(&lua, &blocks, &block_registry) {
+        Ok(node_timer_runner) => node_timer_runner,
+        Err(e) => panic!("{}", e),
+      };
+
+    let mut environment = ServerEnvironment::new();
+    environment.set_block_registry(block_registry);
+    environment.add_emerged_chunk(Chunk::new(IVec3::ZERO), true);
+    for (x, node, elapsed) in [(1, clock, 0.0), (2, clock, 0.5), (3, stone, 0.0)] {
+      environment.set_node(ivec3(x, 0, 0), node);
+      environment
+        .get_map_mut()
+        .set_node_timer(ivec3(x, 0, 0), NodeTimer::new(1.0, elapsed));
+    }
+    let environment = RefCell::new(environment);
+    let active_chunks = AHashSet::from([IVec3::ZERO]);
+
+    // No budget runs only the most overdue timer, the rest wait.
+    node_timer_runner.on_tick(1.0, &lua, &environment, &active_chunks, Duration::ZERO);
+    node_timer_runner.on_tick(
+      0.5,
+      &lua,
+      &environment,
+      &active_chunks,
+      Duration::from_secs(10),
+    );
+
+    let calls: String = match lua.load("return calls").eval() {
+      Ok(calls) => calls,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(calls, "2@1.5 1@1.5 ");
+
+    let environment = environment.borrow();
+    let map = environment.get_map();
+    assert_eq!(
+      map.get_node_timer(ivec3(1, 0, 0)),
+      Some(NodeTimer::new(1.0, 0.0))
+    );
+    assert_eq!(map.get_node_timer(ivec3(2, 0, 0)), None);
+    assert_eq!(map.get_node_timer(ivec3(3, 0, 0)), None);
+  }
+}
@@ -0,0 +1,155 @@
+// The built-in area protection.
+//
+// Owners claim cuboids of the world. Nobody else can change anything inside of them.
+// The engine only ever asks minetest.is_protected(), so mods are free to swap
+// this out for their own protection entirely.
+
+use std::collections::BTreeMap;
+
+use glam::IVec3;
+
+///
+/// A cuboid of the world which belongs to someone.
+///
+/// min and max are both inclusive.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProtectedArea {
+  pub owner: String,
+  pub min: IVec3,
+  pub max: IVec3,
+}
+
+impl ProtectedArea {
+  pub fn new(owner: &str, pos1: IVec3, pos2: IVec3) -> Self {
+    ProtectedArea {
+      owner: owner.to_string(),
+      min: pos1.min(pos2),
+      max: pos1.max(pos2),
+    }
+  }
+
+  pub fn contains(&self, position: IVec3) -> bool {
+    position.cmpge(self.min).all() && position.cmple(self.max).all()
+  }
+}
+
+///
+/// Every ProtectedArea in a world by ID.
+///
+/// IDs are never reused, so a mod holding onto one can't end up removing someone else's area.
+///
+pub struct AreaProtection {
+  areas: BTreeMap<u32, ProtectedArea>,
+  next_id: u32,
+  modified: bool,
+}
+
+impl AreaProtection {
+  pub fn new() -> Self {
+    AreaProtection {
+      areas: BTreeMap::new(),
+      next_id: 1,
+      modified: false,
+    }
+  }
+
+  ///
+  /// Rebuild the areas that were loaded out of the world database.
+  ///
+  pub fn from_areas(areas: BTreeMap<u32, ProtectedArea>) -> Self {
+    let next_id = areas.keys().next_back().map_or(1, |id| id + 1);
+    AreaProtection {
+      areas,
+      next_id,
+      modified: false,
+    }
+  }
+
+  ///
+  /// Claim a cuboid for an owner. Returns the new area's ID.
+  ///
+  pub fn protect(&mut self, owner: &str, pos1: IVec3, pos2: IVec3) -> u32 {
+    let id = self.next_id;
+    self.next_id += 1;
+    self.areas.insert(id, ProtectedArea::new(owner, pos1, pos2));
+    self.modified = true;
+    id
+  }
+
+  ///
+  /// Remove an area. Returns false if there's no area with that ID.
+  ///
+  pub fn unprotect(&mut self, id: u32) -> bool {
+    let removed = self.areas.remove(&id).is_some();
+    self.modified |= removed;
+    removed
+  }
+
+  pub fn get_areas(&self) -> &BTreeMap<u32, ProtectedArea> {
+    &self.areas
+  }
+
+  ///
+  /// Get every area which covers a position.
+  ///
+  pub fn get_areas_at(&self, position: IVec3) -> Vec<(u32, &ProtectedArea)> {
+    self
+      .areas
+      .iter()
+      .filter(|(_, area)| area.contains(position))
+      .map(|(id, area)| (*id, area))
+      .collect()
+  }
+
+  ///
+  /// If a player is kept out of a position.
+  ///
+  /// Owning any of the areas covering a position is enough to build there.
+  ///
+  pub fn is_protected(&self, position: IVec3, player_name: &str) -> bool {
+    let areas = self.get_areas_at(position);
+    !areas.is_empty() && !areas.iter().any(|(_, area)| area.owner == player_name)
+  }
+
+  ///
+  /// Check if the areas changed since the last call.
+  ///
+  pub fn take_modified(&mut self) -> bool {
+    std::mem::take(&mut self.modified)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::ivec3;
+
+  use super::AreaProtection;
+
+  #[test]
+  fn owners_can_build_in_their_areas() {
+    let mut area_protection = AreaProtection::new();
+    let house = area_protection.protect("alice", ivec3(10, 0, 10), ivec3(0, 5, 0));
+    let shed = area_protection.protect("bob", ivec3(8, 0, 8), ivec3(12, 2, 12));
+    assert!(area_protection.take_modified());
+
+    assert!(!area_protection.is_protected(ivec3(5, 5, 5), "alice"));
+    assert!(area_protection.is_protected(ivec3(5, 5, 5), "bob"));
+    assert!(!area_protection.is_protected(ivec3(5, 6, 5), "bob"));
+
+    // Where they overlap, either owner can build.
+    assert!(!area_protection.is_protected(ivec3(9, 1, 9), "alice"));
+    assert!(!area_protection.is_protected(ivec3(9, 1, 9), "bob"));
+    assert!(area_protection.is_protected(ivec3(9, 1, 9), "eve"));
+    assert_eq!(area_protection.get_areas_at(ivec3(9, 1, 9)).len(), 2);
+
+    assert!(area_protection.unprotect(house));
+    assert!(!area_protection.unprotect(house));
+    assert!(!area_protection.is_protected(ivec3(5, 5, 5), "bob"));
+
+    // IDs don't get handed out twice.
+    let mut rebuilt = AreaProtection::from_areas(area_protection.get_areas().clone());
+    assert!(!rebuilt.is_protected(ivec3(12, 2, 12), "bob"));
+    assert!(rebuilt.protect("carol", ivec3(0, 0, 0), ivec3(0, 0, 0)) > shed);
+  }
+}
@@ -0,0 +1,331 @@
+// The rollback journal.
+//
+// While it's enabled, every node change is recorded with who made it.
+// That gets saved into the rollback table of the world database, so a
+// griefer's changes can be looked up and undone later.
+
+use std::{
+  cell::RefCell,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use glam::IVec3;
+use mlua::{FromLuaMulti, Function, IntoLuaMulti, Lua};
+
+use crate::game::{
+  lua_engine::lua_error::{call_mod_function, get_function_mod_name},
+  map::{block_registry::IGNORE_NAME, chunk::Node},
+};
+
+use super::server_environment::ServerEnvironment;
+
+///
+/// Who made a change when nobody more specific claims it.
+///
+pub const DEFAULT_ACTOR: &str = ":mod";
+
+///
+/// Engine actors start with ":" so they can never clash with a player.
+///
+pub const ABM_ACTOR: &str = ":abm";
+pub const NODE_TIMER_ACTOR: &str = ":node_timer";
+pub const LIQUID_ACTOR: &str = ":liquid";
+pub const FALLING_NODE_ACTOR: &str = ":falling_node";
+pub const CONSOLE_ACTOR: &str = ":console";
+pub const ROLLBACK_ACTOR: &str = ":rollback";
+
+///
+/// The actor name of a player. (player:name)
+///
+pub fn get_player_actor(player_name: &str) -> String {
+  format!("player:{}", player_name)
+}
+
+///
+/// Call a function which a mod handed to the engine, blaming its node changes on that mod.
+///
+/// Mods are blamed by their name. Mod names can't have a colon, so they never clash with anyone else.
+/// engine_actor gets the blame when the function doesn't belong to a mod.
+/// The actor from before gets put back afterward.
+///
+pub fn call_as_mod<'lua, A, R>(
+  lua: &'lua Lua,
+  environment: &RefCell<ServerEnvironment>,
+  function: &Function<'lua>,
+  engine_actor: &str,
+  context: &str,
+  args: A,
+) -> Option<R>
+where
+  A: IntoLuaMulti<'lua>,
+  R: FromLuaMulti<'lua>,
+{
+  let actor = get_function_mod_name(lua, function).unwrap_or_else(|| engine_actor.to_string());
+  let old_actor = environment.borrow().get_actor().to_string();
+
+  environment.borrow_mut().set_actor(&actor);
+  let result = call_mod_function(lua, function, context, args);
+  environment.borrow_mut().set_actor(&old_actor);
+
+  result
+}
+
+///
+/// Seconds since the unix epoch. Node actions are timed with this.
+///
+pub fn get_unix_time() -> i64 {
+  match SystemTime::now().duration_since(UNIX_EPOCH) {
+    Ok(unix_time) => unix_time.as_secs() as i64,
+    Err(e) => panic!("Rollback: the clock is broken. {}", e),
+  }
+}
+
+///
+/// The unix time a rollback of the last few seconds reaches back to.
+///
+pub fn get_rollback_start_time(seconds: u64) -> Result<i64, String> {
+  match i64::try_from(seconds)
+    .ok()
+    .and_then(|seconds| get_unix_time().checked_sub(seconds))
+  {
+    Some(start_time) => Ok(start_time),
+    None => Err(format!(
+      "Rollback: [{}] seconds is further back than time goes.",
+      seconds
+    )),
+  }
+}
+
+///
+/// A node by name, so it still means the same thing after block IDs get reassigned.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RollbackNode {
+  pub name: String,
+  pub param2: u8,
+}
+
+///
+/// A single node change in the rollback journal.
+///
+/// * time     - Unix time of the change in seconds.
+/// * actor    - player:name for players, the mod name for mod callbacks, or an engine actor like :liquid.
+/// * position - Where the change happened.
+/// * old_node - What was there before.
+/// * new_node - What it was changed into.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeAction {
+  pub time: i64,
+  pub actor: String,
+  pub position: IVec3,
+  pub old_node: RollbackNode,
+  pub new_node: RollbackNode,
+}
+
+///
+/// Undo node actions, newest first.
+///
+/// A node only gets put back if it is still what the action changed it into.
+/// That way undoing a griefer doesn't wipe out anyone who fixed things up afterward.
+///
+/// Returns how many nodes were put back.
+///
+pub fn revert_node_actions(environment: &mut ServerEnvironment, actions: &[NodeAction]) -> usize {
+  let mut reverted = 0;
+
+  for action in actions {
+    let current = match environment.get_node(action.position) {
+      Some(current) => current,
+      None => continue,
+    };
+
+    let block_registry = environment.get_block_registry();
+    let current_name = block_registry
+      .get_name(current.block_id)
+      .unwrap_or(IGNORE_NAME);
+    if current_name != action.new_node.name || current.param2 != action.new_node.param2 {
+      continue;
+    }
+
+    let old_block_id = match block_registry.get_id(&action.old_node.name) {
+      Some(old_block_id) => old_block_id,
+      None => {
+        println!(
+          "Rollback: can't put back [{}] at [{}], it isn't registered anymore.",
+          action.old_node.name, action.position
+        );
+        continue;
+      }
+    };
+
+    environment.set_node(
+      action.position,
+      Node::new(old_block_id, action.old_node.param2),
+    );
+    reverted += 1;
+  }
+
+  reverted
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, rc::Rc};
+
+  use glam::{ivec3, IVec3};
+  use mlua::{Function, Lua};
+
+  use super::{
+    call_as_mod, get_player_actor, get_rollback_start_time, get_unix_time, revert_node_actions,
+    ABM_ACTOR, LIQUID_ACTOR, ROLLBACK_ACTOR,
+  };
+  use crate::game::{
+    lua_engine::lua_error::{add_mod, install_error_handler},
+    map::{
+      block_registry::{BlockDefinition, BlockRegistry},
+      chunk::{Chunk, Node},
+    },
+    server::{map_database::MapDatabase, server_environment::ServerEnvironment},
+  };
+
+  #[test]
+  fn griefing_gets_rolled_back() {
+    let mut block_registry = BlockRegistry::new();
+    let mut register = |name| match block_registry.register_block(BlockDefinition::test(name)) {
+      Ok(id) => Node::new(id, 0),
+      Err(e) => panic!("{}", e),
+    };
+    let stone = register("test:stone");
+    let wood = register("test:wood");
+
+    let database = match MapDatabase::new(":memory:") {
+      Ok(database) => database,
+      Err(e) => panic!("{}", e),
+    };
+
+    let mut environment = ServerEnvironment::new();
+    environment.set_block_registry(block_registry);
+    environment.add_emerged_chunk(Chunk::new(IVec3::ZERO), true);
+    environment.set_rollback_enabled(true);
+
+    environment.set_actor(&get_player_actor("builder"));
+    for x in 0..4 {
+      environment.set_node(ivec3(x, 0, 0), wood);
+    }
+
+    environment.set_actor(&get_player_actor("griefer"));
+    for x in 0..4 {
+      environment.set_node(ivec3(x, 0, 0), Node::air());
+    }
+    environment.set_node(ivec3(8, 0, 0), stone);
+
+    // Someone already fixed one of them up.
+    environment.set_actor(&get_player_actor("builder"));
+    environment.set_node(ivec3(0, 0, 0), stone);
+
+    if let Err(e) = database.save_node_actions(&environment.take_node_actions()) {
+      panic!("{}", e);
+    }
+
+    let area_actions = match database.get_node_actions_in_area(ivec3(0, 0, 0), ivec3(1, 0, 0), 0) {
+      Ok(area_actions) => area_actions,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(area_actions.len(), 5);
+    assert_eq!(area_actions[0].actor, "player:builder");
+    assert_eq!(area_actions[0].new_node.name, "test:stone");
+
+    let griefer_actions = match database.get_actor_node_actions("player:griefer", 0) {
+      Ok(griefer_actions) => griefer_actions,
+      Err(e) => panic!("{}", e),
+    };
+    assert_eq!(griefer_actions.len(), 5);
+
+    environment.set_actor(ROLLBACK_ACTOR);
+    assert_eq!(revert_node_actions(&mut environment, &griefer_actions), 4);
+
+    assert_eq!(environment.get_node(ivec3(0, 0, 0)), Some(stone));
+    assert_eq!(environment.get_node(ivec3(3, 0, 0)), Some(wood));
+    assert_eq!(environment.get_node(ivec3(8, 0, 0)), Some(Node::air()));
+
+    // The rollback got journaled as well.
+    let rollback_actions = environment.take_node_actions();
+    assert_eq!(rollback_actions.len(), 4);
+    assert!(rollback_actions
+      .iter()
+      .all(|action| action.actor == ROLLBACK_ACTOR));
+  }
+
+  #[test]
+  fn mod_callbacks_are_blamed_on_their_mod() {
+    let mut block_registry = BlockRegistry::new();
+    let wood = match block_registry.register_block(BlockDefinition::test("test:wood")) {
+      Ok(id) => Node::new(id, 0),
+      Err(e) => panic!("{}", e),
+    };
+
+    let mut environment = ServerEnvironment::new();
+    environment.set_block_registry(block_registry);
+    environment.add_emerged_chunk(Chunk::new(IVec3::ZERO), true);
+    environment.set_rollback_enabled(true);
+    environment.set_actor(LIQUID_ACTOR);
+    let environment = Rc::new(RefCell::new(environment));
+
+    let lua = Lua::new();
+    install_error_handler(&lua);
+    add_mod(&lua, "trees", "./mods/trees");
+
+    let place_environment = environment.clone();
+    let place = match lua.create_function(move |_, x: i32| {
+      place_environment
+        .borrow_mut()
+        .set_node(ivec3(x, 0, 0), wood);
+      Ok(())
+    }) {
+      Ok(place) => place,
+      Err(e) => panic!("{}", e),
+    };
+    if let Err(e) = lua.globals().set("place", place) {
+      panic!("{}", e);
+    }
+
+    let load = |file: &str| match lua
+      .load("return function(x) place(x) end")
+      .set_name(file)
+      .eval::<Function>()
+    {
+      Ok(function) => function,
+      Err(e) => panic!("{}", e),
+    };
+    let from_mod = load("@./mods/trees/main.lua");
+    let from_engine = load("@./api/api.lua");
+
+    call_as_mod::<_, ()>(&lua, &environment, &from_mod, ABM_ACTOR, "test", 0);
+    call_as_mod::<_, ()>(&lua, &environment, &from_engine, ABM_ACTOR, "test", 1);
+
+    let mut environment = environment.borrow_mut();
+    let actors: Vec<String> = environment
+      .take_node_actions()
+      .into_iter()
+      .map(|action| action.actor)
+      .collect();
+    assert_eq!(actors, vec!["trees", ABM_ACTOR]);
+
+    // Whoever was blamed before gets the blame back.
+    assert_eq!(environment.get_actor(), LIQUID_ACTOR);
+  }
+
+  #[test]
+  fn rollbacks_cant_reach_past_the_start_of_time() {
+    let now = get_unix_time();
+    match get_rollback_start_time(60) {
+      Ok(start_time) => assert!((now - 60..=now - 59).contains(&start_time)),
+      Err(e) => panic!("{}", e),
+    }
+
+    assert!(get_rollback_start_time(i64::MAX as u64).is_ok());
+    assert!(get_rollback_start_time(9223372036854775808).is_err());
+    assert!(get_rollback_start_time(u64::MAX).is_err());
+  }
+}
@@ -1,12 +1,69 @@
-use std::{net::ToSocketAddrs, time::Duration};
+use std::{
+  net::ToSocketAddrs,
+  time::{Duration, Instant},
+};
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
+use glam::{IVec3, Vec3};
 use message_io::{
   events::EventReceiver,
   network::{Endpoint, Transport},
   node::{self, NodeHandler, NodeTask, StoredNetEvent, StoredNodeEvent},
 };
 
+use crate::game::packet::Packet;
+
+///
+/// How long a client can go without sending anything before it's dropped.
+/// Clients ping every few seconds, so this is a client that crashed or lost its connection.
+///
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+///
+/// The longest player name a client can join under.
+///
+pub const MAX_PLAYER_NAME_LENGTH: usize = 20;
+
+///
+/// Player names are saved with the world, so they have to stay simple.
+/// 1 to 20 letters, numbers, - and _.
+///
+pub fn is_valid_player_name(name: &str) -> bool {
+  !name.is_empty()
+    && name.len() <= MAX_PLAYER_NAME_LENGTH
+    && name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+///
+/// A client which has completed the handshake.
+///
+/// The name is the one the client asked for in the handshake.
+/// todo: the name should be checked by, and the privileges come from, ServerAuthentication.
+///
+pub struct ConnectedClient {
+  pub name: String,
+  pub position: Vec3,
+  pub privileges: AHashSet<String>,
+  last_heard: Instant,
+}
+
+impl ConnectedClient {
+  pub fn new(name: String, privileges: AHashSet<String>) -> Self {
+    ConnectedClient {
+      name,
+      position: Vec3::ZERO,
+      privileges,
+      last_heard: Instant::now(),
+    }
+  }
+
+  pub fn has_privilege(&self, privilege: &str) -> bool {
+    self.privileges.contains(privilege)
+  }
+}
+
 ///
 /// ServerConnection and Server can be considered 1 entity.
 ///
@@ -19,7 +76,25 @@ pub struct ServerConnection {
   task: NodeTask,
   handler: NodeHandler<()>,
   event_receiver: EventReceiver<StoredNodeEvent<()>>,
-  pub clients: AHashMap<Endpoint, String>,
+  pub clients: AHashMap<Endpoint, ConnectedClient>,
+  default_privileges: AHashSet<String>,
+
+  // Handshakes the Server still has to let in, or turn away, with the name they asked for.
+  pub join_requests: Vec<(Endpoint, String)>,
+  // Clients which disconnected, and if it was because they timed out.
+  pub left_clients: Vec<(ConnectedClient, bool)>,
+
+  // Digging and placing. The Server validates these, not the connection.
+  pub interaction_requests: Vec<(Endpoint, Packet)>,
+
+  // Moving items around. The Server and mods decide if they happen.
+  pub inventory_requests: Vec<(Endpoint, Packet)>,
+
+  // Chat messages and commands, still untrimmed and unchecked.
+  pub chat_requests: Vec<(Endpoint, String)>,
+
+  // Chunks clients say they got. The ChunkStreamer resends the rest.
+  pub chunk_acks: Vec<(Endpoint, Vec<IVec3>)>,
 
   // Multiple shutdown requests from valid endpoints can be sent in the same tick.
   // We want to process them all.
@@ -65,6 +140,18 @@ impl ServerConnection {
       handler,
       event_receiver,
       clients: AHashMap::new(),
+      default_privileges: AHashSet::new(),
+
+      join_requests: vec![],
+      left_clients: vec![],
+
+      interaction_requests: vec![],
+
+      inventory_requests: vec![],
+
+      chat_requests: vec![],
+
+      chunk_acks: vec![],
 
       shutdown_requests: vec![],
     }
@@ -84,6 +171,13 @@ impl ServerConnection {
     self.port = new_port;
   }
 
+  ///
+  /// Set the privileges clients get when they connect.
+  ///
+  pub fn set_default_privileges(&mut self, default_privileges: &[String]) {
+    self.default_privileges = default_privileges.iter().cloned().collect();
+  }
+
   ///
   /// Construct the address & port into a parsable socket string.
   ///
@@ -95,6 +189,104 @@ impl ServerConnection {
     socket
   }
 
+  ///
+  /// An Endpoint of a made up client on a local port, so tests can act as one.
+  ///
+  #[cfg(test)]
+  pub fn get_test_end_point(&self, port: u16) -> Endpoint {
+    match self.handler.network().listen(Transport::Udp, "127.0.0.1:0") {
+      Ok((listener, _)) => Endpoint::from_listener(listener, ([127, 0, 0, 1], port).into()),
+      Err(e) => panic!(
+        "ServerConnection: failed to listen for a test client. {}",
+        e
+      ),
+    }
+  }
+
+  ///
+  /// Let a client which asked to join in under a name, and confirm the handshake.
+  ///
+  pub fn accept_client(&mut self, end_point: Endpoint, name: &str) {
+    self.clients.insert(
+      end_point,
+      ConnectedClient::new(name.to_string(), self.default_privileges.clone()),
+    );
+    self.send_data(end_point, "MINETEST_HAND_SHAKE_CONFIRMED")
+  }
+
+  ///
+  /// Turn a client which asked to join away, telling it why.
+  ///
+  pub fn reject_client(&self, end_point: Endpoint, reason: &str) {
+    self.send_data(end_point, &format!("MINETEST_HAND_SHAKE_DENIED {}", reason));
+  }
+
+  ///
+  /// Why a client can't join under a name, if it can't.
+  ///
+  /// todo: a password check goes here once there is ServerAuthentication.
+  ///
+  fn get_join_refusal(&self, end_point: Endpoint, name: &str) -> Option<String> {
+    if !is_valid_player_name(name) {
+      return Some(format!(
+        "[{}] is not a valid player name. Use 1 to {} letters, numbers, - and _.",
+        name, MAX_PLAYER_NAME_LENGTH
+      ));
+    }
+
+    let taken = self.clients.values().any(|client| client.name == name)
+      || self
+        .join_requests
+        .iter()
+        .any(|(other, other_name)| *other != end_point && other_name == name);
+    if taken {
+      return Some(format!("[{}] is already playing.", name));
+    }
+
+    None
+  }
+
+  ///
+  /// A client asked to join under a name.
+  ///
+  /// The Server decides if they get in. Clients already in just get their confirmation again.
+  ///
+  fn handshake_reaction(&mut self, end_point: Endpoint, name: &str) {
+    if self.clients.contains_key(&end_point) {
+      self.send_data(end_point, "MINETEST_HAND_SHAKE_CONFIRMED");
+      return;
+    }
+
+    if let Some(reason) = self.get_join_refusal(end_point, name) {
+      println!(
+        "ServerConnection: turned [{}] away. {}",
+        end_point.addr(),
+        reason
+      );
+      self.reject_client(end_point, &reason);
+      return;
+    }
+
+    if !self
+      .join_requests
+      .iter()
+      .any(|(other, _)| *other == end_point)
+    {
+      self.join_requests.push((end_point, name.to_string()));
+    }
+  }
+
+  ///
+  /// Get the position of every connected client.
+  ///
+  pub fn get_client_positions(&self) -> Vec<Vec3> {
+    self
+      .clients
+      .values()
+      .map(|client| client.position)
+      .collect()
+  }
+
   ///
   /// Send raw data to an EndPoint (ClientConnection).
   ///
@@ -102,6 +294,48 @@ impl ServerConnection {
     self.handler.network().send(end_point, data.as_bytes());
   }
 
+  ///
+  /// Send a binary Packet to an EndPoint (ClientConnection).
+  ///
+  pub fn send_packet(&self, end_point: Endpoint, packet: &Packet) {
+    self.handler.network().send(end_point, &packet.serialize());
+  }
+
+  ///
+  /// React to a binary Packet from a client.
+  ///
+  /// Clients which haven't done the handshake are ignored.
+  ///
+  fn packet_reaction(&mut self, end_point: Endpoint, raw_packet: &[u8]) {
+    let client = match self.clients.get_mut(&end_point) {
+      Some(client) => client,
+      None => return,
+    };
+
+    match Packet::deserialize(raw_packet) {
+      Ok(Packet::PlayerPosition(position)) if position.is_finite() => client.position = position,
+      Ok(packet @ (Packet::DigStart { .. } | Packet::DigStop(_) | Packet::Place { .. })) => {
+        self.interaction_requests.push((end_point, packet))
+      }
+      Ok(packet @ Packet::InventoryMove { .. }) => {
+        self.inventory_requests.push((end_point, packet))
+      }
+      Ok(Packet::ChatMessage(message)) => self.chat_requests.push((end_point, message)),
+      Ok(Packet::ChunksReceived(chunk_positions)) => {
+        self.chunk_acks.push((end_point, chunk_positions))
+      }
+      Ok(_) => println!(
+        "ServerConnection: [{}] sent a packet only the Server can send.",
+        end_point.addr()
+      ),
+      Err(e) => println!(
+        "ServerConnection: bad packet from [{}]. {}",
+        end_point.addr(),
+        e
+      ),
+    }
+  }
+
   ///
   /// A procedure to react to a network event.
   ///
@@ -110,6 +344,15 @@ impl ServerConnection {
   pub fn event_reaction(&mut self, event: StoredNetEvent) {
     // We don't need to match, we're using UDP which is connectionless.
     if let StoredNetEvent::Message(end_point, raw_message) = event {
+      if let Some(client) = self.clients.get_mut(&end_point) {
+        client.last_heard = Instant::now();
+      }
+
+      if Packet::is_packet(&raw_message) {
+        self.packet_reaction(end_point, &raw_message);
+        return;
+      }
+
       // todo: use https://github.com/serde-rs/bytes
       let receieved_string = match String::from_utf8(raw_message) {
         Ok(new_string) => new_string,
@@ -119,16 +362,29 @@ impl ServerConnection {
         }
       };
 
-      println!("ServerConnection Server received message: {}", receieved_string);
+      println!(
+        "ServerConnection Server received message: {}",
+        receieved_string
+      );
+
+      if let Some(name) = receieved_string.strip_prefix("MINETEST_HAND_SHAKE ") {
+        self.handshake_reaction(end_point, name);
+        return;
+      }
 
       match receieved_string.as_str() {
         "hi" => self.send_data(end_point, "hi there!"),
-        "MINETEST_HAND_SHAKE" => self.send_data(end_point, "MINETEST_HAND_SHAKE_CONFIRMED"),
+        "MINETEST_HAND_SHAKE" => self.reject_client(end_point, "No player name was sent."),
         "MINETEST_PING_REQUEST" => {
           println!("ServerConnection ServerConnection got ping request, sending confirmation to ClientConnection.");
           self.send_data(end_point, "MINETEST_PING_CONFIRMATION")
         }
         "MINETEST_SHUT_DOWN_REQUEST" => self.shutdown_requests.push(end_point),
+        "MINETEST_DISCONNECT" => {
+          if let Some(client) = self.clients.remove(&end_point) {
+            self.left_clients.push((client, false));
+          }
+        }
         _ => (),
       }
     }
@@ -157,6 +413,27 @@ impl ServerConnection {
         has_new_event = false;
       }
     }
+
+    self.drop_timed_out_clients();
+  }
+
+  ///
+  /// Drop every client which hasn't sent anything in CLIENT_TIMEOUT.
+  ///
+  fn drop_timed_out_clients(&mut self) {
+    let timed_out: Vec<Endpoint> = self
+      .clients
+      .iter()
+      .filter(|(_, client)| client.last_heard.elapsed() >= CLIENT_TIMEOUT)
+      .map(|(end_point, _)| *end_point)
+      .collect();
+
+    for end_point in timed_out {
+      if let Some(client) = self.clients.remove(&end_point) {
+        println!("ServerConnection: [{}] timed out.", client.name);
+        self.left_clients.push((client, true));
+      }
+    }
   }
 }
 
@@ -169,3 +446,66 @@ impl Drop for ServerConnection {
     println!("ServerConnection dropped!");
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use message_io::{
+    network::{Endpoint, Transport},
+    node::StoredNetEvent,
+  };
+
+  use super::{is_valid_player_name, ServerConnection};
+
+  fn handshake(connection: &mut ServerConnection, end_point: Endpoint, name: &str) {
+    let message = format!("MINETEST_HAND_SHAKE {}", name).into_bytes();
+    connection.event_reaction(StoredNetEvent::Message(end_point, message));
+  }
+
+  #[test]
+  fn players_join_under_unique_names() {
+    assert!(is_valid_player_name("singleplayer"));
+    assert!(is_valid_player_name("Sam_the-3rd"));
+    assert!(!is_valid_player_name(""));
+    assert!(!is_valid_player_name("a name"));
+    assert!(!is_valid_player_name("127.0.0.1:30001"));
+    assert!(!is_valid_player_name("x".repeat(21).as_str()));
+
+    let mut connection = ServerConnection::new("127.0.0.1".to_string(), 0);
+    let listener = match connection
+      .handler
+      .network()
+      .listen(Transport::Udp, "127.0.0.1:0")
+    {
+      Ok((listener, _)) => listener,
+      Err(e) => panic!("{}", e),
+    };
+    let end_point = |port: u16| Endpoint::from_listener(listener, ([127, 0, 0, 1], port).into());
+
+    handshake(&mut connection, end_point(40001), "alice");
+    handshake(&mut connection, end_point(40001), "alice");
+    handshake(&mut connection, end_point(40002), "alice");
+    handshake(&mut connection, end_point(40003), "bad name");
+    assert_eq!(
+      connection.join_requests,
+      vec![(end_point(40001), "alice".to_string())]
+    );
+
+    // Once in, the name stays taken. Reconnecting from a new port doesn't make a new player.
+    for (join_end_point, name) in std::mem::take(&mut connection.join_requests) {
+      connection.accept_client(join_end_point, &name);
+    }
+    handshake(&mut connection, end_point(40004), "alice");
+    handshake(&mut connection, end_point(40004), "bob");
+    assert_eq!(
+      connection.join_requests,
+      vec![(end_point(40004), "bob".to_string())]
+    );
+    assert_eq!(
+      connection
+        .clients
+        .get(&end_point(40001))
+        .map(|client| client.name.as_str()),
+      Some("alice")
+    );
+  }
+}
@@ -0,0 +1,468 @@
+use std::rc::Rc;
+
+use ahash::{AHashMap, AHashSet};
+use glam::IVec3;
+
+use crate::game::{
+  crafting::CraftRegistry,
+  inventory::{Inventory, InventoryLocation},
+  item_registry::ItemRegistry,
+  map::{
+    block_registry::{BlockRegistry, IGNORE_NAME},
+    chunk::{Chunk, Node},
+    lighting::{connect_chunk_light, light_new_chunk, update_node_light},
+    Map,
+  },
+};
+
+use super::{
+  protection::AreaProtection,
+  rollback::{get_unix_time, NodeAction, RollbackNode, DEFAULT_ACTOR},
+};
+
+///
+/// The most hp a player can have. Players join and respawn with this much.
+///
+pub const PLAYER_HP_MAX: u16 = 20;
+
+///
+/// A detached inventory. Only player_name gets to see it, or everyone if it's None.
+///
+pub struct DetachedInventory {
+  pub inventory: Inventory,
+  pub player_name: Option<String>,
+}
+
+///
+/// Everything in the world that both the Server and the Lua API touch.
+///
+/// The Server holds this in an Rc<RefCell>, and so do the Lua API functions.
+///
+/// ! Never hold a borrow of this while calling into Lua!
+/// ! Lua will try to borrow it right back and the RefCell will panic.
+///
+pub struct ServerEnvironment {
+  map: Map,
+  block_registry: BlockRegistry,
+  item_registry: Rc<ItemRegistry>,
+  craft_registry: CraftRegistry,
+  area_protection: AreaProtection,
+
+  player_inventories: AHashMap<String, Inventory>,
+  player_hp: AHashMap<String, u16>,
+  // Players whose hp hit 0 since the last take_player_deaths(), and what did it.
+  player_deaths: Vec<(String, String)>,
+  detached_inventories: AHashMap<String, DetachedInventory>,
+  // Every Inventory handed out to be changed since the last take_modified_inventories().
+  modified_inventories: AHashSet<InventoryLocation>,
+
+  // Every position set_node changed since the last take_node_changes().
+  node_changes: Vec<IVec3>,
+
+  // The rollback journal since the last take_node_actions(). None while it's off.
+  node_actions: Option<Vec<NodeAction>>,
+  // Who gets the blame for set_node right now.
+  actor: String,
+}
+
+impl ServerEnvironment {
+  pub fn new() -> Self {
+    ServerEnvironment {
+      map: Map::new(),
+      block_registry: BlockRegistry::new(),
+      item_registry: Rc::new(ItemRegistry::new()),
+      craft_registry: CraftRegistry::new(),
+      area_protection: AreaProtection::new(),
+
+      player_inventories: AHashMap::new(),
+      player_hp: AHashMap::new(),
+      player_deaths: vec![],
+      detached_inventories: AHashMap::new(),
+      modified_inventories: AHashSet::new(),
+
+      node_changes: vec![],
+
+      node_actions: None,
+      actor: DEFAULT_ACTOR.to_string(),
+    }
+  }
+
+  pub fn get_map(&self) -> &Map {
+    &self.map
+  }
+
+  pub fn get_map_mut(&mut self) -> &mut Map {
+    &mut self.map
+  }
+
+  pub fn get_block_registry(&self) -> &BlockRegistry {
+    &self.block_registry
+  }
+
+  pub fn set_block_registry(&mut self, block_registry: BlockRegistry) {
+    self.block_registry = block_registry;
+  }
+
+  ///
+  /// The ItemRegistry is shared, so it can be held onto while the ServerEnvironment changes.
+  ///
+  pub fn get_item_registry(&self) -> Rc<ItemRegistry> {
+    self.item_registry.clone()
+  }
+
+  pub fn set_item_registry(&mut self, item_registry: Rc<ItemRegistry>) {
+    self.item_registry = item_registry;
+  }
+
+  pub fn get_craft_registry(&self) -> &CraftRegistry {
+    &self.craft_registry
+  }
+
+  pub fn set_craft_registry(&mut self, craft_registry: CraftRegistry) {
+    self.craft_registry = craft_registry;
+  }
+
+  pub fn get_area_protection(&self) -> &AreaProtection {
+    &self.area_protection
+  }
+
+  pub fn get_area_protection_mut(&mut self) -> &mut AreaProtection {
+    &mut self.area_protection
+  }
+
+  pub fn set_area_protection(&mut self, area_protection: AreaProtection) {
+    self.area_protection = area_protection;
+  }
+
+  ///
+  /// Get the Inventory at a location.
+  ///
+  /// Returns None if it doesn't exist, or the node's Chunk isn't loaded.
+  ///
+  pub fn get_inventory(&self, location: &InventoryLocation) -> Option<&Inventory> {
+    match location {
+      InventoryLocation::Node(position) => self
+        .map
+        .get_node_meta(*position)
+        .map(|node_meta| node_meta.get_inventory()),
+      InventoryLocation::Player(name) => self.player_inventories.get(name),
+      InventoryLocation::Detached(name) => self
+        .detached_inventories
+        .get(name)
+        .map(|detached| &detached.inventory),
+    }
+  }
+
+  ///
+  /// Get the Inventory at a location to modify it. It gets synced to clients after the tick.
+  ///
+  /// Node inventories get created if the node's Chunk is loaded.
+  ///
+  pub fn get_inventory_mut(&mut self, location: &InventoryLocation) -> Option<&mut Inventory> {
+    let inventory = match location {
+      InventoryLocation::Node(position) => self
+        .map
+        .get_node_meta_mut(*position)
+        .map(|node_meta| node_meta.get_inventory_mut()),
+      InventoryLocation::Player(name) => self.player_inventories.get_mut(name),
+      InventoryLocation::Detached(name) => self
+        .detached_inventories
+        .get_mut(name)
+        .map(|detached| &mut detached.inventory),
+    };
+
+    if inventory.is_some() {
+      self.modified_inventories.insert(location.clone());
+    }
+
+    inventory
+  }
+
+  pub fn has_player_inventory(&self, name: &str) -> bool {
+    self.player_inventories.contains_key(name)
+  }
+
+  pub fn set_player_inventory(&mut self, name: &str, inventory: Inventory) {
+    self.player_inventories.insert(name.to_string(), inventory);
+    self
+      .modified_inventories
+      .insert(InventoryLocation::Player(name.to_string()));
+  }
+
+  pub fn get_player_inventories(&self) -> &AHashMap<String, Inventory> {
+    &self.player_inventories
+  }
+
+  ///
+  /// Put a player who just joined into the world with full hp.
+  ///
+  pub fn add_player(&mut self, name: &str, inventory: Inventory) {
+    self.set_player_inventory(name, inventory);
+    self.player_hp.insert(name.to_string(), PLAYER_HP_MAX);
+  }
+
+  ///
+  /// Take a player who left out of the world. Returns their Inventory so it can be saved.
+  ///
+  pub fn remove_player(&mut self, name: &str) -> Option<Inventory> {
+    self.player_hp.remove(name);
+    self
+      .player_deaths
+      .retain(|(player_name, _)| player_name != name);
+
+    // Nobody is left to sync it to.
+    let location = InventoryLocation::Player(name.to_string());
+    self.modified_inventories.remove(&location);
+    self.player_inventories.remove(name)
+  }
+
+  ///
+  /// Get a player's hp. None if they aren't in the world.
+  ///
+  pub fn get_player_hp(&self, name: &str) -> Option<u16> {
+    self.player_hp.get(name).copied()
+  }
+
+  ///
+  /// Set a player's hp, up to PLAYER_HP_MAX.
+  ///
+  /// A living player dropping to 0 dies, reason is what killed them.
+  /// Returns false if they aren't in the world.
+  ///
+  pub fn set_player_hp(&mut self, name: &str, hp: u16, reason: &str) -> bool {
+    let player_hp = match self.player_hp.get_mut(name) {
+      Some(player_hp) => player_hp,
+      None => return false,
+    };
+
+    let was_alive = *player_hp > 0;
+    *player_hp = hp.min(PLAYER_HP_MAX);
+    if was_alive && *player_hp == 0 {
+      self
+        .player_deaths
+        .push((name.to_string(), reason.to_string()));
+    }
+
+    true
+  }
+
+  ///
+  /// Get every player who died since the last call, and what killed them.
+  ///
+  pub fn take_player_deaths(&mut self) -> Vec<(String, String)> {
+    std::mem::take(&mut self.player_deaths)
+  }
+
+  ///
+  /// Create an empty detached inventory, replacing any with the same name.
+  ///
+  /// player_name limits who can see it. None is everyone.
+  ///
+  pub fn create_detached_inventory(&mut self, name: &str, player_name: Option<String>) {
+    self.detached_inventories.insert(
+      name.to_string(),
+      DetachedInventory {
+        inventory: Inventory::new(),
+        player_name,
+      },
+    );
+    self
+      .modified_inventories
+      .insert(InventoryLocation::Detached(name.to_string()));
+  }
+
+  ///
+  /// Returns false if there was no detached inventory with the name.
+  ///
+  pub fn remove_detached_inventory(&mut self, name: &str) -> bool {
+    let removed = self.detached_inventories.remove(name).is_some();
+    if removed {
+      self
+        .modified_inventories
+        .insert(InventoryLocation::Detached(name.to_string()));
+    }
+    removed
+  }
+
+  ///
+  /// Check if a player gets to see an Inventory, and move items around in it.
+  ///
+  /// todo: node inventories need formspecs before players can get to them.
+  ///
+  pub fn can_player_see_inventory(&self, player_name: &str, location: &InventoryLocation) -> bool {
+    match location {
+      InventoryLocation::Node(_) => false,
+      InventoryLocation::Player(name) => {
+        name == player_name && self.player_inventories.contains_key(name)
+      }
+      InventoryLocation::Detached(name) => match self.detached_inventories.get(name) {
+        Some(detached) => match &detached.player_name {
+          Some(name) => name == player_name,
+          None => true,
+        },
+        None => false,
+      },
+    }
+  }
+
+  ///
+  /// Get every Inventory a player can see.
+  ///
+  pub fn get_player_visible_inventories(&self, player_name: &str) -> Vec<InventoryLocation> {
+    let mut locations = vec![InventoryLocation::Player(player_name.to_string())];
+    locations.extend(
+      self
+        .detached_inventories
+        .keys()
+        .map(|name| InventoryLocation::Detached(name.clone())),
+    );
+    locations.retain(|location| self.can_player_see_inventory(player_name, location));
+    locations
+  }
+
+  ///
+  /// Get every Inventory which may have changed since the last call.
+  ///
+  pub fn take_modified_inventories(&mut self) -> Vec<InventoryLocation> {
+    self.modified_inventories.drain().collect()
+  }
+
+  ///
+  /// Get the name of the player who gets the blame for set_node right now, if it's a player.
+  ///
+  pub fn get_player_actor_name(&self) -> Option<&str> {
+    self.actor.strip_prefix("player:")
+  }
+
+  ///
+  /// Put a Chunk fresh out of the emerge workers into the Map and light it.
+  ///
+  /// Generated Chunks get fully lit. Chunks from the disk already have
+  /// their light, they only need to be joined up with their neighbors.
+  ///
+  pub fn add_emerged_chunk(&mut self, chunk: Chunk, generated: bool) {
+    let chunk_position = chunk.get_position();
+    self.map.insert_chunk(chunk);
+
+    match generated {
+      true => light_new_chunk(&mut self.map, &self.block_registry, chunk_position),
+      false => connect_chunk_light(&mut self.map, &self.block_registry, chunk_position),
+    }
+  }
+
+  ///
+  /// Get a Node out of the Map.
+  ///
+  /// Returns None if the Chunk is not in memory.
+  ///
+  pub fn get_node(&self, world_position: IVec3) -> Option<Node> {
+    self.map.get_node(world_position)
+  }
+
+  ///
+  /// Set a Node in the Map and fix up everything that depends on it.
+  ///
+  /// Everything on the Server that changes a Node should go through here.
+  ///
+  /// Returns false if the Chunk is not in memory.
+  ///
+  pub fn set_node(&mut self, world_position: IVec3, node: Node) -> bool {
+    let old_node = match self.map.get_node(world_position) {
+      Some(old_node) => old_node,
+      None => return false,
+    };
+
+    self.map.set_node(world_position, node);
+
+    if old_node != node {
+      self.record_node_action(world_position, old_node, node);
+    }
+
+    update_node_light(&mut self.map, &self.block_registry, world_position);
+    self.node_changes.push(world_position);
+
+    true
+  }
+
+  ///
+  /// Get every position that set_node changed since the last call.
+  ///
+  /// This is how changes get streamed out to clients.
+  /// The same position can show up more than once.
+  ///
+  pub fn take_node_changes(&mut self) -> Vec<IVec3> {
+    std::mem::take(&mut self.node_changes)
+  }
+
+  ///
+  /// Turn the rollback journal on or off.
+  ///
+  pub fn set_rollback_enabled(&mut self, enabled: bool) {
+    self.node_actions = match enabled {
+      true => Some(self.node_actions.take().unwrap_or_default()),
+      false => None,
+    };
+  }
+
+  ///
+  /// Set who gets the blame for every set_node from now on.
+  ///
+  /// Players are player:name, mods are their mod name, engine actors start with a colon.
+  /// (rollback::get_player_actor)
+  ///
+  pub fn set_actor(&mut self, actor: &str) {
+    if self.actor != actor {
+      self.actor = actor.to_string();
+    }
+  }
+
+  pub fn get_actor(&self) -> &str {
+    &self.actor
+  }
+
+  fn record_node_action(&mut self, world_position: IVec3, old_node: Node, new_node: Node) {
+    let node_actions = match &mut self.node_actions {
+      Some(node_actions) => node_actions,
+      None => return,
+    };
+
+    let to_rollback_node = |node: Node| RollbackNode {
+      name: self
+        .block_registry
+        .get_name(node.block_id)
+        .unwrap_or(IGNORE_NAME)
+        .to_string(),
+      param2: node.param2,
+    };
+
+    node_actions.push(NodeAction {
+      time: get_unix_time(),
+      actor: self.actor.clone(),
+      position: world_position,
+      old_node: to_rollback_node(old_node),
+      new_node: to_rollback_node(new_node),
+    });
+  }
+
+  ///
+  /// Get the rollback journal since the last call, oldest first.
+  ///
+  /// It only gets saved once the Server puts it into the database.
+  ///
+  pub fn take_node_actions(&mut self) -> Vec<NodeAction> {
+    match &mut self.node_actions {
+      Some(node_actions) => std::mem::take(node_actions),
+      None => vec![],
+    }
+  }
+
+  ///
+  /// Look at the rollback journal which hasn't been taken yet.
+  ///
+  pub fn get_node_actions(&self) -> &[NodeAction] {
+    match &self.node_actions {
+      Some(node_actions) => node_actions,
+      None => &[],
+    }
+  }
+}
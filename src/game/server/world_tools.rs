@@ -0,0 +1,210 @@
+// Tools for copying worlds and pieces of worlds around.
+//
+// These only need a MapDatabase, so they work from the command line
+// as well as from the server console while the world is running.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use glam::{ivec3, IVec3};
+
+use crate::{
+  command_line::WorldCommand,
+  file_utilities::{create_dir, file_exists},
+  game::map::{chunk::world_to_chunk_position, region::Region, Map},
+};
+
+use super::{map_database::MapDatabase, Server};
+
+///
+/// Where a backup goes when no path is given.
+///
+/// {world_path}/backups/backup_{unix time}.sqlite
+///
+pub fn get_default_backup_path(world_path: &str) -> Result<String, String> {
+  let backups_path = format!("{}/backups", world_path);
+  create_dir(&backups_path)?;
+
+  let unix_time = match SystemTime::now().duration_since(UNIX_EPOCH) {
+    Ok(unix_time) => unix_time.as_secs(),
+    Err(e) => return Err(format!("WorldTools: the clock is broken. {}", e)),
+  };
+
+  Ok(format!("{}/backup_{}.sqlite", backups_path, unix_time))
+}
+
+///
+/// Export every node between two corners in the database into a region file.
+///
+/// Only what's in the database gets exported, the Server must save_map() first.
+///
+pub fn export_region(
+  database: &MapDatabase,
+  pos1: IVec3,
+  pos2: IVec3,
+  region_path: &str,
+) -> Result<Region, String> {
+  let block_names = match database.get_block_names()? {
+    Some(block_names) => block_names,
+    None => return Err("WorldTools: this world has never been loaded by a server.".to_string()),
+  };
+
+  let min = world_to_chunk_position(pos1.min(pos2));
+  let max = world_to_chunk_position(pos1.max(pos2));
+
+  let mut map = Map::new();
+  for x in min.x..=max.x {
+    for y in min.y..=max.y {
+      for z in min.z..=max.z {
+        let chunk_position = ivec3(x, y, z);
+        match database.load_chunk(chunk_position, |id| (id as usize) < block_names.len())? {
+          Some(chunk) => map.insert_chunk(chunk),
+          None => {
+            return Err(format!(
+              "WorldTools: chunk [{}] has not been generated yet.",
+              chunk_position
+            ))
+          }
+        }
+      }
+    }
+  }
+
+  let region = Region::from_map(&map, &block_names, pos1, pos2)?;
+  region.save_to_file(region_path)?;
+
+  Ok(region)
+}
+
+///
+/// Run a world tool from the command line.
+///
+pub fn run_world_command(command: &WorldCommand, world_name: &str) -> Result<(), String> {
+  let world_path = Server::get_world_path(world_name);
+  let database_path = Server::get_database_path(&world_path);
+
+  // MapDatabase::new() would happily create an empty world.
+  if !file_exists(&database_path) {
+    return Err(format!(
+      "WorldTools: world [{}] does not exist.",
+      world_name
+    ));
+  }
+  let database = MapDatabase::new(&database_path)?;
+
+  match command {
+    WorldCommand::Backup { output } => {
+      let backup_path = match output {
+        Some(output) => output.clone(),
+        None => get_default_backup_path(&world_path)?,
+      };
+      database.backup(&backup_path)?;
+      println!(
+        "WorldTools: backed up [{}] to [{}]",
+        world_name, backup_path
+      );
+    }
+    WorldCommand::ExportRegion { pos1, pos2, output } => {
+      let region = export_region(&database, *pos1, *pos2, output)?;
+      println!(
+        "WorldTools: exported [{}] nodes to [{}]",
+        region.get_node_count(),
+        output
+      );
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{ivec3, IVec3};
+  use mlua::{Lua, Table};
+
+  use super::export_region;
+  use crate::game::{
+    map::{
+      block_registry::{BlockRegistry, IGNORE_NAME},
+      chunk::{Chunk, Node},
+    },
+    server::map_database::MapDatabase,
+  };
+
+  // What Server::load_definitions() does with the world's block table.
+  fn start_server(lua: &Lua, database: &MapDatabase, block_names: &[&str]) -> BlockRegistry {
+    let blocks: Table = match lua.create_table() {
+      Ok(blocks) => blocks,
+      Err(e) => panic!("{}", e),
+    };
+    for name in block_names {
+      let definition = lua
+        .load(format!("return {{name = \"{}\", drawtype = 1}}", name))
+        .eval::<Table>()
+        .and_then(|definition| blocks.set(*name, definition));
+      if let Err(e) = definition {
+        panic!("{}", e);
+      }
+    }
+
+    let saved_block_names = match database.get_block_names() {
+      Ok(saved_block_names) => saved_block_names.unwrap_or_default(),
+      Err(e) => panic!("{}", e),
+    };
+    let block_registry = match BlockRegistry::from_lua_table(&blocks, &saved_block_names) {
+      Ok(block_registry) => block_registry,
+      Err(e) => panic!("{}", e),
+    };
+    if let Err(e) = database.set_block_names(block_registry.get_block_names()) {
+      panic!("{}", e);
+    }
+    block_registry
+  }
+
+  #[test]
+  fn old_chunks_export_with_their_own_block_names() {
+    let lua = Lua::new();
+    let database = match MapDatabase::new(":memory:") {
+      Ok(database) => database,
+      Err(e) => panic!("{}", e),
+    };
+
+    let first = start_server(&lua, &database, &["test:stone", "test:dirt"]);
+    let dirt_id = match first.get_id("test:dirt") {
+      Some(dirt_id) => dirt_id,
+      None => panic!("dirt was not registered."),
+    };
+    let mut chunk = Chunk::new(IVec3::ZERO);
+    chunk.set_node(ivec3(1, 0, 0), Node::new(dirt_id, 0));
+    chunk.set_node(ivec3(2, 0, 0), Node::new(42, 0));
+    if let Err(e) = database.save_chunks(&[&chunk]) {
+      panic!("{}", e);
+    }
+
+    // A mod adding a block that sorts first must not rename the old chunk.
+    start_server(&lua, &database, &["test:apple", "test:stone", "test:dirt"]);
+
+    let region_path = std::env::temp_dir().join(format!(
+      "minetest_export_test_{}.region",
+      std::process::id()
+    ));
+    let region_path = region_path.to_string_lossy().to_string();
+    let region = export_region(&database, IVec3::ZERO, ivec3(2, 0, 0), &region_path);
+    let _ = std::fs::remove_file(&region_path);
+    let region = match region {
+      Ok(region) => region,
+      Err(e) => panic!("{}", e),
+    };
+
+    let names = ["air", "test:dirt", IGNORE_NAME];
+    let nodes = region.get_nodes(|name| names.iter().position(|n| *n == name).map(|id| id as u16));
+    let nodes = match nodes {
+      Ok(nodes) => nodes,
+      Err(e) => panic!("{}", e),
+    };
+
+    // The unknown block is ignore, so it's left out.
+    assert_eq!(nodes.len(), 2);
+    assert_eq!(nodes[0].1, Node::new(0, 0));
+    assert_eq!(nodes[1].1, Node::new(1, 0));
+  }
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use mlua::Table;
+
+use super::{
+  lua_engine::lua_table_helpers::{get_field, get_field_or},
+  map::block_registry::BlockDefinition,
+};
+
+///
+/// How fast a tool digs blocks in one group.
+///
+/// * times    - Seconds to dig, by the block's rating in the group. times[0] is rating 1.
+/// * maxlevel - The highest block level (the "level" group) this can dig.
+///
+/// Every level the tool has over the block speeds digging up.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupCap {
+  pub times: Vec<f32>,
+  pub maxlevel: i32,
+}
+
+///
+/// What an item can dig and how fast. (tool_capabilities in Lua)
+///
+/// The hand is an item too, it's named "".
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToolCapabilities {
+  pub groupcaps: BTreeMap<String, GroupCap>,
+}
+
+impl ToolCapabilities {
+  pub fn new() -> Self {
+    ToolCapabilities {
+      groupcaps: BTreeMap::new(),
+    }
+  }
+
+  ///
+  /// Parse tool_capabilities out of a Lua item definition.
+  ///
+  /// {groupcaps = {cracky = {times = {3.0, 1.5, 0.8}, maxlevel = 1}}}
+  ///
+  pub fn from_lua_table(table: &Table) -> Result<Self, String> {
+    let mut tool_capabilities = ToolCapabilities::new();
+
+    let groupcaps: Option<Table> = get_field_or(table, "groupcaps", None)?;
+    let groupcaps = match groupcaps {
+      Some(groupcaps) => groupcaps,
+      None => return Ok(tool_capabilities),
+    };
+
+    for pair in groupcaps.pairs::<String, Table>() {
+      let (group, groupcap) = match pair {
+        Ok(pair) => pair,
+        Err(e) => return Err(format!("groupcaps must be group = table pairs. {}", e)),
+      };
+
+      let times: Vec<f32> =
+        get_field(&groupcap, "times").map_err(|e| format!("groupcap [{}] {}", group, e))?;
+      if times.iter().any(|time| !time.is_finite() || *time < 0.0) {
+        return Err(format!(
+          "groupcap [{}] times must be numbers of 0 or more.",
+          group
+        ));
+      }
+
+      let maxlevel = get_field_or(&groupcap, "maxlevel", 0)
+        .map_err(|e| format!("groupcap [{}] {}", group, e))?;
+
+      tool_capabilities
+        .groupcaps
+        .insert(group, GroupCap { times, maxlevel });
+    }
+
+    Ok(tool_capabilities)
+  }
+
+  ///
+  /// How many seconds it takes to dig a block with these capabilities.
+  ///
+  /// Returns None if the block can't be dug with them at all.
+  ///
+  /// dig_immediate works no matter what's doing the digging:
+  /// * dig_immediate = 2 - Half a second.
+  /// * dig_immediate = 3 - Instantly.
+  ///
+  pub fn get_dig_time(&self, definition: &BlockDefinition) -> Option<f32> {
+    match definition.get_group("dig_immediate") {
+      2 => return Some(0.5),
+      3 => return Some(0.0),
+      _ => (),
+    }
+
+    let level = definition.get_group("level");
+
+    self
+      .groupcaps
+      .iter()
+      .filter_map(|(group, groupcap)| {
+        let rating = definition.get_group(group);
+        let leveldiff = groupcap.maxlevel - level;
+        if rating <= 0 || leveldiff < 0 {
+          return None;
+        }
+
+        let time = *groupcap.times.get(rating as usize - 1)?;
+        Some(match leveldiff > 1 {
+          true => time / leveldiff as f32,
+          false => time,
+        })
+      })
+      .min_by(|a, b| a.total_cmp(b))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{GroupCap, ToolCapabilities};
+  use crate::game::map::block_registry::BlockDefinition;
+
+  fn test_block(groups: &[(&str, i32)]) -> BlockDefinition {
+    BlockDefinition {
+      groups: groups
+        .iter()
+        .map(|(group, rating)| (group.to_string(), *rating))
+        .collect(),
+      ..BlockDefinition::test("test:block")
+    }
+  }
+
+  #[test]
+  fn dig_times_come_from_the_fastest_group() {
+    let mut tool_capabilities = ToolCapabilities::new();
+    tool_capabilities.groupcaps.insert(
+      "cracky".to_string(),
+      GroupCap {
+        times: vec![4.0, 2.0, 1.0],
+        maxlevel: 3,
+      },
+    );
+    tool_capabilities.groupcaps.insert(
+      "crumbly".to_string(),
+      GroupCap {
+        times: vec![0.6],
+        maxlevel: 1,
+      },
+    );
+
+    // Not in any group the tool knows.
+    assert_eq!(tool_capabilities.get_dig_time(&test_block(&[])), None);
+    // Rating past the end of times.
+    assert_eq!(
+      tool_capabilities.get_dig_time(&test_block(&[("crumbly", 2)])),
+      None
+    );
+    // Level 1 stone with a maxlevel 3 tool is twice as fast.
+    assert_eq!(
+      tool_capabilities.get_dig_time(&test_block(&[("cracky", 2), ("level", 1)])),
+      Some(1.0)
+    );
+    // Too high of a level.
+    assert_eq!(
+      tool_capabilities.get_dig_time(&test_block(&[("crumbly", 1), ("level", 2)])),
+      None
+    );
+    assert_eq!(
+      tool_capabilities.get_dig_time(&test_block(&[("cracky", 1), ("crumbly", 1)])),
+      Some(0.6)
+    );
+    assert_eq!(
+      ToolCapabilities::new().get_dig_time(&test_block(&[("dig_immediate", 3)])),
+      Some(0.0)
+    );
+  }
+}